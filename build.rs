@@ -34,6 +34,8 @@ fn main() -> std::io::Result<()> {
     let mut bin_entries = Vec::new();
     let mut root_entries = Vec::new();
     let mut admin_entries = Vec::new();
+    let mut ws_entries = Vec::new();
+    let mut admin_ws_entries = Vec::new();
 
     if extensions_dir.exists() {
         for entry in fs::read_dir(&extensions_dir)? {
@@ -60,6 +62,14 @@ fn main() -> std::io::Result<()> {
                         let stem = &name[..name.len() - ".admin.rs".len()];
                         let ident = sanitize_ident(stem);
                         admin_entries.push((ident, name.to_string()));
+                    } else if name.ends_with(".admin.ws.rs") {
+                        let stem = &name[..name.len() - ".admin.ws.rs".len()];
+                        let ident = sanitize_ident(stem);
+                        admin_ws_entries.push((ident, name.to_string()));
+                    } else if name.ends_with(".ws.rs") {
+                        let stem = &name[..name.len() - ".ws.rs".len()];
+                        let ident = sanitize_ident(stem);
+                        ws_entries.push((ident, name.to_string()));
                     }
                 }
             }
@@ -70,6 +80,8 @@ fn main() -> std::io::Result<()> {
     bin_entries.sort_by(|a, b| a.0.cmp(&b.0));
     root_entries.sort_by(|a, b| a.0.cmp(&b.0));
     admin_entries.sort_by(|a, b| a.0.cmp(&b.0));
+    ws_entries.sort_by(|a, b| a.0.cmp(&b.0));
+    admin_ws_entries.sort_by(|a, b| a.0.cmp(&b.0));
 
     let mut out = String::new();
     out.push_str("// Auto-generated by build.rs. Do not edit.\n\n");
@@ -110,6 +122,24 @@ fn main() -> std::io::Result<()> {
         ));
     }
 
+    // Generate WebSocket extensions
+    for (ident, filename) in &ws_entries {
+        out.push_str(&format!(
+            "mod {ident}_ws {{\n    include!(concat!(env!(\"CARGO_MANIFEST_DIR\"), \"/extensions/{file}\"));\n}}\n\n",
+            ident = ident,
+            file = filename
+        ));
+    }
+
+    // Generate admin WebSocket extensions
+    for (ident, filename) in &admin_ws_entries {
+        out.push_str(&format!(
+            "mod {ident}_admin_ws {{\n    include!(concat!(env!(\"CARGO_MANIFEST_DIR\"), \"/extensions/{file}\"));\n}}\n\n",
+            ident = ident,
+            file = filename
+        ));
+    }
+
     // Generate ExtensionRegistry
     out.push_str("pub struct ExtensionRegistry {\n");
     out.push_str("    admin_keys: std::collections::HashSet<String>,\n");
@@ -122,9 +152,9 @@ fn main() -> std::io::Result<()> {
     out.push_str("        };\n");
     out.push_str("        // Load admin keys on initialization\n");
     out.push_str("        let _ = registry.load_existing_admin_keys();\n");
-    out.push_str("        println!(\"DEBUG: After loading, admin keys: {:?}\", registry.admin_keys.iter().collect::<Vec<_>>());\n");
     out.push_str("        let _ = registry.generate_missing_admin_keys();\n");
-    out.push_str("        println!(\"DEBUG: After generation, admin keys: {:?}\", registry.admin_keys.iter().collect::<Vec<_>>());\n");
+    out.push_str("        // Admin paths embed their keys, so only the count is ever logged\n");
+    out.push_str("        log::debug!(target: \"admin\", count = registry.admin_keys.len(); \"admin keys loaded\");\n");
     out.push_str("        registry\n");
     out.push_str("    }\n\n");
 
@@ -157,6 +187,10 @@ fn main() -> std::io::Result<()> {
             "            let replacement = {}::extend(url, args);\n",
             ident
         ));
+        out.push_str(&format!(
+            "            crate::metrics::record_extension(\"{}\", false);\n",
+            ident
+        ));
         out.push_str(&format!(
             "            result.replace_range(start..end, &replacement);\n"
         ));
@@ -186,9 +220,6 @@ fn main() -> std::io::Result<()> {
     out.push_str("        // Process admin request by checking for admin extensions\n");
     out.push_str("        for admin_path in &self.admin_keys {\n");
     out.push_str("            let full_path = format!(\"/{}\", admin_path);\n");
-    out.push_str(
-        "            println!(\"DEBUG: Checking path {}\", full_path);\n",
-    );
     out.push_str("            if path.starts_with(&full_path) {\n");
     out.push_str("                // Extract extension name from admin path\n");
     out.push_str("                let ext_name = admin_path.split('_').next().unwrap_or(\"\");\n");
@@ -211,10 +242,14 @@ fn main() -> std::io::Result<()> {
                     out.push_str("                            }\n");
                     out.push_str("                        }\n");
                     out.push_str(&format!(
-                        "                        {}_admin::handle_{}_admin_request(path, method, query, body, headers, &admin_keys_map)\n",
+                        "                        let result = {}_admin::handle_{}_admin_request(path, method, query, body, headers, &admin_keys_map);\n",
                         ext_name, ext_name
                     ));
-                    out.push_str("                            .map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)) as Box<dyn std::error::Error + Send + Sync>)\n");
+                    out.push_str(&format!(
+                        "                        crate::metrics::record_extension(\"{}.admin\", result.is_err());\n",
+                        ext_name
+                    ));
+                    out.push_str("                        result.map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)) as Box<dyn std::error::Error + Send + Sync>)\n");
                     out.push_str("                    }\n");
                 }
             }
@@ -248,7 +283,6 @@ fn main() -> std::io::Result<()> {
     out.push_str(
         "                        self.admin_keys.insert(admin_key.clone());\n",
     );
-    out.push_str("                        println!(\"DEBUG: Loaded admin key: {}\", admin_key);\n");
     out.push_str("                    }\n");
     out.push_str("                }\n");
     out.push_str("            }\n");
@@ -272,12 +306,8 @@ fn main() -> std::io::Result<()> {
     );
     out.push_str("                        // Check if any admin path for this extension already exists\n");
     out.push_str("                        let prefix = format!(\"{}_{}\", ext_name, \"\");\n");
-    out.push_str("                        println!(\"DEBUG: Checking prefix: {}\", prefix);\n");
-    out.push_str("                        for key in self.admin_keys.iter() {\n");
-    out.push_str("                            println!(\"DEBUG: Existing key: {}\", key);\n");
-    out.push_str("                        }\n");
     out.push_str("                        let has_existing_key = self.admin_keys.iter().any(|key| key.starts_with(&prefix));\n");
-    out.push_str("                        println!(\"DEBUG: Has existing key for {}: {}\", ext_name, has_existing_key);\n");
+    out.push_str("                        log::debug!(target: \"admin\", extension = ext_name.as_str(), configured = has_existing_key; \"checked admin path\");\n");
     out.push_str("                        if !has_existing_key {\n");
     out.push_str("                            use std::collections::hash_map::DefaultHasher;\n");
     out.push_str("                            use std::hash::{Hash, Hasher};\n");
@@ -326,7 +356,7 @@ fn main() -> std::io::Result<()> {
             ident, ident
         ));
         out.push_str(&format!(
-            "            println!(\"Warning: Failed to initialize {} root extension: {{}}\", e);\n",
+            "            log::warn!(target: \"extensions\", \"failed to initialize {} root extension: {{}}\", e);\n",
             ident
         ));
         out.push_str("        }\n");
@@ -347,10 +377,14 @@ fn main() -> std::io::Result<()> {
                     let ext_name = file_name.replace(".bin.rs", "");
                     out.push_str(&format!("            \"/cgi-bin/{}\" => {{\n", ext_name));
                     out.push_str(&format!(
-                        "                {}_bin::handle_{}_request(method, bin_path, \"localhost\", query_string, headers)\n",
+                        "                let result = {}_bin::handle_{}_request(method, bin_path, \"localhost\", query_string, headers);\n",
                         ext_name, ext_name
                     ));
-                    out.push_str("                    .map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)) as Box<dyn std::error::Error + Send + Sync>)\n");
+                    out.push_str(&format!(
+                        "                crate::metrics::record_extension(\"{}.bin\", result.is_err());\n",
+                        ext_name
+                    ));
+                    out.push_str("                result.map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)) as Box<dyn std::error::Error + Send + Sync>)\n");
                     out.push_str("            }\n");
                 }
             }
@@ -374,6 +408,70 @@ fn main() -> std::io::Result<()> {
     out.push_str("    ]\n");
     out.push_str("}\n");
 
+    // WebSocket endpoints (/ws/<name>) are plain functions: handlers run as their own
+    // tasks and must not hold the registry lock
+    out.push_str("\n/// Whether a WebSocket extension exists for /ws/<name> (generated at build time)\n");
+    out.push_str("pub fn is_ws_endpoint(name: &str) -> bool {\n");
+    out.push_str("    [\n");
+    for (ident, _filename) in &ws_entries {
+        out.push_str(&format!("        \"{}\",\n", ident));
+    }
+    out.push_str("    ]\n");
+    out.push_str("    .contains(&name)\n");
+    out.push_str("}\n\n");
+
+    out.push_str("/// Run the WebSocket extension for /ws/<name> on an accepted connection\n");
+    out.push_str("pub async fn handle_ws_connection(name: String, channel: crate::websocket::WsChannel) -> Result<(), String> {\n");
+    out.push_str("    match name.as_str() {\n");
+    for (ident, _filename) in &ws_entries {
+        out.push_str(&format!("        \"{}\" => {{\n", ident));
+        out.push_str(&format!("            let result = {}_ws::handle_{}_ws(channel).await;\n", ident, ident));
+        out.push_str(&format!(
+            "            crate::metrics::record_extension(\"{}.ws\", result.is_err());\n",
+            ident
+        ));
+        out.push_str("            result\n");
+        out.push_str("        }\n");
+    }
+    out.push_str("        _ => {\n");
+    out.push_str("            let _ = channel;\n");
+    out.push_str("            Err(format!(\"WebSocket extension '{}' not found\", name))\n");
+    out.push_str("        }\n");
+    out.push_str("    }\n");
+    out.push_str("}\n");
+
+    // Admin WebSocket endpoints (/ws/<name>_<key>) share the admin key of the
+    // extension's panel; the caller checks the key before connecting
+    out.push_str("\n/// Whether an admin WebSocket extension exists for /ws/<name>_<key> (generated at build time)\n");
+    out.push_str("pub fn is_admin_ws_endpoint(name: &str) -> bool {\n");
+    out.push_str("    [\n");
+    for (ident, _filename) in &admin_ws_entries {
+        out.push_str(&format!("        \"{}\",\n", ident));
+    }
+    out.push_str("    ]\n");
+    out.push_str("    .contains(&name)\n");
+    out.push_str("}\n\n");
+
+    out.push_str("/// Run the admin WebSocket extension for /ws/<name>_<key> on an accepted connection\n");
+    out.push_str("pub async fn handle_admin_ws_connection(name: String, channel: crate::websocket::WsChannel) -> Result<(), String> {\n");
+    out.push_str("    match name.as_str() {\n");
+    for (ident, _filename) in &admin_ws_entries {
+        out.push_str(&format!("        \"{}\" => {{\n", ident));
+        out.push_str(&format!("            let result = {}_admin_ws::handle_{}_admin_ws(channel).await;\n", ident, ident));
+        out.push_str(&format!(
+            "            crate::metrics::record_extension(\"{}.admin.ws\", result.is_err());\n",
+            ident
+        ));
+        out.push_str("            result\n");
+        out.push_str("        }\n");
+    }
+    out.push_str("        _ => {\n");
+    out.push_str("            let _ = channel;\n");
+    out.push_str("            Err(format!(\"Admin WebSocket extension '{}' not found\", name))\n");
+    out.push_str("        }\n");
+    out.push_str("    }\n");
+    out.push_str("}\n");
+
     fs::write(&dest_path, out)?;
     println!("cargo:rerun-if-changed=extensions");
     Ok(())
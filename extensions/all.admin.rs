@@ -63,6 +63,8 @@ fn generate_all_admin_panel(admin_keys: &std::collections::HashMap<String, Strin
             "upload" => "File Upload Manager",
             "logs" => "Server Logs",
             "about" => "About",
+            "upstreams" => "Upstream Health",
+            "metrics" => "Prometheus Metrics",
             _ => ext_name,
         };
 
@@ -72,6 +74,8 @@ fn generate_all_admin_panel(admin_keys: &std::collections::HashMap<String, Strin
             "upload" => "Upload, manage, and organize files. View uploaded files, delete unwanted files, and monitor storage usage.",
             "logs" => "View and monitor server logs in real-time. Search, filter, and analyze log messages for debugging and monitoring.",
             "about" => "View server information, version details, and system configuration. Learn about the Easyp server and its capabilities.",
+            "upstreams" => "Check the health of load-balanced upstream groups. See which servers are up, ejected or failing health checks, with live request counts.",
+            "metrics" => "Raw Prometheus metrics: requests, latency, connections, TLS handshakes, certificate expiry, extension and cache counters. Point a scraper at this URL.",
             _ => &format!("Manage {} settings and data.", ext_name),
         };
 
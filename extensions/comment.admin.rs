@@ -208,19 +208,9 @@ fn accept_comment(comment: &str) -> Result<(), String> {
     // Append to live comments directory based on MD5 of return_url
     match get_comment_md5(clean_comment) {
         Ok(md5_hash) => {
-            // Write debug messages to a file
-            let debug_file = Path::new("/tmp/comment_debug.log");
-            let mut debug_handle = fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(debug_file)
-                .unwrap_or_else(|_| std::fs::File::create("/tmp/comment_debug_fallback.log").unwrap());
-            writeln!(debug_handle, "DEBUG: Comment: {}", comment).unwrap_or_default();
-            writeln!(debug_handle, "DEBUG: Clean comment: {}", clean_comment).unwrap_or_default();
-            writeln!(debug_handle, "DEBUG: MD5 hash: {}", md5_hash).unwrap_or_default();
+            log::debug!(target: "extensions", "accepted comment {} (hash {})", clean_comment, md5_hash);
             
             let live_file = Path::new("/var/spool/easyp/comments/live").join(&md5_hash);
-            writeln!(debug_handle, "DEBUG: Live file path: {:?}", live_file).unwrap_or_default();
             
             let mut live_file_handle = fs::OpenOptions::new()
                 .create(true)
@@ -231,17 +221,10 @@ fn accept_comment(comment: &str) -> Result<(), String> {
             writeln!(live_file_handle, "{}", clean_comment)
                 .map_err(|e| format!("Failed to write to live file: {}", e))?;
             
-            writeln!(debug_handle, "DEBUG: Successfully wrote to live file").unwrap_or_default();
+            log::debug!(target: "extensions", "wrote comment to live file {}", live_file.display());
         }
         Err(e) => {
-            let debug_file = Path::new("/tmp/comment_debug.log");
-            let mut debug_handle = fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(debug_file)
-                .unwrap_or_else(|_| std::fs::File::create("/tmp/comment_debug_fallback.log").unwrap());
-            writeln!(debug_handle, "DEBUG: Failed to get MD5 hash: {}", e).unwrap_or_default();
-            writeln!(debug_handle, "DEBUG: Comment was: {}", comment).unwrap_or_default();
+            log::debug!(target: "extensions", "no live file for comment {}: {}", comment, e);
         }
     }
     
@@ -494,11 +477,8 @@ pub fn handle_comment_admin_request(
     }
     
     // Get admin key from memory and validate
-    println!("DEBUG: Admin keys map: {:?}", admin_keys);
-    println!("DEBUG: Looking for 'comment' key in admin_keys");
     let admin_key = admin_keys.get("comment")
         .ok_or("Comment admin key not found".to_string())?;
-    println!("DEBUG: Found admin key: {}", admin_key);
     let expected_path = format!("/comment_{}", admin_key);
     
     if path != expected_path {
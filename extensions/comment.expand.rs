@@ -306,7 +306,51 @@ pub fn extend(url: &str, args: &str) -> String {
 
             // Listen for system theme changes
             window.matchMedia('(prefers-color-scheme: dark)').addEventListener('change', applyDarkMode);
+
+            // Show newly approved comments without a reload
+            connectLiveComments();
         }});
+
+        function connectLiveComments() {{
+            if (!window.WebSocket) {{
+                return;
+            }}
+            const scheme = location.protocol === 'https:' ? 'wss://' : 'ws://';
+            const socket = new WebSocket(scheme + location.host + '/ws/comment?page=' + encodeURIComponent(location.pathname));
+            socket.onmessage = function(event) {{
+                const comment = JSON.parse(event.data);
+                let container = document.querySelector('.live-comments');
+                if (!container) {{
+                    container = document.createElement('div');
+                    container.className = 'live-comments';
+                    container.style.cssText = 'margin: 20px 0; padding: 20px; border: 1px solid #e0e0e0; border-radius: 5px; background-color: #f8f9fa;';
+                    const heading = document.createElement('h3');
+                    heading.textContent = 'Comments';
+                    container.appendChild(heading);
+                    const form = document.querySelector('.comment-form');
+                    form.parentNode.insertBefore(container, form);
+                }}
+                const entry = document.createElement('div');
+                entry.className = 'comment';
+                entry.style.cssText = 'margin: 15px 0; padding: 15px; border-left: 3px solid #007cba; background-color: white; border-radius: 3px;';
+                const author = document.createElement('div');
+                author.className = 'comment-author';
+                author.style.cssText = 'font-weight: bold; color: #333; margin-bottom: 8px;';
+                author.textContent = comment.user;
+                const text = document.createElement('div');
+                text.className = 'comment-text';
+                text.style.cssText = 'color: #555; line-height: 1.5;';
+                text.textContent = comment.text;
+                entry.appendChild(author);
+                entry.appendChild(text);
+                container.appendChild(entry);
+                applyDarkMode();
+            }};
+            // Reconnect after server restarts or idle disconnects
+            socket.onclose = function() {{
+                setTimeout(connectLiveComments, 30000);
+            }};
+        }}
     </script>
 </div>
 "#, live_comments_html, form_action)
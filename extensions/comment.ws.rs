@@ -0,0 +1,121 @@
+// comment.ws.rs - Live comment updates over WebSocket
+// Connect to /ws/comment?page=/path/of/page to receive comments as they are approved
+
+use std::path::Path;
+use std::time::Duration;
+
+use crate::websocket::{WsChannel, WsMessage};
+
+// How often the live comments file is checked for new entries
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Hash of the canonical page path, matching comment.expand.rs and comment.admin.rs
+fn calculate_md5(input: &str) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    input.hash(&mut hasher);
+    format!("{:x}", hasher.finish())
+}
+
+/// Read the non-empty lines of the live comments file for a page
+fn read_live_comments(live_file: &Path) -> Vec<String> {
+    std::fs::read_to_string(live_file)
+        .map(|content| {
+            content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| line.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Turn a stored comment ("return_url=URL&USER=NAME&TEXT=COMMENT") into a JSON message
+fn comment_json(comment: &str) -> Option<String> {
+    let mut user = None;
+    let mut text = None;
+    for pair in comment.split('&') {
+        match pair.split_once('=') {
+            Some(("USER", value)) => user = urlencoding::decode(&value.replace('+', " ")).ok().map(|v| v.into_owned()),
+            Some(("TEXT", value)) => text = urlencoding::decode(&value.replace('+', " ")).ok().map(|v| v.into_owned()),
+            _ => {}
+        }
+    }
+    Some(format!(
+        "{{\"user\":\"{}\",\"text\":\"{}\"}}",
+        json_escape(&user?),
+        json_escape(&text?)
+    ))
+}
+
+// JSON string escape function
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// WebSocket handler: push newly approved comments for one page
+///
+/// The page was rendered with the comments that existed at the time, so only
+/// comments added after the connection opened are sent.
+pub async fn handle_comment_ws(mut channel: WsChannel) -> Result<(), String> {
+    let page = channel.request.query_param("page").unwrap_or_else(|| "/".to_string());
+    let canonical_path = page.split(['?', '#']).next().unwrap_or("/").to_string();
+    let live_file = Path::new("/var/spool/easyp/comments/live").join(calculate_md5(&canonical_path));
+
+    let mut seen = read_live_comments(&live_file).len();
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        tokio::select! {
+            message = channel.incoming.recv() => {
+                // Clients only listen; anything they send is ignored
+                if message.is_none() {
+                    return Ok(());
+                }
+            }
+            _ = poll.tick() => {
+                let comments = read_live_comments(&live_file);
+                // The file only grows, unless an admin rewrote it
+                if comments.len() < seen {
+                    seen = comments.len();
+                }
+                for comment in &comments[seen..] {
+                    if let Some(json) = comment_json(comment) {
+                        if channel.outgoing.send(WsMessage::Text(json)).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+                seen = comments.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comment_json() {
+        assert_eq!(
+            comment_json("return_url=/post&USER=Ann+Lee&TEXT=Hi%20%22there%22%0Abye").unwrap(),
+            "{\"user\":\"Ann Lee\",\"text\":\"Hi \\\"there\\\"\\nbye\"}"
+        );
+        assert!(comment_json("return_url=/post&USER=Ann").is_none());
+    }
+}
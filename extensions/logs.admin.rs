@@ -154,8 +154,9 @@ pub fn add_log_entry(level: &str, message: &str, source: &str) {
     }
 }
 
-// Function to read logs from log files
-fn read_log_files() -> Vec<LogEntry> {
+// Function to read logs from log files, plus up to `history` rotated generations of each
+// (newest first); older generations are only decompressed when the viewer asks for them
+fn read_log_files(history: usize) -> Vec<LogEntry> {
     let mut entries = Vec::new();
 
     // Get the actual log file path from the file logger
@@ -172,6 +173,21 @@ fn read_log_files() -> Vec<LogEntry> {
     ];
 
     for log_file in &log_files {
+        // Oldest rotated generation first, so entries stay in time order
+        let mut generations = crate::log_rotation::rotated_generations(std::path::Path::new(log_file));
+        generations.truncate(history);
+        generations.reverse();
+        for generation in &generations {
+            if let Ok(content) = crate::log_rotation::read_log_file(generation) {
+                let source = generation.to_string_lossy();
+                for line in content.lines() {
+                    if let Some(entry) = parse_log_line(line, &source) {
+                        entries.push(entry);
+                    }
+                }
+            }
+        }
+
         if let Ok(file) = fs::File::open(log_file) {
             let reader = BufReader::new(file);
             for line in reader.lines() {
@@ -325,7 +341,7 @@ fn escape_html_preserve_unicode(text: &str) -> String {
 }
 
 // Generate the logs admin panel HTML
-fn generate_logs_panel(admin_key: &str, filter: Option<&str>, level_filter: Option<&str>, limit: Option<usize>) -> String {
+fn generate_logs_panel(admin_key: &str, filter: Option<&str>, level_filter: Option<&str>, limit: Option<usize>, history: usize) -> String {
     let mut html = String::new();
 
     html.push_str("<!DOCTYPE html>\n");
@@ -378,12 +394,18 @@ fn generate_logs_panel(admin_key: &str, filter: Option<&str>, level_filter: Opti
     };
 
     // Also try to read from log files
-    let file_entries = read_log_files();
+    let file_entries = read_log_files(history);
     all_entries.extend(file_entries);
 
     // Sort by timestamp (newest first)
     all_entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
 
+    // Statistics (calculate from all entries before filtering)
+    let total_entries = all_entries.len();
+    let error_count = all_entries.iter().filter(|e| e.level == "ERROR").count();
+    let warn_count = all_entries.iter().filter(|e| e.level == "WARN").count();
+    let info_count = all_entries.iter().filter(|e| e.level == "INFO").count();
+
     // Apply filters to the combined list
     let mut filtered_entries = all_entries;
 
@@ -418,19 +440,6 @@ fn generate_logs_panel(admin_key: &str, filter: Option<&str>, level_filter: Opti
     // Use filtered entries for display
     let all_entries = filtered_entries;
 
-    // Statistics (calculate from all entries before filtering)
-    let mut all_unfiltered_entries = if let Ok(storage) = get_log_storage().lock() {
-        storage.get_entries(None, None, None)
-    } else {
-        Vec::new()
-    };
-    all_unfiltered_entries.extend(read_log_files());
-
-    let total_entries = all_unfiltered_entries.len();
-    let error_count = all_unfiltered_entries.iter().filter(|e| e.level == "ERROR").count();
-    let warn_count = all_unfiltered_entries.iter().filter(|e| e.level == "WARN").count();
-    let info_count = all_unfiltered_entries.iter().filter(|e| e.level == "INFO").count();
-
     html.push_str("<div class=\"stats\">\n");
     html.push_str(&format!("<div class=\"stat-item\">Total Logs: <span class=\"stat-value\">{}</span></div>\n", total_entries));
     html.push_str(&format!("<div class=\"stat-item\">Errors: <span class=\"stat-value\" style=\"color: #f44747;\">{}</span></div>\n", error_count));
@@ -476,10 +485,38 @@ fn generate_logs_panel(admin_key: &str, filter: Option<&str>, level_filter: Opti
     html.push_str("</select>\n");
     html.push_str("</div>\n");
 
+    html.push_str("<div class=\"control-group\">\n");
+    html.push_str("<label for=\"history\">Rotated files:</label>\n");
+    html.push_str("<select id=\"history\" name=\"history\">\n");
+    for (value, count, label) in [("0", 0, "none"), ("1", 1, "newest"), ("3", 3, "newest 3"), ("all", usize::MAX, "all")] {
+        html.push_str(&format!("<option value=\"{}\"{}>{}</option>\n", value, if history == count { " selected" } else { "" }, label));
+    }
+    html.push_str("</select>\n");
+    html.push_str("</div>\n");
+
     html.push_str("<button class=\"btn\" onclick=\"applyFilters()\">Apply Filters</button>\n");
     html.push_str("<button class=\"btn btn-danger\" onclick=\"clearLogs()\">Clear Logs</button>\n");
     html.push_str("</div>\n");
 
+    // Runtime log levels, one selector per subsystem target
+    let levels = crate::logging::current_levels();
+    html.push_str("<div class=\"controls\">\n");
+    let mut targets = vec!["*"];
+    targets.extend_from_slice(crate::logging::TARGETS);
+    for target in targets {
+        let current = if target == "*" { levels.default_level() } else { levels.level_for(target) };
+        html.push_str("<div class=\"control-group\">\n");
+        html.push_str(&format!("<label>{}:</label>\n", if target == "*" { "default" } else { target }));
+        html.push_str(&format!("<select onchange=\"setLevel('{}', this.value)\">\n", target));
+        for level in ["off", "error", "warn", "info", "debug", "trace"] {
+            let selected = current.to_string().eq_ignore_ascii_case(level);
+            html.push_str(&format!("<option value=\"{}\"{}>{}</option>\n", level, if selected { " selected" } else { "" }, level));
+        }
+        html.push_str("</select>\n");
+        html.push_str("</div>\n");
+    }
+    html.push_str("</div>\n");
+
     // Log entries
     html.push_str("<div class=\"log-container\">\n");
 
@@ -513,6 +550,7 @@ fn generate_logs_panel(admin_key: &str, filter: Option<&str>, level_filter: Opti
     html.push_str("  const filter = document.getElementById('filter').value;\n");
     html.push_str("  const level = document.getElementById('level').value;\n");
     html.push_str("  const limit = document.getElementById('limit').value;\n");
+    html.push_str("  const history = document.getElementById('history').value;\n");
     html.push_str("  \n");
     html.push_str("  console.log('Filter values:', { filter, level, limit });\n");
     html.push_str("  \n");
@@ -521,6 +559,7 @@ fn generate_logs_panel(admin_key: &str, filter: Option<&str>, level_filter: Opti
     html.push_str("  if (filter) params.append('filter', filter);\n");
     html.push_str("  if (level !== 'all') params.append('level', level);\n");
     html.push_str("  if (limit !== '100') params.append('limit', limit);\n");
+    html.push_str("  if (history !== '0') params.append('history', history);\n");
     html.push_str("  \n");
     html.push_str("  if (params.toString()) {\n");
     html.push_str("    url += '?' + params.toString();\n");
@@ -554,6 +593,18 @@ fn generate_logs_panel(admin_key: &str, filter: Option<&str>, level_filter: Opti
     html.push_str("  }\n");
     html.push_str("}\n");
     html.push_str("\n");
+    html.push_str("function setLevel(target, level) {\n");
+    html.push_str("  fetch(window.location.pathname, {\n");
+    html.push_str("    method: 'POST',\n");
+    html.push_str("    headers: { 'Content-Type': 'application/x-www-form-urlencoded' },\n");
+    html.push_str("    body: 'action=set_level&target=' + encodeURIComponent(target) + '&level=' + encodeURIComponent(level)\n");
+    html.push_str("  }).then(response => {\n");
+    html.push_str("    if (!response.ok) {\n");
+    html.push_str("      alert('Error changing log level. Please try again.');\n");
+    html.push_str("    }\n");
+    html.push_str("  });\n");
+    html.push_str("}\n");
+    html.push_str("\n");
     html.push_str("// Add event listeners for better UX\n");
     html.push_str("document.addEventListener('DOMContentLoaded', function() {\n");
     html.push_str("  // Auto-apply filters when Enter is pressed in search box\n");
@@ -569,6 +620,7 @@ fn generate_logs_panel(admin_key: &str, filter: Option<&str>, level_filter: Opti
     html.push_str("  // Auto-apply filters when dropdowns change\n");
     html.push_str("  const levelSelect = document.getElementById('level');\n");
     html.push_str("  const limitSelect = document.getElementById('limit');\n");
+    html.push_str("  const historySelect = document.getElementById('history');\n");
     html.push_str("  \n");
     html.push_str("  if (levelSelect) {\n");
     html.push_str("    levelSelect.addEventListener('change', function() {\n");
@@ -581,11 +633,18 @@ fn generate_logs_panel(admin_key: &str, filter: Option<&str>, level_filter: Opti
     html.push_str("      applyFilters();\n");
     html.push_str("    });\n");
     html.push_str("  }\n");
+    html.push_str("  \n");
+    html.push_str("  if (historySelect) {\n");
+    html.push_str("    historySelect.addEventListener('change', function() {\n");
+    html.push_str("      applyFilters();\n");
+    html.push_str("    });\n");
+    html.push_str("  }\n");
     html.push_str("});\n");
     html.push_str("</script>\n");
 
     html.push_str("<div class=\"refresh-info\">\n");
     html.push_str("<p>This page refreshes automatically every 10 seconds</p>\n");
+    html.push_str(&format!("<p>Live tail over WebSocket: /ws/logs_{}?target=&lt;target&gt;&amp;level=&lt;level&gt;</p>\n", admin_key));
     html.push_str(&format!("<p>Last updated: {}</p>\n", get_current_timestamp()));
     html.push_str("</div>\n");
 
@@ -627,6 +686,27 @@ pub fn handle_logs_admin_request(
             }
             return Ok("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 22\r\nAccess-Control-Allow-Origin: *\r\nAccess-Control-Allow-Methods: GET, POST\r\nAccess-Control-Allow-Headers: Content-Type\r\n\r\nLogs cleared successfully".to_string());
         }
+        if body.contains("action=set_level") {
+            let mut target = "";
+            let mut level = "";
+            for param in body.split('&') {
+                match param.split_once('=') {
+                    Some(("target", value)) => target = value,
+                    Some(("level", value)) => level = value,
+                    _ => {}
+                }
+            }
+            if target != "*" && !crate::logging::TARGETS.contains(&target) {
+                return Err(format!("Unknown log target: {}", target));
+            }
+            crate::logging::set_target_level(target, level)?;
+            let message = format!("{}={}", target, level);
+            return Ok(format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+                message.len(),
+                message
+            ));
+        }
     }
 
     // Handle GET requests (display logs panel)
@@ -635,6 +715,8 @@ pub fn handle_logs_admin_request(
         let mut filter = None;
         let mut level_filter = None;
         let mut limit = None;
+        // Rotated generations to read per log file; none unless asked for
+        let mut history = 0;
 
         if !query_string.is_empty() {
             for param in query_string.split('&') {
@@ -643,13 +725,15 @@ pub fn handle_logs_admin_request(
                         "filter" => filter = Some(value),
                         "level" => level_filter = Some(value),
                         "limit" => limit = value.parse().ok(),
+                        "history" if value == "all" => history = usize::MAX,
+                        "history" => history = value.parse().unwrap_or(0),
                         _ => {}
                     }
                 }
             }
         }
 
-        let html = generate_logs_panel(admin_key, filter, level_filter, limit);
+        let html = generate_logs_panel(admin_key, filter, level_filter, limit, history);
 
         return Ok(format!(
             "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n{}",
@@ -0,0 +1,82 @@
+// logs.admin.ws.rs - Live server log tail over WebSocket
+// Connect to /ws/logs_<admin key>?target=tls&level=debug to receive log lines as JSON
+
+use log::LevelFilter;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::logging::TailLine;
+use crate::websocket::{WsChannel, WsMessage};
+
+/// Which lines a client asked for
+struct TailFilter {
+    /// Target prefix, e.g. "tls" also matches "tls::sni"
+    target: Option<String>,
+    level: LevelFilter,
+}
+
+impl TailFilter {
+    fn matches(&self, line: &TailLine) -> bool {
+        line.level <= self.level
+            && self.target.as_deref().is_none_or(|target| {
+                line.target == target || line.target.strip_prefix(target).is_some_and(|rest| rest.starts_with("::"))
+            })
+    }
+}
+
+/// WebSocket handler: stream server log lines as they are written
+///
+/// Only lines that pass the server's own level filter are logged at all, so
+/// `level` can narrow the tail but not widen it.
+pub async fn handle_logs_admin_ws(mut channel: WsChannel) -> Result<(), String> {
+    let filter = TailFilter {
+        target: channel.request.query_param("target").filter(|target| !target.is_empty()),
+        level: match channel.request.query_param("level") {
+            Some(level) => level.parse().map_err(|_| format!("Unknown log level '{}'", level))?,
+            None => LevelFilter::Trace,
+        },
+    };
+    let mut lines = crate::logging::subscribe();
+
+    loop {
+        tokio::select! {
+            message = channel.incoming.recv() => {
+                // Clients only listen; anything they send is ignored
+                if message.is_none() {
+                    return Ok(());
+                }
+            }
+            line = lines.recv() => {
+                let json = match line {
+                    Ok(line) if filter.matches(&line) => line.json,
+                    Ok(_) => continue,
+                    // Tell the client how much it missed rather than dropping it
+                    Err(RecvError::Lagged(missed)) => format!("{{\"missed\":{}}}", missed),
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                if channel.outgoing.send(WsMessage::Text(json)).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn test_tail_filter() {
+        let line = |level, target: &str| TailLine { level, target: target.to_string(), json: String::new() };
+        let filter = TailFilter { target: Some("tls".to_string()), level: LevelFilter::Info };
+        assert!(filter.matches(&line(Level::Warn, "tls")));
+        assert!(filter.matches(&line(Level::Info, "tls::sni")));
+        assert!(!filter.matches(&line(Level::Debug, "tls")));
+        assert!(!filter.matches(&line(Level::Info, "tlsx")));
+        assert!(!filter.matches(&line(Level::Info, "acme")));
+
+        let everything = TailFilter { target: None, level: LevelFilter::Trace };
+        assert!(everything.matches(&line(Level::Trace, "acme")));
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+// Main admin handler: Prometheus can scrape /metrics_<key> when no separate
+// --metrics-listen address is configured
+pub fn handle_metrics_admin_request(
+    path: &str,
+    method: &str,
+    _query_string: &str,
+    _body: &str,
+    _headers: &HashMap<String, String>,
+    admin_keys: &std::collections::HashMap<String, String>,
+) -> Result<String, String> {
+    // Check if this looks like a metrics admin request
+    if !path.starts_with("/metrics_") {
+        return Err("Not a metrics admin request".to_string());
+    }
+
+    // Get admin key from memory and validate
+    let admin_key = admin_keys.get("metrics")
+        .ok_or("Metrics admin key not found".to_string())?;
+    let expected_path = format!("/metrics_{}", admin_key);
+
+    if path != expected_path {
+        return Err("Invalid admin key".to_string());
+    }
+
+    if method == "GET" {
+        let body = crate::metrics::render();
+
+        return Ok(format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nCache-Control: no-cache\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+    }
+
+    Err("Method not allowed".to_string())
+}
+
+/// Get all admin panel paths for the metrics extension
+pub fn get_metrics_admin_paths() -> Vec<String> {
+    vec!["metrics".to_string()]
+}
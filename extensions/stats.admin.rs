@@ -1,5 +1,12 @@
 // stats.admin.rs - Admin panel for system statistics
-// Handles system stats interface including memory info, load average, and hourly stats
+// Handles system stats interface including memory info, load average, hourly stats
+// and the minute/hour/day/month traffic rollups from traffic_stats.rs
+//
+// Machine-readable exports live under the same key (append .json or .csv):
+//   /stats_<key>/api/series   traffic buckets  ?resolution=hour&from=2024-01-01&to=...&domain=...
+//   /stats_<key>/api/hourly   48-hour memory/CPU/request samples  ?from=&to=
+//   /stats_<key>/api/system   live memory, load, CPU, uptime and disk snapshot
+// Field names and CSV columns are part of the API: add new ones, never rename.
 
 use std::fs;
 use std::collections::HashMap;
@@ -373,7 +380,7 @@ fn calculate_cpu_usage(cpu_info: &CpuInfo) -> f64 {
 }
 
 // Generate stats admin panel HTML
-fn generate_stats_panel(admin_key: &str) -> String {
+fn generate_stats_panel(admin_key: &str, query_string: &str) -> String {
     let mut html = String::new();
 
     html.push_str("<!DOCTYPE html>\n");
@@ -397,6 +404,11 @@ fn generate_stats_panel(admin_key: &str) -> String {
     html.push_str(".progress-fill.medium { background-color: #ffc107; }\n");
     html.push_str(".refresh-info { text-align: center; color: #666; font-size: 0.9em; margin-top: 20px; }\n");
     html.push_str(".error { background-color: #f8d7da; color: #721c24; padding: 15px; border-radius: 4px; margin: 10px 0; }\n");
+    html.push_str(".traffic-table { width: 100%; border-collapse: collapse; font-size: 0.9em; }\n");
+    html.push_str(".traffic-table th, .traffic-table td { padding: 6px 8px; border-bottom: 1px solid #dee2e6; text-align: right; }\n");
+    html.push_str(".traffic-table th:first-child, .traffic-table td:first-child { text-align: left; }\n");
+    html.push_str(".view-links a { margin-right: 12px; }\n");
+    html.push_str(".view-links a.active { font-weight: bold; text-decoration: none; color: #333; }\n");
     html.push_str("</style>\n");
     html.push_str("</head>\n");
     html.push_str("<body>\n");
@@ -571,6 +583,66 @@ fn generate_stats_panel(admin_key: &str) -> String {
         }
     }
 
+    html.push_str("</div>\n");
+
+    // In-memory file cache
+    html.push_str("<div class=\"stat-card\">\n");
+    html.push_str("<h3>&#x1F5C3;&#xFE0F; File Cache</h3>\n");
+
+    let cache = crate::content_cache::stats();
+    if cache.capacity == 0 {
+        html.push_str("<div class=\"stat-item\">\n");
+        html.push_str("<span class=\"stat-label\">Status:</span>\n");
+        html.push_str("<span class=\"stat-value\">Disabled</span>\n");
+        html.push_str("</div>\n");
+    } else {
+        let hit_ratio = match cache.hit_ratio() {
+            Some(ratio) => format!("{:.1}%", ratio * 100.0),
+            None => "-".to_string(),
+        };
+        for (label, value) in [
+            ("Hit ratio:", hit_ratio),
+            ("Hits / misses:", format!("{} / {}", cache.hits, cache.misses)),
+            ("Entries:", cache.entries.to_string()),
+            ("Memory:", format!("{} / {}", format_bytes(cache.bytes), format_bytes(cache.capacity))),
+            ("Evictions:", cache.evictions.to_string()),
+            ("Invalidations:", cache.invalidations.to_string()),
+        ] {
+            html.push_str("<div class=\"stat-item\">\n");
+            html.push_str(&format!("<span class=\"stat-label\">{}</span>\n", label));
+            html.push_str(&format!("<span class=\"stat-value\">{}</span>\n", value));
+            html.push_str("</div>\n");
+        }
+
+        let usage = cache.bytes as f64 * 100.0 / cache.capacity as f64;
+        html.push_str("<div class=\"progress-bar\">\n");
+        html.push_str(&format!("<div class=\"progress-fill\" style=\"width: {:.1}%\"></div>\n", usage));
+        html.push_str("</div>\n");
+    }
+
+    html.push_str("</div>\n");
+
+    // TLS handshakes and session resumption
+    html.push_str("<div class=\"stat-card\">\n");
+    html.push_str("<h3>&#x1F512; TLS Sessions</h3>\n");
+
+    let tls = crate::tls_policy::stats();
+    let resumption = match tls.resumption_ratio() {
+        Some(ratio) => format!("{:.1}%", ratio * 100.0),
+        None => "-".to_string(),
+    };
+    for (label, value) in [
+        ("Handshakes:", tls.handshakes().to_string()),
+        ("TLS 1.3 / 1.2:", format!("{} / {}", tls.tls13, tls.tls12)),
+        ("Resumed:", tls.resumed.to_string()),
+        ("Resumption rate:", resumption),
+    ] {
+        html.push_str("<div class=\"stat-item\">\n");
+        html.push_str(&format!("<span class=\"stat-label\">{}</span>\n", label));
+        html.push_str(&format!("<span class=\"stat-value\">{}</span>\n", value));
+        html.push_str("</div>\n");
+    }
+
     html.push_str("</div>\n");
     html.push_str("</div>\n");
 
@@ -693,6 +765,8 @@ fn generate_stats_panel(admin_key: &str) -> String {
     html.push_str("</div>\n");
     html.push_str("</div>\n");
 
+    html.push_str(&generate_traffic_section(admin_key, query_string));
+
     html.push_str("<div class=\"refresh-info\">\n");
     html.push_str("<p>This page refreshes automatically every 30 seconds</p>\n");
     html.push_str(&format!("<p>Last updated: {}</p>\n", get_current_time()));
@@ -705,6 +779,607 @@ fn generate_stats_panel(admin_key: &str) -> String {
     html
 }
 
+// Query parameter from the request's query string
+fn query_param(query_string: &str, name: &str) -> Option<String> {
+    query_string.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        if key != name || value.is_empty() {
+            return None;
+        }
+        urlencoding::decode(&value.replace('+', " ")).ok().map(|v| v.into_owned())
+    })
+}
+
+// Traffic rollups: ?view=minute|hour|day|month&domain=<host>
+fn generate_traffic_section(admin_key: &str, query_string: &str) -> String {
+    use crate::traffic_stats::{traffic_stats, Resolution, StatsBucket};
+
+    let view = query_param(query_string, "view")
+        .and_then(|v| Resolution::parse(&v))
+        .unwrap_or(Resolution::Hour);
+    let domain = query_param(query_string, "domain");
+    let shown = match view {
+        Resolution::Minute => 60,
+        Resolution::Hour => 48,
+        Resolution::Day => 31,
+        Resolution::Month => 12,
+    };
+
+    let mut html = String::new();
+    html.push_str("<div class=\"stats-grid\">\n");
+    html.push_str("<div class=\"stat-card\" style=\"grid-column: 1 / -1;\">\n");
+    html.push_str(&format!("<h3>&#x1F310; Traffic by {}</h3>\n", view.name()));
+
+    // Resolution links keep the domain filter
+    let domain_param = domain
+        .as_deref()
+        .map(|d| format!("&domain={}", urlencoding::encode(d)))
+        .unwrap_or_default();
+    html.push_str("<p class=\"view-links\">\n");
+    for resolution in Resolution::ALL {
+        let class = if resolution == view { " class=\"active\"" } else { "" };
+        html.push_str(&format!(
+            "<a href=\"?view={}{}\"{}>{}</a>\n",
+            resolution.name(),
+            domain_param,
+            class,
+            resolution.name()
+        ));
+    }
+    html.push_str("</p>\n");
+    html.push_str(&format!(
+        "<p class=\"view-links\">Export: <a href=\"/stats_{key}/api/series.json?resolution={res}{dom}\">JSON</a>\
+         <a href=\"/stats_{key}/api/series.csv?resolution={res}{dom}\">CSV</a>\
+         <a href=\"/stats_{key}/api/system.json\">System snapshot</a></p>\n",
+        key = admin_key,
+        res = view.name(),
+        dom = domain_param
+    ));
+
+    let Some(stats) = traffic_stats() else {
+        html.push_str("<p>Traffic statistics are not enabled.</p>\n</div>\n</div>\n");
+        return html;
+    };
+    let series = stats.series(view, 0, u64::MAX);
+    let buckets = &series[series.len().saturating_sub(shown)..];
+    if buckets.is_empty() {
+        html.push_str("<p>No traffic recorded yet.</p>\n</div>\n</div>\n");
+        return html;
+    }
+
+    let mut total = StatsBucket::new(buckets[0].start);
+    for bucket in buckets {
+        total.merge(bucket);
+    }
+
+    // Domain filter, busiest first
+    let mut domains: Vec<(&String, u64)> = total.domains.iter().map(|(name, c)| (name, c.requests)).collect();
+    domains.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    html.push_str("<form method=\"get\" style=\"margin-bottom: 15px;\">\n");
+    html.push_str(&format!("<input type=\"hidden\" name=\"view\" value=\"{}\">\n", view.name()));
+    html.push_str("<label>Domain: <select name=\"domain\" onchange=\"this.form.submit()\">\n");
+    html.push_str("<option value=\"\">All domains</option>\n");
+    for (name, requests) in &domains {
+        let selected = if domain.as_deref() == Some(name.as_str()) { " selected" } else { "" };
+        html.push_str(&format!(
+            "<option value=\"{}\"{}>{} ({})</option>\n",
+            html_escape(name),
+            selected,
+            html_escape(name),
+            requests
+        ));
+    }
+    html.push_str("</select></label>\n</form>\n");
+
+    if let Some(ref domain) = domain {
+        // Only request and byte counters are kept per domain
+        let counters = total.domains.get(domain).cloned().unwrap_or_default();
+        html.push_str(&format!(
+            "<p><strong>{}</strong>: {} requests, {} in the last {} {}s</p>\n",
+            html_escape(domain),
+            counters.requests,
+            format_bytes(counters.bytes),
+            buckets.len(),
+            view.name()
+        ));
+        html.push_str("<table class=\"traffic-table\">\n");
+        html.push_str("<tr><th>Period</th><th>Requests</th><th>Bytes</th><th>Share of all requests</th></tr>\n");
+        for bucket in buckets.iter().rev() {
+            let counters = bucket.domains.get(domain).cloned().unwrap_or_default();
+            let share = if bucket.requests > 0 { counters.requests as f64 * 100.0 / bucket.requests as f64 } else { 0.0 };
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:.1}%</td></tr>\n",
+                view.label(bucket.start),
+                counters.requests,
+                format_bytes(counters.bytes),
+                share
+            ));
+        }
+        html.push_str("</table>\n</div>\n</div>\n");
+        return html;
+    }
+
+    let format_ms = |ms: Option<f64>| ms.map(|ms| format!("{:.0} ms", ms)).unwrap_or_else(|| "-".to_string());
+    let classes = total.status_classes();
+    html.push_str(&format!(
+        "<p><strong>Last {} {}s:</strong> {} requests, {} sent, ~{} unique visitors, \
+         latency p50 {} / p90 {} / p99 {}</p>\n",
+        buckets.len(),
+        view.name(),
+        total.requests,
+        format_bytes(total.bytes),
+        total.unique_visitors(),
+        format_ms(total.latency_percentile(0.5)),
+        format_ms(total.latency_percentile(0.9)),
+        format_ms(total.latency_percentile(0.99)),
+    ));
+    html.push_str(&format!(
+        "<p>2xx: {} &nbsp; 3xx: {} &nbsp; 4xx: {} &nbsp; 5xx: {}</p>\n",
+        classes[1], classes[2], classes[3], classes[4]
+    ));
+
+    // Most frequent status codes and busiest domains over the shown range
+    let mut statuses: Vec<(&u16, &u64)> = total.statuses.iter().collect();
+    statuses.sort_by(|a, b| b.1.cmp(a.1));
+    html.push_str("<div style=\"display: grid; grid-template-columns: 1fr 1fr; gap: 20px;\">\n");
+    html.push_str("<table class=\"traffic-table\">\n<tr><th>Status</th><th>Requests</th></tr>\n");
+    for (status, count) in statuses.iter().take(10) {
+        html.push_str(&format!("<tr><td>{}</td><td>{}</td></tr>\n", status, count));
+    }
+    html.push_str("</table>\n");
+    html.push_str("<table class=\"traffic-table\">\n<tr><th>Domain</th><th>Requests</th><th>Bytes</th></tr>\n");
+    for (name, _) in domains.iter().take(10) {
+        let counters = &total.domains[*name];
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            html_escape(name),
+            counters.requests,
+            format_bytes(counters.bytes)
+        ));
+    }
+    html.push_str("</table>\n</div>\n");
+
+    html.push_str("<h4>Per period</h4>\n");
+    html.push_str("<table class=\"traffic-table\">\n");
+    html.push_str("<tr><th>Period</th><th>Requests</th><th>Bytes</th><th>2xx</th><th>3xx</th><th>4xx</th><th>5xx</th><th>p50</th><th>p99</th><th>Visitors</th></tr>\n");
+    for bucket in buckets.iter().rev() {
+        let classes = bucket.status_classes();
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>~{}</td></tr>\n",
+            view.label(bucket.start),
+            bucket.requests,
+            format_bytes(bucket.bytes),
+            classes[1],
+            classes[2],
+            classes[3],
+            classes[4],
+            format_ms(bucket.latency_percentile(0.5)),
+            format_ms(bucket.latency_percentile(0.99)),
+            bucket.unique_visitors()
+        ));
+    }
+    html.push_str("</table>\n");
+    html.push_str("<p style=\"color: #666; font-size: 0.85em;\">Times are UTC. Visitor counts are HyperLogLog estimates (about 3% error).</p>\n");
+
+    html.push_str("</div>\n");
+    html.push_str("</div>\n");
+    html
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExportFormat {
+    Json,
+    Csv,
+}
+
+// Escape a string for a JSON string literal
+fn json_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Number rounded to three decimals, or None when missing or not finite
+fn rounded(value: Option<f64>) -> Option<String> {
+    value.filter(|v| v.is_finite()).map(|v| format!("{}", (v * 1000.0).round() / 1000.0))
+}
+
+// JSON number, or null when missing or not finite
+fn json_number(value: Option<f64>) -> String {
+    rounded(value).unwrap_or_else(|| "null".to_string())
+}
+
+// Quote a CSV field when needed (RFC 4180)
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+// CSV number, or an empty field when missing or not finite
+fn csv_number(value: Option<f64>) -> String {
+    rounded(value).unwrap_or_default()
+}
+
+fn api_response(status: &str, format: ExportFormat, body: &str) -> String {
+    let content_type = match format {
+        ExportFormat::Json => "application/json",
+        ExportFormat::Csv => "text/csv; charset=utf-8",
+    };
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn api_error(status: &str, message: &str) -> String {
+    api_response(status, ExportFormat::Json, &format!("{{\"error\":\"{}\"}}\n", json_escape(message)))
+}
+
+// Route /stats_<key>/api/<name>[.json|.csv]; ?format=csv works too
+fn handle_stats_api(endpoint: &str, query_string: &str) -> String {
+    let (name, format) = match endpoint.rsplit_once('.') {
+        Some((name, "json")) => (name, ExportFormat::Json),
+        Some((name, "csv")) => (name, ExportFormat::Csv),
+        _ => match query_param(query_string, "format").as_deref() {
+            Some("csv") => (endpoint, ExportFormat::Csv),
+            _ => (endpoint, ExportFormat::Json),
+        },
+    };
+
+    let from = match query_param(query_string, "from").map(|v| crate::traffic_stats::parse_time(&v).ok_or(v)) {
+        None => 0,
+        Some(Ok(time)) => time,
+        Some(Err(v)) => return api_error("400 Bad Request", &format!("Invalid 'from' time '{}': use Unix seconds or YYYY-MM-DD[THH:MM[:SS]]", v)),
+    };
+    let to = match query_param(query_string, "to").map(|v| crate::traffic_stats::parse_time(&v).ok_or(v)) {
+        None => u64::MAX,
+        Some(Ok(time)) => time,
+        Some(Err(v)) => return api_error("400 Bad Request", &format!("Invalid 'to' time '{}': use Unix seconds or YYYY-MM-DD[THH:MM[:SS]]", v)),
+    };
+
+    match name {
+        "series" => {
+            let resolution = match query_param(query_string, "resolution") {
+                None => crate::traffic_stats::Resolution::Hour,
+                Some(value) => match crate::traffic_stats::Resolution::parse(&value) {
+                    Some(resolution) => resolution,
+                    None => return api_error("400 Bad Request", &format!("Unknown resolution '{}': use minute, hour, day or month", value)),
+                },
+            };
+            let domain = query_param(query_string, "domain").map(|d| d.to_lowercase());
+            let Some(stats) = crate::traffic_stats::traffic_stats() else {
+                return api_error("503 Service Unavailable", "Traffic statistics are not enabled");
+            };
+            let buckets = stats.series(resolution, from, to);
+            let body = match format {
+                ExportFormat::Json => series_json(resolution, from, to, domain.as_deref(), &buckets),
+                ExportFormat::Csv => series_csv(domain.as_deref(), &buckets, resolution),
+            };
+            api_response("200 OK", format, &body)
+        }
+        "hourly" => match load_hourly_stats() {
+            Ok(samples) => {
+                let samples: Vec<HourlyStats> = samples.into_iter().filter(|s| s.timestamp >= from && s.timestamp < to).collect();
+                api_response("200 OK", format, &hourly_export(&samples, format))
+            }
+            Err(e) => api_error("500 Internal Server Error", &e),
+        },
+        "system" => api_response("200 OK", format, &system_export(format)),
+        _ => api_error("404 Not Found", "Unknown endpoint: use api/series, api/hourly or api/system (.json or .csv)"),
+    }
+}
+
+// Traffic buckets as JSON; per-domain views only have request and byte counts, the
+// other fields are null so the shape stays the same
+fn series_json(
+    resolution: crate::traffic_stats::Resolution,
+    from: u64,
+    to: u64,
+    domain: Option<&str>,
+    buckets: &[crate::traffic_stats::StatsBucket],
+) -> String {
+    use crate::traffic_stats::iso_time;
+
+    let mut json = String::new();
+    json.push_str(&format!("{{\"resolution\":\"{}\",", resolution.name()));
+    json.push_str(&format!("\"from\":{},", if from == 0 { "null".to_string() } else { from.to_string() }));
+    json.push_str(&format!("\"to\":{},", if to == u64::MAX { "null".to_string() } else { to.to_string() }));
+    match domain {
+        Some(d) => json.push_str(&format!("\"domain\":\"{}\",", json_escape(d))),
+        None => json.push_str("\"domain\":null,"),
+    }
+    json.push_str("\"buckets\":[");
+    for (i, bucket) in buckets.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        json.push_str(&format!("{{\"start\":{},\"start_iso\":\"{}\",", bucket.start, iso_time(bucket.start)));
+        if let Some(d) = domain {
+            let counters = bucket.domains.get(d).cloned().unwrap_or_default();
+            json.push_str(&format!(
+                "\"requests\":{},\"bytes\":{},\"status_classes\":null,\"status_codes\":null,\"latency_ms\":null,\"unique_visitors\":null,\"domains\":null}}",
+                counters.requests, counters.bytes
+            ));
+            continue;
+        }
+        let classes = bucket.status_classes();
+        json.push_str(&format!("\"requests\":{},\"bytes\":{},", bucket.requests, bucket.bytes));
+        json.push_str(&format!(
+            "\"status_classes\":{{\"1xx\":{},\"2xx\":{},\"3xx\":{},\"4xx\":{},\"5xx\":{}}},",
+            classes[0], classes[1], classes[2], classes[3], classes[4]
+        ));
+        let codes: Vec<String> = bucket.statuses.iter().map(|(code, count)| format!("\"{}\":{}", code, count)).collect();
+        json.push_str(&format!("\"status_codes\":{{{}}},", codes.join(",")));
+        json.push_str(&format!(
+            "\"latency_ms\":{{\"p50\":{},\"p90\":{},\"p99\":{}}},",
+            json_number(bucket.latency_percentile(0.5)),
+            json_number(bucket.latency_percentile(0.9)),
+            json_number(bucket.latency_percentile(0.99))
+        ));
+        json.push_str(&format!("\"unique_visitors\":{},", bucket.unique_visitors()));
+        let domains: Vec<String> = bucket
+            .domains
+            .iter()
+            .map(|(name, c)| format!("\"{}\":{{\"requests\":{},\"bytes\":{}}}", json_escape(name), c.requests, c.bytes))
+            .collect();
+        json.push_str(&format!("\"domains\":{{{}}}}}", domains.join(",")));
+    }
+    json.push_str("]}\n");
+    json
+}
+
+// Traffic buckets as CSV, one row per bucket (per-domain rows leave the totals-only columns empty)
+fn series_csv(domain: Option<&str>, buckets: &[crate::traffic_stats::StatsBucket], resolution: crate::traffic_stats::Resolution) -> String {
+    use crate::traffic_stats::iso_time;
+
+    let mut csv = String::from(
+        "start,start_iso,period,domain,requests,bytes,status_1xx,status_2xx,status_3xx,status_4xx,status_5xx,latency_p50_ms,latency_p90_ms,latency_p99_ms,unique_visitors\r\n",
+    );
+    for bucket in buckets {
+        let prefix = format!("{},{},{},{}", bucket.start, iso_time(bucket.start), csv_field(&resolution.label(bucket.start)), csv_field(domain.unwrap_or("")));
+        if let Some(d) = domain {
+            let counters = bucket.domains.get(d).cloned().unwrap_or_default();
+            csv.push_str(&format!("{},{},{},,,,,,,,,\r\n", prefix, counters.requests, counters.bytes));
+            continue;
+        }
+        let classes = bucket.status_classes();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{}\r\n",
+            prefix,
+            bucket.requests,
+            bucket.bytes,
+            classes[0],
+            classes[1],
+            classes[2],
+            classes[3],
+            classes[4],
+            csv_number(bucket.latency_percentile(0.5)),
+            csv_number(bucket.latency_percentile(0.9)),
+            csv_number(bucket.latency_percentile(0.99)),
+            bucket.unique_visitors()
+        ));
+    }
+    csv
+}
+
+fn hourly_export(samples: &[HourlyStats], format: ExportFormat) -> String {
+    use crate::traffic_stats::iso_time;
+
+    match format {
+        ExportFormat::Json => {
+            let rows: Vec<String> = samples
+                .iter()
+                .map(|s| {
+                    format!(
+                        "{{\"timestamp\":{},\"timestamp_iso\":\"{}\",\"memory_used_mb\":{},\"cpu_usage_percent\":{},\"request_count\":{}}}",
+                        s.timestamp,
+                        iso_time(s.timestamp),
+                        json_number(Some(s.memory_used_mb)),
+                        json_number(Some(s.cpu_usage_percent)),
+                        s.request_count
+                    )
+                })
+                .collect();
+            format!("{{\"samples\":[{}]}}\n", rows.join(","))
+        }
+        ExportFormat::Csv => {
+            let mut csv = String::from("timestamp,timestamp_iso,memory_used_mb,cpu_usage_percent,request_count\r\n");
+            for s in samples {
+                csv.push_str(&format!(
+                    "{},{},{},{},{}\r\n",
+                    s.timestamp,
+                    iso_time(s.timestamp),
+                    csv_number(Some(s.memory_used_mb)),
+                    csv_number(Some(s.cpu_usage_percent)),
+                    s.request_count
+                ));
+            }
+            csv
+        }
+    }
+}
+
+// Live snapshot; a section that cannot be read is null (JSON) or absent (CSV) and its
+// error is listed under "errors"
+fn system_export(format: ExportFormat) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let memory = parse_meminfo();
+    let load = parse_loadavg();
+    let cpu = parse_cpu_stat();
+    let uptime = parse_uptime();
+    let disks = parse_disk_usage();
+
+    // (metric, value) pairs shared by both formats
+    let mut metrics: Vec<(String, Option<String>)> = Vec::new();
+    let mut errors: Vec<(&str, String)> = Vec::new();
+    match &memory {
+        Ok(m) => {
+            metrics.push(("memory.total_bytes".to_string(), Some(m.total.to_string())));
+            metrics.push(("memory.free_bytes".to_string(), Some(m.free.to_string())));
+            metrics.push(("memory.available_bytes".to_string(), Some(m.available.to_string())));
+            metrics.push(("memory.buffers_bytes".to_string(), Some(m.buffers.to_string())));
+            metrics.push(("memory.cached_bytes".to_string(), Some(m.cached.to_string())));
+            metrics.push(("memory.swap_total_bytes".to_string(), Some(m.swap_total.to_string())));
+            metrics.push(("memory.swap_free_bytes".to_string(), Some(m.swap_free.to_string())));
+            metrics.push(("memory.usage_percent".to_string(), rounded(Some(calculate_memory_usage(m)))));
+        }
+        Err(e) => errors.push(("memory", e.clone())),
+    }
+    match &load {
+        Ok(l) => {
+            metrics.push(("load.one_minute".to_string(), rounded(Some(l.one_minute))));
+            metrics.push(("load.five_minutes".to_string(), rounded(Some(l.five_minutes))));
+            metrics.push(("load.fifteen_minutes".to_string(), rounded(Some(l.fifteen_minutes))));
+        }
+        Err(e) => errors.push(("load", e.clone())),
+    }
+    match &cpu {
+        Ok(c) => {
+            for (name, value) in [
+                ("user", c.user),
+                ("nice", c.nice),
+                ("system", c.system),
+                ("idle", c.idle),
+                ("iowait", c.iowait),
+                ("irq", c.irq),
+                ("softirq", c.softirq),
+                ("steal", c.steal),
+            ] {
+                metrics.push((format!("cpu.{}_ticks", name), Some(value.to_string())));
+            }
+            metrics.push(("cpu.usage_percent".to_string(), rounded(Some(calculate_cpu_usage(c)))));
+        }
+        Err(e) => errors.push(("cpu", e.clone())),
+    }
+    match &uptime {
+        Ok(u) => {
+            metrics.push(("uptime.seconds".to_string(), rounded(Some(u.uptime_seconds))));
+            metrics.push(("uptime.idle_seconds".to_string(), rounded(Some(u.idle_seconds))));
+        }
+        Err(e) => errors.push(("uptime", e.clone())),
+    }
+    if let Err(e) = &disks {
+        errors.push(("disks", e.clone()));
+    }
+    let cache = crate::content_cache::stats();
+    metrics.push(("file_cache.hits".to_string(), Some(cache.hits.to_string())));
+    metrics.push(("file_cache.misses".to_string(), Some(cache.misses.to_string())));
+    metrics.push(("file_cache.evictions".to_string(), Some(cache.evictions.to_string())));
+    metrics.push(("file_cache.invalidations".to_string(), Some(cache.invalidations.to_string())));
+    metrics.push(("file_cache.entries".to_string(), Some(cache.entries.to_string())));
+    metrics.push(("file_cache.bytes".to_string(), Some(cache.bytes.to_string())));
+    metrics.push(("file_cache.capacity_bytes".to_string(), Some(cache.capacity.to_string())));
+    let tls = crate::tls_policy::stats();
+    metrics.push(("tls.handshakes".to_string(), Some(tls.handshakes().to_string())));
+    metrics.push(("tls.tls13".to_string(), Some(tls.tls13.to_string())));
+    metrics.push(("tls.tls12".to_string(), Some(tls.tls12.to_string())));
+    metrics.push(("tls.resumed".to_string(), Some(tls.resumed.to_string())));
+
+    format_system_export(format, now, &metrics, &disks, &errors)
+}
+
+// Missing values are null in JSON and an empty field in CSV
+fn format_system_export(
+    format: ExportFormat,
+    now: u64,
+    metrics: &[(String, Option<String>)],
+    disks: &Result<Vec<DiskUsage>, String>,
+    errors: &[(&str, String)],
+) -> String {
+    match format {
+        ExportFormat::Json => {
+            let section = |prefix: &str| -> String {
+                let fields: Vec<String> = metrics
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        name.strip_prefix(prefix)
+                            .map(|field| format!("\"{}\":{}", field, value.as_deref().unwrap_or("null")))
+                    })
+                    .collect();
+                if fields.is_empty() {
+                    "null".to_string()
+                } else {
+                    format!("{{{}}}", fields.join(","))
+                }
+            };
+            let disks_json = match disks {
+                Ok(disks) => {
+                    let rows: Vec<String> = disks
+                        .iter()
+                        .map(|d| {
+                            format!(
+                                "{{\"filesystem\":\"{}\",\"mount_point\":\"{}\",\"total_bytes\":{},\"used_bytes\":{},\"available_bytes\":{},\"usage_percent\":{}}}",
+                                json_escape(&d.filesystem),
+                                json_escape(&d.mount_point),
+                                d.total,
+                                d.used,
+                                d.available,
+                                json_number(Some(d.usage_percent))
+                            )
+                        })
+                        .collect();
+                    format!("[{}]", rows.join(","))
+                }
+                Err(_) => "null".to_string(),
+            };
+            let errors_json: Vec<String> =
+                errors.iter().map(|(section, e)| format!("\"{}\":\"{}\"", section, json_escape(e))).collect();
+            format!(
+                "{{\"timestamp\":{},\"memory\":{},\"load\":{},\"cpu\":{},\"uptime\":{},\"disks\":{},\"file_cache\":{},\"tls\":{},\"errors\":{{{}}}}}\n",
+                now,
+                section("memory."),
+                section("load."),
+                section("cpu."),
+                section("uptime."),
+                disks_json,
+                section("file_cache."),
+                section("tls."),
+                errors_json.join(",")
+            )
+        }
+        ExportFormat::Csv => {
+            // Long format keeps the columns stable whatever is available
+            let mut csv = String::from("timestamp,metric,mount_point,value\r\n");
+            for (name, value) in metrics {
+                csv.push_str(&format!("{},{},,{}\r\n", now, name, value.as_deref().unwrap_or("")));
+            }
+            if let Ok(disks) = disks {
+                for d in disks {
+                    let mount = &d.mount_point;
+                    for (field, value) in [
+                        ("total_bytes", d.total.to_string()),
+                        ("used_bytes", d.used.to_string()),
+                        ("available_bytes", d.available.to_string()),
+                        ("usage_percent", csv_number(Some(d.usage_percent))),
+                    ] {
+                        csv.push_str(&format!("{},disk.{},{},{}\r\n", now, field, csv_field(mount), value));
+                    }
+                }
+            }
+            csv
+        }
+    }
+}
+
 // Get current time in a simple format
 fn get_current_time() -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
@@ -792,7 +1467,7 @@ fn html_escape(text: &str) -> String {
 pub fn handle_stats_admin_request(
     path: &str,
     method: &str,
-    _query_string: &str,
+    query_string: &str,
     _body: &str,
     _headers: &HashMap<String, String>,
     admin_keys: &std::collections::HashMap<String, String>,
@@ -807,13 +1482,21 @@ pub fn handle_stats_admin_request(
         .ok_or("Stats admin key not found".to_string())?;
     let expected_path = format!("/stats_{}", admin_key);
 
+    // Export API: /stats_<key>/api/<endpoint>
+    if let Some(endpoint) = path.strip_prefix(&expected_path).and_then(|rest| rest.strip_prefix("/api/")) {
+        if method != "GET" {
+            return Err("Method not allowed".to_string());
+        }
+        return Ok(handle_stats_api(endpoint, query_string));
+    }
+
     if path != expected_path {
         return Err("Invalid admin key".to_string());
     }
 
     // Handle GET requests (display stats panel)
     if method == "GET" {
-        let html = generate_stats_panel(admin_key);
+        let html = generate_stats_panel(admin_key, query_string);
 
         return Ok(format!(
             "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n{}",
@@ -1023,3 +1706,120 @@ mod windows_stats {
 pub fn get_stats_admin_paths() -> Vec<String> {
     vec!["/stats_".to_string()]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traffic_stats::{DomainCounters, Resolution, StatsBucket};
+
+    fn bucket() -> StatsBucket {
+        let mut bucket = StatsBucket::new(1_700_000_000);
+        bucket.requests = 3;
+        bucket.bytes = 300;
+        bucket.statuses.insert(200, 2);
+        bucket.statuses.insert(404, 1);
+        bucket.domains.insert("example.com".to_string(), DomainCounters { requests: 3, bytes: 300 });
+        bucket
+    }
+
+    #[test]
+    fn test_series_json_fields() {
+        let json = series_json(Resolution::Hour, 0, u64::MAX, None, &[bucket()]);
+        for field in [
+            "\"resolution\":\"hour\"",
+            "\"from\":null",
+            "\"to\":null",
+            "\"domain\":null",
+            "\"buckets\":[",
+            "\"start\":1700000000",
+            "\"start_iso\":",
+            "\"requests\":3",
+            "\"bytes\":300",
+            "\"status_classes\":{\"1xx\":0,\"2xx\":2,\"3xx\":0,\"4xx\":1,\"5xx\":0}",
+            "\"status_codes\":{\"200\":2,\"404\":1}",
+            "\"latency_ms\":{\"p50\":",
+            "\"p90\":",
+            "\"p99\":",
+            "\"unique_visitors\":",
+            "\"domains\":{\"example.com\":{\"requests\":3,\"bytes\":300}}",
+        ] {
+            assert!(json.contains(field), "missing {} in {}", field, json);
+        }
+
+        let json = series_json(Resolution::Day, 1, 2, Some("example.com"), &[bucket()]);
+        assert!(json.contains("\"from\":1,\"to\":2,\"domain\":\"example.com\""));
+        assert!(json.contains("\"requests\":3,\"bytes\":300,\"status_classes\":null,\"status_codes\":null,\"latency_ms\":null,\"unique_visitors\":null,\"domains\":null"));
+    }
+
+    #[test]
+    fn test_series_csv_columns() {
+        let csv = series_csv(None, &[bucket()], Resolution::Hour);
+        let mut lines = csv.split("\r\n");
+        let header = lines.next().unwrap();
+        assert_eq!(
+            header,
+            "start,start_iso,period,domain,requests,bytes,status_1xx,status_2xx,status_3xx,status_4xx,status_5xx,latency_p50_ms,latency_p90_ms,latency_p99_ms,unique_visitors"
+        );
+        let columns = header.split(',').count();
+        let row: Vec<&str> = lines.next().unwrap().split(',').collect();
+        assert_eq!(row.len(), columns);
+        assert_eq!(row[4..11], ["3", "300", "0", "2", "0", "1", "0"]);
+        // No latency samples: empty fields, not null
+        assert_eq!(row[11..14], ["", "", ""]);
+
+        let csv = series_csv(Some("example.com"), &[bucket()], Resolution::Hour);
+        let row: Vec<&str> = csv.split("\r\n").nth(1).unwrap().split(',').collect();
+        assert_eq!(row.len(), columns);
+        assert_eq!(row[3..6], ["example.com", "3", "300"]);
+        assert!(row[6..].iter().all(|field| field.is_empty()));
+    }
+
+    #[test]
+    fn test_hourly_export_fields() {
+        let samples = [HourlyStats { timestamp: 1_700_000_000, memory_used_mb: 512.25, cpu_usage_percent: f64::NAN, request_count: 7 }];
+
+        let json = hourly_export(&samples, ExportFormat::Json);
+        assert!(json.starts_with("{\"samples\":[{\"timestamp\":1700000000,\"timestamp_iso\":\""));
+        assert!(json.contains("\"memory_used_mb\":512.25,\"cpu_usage_percent\":null,\"request_count\":7}"));
+
+        let csv = hourly_export(&samples, ExportFormat::Csv);
+        let mut lines = csv.split("\r\n");
+        assert_eq!(lines.next().unwrap(), "timestamp,timestamp_iso,memory_used_mb,cpu_usage_percent,request_count");
+        let row: Vec<&str> = lines.next().unwrap().split(',').collect();
+        assert_eq!(row.len(), 5);
+        assert_eq!([row[0], row[2], row[3], row[4]], ["1700000000", "512.25", "", "7"]);
+    }
+
+    #[test]
+    fn test_system_export_fields() {
+        let metrics = vec![
+            ("memory.total_bytes".to_string(), Some("1024".to_string())),
+            ("memory.usage_percent".to_string(), rounded(Some(f64::NAN))),
+            ("tls.handshakes".to_string(), Some("5".to_string())),
+        ];
+        let disks = Ok(vec![DiskUsage {
+            filesystem: "/dev/sda1".to_string(),
+            total: 100,
+            used: 40,
+            available: 60,
+            usage_percent: 40.0,
+            mount_point: "/srv,data".to_string(),
+        }]);
+        let errors = [("load", "unavailable".to_string())];
+
+        let json = format_system_export(ExportFormat::Json, 42, &metrics, &disks, &errors);
+        assert!(json.starts_with("{\"timestamp\":42,\"memory\":{\"total_bytes\":1024,\"usage_percent\":null},\"load\":null,\"cpu\":null,\"uptime\":null,"));
+        assert!(json.contains("\"disks\":[{\"filesystem\":\"/dev/sda1\",\"mount_point\":\"/srv,data\",\"total_bytes\":100,\"used_bytes\":40,\"available_bytes\":60,\"usage_percent\":40}]"));
+        assert!(json.contains("\"file_cache\":null,\"tls\":{\"handshakes\":5},\"errors\":{\"load\":\"unavailable\"}}"));
+
+        let csv = format_system_export(ExportFormat::Csv, 42, &metrics, &disks, &errors);
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(lines[0], "timestamp,metric,mount_point,value");
+        assert_eq!(lines[1], "42,memory.total_bytes,,1024");
+        assert_eq!(lines[2], "42,memory.usage_percent,,");
+        assert_eq!(lines[3], "42,tls.handshakes,,5");
+        assert_eq!(lines[4], "42,disk.total_bytes,\"/srv,data\",100");
+        assert_eq!(lines[7], "42,disk.usage_percent,\"/srv,data\",40");
+        assert!(!csv.contains("null"));
+    }
+}
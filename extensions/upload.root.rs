@@ -11,7 +11,7 @@ pub fn setup_upload_directories() -> Result<(), Box<dyn std::error::Error>> {
     // Create upload directory
     if !upload_dir.exists() {
         fs::create_dir_all(upload_dir)?;
-        println!("Created upload directory: {}", upload_dir.display());
+        log::info!(target: "extensions", "created upload directory: {}", upload_dir.display());
     }
 
     // Set permissions
@@ -21,14 +21,14 @@ pub fn setup_upload_directories() -> Result<(), Box<dyn std::error::Error>> {
         let mut perms = fs::metadata(upload_dir)?.permissions();
         perms.set_mode(0o755);
         fs::set_permissions(upload_dir, perms)?;
-        println!("Set permissions for upload directory: {}", upload_dir.display());
+        log::info!(target: "extensions", "set permissions for upload directory: {}", upload_dir.display());
     }
 
     // Create public uploads directory for serving files
     let public_uploads_dir = Path::new("/var/www/html/uploads");
     if !public_uploads_dir.exists() {
         fs::create_dir_all(public_uploads_dir)?;
-        println!("Created public uploads directory: {}", public_uploads_dir.display());
+        log::info!(target: "extensions", "created public uploads directory: {}", public_uploads_dir.display());
 
         #[cfg(unix)]
         {
@@ -49,7 +49,7 @@ pub fn setup_upload_directories() -> Result<(), Box<dyn std::error::Error>> {
                 fs::remove_dir(symlink_path)?;
             }
             std::os::unix::fs::symlink(upload_dir, symlink_path)?;
-            println!("Created symlink from {} to {}", symlink_path.display(), upload_dir.display());
+            log::info!(target: "extensions", "created symlink from {} to {}", symlink_path.display(), upload_dir.display());
         }
     }
 
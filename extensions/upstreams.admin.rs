@@ -0,0 +1,152 @@
+// upstreams.admin.rs - Admin panel for upstream group health
+// Shows every load-balanced upstream group with per-server state and counters
+
+use std::collections::HashMap;
+
+// HTML escape function
+fn html_escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '&' => "&amp;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#x27;".to_string(),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+// Describe how long ago a unix timestamp was
+fn format_age(timestamp: u64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let age = now.saturating_sub(timestamp);
+    if age < 60 {
+        format!("{}s ago", age)
+    } else if age < 3600 {
+        format!("{}m ago", age / 60)
+    } else {
+        format!("{}h ago", age / 3600)
+    }
+}
+
+// Generate the upstreams panel HTML
+fn generate_upstreams_panel() -> String {
+    let mut html = String::new();
+
+    html.push_str("<!DOCTYPE html>\n");
+    html.push_str("<html>\n");
+    html.push_str("<head>\n");
+    html.push_str("<title>Upstream Health</title>\n");
+    html.push_str("<meta http-equiv=\"refresh\" content=\"10\">\n");
+    html.push_str("<style>\n");
+    html.push_str("body { font-family: Arial, sans-serif; margin: 20px; background-color: #f5f5f5; }\n");
+    html.push_str(".container { max-width: 1200px; margin: 0 auto; background: white; padding: 20px; border-radius: 8px; box-shadow: 0 2px 4px rgba(0,0,0,0.1); }\n");
+    html.push_str("h1 { color: #333; border-bottom: 2px solid #007bff; padding-bottom: 10px; }\n");
+    html.push_str(".group { background-color: #f8f9fa; padding: 20px; border-radius: 8px; border-left: 4px solid #007bff; margin: 20px 0; }\n");
+    html.push_str(".group h3 { margin-top: 0; color: #333; }\n");
+    html.push_str(".group-info { color: #666; margin-bottom: 10px; }\n");
+    html.push_str("table { width: 100%; border-collapse: collapse; }\n");
+    html.push_str("th, td { text-align: left; padding: 8px; border-bottom: 1px solid #dee2e6; }\n");
+    html.push_str("th { color: #555; }\n");
+    html.push_str(".state { display: inline-block; padding: 2px 8px; border-radius: 4px; color: white; font-weight: bold; }\n");
+    html.push_str(".state-up { background-color: #28a745; }\n");
+    html.push_str(".state-ejected { background-color: #ffc107; color: #333; }\n");
+    html.push_str(".state-down { background-color: #dc3545; }\n");
+    html.push_str(".error-text { color: #721c24; font-family: monospace; font-size: 0.9em; }\n");
+    html.push_str(".refresh-info { text-align: center; color: #666; font-size: 0.9em; margin-top: 20px; }\n");
+    html.push_str("</style>\n");
+    html.push_str("</head>\n");
+    html.push_str("<body>\n");
+    html.push_str("<div class=\"container\">\n");
+
+    html.push_str("<h1>&#x1F500; Upstream Health</h1>\n");
+
+    let groups = crate::upstream::installed_registry()
+        .map(|registry| registry.status())
+        .unwrap_or_default();
+
+    if groups.is_empty() {
+        html.push_str("<p>No upstream groups are configured. Use <code>--upstream NAME=URL,URL</code> and <code>--proxy-pass HOST=upstream:NAME</code>.</p>\n");
+    }
+
+    for group in &groups {
+        let up = group.servers.iter().filter(|s| s.state == "up").count();
+        html.push_str("<div class=\"group\">\n");
+        html.push_str(&format!("<h3>{}</h3>\n", html_escape(&group.name)));
+        html.push_str(&format!(
+            "<div class=\"group-info\">Policy: {} &middot; {} of {} servers up &middot; Health check: {}</div>\n",
+            group.policy,
+            up,
+            group.servers.len(),
+            match &group.health_check {
+                Some(check) => format!("{} every {}s", html_escape(&check.path), check.interval.as_secs()),
+                None => "passive only".to_string(),
+            }
+        ));
+        html.push_str("<table>\n");
+        html.push_str("<tr><th>Server</th><th>State</th><th>Active</th><th>Requests</th><th>Failures</th><th>Streak</th><th>Last check</th><th>Last error</th></tr>\n");
+        for server in &group.servers {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td><span class=\"state state-{}\">{}</span></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"error-text\">{}</td></tr>\n",
+                html_escape(&server.address),
+                server.state,
+                server.state,
+                server.active,
+                server.requests,
+                server.failures,
+                server.consecutive_failures,
+                server.last_check.map(format_age).unwrap_or_else(|| "never".to_string()),
+                html_escape(server.last_error.as_deref().unwrap_or("")),
+            ));
+        }
+        html.push_str("</table>\n");
+        html.push_str("</div>\n");
+    }
+
+    html.push_str("<div class=\"refresh-info\">This page refreshes every 10 seconds</div>\n");
+    html.push_str("</div>\n");
+    html.push_str("</body>\n");
+    html.push_str("</html>\n");
+
+    html
+}
+
+// Main admin handler
+pub fn handle_upstreams_admin_request(
+    path: &str,
+    method: &str,
+    _query_string: &str,
+    _body: &str,
+    _headers: &HashMap<String, String>,
+    admin_keys: &std::collections::HashMap<String, String>,
+) -> Result<String, String> {
+    // Check if this looks like an upstreams admin request
+    if !path.starts_with("/upstreams_") {
+        return Err("Not an upstreams admin request".to_string());
+    }
+
+    // Get admin key from memory and validate
+    let admin_key = admin_keys.get("upstreams")
+        .ok_or("Upstreams admin key not found".to_string())?;
+    let expected_path = format!("/upstreams_{}", admin_key);
+
+    if path != expected_path {
+        return Err("Invalid admin key".to_string());
+    }
+
+    if method == "GET" {
+        let html = generate_upstreams_panel();
+
+        return Ok(format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\r\n{}",
+            html.len(),
+            html
+        ));
+    }
+
+    Err("Method not allowed".to_string())
+}
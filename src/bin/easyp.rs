@@ -14,20 +14,32 @@ use std::collections::{BTreeMap, HashMap};
 use std::io::{Read, Write};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio_rustls::TlsAcceptor;
-use tokio_rustls::rustls::ServerConfig as TokioServerConfig;
+use tokio_rustls::LazyConfigAcceptor;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
 
 // Import hourly stats collection
 #[path = "../modules/hourly_stats.rs"]
 mod hourly_stats;
 use hourly_stats::{HourlyStatsCollector, start_stats_collection_task};
+#[path = "../modules/traffic_stats.rs"]
+mod traffic_stats;
+use traffic_stats::{init_traffic_stats, start_traffic_stats_task, Retention};
 
 // Import file logger
 #[path = "../modules/file_logger.rs"]
 mod file_logger;
-use file_logger::{init_file_logger, write_file_log, get_log_file_path};
+use file_logger::{init_file_logger, get_log_file_path};
+#[path = "../modules/logging.rs"]
+mod logging;
+use logging::{init_logging, LogLevels, LogOutput};
+#[path = "../modules/log_rotation.rs"]
+mod log_rotation;
+use log_rotation::RotationPolicy;
+#[path = "../modules/metrics.rs"]
+mod metrics;
+#[path = "../modules/health.rs"]
+mod health;
 use std::time::Duration;
 
 // Import enhanced error reporting
@@ -41,26 +53,6 @@ use rustls::{ServerConfig, ServerConnection};
 use rustls::sign::CertifiedKey;
 
 // Simple logger without regex dependency
-struct SimpleLogger;
-
-impl log::Log for SimpleLogger {
-    fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= log::max_level()
-    }
-
-    fn log(&self, record: &log::Record) {
-        if self.enabled(record.metadata()) {
-            let log_message = format!("{}: {}", record.level(), record.args());
-            println!("{}", log_message);
-
-            // Also write to file
-            write_file_log(&record.level().to_string(), &record.args().to_string());
-        }
-    }
-
-    fn flush(&self) {}
-}
-
 #[cfg(feature = "acme")]
 use rustls_acme::{AcmeClient, OnDemandCertResolver, DnsValidator};
 use rustls_acme::{AcmeConfig, ChallengeType};
@@ -71,6 +63,63 @@ mod cgi_env;
 #[path = "../modules/secure_file_server_module.rs"]
 mod secure_file_server_module;
 use secure_file_server_module::{SecureFileServer, SecurityConfig};
+#[path = "../modules/mime_types.rs"]
+mod mime_types;
+use mime_types::MimeConfig;
+#[path = "../modules/vhost.rs"]
+mod vhost;
+use vhost::{CanonicalHost, UnknownHostPolicy, VhostConfig};
+#[path = "../modules/try_files.rs"]
+mod try_files;
+use try_files::TryFilesConfig;
+#[path = "../modules/reverse_proxy.rs"]
+mod reverse_proxy;
+use reverse_proxy::{proxy_request, ForwardInfo, ProxyConfig};
+
+#[path = "../modules/upstream.rs"]
+mod upstream;
+use upstream::{UpstreamConfig, UpstreamRegistry};
+#[path = "../modules/fastcgi.rs"]
+mod fastcgi;
+use fastcgi::{fastcgi_request, ConnectionInfo, FastCgiConfig};
+#[path = "../modules/cgi_exec.rs"]
+mod cgi_exec;
+use cgi_exec::{run_cgi, CgiExecConfig};
+#[path = "../modules/websocket.rs"]
+mod websocket;
+use websocket::{serve_websocket, WsConfig};
+#[path = "../modules/access_log.rs"]
+mod access_log;
+use access_log::{init_access_log, AccessLogConfig, AccessRecorder, LogFormat};
+
+#[path = "../modules/hostname.rs"]
+mod hostname;
+use hostname::normalize_hostname;
+#[path = "../modules/static_certs.rs"]
+mod static_certs;
+use static_certs::{spawn_cert_reloader, CertSpec, ReloadCerts, StaticCertResolver, WatchedCert, RELOAD_INTERVAL};
+#[path = "../modules/digest.rs"]
+mod digest;
+#[path = "../modules/x509.rs"]
+mod x509;
+#[path = "../modules/ocsp.rs"]
+mod ocsp;
+use ocsp::{OcspConfig, OcspStapler};
+#[path = "../modules/tls_policy.rs"]
+mod tls_policy;
+use tls_policy::{ServerConfigs, TlsPolicy, TlsVersion};
+#[path = "../modules/client_auth.rs"]
+mod client_auth;
+use client_auth::{ClientAuthConfig, ClientAuthRule, ClientIdentity};
+#[path = "../modules/acme_challenge.rs"]
+mod acme_challenge;
+use acme_challenge::{select_challenge, ChallengeKind, ChallengePreference, ACME_TLS_ALPN_PROTOCOL};
+#[path = "../modules/tls_alpn.rs"]
+mod tls_alpn;
+use tls_alpn::{TlsAlpnChallenges, TlsAlpnResolver};
+#[path = "../modules/dns01.rs"]
+mod dns01;
+use dns01::{Dns01Config, Dns01Solver, ProviderSpec, TsigKey};
 
 #[path = "../modules/file_handler.rs"]
 mod file_handler;
@@ -87,6 +136,11 @@ mod http_response;
 mod connection_policy;
 #[path = "../modules/file_cache.rs"]
 mod file_cache;
+#[path = "../modules/content_cache.rs"]
+mod content_cache;
+#[path = "../modules/etag_index.rs"]
+mod etag_index;
+use etag_index::EtagMode;
 
 use http_version::HttpVersion;
 use http_response::HttpResponse;
@@ -99,12 +153,12 @@ include!(concat!(env!("OUT_DIR"), "/generated_extensions.rs"));
 // Debug: Check if extensions feature is enabled
 #[cfg(feature = "extensions")]
 fn debug_extensions_enabled() {
-    println!("DEBUG: Extensions feature is ENABLED");
+    log::debug!(target: "extensions", "extensions feature is ENABLED");
 }
 
 #[cfg(not(feature = "extensions"))]
 fn debug_extensions_enabled() {
-    println!("DEBUG: Extensions feature is DISABLED");
+    log::debug!(target: "extensions", "extensions feature is DISABLED");
 }
 
 // Always define ExtensionRegistry - either from generated code or as placeholder
@@ -151,8 +205,33 @@ struct Args {
     port: u16,
     acme_directory: String,
     acme_email: Option<String>,
-    challenge_type: String,
+    challenge_type: ChallengePreference,
     admin_urls: bool,
+    vhosts: VhostConfig,
+    try_files: TryFilesConfig,
+    proxy: ProxyConfig,
+    fastcgi: FastCgiConfig,
+    cgi: CgiExecConfig,
+    websocket: WsConfig,
+    access_log: AccessLogConfig,
+    access_log_off: bool,
+    log_rotation: RotationPolicy,
+    log_levels: LogLevels,
+    log_output: LogOutput,
+    metrics_listen: Option<std::net::SocketAddr>,
+    stats_retention: Retention,
+    health_path: Option<String>,
+    health_listen: Option<std::net::SocketAddr>,
+    file_cache_size: u64,
+    etag_mode: EtagMode,
+    etag_index: Option<PathBuf>,
+    mime: MimeConfig,
+    static_certs: Vec<CertSpec>,
+    default_cert: Option<(PathBuf, PathBuf)>,
+    ocsp: OcspConfig,
+    tls: TlsPolicy,
+    client_auth: ClientAuthConfig,
+    dns01: Dns01Config,
 }
 
 impl Args {
@@ -182,8 +261,35 @@ impl Args {
         let mut port = 443;
         let mut acme_directory = "https://acme-v02.api.letsencrypt.org/directory".to_string();
         let mut acme_email = None;
-        let mut challenge_type = "http01".to_string();
+        let mut challenge_type = ChallengePreference::Auto;
         let mut admin_urls = false;
+        let mut vhosts = VhostConfig::default();
+        let mut try_files = TryFilesConfig::default();
+        let mut clean_urls = false;
+        let mut proxy = ProxyConfig::default();
+        let mut upstreams = UpstreamConfig::default();
+        let mut fastcgi = FastCgiConfig::default();
+        let mut cgi = CgiExecConfig::default();
+        let mut websocket = WsConfig::default();
+        let mut access_log = AccessLogConfig::default();
+        let mut access_log_off = false;
+        let mut log_rotation = RotationPolicy::default();
+        let mut log_levels = LogLevels::default();
+        let mut log_output = LogOutput::Text;
+        let mut metrics_listen = None;
+        let mut stats_retention = Retention::default();
+        let mut health_path = None;
+        let mut health_listen = None;
+        let mut file_cache_size = content_cache::DEFAULT_CACHE_SIZE;
+        let mut etag_mode = EtagMode::Metadata;
+        let mut etag_index = None;
+        let mut mime = MimeConfig::default();
+        let mut static_certs = Vec::new();
+        let mut default_cert = None;
+        let mut ocsp = OcspConfig::default();
+        let mut tls = TlsPolicy::default();
+        let mut client_auth = ClientAuthConfig::default();
+        let mut dns01 = Dns01Config::default();
 
         let mut parser = lexopt::Parser::from_env();
         while let Some(arg) = parser.next()? {
@@ -243,11 +349,252 @@ impl Args {
                     acme_email = Some(parser.value()?.to_string_lossy().to_string());
                 }
                 Long("challenge-type") => {
-                    challenge_type = parser.value()?.to_string_lossy().to_string();
+                    challenge_type = ChallengePreference::parse(&parser.value()?.to_string_lossy())?;
                 }
                 Long("admin-urls") => {
                     admin_urls = true;
                 }
+                Long("root-template") => {
+                    vhosts.root_template = parser.value()?.to_string_lossy().to_string();
+                }
+                Long("vhost-alias") => {
+                    vhosts.add_alias_spec(&parser.value()?.to_string_lossy())?;
+                }
+                Long("wildcard-vhost") => {
+                    vhosts.add_wildcard_spec(&parser.value()?.to_string_lossy())?;
+                }
+                Long("canonical-host") => {
+                    vhosts.canonical_host = CanonicalHost::parse(&parser.value()?.to_string_lossy())?;
+                }
+                Long("unknown-host") => {
+                    vhosts.unknown_host = UnknownHostPolicy::parse(&parser.value()?.to_string_lossy())?;
+                }
+                Long("index") => {
+                    try_files.set_index_files(&parser.value()?.to_string_lossy())?;
+                }
+                Long("try-files") => {
+                    try_files.add_location_spec(&parser.value()?.to_string_lossy())?;
+                }
+                Long("clean-urls") => {
+                    clean_urls = true;
+                }
+                Long("spa") => {
+                    try_files.spa_shell = Some(parser.value()?.to_string_lossy().to_string());
+                }
+                Long("proxy-pass") => {
+                    proxy.add_route_spec(&parser.value()?.to_string_lossy())?;
+                }
+                Long("proxy-connect-timeout") => {
+                    proxy.connect_timeout = Duration::from_secs(parser.value()?.parse()?);
+                }
+                Long("proxy-timeout") => {
+                    proxy.io_timeout = Duration::from_secs(parser.value()?.parse()?);
+                }
+                Long("upstream") => {
+                    upstreams.add_group_spec(&parser.value()?.to_string_lossy())?;
+                }
+                Long("upstream-policy") => {
+                    upstreams.set_policy_spec(&parser.value()?.to_string_lossy())?;
+                }
+                Long("health-check") => {
+                    upstreams.set_health_check_spec(&parser.value()?.to_string_lossy())?;
+                }
+                Long("max-fails") => {
+                    upstreams.max_fails = parser.value()?.parse()?;
+                }
+                Long("fail-timeout") => {
+                    upstreams.fail_timeout = Duration::from_secs(parser.value()?.parse()?);
+                }
+                Long("fastcgi") => {
+                    fastcgi.add_rule_spec(&parser.value()?.to_string_lossy())?;
+                }
+                Long("fastcgi-index") => {
+                    fastcgi.index_file = parser.value()?.to_string_lossy().to_string();
+                }
+                Long("fastcgi-timeout") => {
+                    fastcgi.io_timeout = Duration::from_secs(parser.value()?.parse()?);
+                }
+                Long("cgi-dir") => {
+                    cgi.dir_template = Some(parser.value()?.to_string_lossy().to_string());
+                }
+                Long("cgi-timeout") => {
+                    cgi.timeout = Duration::from_secs(parser.value()?.parse()?);
+                }
+                Long("cgi-max-output") => {
+                    cgi.max_output = parser.value()?.parse()?;
+                }
+                Long("ws-max-message") => {
+                    websocket.max_message_size = parser.value()?.parse()?;
+                }
+                Long("ws-ping-interval") => {
+                    websocket.ping_interval = Duration::from_secs(parser.value()?.parse()?);
+                    websocket.idle_timeout = websocket.ping_interval * 5 / 2;
+                }
+                Long("ws-max-connections") => {
+                    websocket.max_connections = parser.value()?.parse()?;
+                }
+                Long("access-log") => {
+                    let value = parser.value()?.to_string_lossy().to_string();
+                    access_log_off = value == "off";
+                    access_log.path = if access_log_off { None } else { Some(PathBuf::from(value)) };
+                }
+                Long("access-log-format") => {
+                    access_log.format = LogFormat::parse(&parser.value()?.to_string_lossy())?;
+                }
+                Long("access-log-per-domain") => {
+                    access_log.domain_template = Some(parser.value()?.to_string_lossy().to_string());
+                }
+                Long("anonymize-ip") => {
+                    access_log.anonymize_ip = true;
+                }
+                Long("log-max-size") => {
+                    let value = parser.value()?.to_string_lossy().to_string();
+                    log_rotation.max_size = if value == "off" { None } else { Some(RotationPolicy::parse_size(&value)?) };
+                }
+                Long("log-rotate-daily") => {
+                    log_rotation.daily = true;
+                }
+                Long("log-keep") => {
+                    log_rotation.keep = parser.value()?.parse()?;
+                }
+                Long("no-log-compress") => {
+                    log_rotation.compress = false;
+                }
+                Long("log-level") => {
+                    log_levels = LogLevels::parse(&parser.value()?.to_string_lossy())?;
+                }
+                Long("log-format") => {
+                    log_output = LogOutput::parse(&parser.value()?.to_string_lossy())?;
+                }
+                Long("metrics-listen") => {
+                    metrics_listen = Some(parser.value()?.to_string_lossy().parse()?);
+                }
+                Long("stats-retention") => {
+                    stats_retention = Retention::parse(&parser.value()?.to_string_lossy())?;
+                }
+                Long("health-path") => {
+                    let value = parser.value()?.to_string_lossy().to_string();
+                    health_path = match value.as_str() {
+                        "off" => None,
+                        prefix if prefix.is_empty() || prefix.starts_with('/') => Some(prefix.to_string()),
+                        prefix => return Err(format!("Invalid --health-path '{}': must start with '/' or be 'off'", prefix).into()),
+                    };
+                }
+                Long("health-listen") => {
+                    health_listen = Some(parser.value()?.to_string_lossy().parse()?);
+                }
+                Long("file-cache-size") => {
+                    let value = parser.value()?.to_string_lossy().to_string();
+                    file_cache_size = if value == "0" {
+                        0
+                    } else {
+                        RotationPolicy::parse_size(&value).map_err(|_| format!("invalid --file-cache-size '{}'", value))?
+                    };
+                }
+                Long("etag") => {
+                    etag_mode = EtagMode::parse(&parser.value()?.to_string_lossy())?;
+                }
+                Long("etag-index") => {
+                    etag_index = Some(PathBuf::from(parser.value()?));
+                }
+                Long("mime-types") => {
+                    let path = PathBuf::from(parser.value()?);
+                    if !path.is_file() {
+                        return Err(format!("--mime-types file not found: {}", path.display()).into());
+                    }
+                    mime.files.push(path);
+                }
+                Long("mime-types-for") => {
+                    let value = parser.value()?.to_string_lossy().to_string();
+                    let Some((domain, path)) = value.split_once('=') else {
+                        return Err(format!("Invalid --mime-types-for '{}': expected DOMAIN=FILE", value).into());
+                    };
+                    let path = PathBuf::from(path);
+                    if !path.is_file() {
+                        return Err(format!("--mime-types-for file not found: {}", path.display()).into());
+                    }
+                    mime.domain_files.push((domain.to_string(), path));
+                }
+                Long("system-mime-types") => {
+                    let value = parser.value()?.to_string_lossy().to_string();
+                    mime.system_file = if value == "off" { None } else { Some(PathBuf::from(value)) };
+                }
+                Long("default-type") => {
+                    mime.default_type = parser.value()?.to_string_lossy().to_string();
+                }
+                Long("charset") => {
+                    let value = parser.value()?.to_string_lossy().to_string();
+                    mime.charset = if value == "off" { None } else { Some(value) };
+                }
+                Long("mime-sniff") => {
+                    mime.sniff = true;
+                }
+                Long("cert") => {
+                    static_certs.push(CertSpec::parse(&parser.value()?.to_string_lossy())?);
+                }
+                Long("default-cert") => {
+                    let value = parser.value()?.to_string_lossy().to_string();
+                    default_cert = Some(static_certs::parse_pair(&value)
+                        .ok_or_else(|| format!("Invalid --default-cert '{}': expected CERT,KEY", value))?);
+                }
+                Long("no-ocsp") => {
+                    ocsp.enabled = false;
+                }
+                Long("ocsp-must-staple") => {
+                    ocsp.must_staple = true;
+                }
+                Long("ocsp-responder") => {
+                    let value = parser.value()?.to_string_lossy().to_string();
+                    if !value.starts_with("http://") {
+                        return Err(format!("Invalid --ocsp-responder '{}': expected an http:// URL", value).into());
+                    }
+                    ocsp.responder = Some(value);
+                }
+                Long("tls-min-version") => {
+                    tls.min_version = TlsVersion::parse(&parser.value()?.to_string_lossy())?;
+                }
+                Long("tls-ciphers") => {
+                    tls.cipher_suites = tls_policy::parse_list(&parser.value()?.to_string_lossy());
+                }
+                Long("tls-groups") => {
+                    tls.kx_groups = tls_policy::parse_list(&parser.value()?.to_string_lossy());
+                }
+                Long("alpn") => {
+                    tls.alpn = tls_policy::parse_alpn(&parser.value()?.to_string_lossy())?;
+                }
+                Long("tls-session-cache") => {
+                    tls.session_cache = parser.value()?.parse()?;
+                }
+                Long("no-tls-tickets") => {
+                    tls.tickets = false;
+                }
+                Long("tls-early-data") => {
+                    tls.early_data = parser.value()?.parse()?;
+                }
+                Long("client-ca") => {
+                    client_auth.ca_file = Some(PathBuf::from(parser.value()?));
+                }
+                Long("client-auth") => {
+                    client_auth.rules.push(ClientAuthRule::parse(&parser.value()?.to_string_lossy())?);
+                }
+                Long("admin-client-cert") => {
+                    client_auth.admin_identities.push(parser.value()?.to_string_lossy().to_string());
+                }
+                Long("dns-provider") => {
+                    dns01.provider = Some(ProviderSpec::parse(&parser.value()?.to_string_lossy())?);
+                }
+                Long("dns-zone") => {
+                    dns01.zone = Some(parser.value()?.to_string_lossy().to_string());
+                }
+                Long("dns-tsig-key") => {
+                    dns01.tsig_key = Some(TsigKey::parse(&parser.value()?.to_string_lossy())?);
+                }
+                Long("dns-ttl") => {
+                    dns01.ttl = parser.value()?.parse()?;
+                }
+                Long("dns-propagation-wait") => {
+                    dns01.propagation_wait = Duration::from_secs(parser.value()?.parse()?);
+                }
                 Long("help") => {
                     println!("easyp - On-demand HTTPS server with ACME certificate management");
                     println!();
@@ -276,8 +623,85 @@ impl Args {
                     println!("        --bogus-domain <DOMAIN>           Bogus domain to use for ACME requests (workaround for rate limits)");
                     println!("        --acme-directory <URL>            ACME directory URL (legacy, use --staging instead) [default: https://acme-v02.api.letsencrypt.org/directory]");
                     println!("        --acme-email <EMAIL>              Email address for ACME account (legacy, use --email instead)");
-                    println!("        --challenge-type <TYPE>           Challenge type (http01 or dns01) [default: http01]");
+                    println!("        --challenge-type <TYPE>           Challenge type: auto, http01, tls-alpn01 or dns01 [default: auto,");
+                    println!("                                          http01 when port 80 is reachable, else tls-alpn01 on port 443]");
                     println!("        --admin-urls                      Print admin URLs for all domains and admin keys, then exit");
+                    println!("        --root-template <TEMPLATE>        Per-domain document root, {{domain}} is replaced by the host [default: /var/www/{{domain}}]");
+                    println!("        --vhost-alias <DOMAIN=ALIASES>    Serve comma-separated ALIASES from DOMAIN's document root (repeatable)");
+                    println!("        --wildcard-vhost <SPEC>           Map *.DOMAIN=TEMPLATE, {{sub}} is replaced by the subdomain label (repeatable)");
+                    println!("        --canonical-host <POLICY>         Redirect to canonical host: none, www-to-apex or apex-to-www [default: none]");
+                    println!("        --unknown-host <POLICY>           Unknown hosts: serve (default root), 421 or 404 [default: serve]");
+                    println!("        --index <FILES>                   Directory index files, comma-separated [default: index.html,index.htm]");
+                    println!("        --try-files <[PREFIX=]CHAIN>      try_files chain, e.g. '/docs=$uri,$uri.html,$uri/,=404' (repeatable)");
+                    println!("        --clean-urls                      Serve /page from page.html and redirect /page.html to /page");
+                    println!("        --spa <SHELL>                     Serve SHELL (e.g. /index.html) for unknown non-asset paths");
+                    println!("        --proxy-pass <ROUTE>              Proxy [HOST][/PREFIX]=URL to http://HOST:PORT[/PATH] or unix:/PATH (repeatable)");
+                    println!("        --proxy-connect-timeout <SECS>    Upstream connect timeout [default: 5]");
+                    println!("        --proxy-timeout <SECS>            Upstream idle read/write timeout [default: 60]");
+                    println!("        --upstream <NAME=URLS>            Upstream group of comma-separated URLs, used as upstream:NAME in --proxy-pass (repeatable)");
+                    println!("        --upstream-policy <NAME=POLICY>   Group policy: round-robin, least-conn or ip-hash [default: round-robin]");
+                    println!("        --health-check <NAME=PATH[,SECS]> Actively check PATH on every group member [default interval: 10]");
+                    println!("        --max-fails <N>                   Consecutive failures before a member is ejected, 0 disables [default: 3]");
+                    println!("        --fail-timeout <SECS>             How long an ejected member is skipped [default: 30]");
+                    println!("        --fastcgi <RULE>                  Run [HOST/]*.EXT or [HOST]/SCRIPT via FastCGI at HOST:PORT or unix:/PATH (repeatable)");
+                    println!("        --fastcgi-index <FILE>            Script run for directory requests under *.EXT rules [default: index.php]");
+                    println!("        --fastcgi-timeout <SECS>          FastCGI backend idle read/write timeout [default: 60]");
+                    println!("        --cgi-dir <TEMPLATE>              Run executables in this cgi-bin directory as CGI scripts, {{domain}} is replaced by the host");
+                    println!("        --cgi-timeout <SECS>              Kill CGI scripts running longer than this [default: 30]");
+                    println!("        --cgi-max-output <BYTES>          Maximum CGI script output [default: 16777216]");
+                    println!("        --ws-max-message <BYTES>          Largest WebSocket message accepted from a client [default: 65536]");
+                    println!("        --ws-ping-interval <SECS>         WebSocket keepalive ping interval, idle connections close after 2.5x [default: 30]");
+                    println!("        --ws-max-connections <N>          Maximum open WebSocket connections [default: 256]");
+                    println!("        --access-log <PATH|off>           Access log file [default: access.log next to server.log]");
+                    println!("        --access-log-format <FORMAT>      combined, common, json or an Apache-style format string [default: combined]");
+                    println!("        --access-log-per-domain <TEMPLATE> Log configured hosts to per-host files instead, {{domain}} is the site name");
+                    println!("        --anonymize-ip                    Log client addresses truncated to /24 (IPv4) or /48 (IPv6)");
+                    println!("        --log-max-size <SIZE|off>         Rotate server and access logs at this size, e.g. 50M [default: 100M]");
+                    println!("        --log-rotate-daily                Also rotate logs at the first write of each UTC day");
+                    println!("        --log-keep <N>                    Rotated generations to keep (name.log.1 is newest) [default: 7]");
+                    println!("        --no-log-compress                 Keep rotated generations uncompressed instead of gzipping them");
+                    println!("                                          SIGUSR1 reopens all log files (for external logrotate)");
+                    println!("        --log-level <SPEC>                Level per subsystem, e.g. 'info,tls=debug,acme=trace' [default: info]");
+                    println!("                                          Targets: server, tls, acme, http, files, proxy, fastcgi, cgi, websocket, extensions, admin, stats");
+                    println!("        --log-format <text|json>          Console log line format; SIGUSR2 toggles debug for all targets [default: text]");
+                    println!("        --metrics-listen <ADDR>           Serve Prometheus metrics at http://ADDR/metrics, e.g. 127.0.0.1:9100");
+                    println!("                                          (also available under the metrics admin key)");
+                    println!("        --stats-retention <SPEC>          Traffic statistics buckets kept per resolution, 0 disables one");
+                    println!("                                          [default: minute=1440,hour=744,day=400,month=120]");
+                    println!("        --health-path <PREFIX|off>        Also serve PREFIX/healthz and PREFIX/readyz, without details, on every site [default: off]");
+                    println!("        --health-listen <ADDR>            Serve /healthz and /readyz with details on a plain HTTP listener, e.g. 127.0.0.1:8081");
+                    println!("                                          (probe over HTTP or this listener; HTTPS probes still need a certificate)");
+                    println!("        --file-cache-size <SIZE|0>        Memory for small hot files and constant extension pages, 0 disables [default: 64M]");
+                    println!("        --etag <metadata|content>         Derive ETags from size and mtime, or from an MD5 of the file [default: metadata]");
+                    println!("        --etag-index <PATH>               Keep content digests in PATH so restarts need not rehash large files");
+                    println!("        --mime-types <FILE>               Extra mime.types file (repeatable), applied after the built-in types");
+                    println!("        --mime-types-for <DOMAIN=FILE>    mime.types overrides for a single domain (repeatable)");
+                    println!("        --system-mime-types <PATH|off>    System extension table to start from [default: /etc/mime.types]");
+                    println!("        --default-type <TYPE>             Content-Type for unrecognised files [default: application/octet-stream]");
+                    println!("        --charset <CHARSET|off>           Charset added to text, JavaScript, JSON and XML types [default: utf-8]");
+                    println!("        --mime-sniff                      Guess the type of extension-less files from their first bytes");
+                    println!("        --cert <PATTERN=CERT,KEY>         Serve PEM files for a host or *.wildcard instead of ACME (repeatable)");
+                    println!("        --default-cert <CERT,KEY>         PEM certificate for clients that send no SNI");
+                    println!("        --no-ocsp                         Do not fetch and staple OCSP responses");
+                    println!("        --ocsp-must-staple                Refuse to serve a certificate without a fresh OCSP response");
+                    println!("        --ocsp-responder <URL>            Send OCSP requests here instead of the certificate's responder");
+                    println!("        --tls-min-version <1.2|1.3>       Lowest TLS version accepted [default: 1.2]");
+                    println!("        --tls-ciphers <LIST>              Comma-separated cipher suites to allow, e.g. TLS13_AES_256_GCM_SHA384");
+                    println!("        --tls-groups <LIST>               Comma-separated key exchange groups in preference order, e.g. X25519,secp256r1");
+                    println!("        --alpn <LIST>                     ALPN protocols to offer: http/1.1, http/1.0 [default: http/1.1]");
+                    println!("        --tls-session-cache <N>           Sessions kept for resumption, 0 to disable [default: 4096]");
+                    println!("        --no-tls-tickets                  Do not issue session tickets");
+                    println!("        --tls-early-data <BYTES>          Accept up to BYTES of 0-RTT data (replayable) [default: 0, off]");
+                    println!("        --client-ca <PEM>                 CA bundle that client certificates are verified against");
+                    println!("        --client-auth <MODE:TARGET>       Ask for a client certificate, MODE required or optional, TARGET");
+                    println!("                                          HOST, /PATH or HOST/PATH, e.g. required:admin.example.com (repeatable)");
+                    println!("        --admin-client-cert <IDENTITY>    Only allow admin panels for this client certificate CN, SAN or subject (repeatable)");
+                    println!("        --dns-provider <SPEC>             Publish DNS-01 TXT records with rfc2136:SERVER[:PORT], exec:SCRIPT");
+                    println!("                                          (called with add|remove NAME VALUE) or zonefile:PATH");
+                    println!("        --dns-zone <ZONE>                 Zone that RFC 2136 updates are sent for");
+                    println!("        --dns-tsig-key <NAME:SECRET>      TSIG key (HMAC-SHA256, base64 secret) signing RFC 2136 updates");
+                    println!("        --dns-ttl <SECS>                  TTL of the TXT records [default: 60]");
+                    println!("        --dns-propagation-wait <SECS>     Wait after publishing a record before validation [default: 30]");
                     println!("        --help                            Print help information");
                     println!();
                     println!("Non-root usage:");
@@ -293,6 +717,25 @@ impl Args {
             }
         }
 
+        // Clean URLs only add a default chain, so explicit --try-files for "/" wins regardless of order
+        if clean_urls {
+            try_files.enable_clean_urls();
+        }
+
+        // Groups are only complete once every --upstream* flag has been seen
+        proxy.set_upstreams(UpstreamRegistry::new(&upstreams)?)?;
+
+        if ocsp.must_staple && !ocsp.enabled {
+            return Err("--ocsp-must-staple cannot be combined with --no-ocsp".into());
+        }
+        client_auth.validate()?;
+        dns01.validate()?;
+        match (challenge_type, &dns01.provider) {
+            (ChallengePreference::Only(ChallengeKind::Dns01), None) => return Err("--challenge-type dns01 needs a --dns-provider".into()),
+            (ChallengePreference::Only(ChallengeKind::Dns01), Some(_)) | (_, None) => {}
+            (_, Some(_)) => return Err("--dns-provider is only used with --challenge-type dns01".into()),
+        }
+
         // Domains are optional for on-demand HTTPS server
         // The server can discover domains dynamically from certificate requests
 
@@ -325,6 +768,31 @@ impl Args {
             acme_email,
             challenge_type,
             admin_urls,
+            vhosts,
+            try_files,
+            proxy,
+            fastcgi,
+            cgi,
+            websocket,
+            access_log,
+            access_log_off,
+            log_rotation,
+            log_levels,
+            log_output,
+            metrics_listen,
+            stats_retention,
+            health_path,
+            health_listen,
+            file_cache_size,
+            etag_mode,
+            etag_index,
+            mime,
+            static_certs,
+            default_cert,
+            ocsp,
+            tls,
+            client_auth,
+            dns01,
         })
     }
 }
@@ -335,12 +803,12 @@ impl Args {
 struct TestCertResolver {
     allowed_ips: Vec<IpAddr>,
     cert_cache: Arc<Mutex<HashMap<String, CertifiedKey>>>,
-    default_cert: Option<CertifiedKey>,
+    default_cert: Option<WatchedCert>,
 }
 
 impl TestCertResolver {
     fn new(allowed_ips: Vec<IpAddr>) -> Result<Self, Box<dyn std::error::Error>> {
-        println!("🔍 Initializing TestCertResolver with self-signed certificate...");
+        log::debug!(target: "tls", "initializing TestCertResolver with self-signed certificate...");
 
         // Try to load existing self-signed certificate using user-appropriate paths
         let (cert_path, key_path) = if is_running_as_root() {
@@ -354,39 +822,42 @@ impl TestCertResolver {
         };
 
         let default_cert = if std::path::Path::new(&cert_path).exists() && std::path::Path::new(&key_path).exists() {
-            println!("🔍 Loading existing self-signed certificate from {}", cert_path);
+            log::debug!(target: "tls", "loading existing self-signed certificate from {}", cert_path);
             match Self::load_certificate_from_files(&cert_path, &key_path) {
                 Ok(cert) => {
-                    println!("✅ Successfully loaded existing self-signed certificate");
+                    log::info!(target: "tls", "successfully loaded existing self-signed certificate");
                     Some(cert)
                 }
                 Err(e) => {
-                    println!("⚠️  Failed to load existing certificate: {}, will create new one", e);
+                    log::warn!(target: "tls", "failed to load existing certificate: {}, will create new one", e);
                     None
                 }
             }
         } else {
-            println!("🔍 No existing certificate found, creating new self-signed certificate...");
+            log::debug!(target: "tls", "no existing certificate found, creating new self-signed certificate...");
             None
         };
 
         let default_cert = match default_cert {
             Some(cert) => Some(cert),
             None => {
-                println!("🔍 Generating new self-signed certificate for localhost...");
+                log::debug!(target: "tls", "generating new self-signed certificate for localhost...");
                 match Self::generate_self_signed_certificate("localhost") {
                     Ok(cert) => {
-                        println!("✅ Successfully generated new self-signed certificate");
+                        log::info!(target: "tls", "successfully generated new self-signed certificate");
                         Some(cert)
                     }
                     Err(e) => {
-                        println!("❌ Failed to generate self-signed certificate: {}", e);
+                        log::error!(target: "tls", "failed to generate self-signed certificate: {}", e);
                         return Err(e);
                     }
                 }
             }
         };
 
+        // Watch the files so a replaced certificate is picked up without a restart
+        let default_cert = default_cert.map(|cert| WatchedCert::new(&cert_path, &key_path, cert));
+
         Ok(Self {
             allowed_ips,
             cert_cache: Arc::new(Mutex::new(HashMap::new())),
@@ -394,8 +865,16 @@ impl TestCertResolver {
         })
     }
 
+    /// Create the resolver and register it with the certificate reloader
+    fn shared(allowed_ips: Vec<IpAddr>, reloaders: &mut Vec<Weak<dyn ReloadCerts>>) -> Result<Arc<dyn ResolvesServerCert + Send + Sync>, Box<dyn std::error::Error>> {
+        let resolver = Arc::new(Self::new(allowed_ips)?);
+        let reloader: Arc<dyn ReloadCerts> = resolver.clone();
+        reloaders.push(Arc::downgrade(&reloader));
+        Ok(resolver)
+    }
+
     fn load_certificate_from_files(cert_path: &str, key_path: &str) -> Result<CertifiedKey, Box<dyn std::error::Error>> {
-        use rustls_pemfile::{certs, pkcs8_private_keys};
+        use rustls_pemfile::{certs, private_key};
         use std::io::BufReader;
 
         let cert_file = std::fs::File::open(cert_path)?;
@@ -406,18 +885,14 @@ impl TestCertResolver {
             .collect();
         let cert_chain = cert_chain?;
 
-        let key_file = std::fs::File::open(key_path)?;
-        let mut key_reader = BufReader::new(key_file);
-        let keys: Result<Vec<_>, _> = pkcs8_private_keys(&mut key_reader).collect();
-        let mut keys = keys?;
-
-        if keys.is_empty() {
-            return Err("No private key found in key file".into());
+        if cert_chain.is_empty() {
+            return Err("No certificate found in certificate file".into());
         }
 
-        let key = rustls::pki_types::PrivateKeyDer::Pkcs8(
-            rustls::pki_types::PrivatePkcs8KeyDer::from(keys.remove(0))
-        );
+        // PKCS#8, PKCS#1 (RSA) and SEC1 (EC) keys, as issued by most CAs
+        let key_file = std::fs::File::open(key_path)?;
+        let mut key_reader = BufReader::new(key_file);
+        let key = private_key(&mut key_reader)?.ok_or("No private key found in key file")?;
 
         // Create the CertifiedKey using the correct API
         let certified_key = rustls::sign::CertifiedKey::from_der(
@@ -440,9 +915,9 @@ impl TestCertResolver {
             let home_dir = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
             format!("{}/.local/share/easyp/certs/{}", home_dir, domain)
         };
-        println!("DEBUG: Attempting to create certificate directory: {}", cert_dir);
+        log::debug!(target: "tls", "attempting to create certificate directory: {}", cert_dir);
         file_ops::create_dir_all(&cert_dir)?;
-        println!("DEBUG: Certificate directory created successfully");
+        log::debug!(target: "tls", "certificate directory created successfully");
 
         // Generate ECDSA key pair (more commonly supported)
         let key_pair = KeyPair::generate()?;
@@ -502,7 +977,7 @@ impl TestCertResolver {
             file_ops::set_permissions(format!("{}/privkey.pem", cert_dir), key_perms)?;
         }
 
-        println!("✅ Self-signed certificate generated for {} in {}", domain, cert_dir);
+        log::info!(target: "tls", "self-signed certificate generated for {} in {}", domain, cert_dir);
 
         // Convert to CertifiedKey format
         let cert_der = cert.der().to_vec();
@@ -529,13 +1004,14 @@ impl ResolvesServerCert for TestCertResolver {
         let Some(server_name) = client_hello.server_name() else {
             // If no server name, use the default certificate
             if let Some(ref default_cert) = self.default_cert {
-                return default_cert.signer(client_hello.signature_schemes())
+                return default_cert.current().signer(client_hello.signature_schemes())
                     .ok_or(rustls::Error::NoSuitableCertificate);
             }
             return Err(rustls::Error::NoSuitableCertificate);
         };
 
-        let domain = server_name.as_ref();
+        let domain = normalize_hostname(server_name.as_ref()).unwrap_or_else(|_| server_name.as_ref().to_string());
+        let domain = domain.as_str();
 
         // Check cache first
         if let Ok(cache) = self.cert_cache.lock() {
@@ -549,20 +1025,46 @@ impl ResolvesServerCert for TestCertResolver {
         // This ensures we never create new certificates after startup
         if let Some(ref default_cert) = self.default_cert {
             // Just return the signer directly - no need to cache since we only have one cert
-            return default_cert.signer(client_hello.signature_schemes())
+            return default_cert.current().signer(client_hello.signature_schemes())
                 .ok_or(rustls::Error::NoSuitableCertificate);
         }
 
-        println!("❌ No default certificate available for domain: {}", domain);
+        log::error!(target: "tls", "no default certificate available for domain: {}", domain);
         Err(rustls::Error::NoSuitableCertificate)
     }
 }
 
+impl ReloadCerts for TestCertResolver {
+    fn reload_certs(&self) -> usize {
+        let loader = |cert: &str, key: &str| Self::load_certificate_from_files(cert, key).map_err(|e| e.to_string());
+        self.default_cert.as_ref().map_or(0, |cert| cert.reload_if_changed(&loader) as usize)
+    }
+}
+
+/// Resolver that has the DNS-01 solver watch every name a handshake asks for, since the
+/// inner on-demand resolver may have started an order for it
+#[derive(Debug)]
+struct Dns01Resolver {
+    solver: Arc<Dns01Solver>,
+    inner: Arc<dyn ResolvesServerCert + Send + Sync>,
+}
+
+impl ResolvesServerCert for Dns01Resolver {
+    fn resolve(&self, client_hello: &rustls::server::ClientHello<'_>) -> Result<rustls::sign::CertifiedSigner, rustls::Error> {
+        if let Some(server_name) = client_hello.server_name() {
+            if let Ok(domain) = normalize_hostname(server_name.as_ref()) {
+                self.solver.watch(&domain);
+            }
+        }
+        self.inner.resolve(client_hello)
+    }
+}
+
 /// On-demand HTTPS server
 struct OnDemandHttpsServer {
     http_listener: tokio::net::TcpListener,  // Port 80 for ACME challenges
     https_listener: tokio::net::TcpListener, // Port 443 for HTTPS traffic
-    cert_resolver: Arc<dyn ResolvesServerCert + Send + Sync>,
+    tls_configs: Arc<ServerConfigs>, // Shared by all HTTPS connections (session cache, tickets)
     args: Args,
     http_challenges: Arc<Mutex<BTreeMap<String, String>>>, // token -> key_authorization
     #[cfg(feature = "acme")]
@@ -571,7 +1073,13 @@ struct OnDemandHttpsServer {
     extension_registry: Arc<Mutex<ExtensionRegistry>>, // Extension system
     secure_file_server: SecureFileServer, // Secure file serving with security features
     stats_collector: Arc<HourlyStatsCollector>, // Hourly statistics collection
+    reverse_proxy: Arc<ProxyConfig>, // proxy_pass routes to upstream backends
+    fastcgi: Arc<FastCgiConfig>, // FastCGI rules with pooled backend connections
+    cgi: Arc<CgiExecConfig>, // External CGI scripts in per-domain cgi-bin directories
+    websocket: Arc<WsConfig>, // Limits for /ws/<name> extension endpoints
+    client_auth: Arc<ClientAuthConfig>, // Client certificate rules and admin identities
     port_80_available: bool, // Whether port 80 is available for ACME challenges
+    acme_challenge: Option<ChallengeKind>, // Challenge answered for ACME, None when serving self-signed certificates
 }
 
 impl OnDemandHttpsServer {
@@ -606,14 +1114,14 @@ impl OnDemandHttpsServer {
         };
 
         // Create HTTP listener on specified port for ACME challenges
-        println!("DEBUG: Attempting to bind HTTP listener to 0.0.0.0:{}", http_port);
+        log::debug!(target: "server", "attempting to bind HTTP listener to 0.0.0.0:{}", http_port);
         let http_listener = network_ops::bind_tcp_listener(&format!("0.0.0.0:{}", http_port)).await?;
-        println!("DEBUG: HTTP listener bound successfully");
+        log::debug!(target: "server", "HTTP listener bound successfully");
 
         // Create HTTPS listener on specified port for HTTPS traffic
-        println!("DEBUG: Attempting to bind HTTPS listener to 0.0.0.0:{}", final_https_port);
+        log::debug!(target: "server", "attempting to bind HTTPS listener to 0.0.0.0:{}", final_https_port);
         let https_listener = network_ops::bind_tcp_listener(&format!("0.0.0.0:{}", final_https_port)).await?;
-        println!("DEBUG: HTTPS listener bound successfully");
+        log::debug!(target: "server", "HTTPS listener bound successfully");
 
         // Look up www-data UID/GID dynamically (only on UNIX systems and when running as root)
         #[cfg(unix)]
@@ -662,7 +1170,11 @@ impl OnDemandHttpsServer {
 */
                 // System files
                 "htaccess".to_string(), "htpasswd".to_string(),
-            ],
+            ]
+            .into_iter()
+            // Scripts with a FastCGI rule are never sent as source, however the URL is spelled
+            .chain(args.fastcgi.script_extensions())
+            .collect(),
             #[cfg(unix)]
             drop_to_uid: if is_running_as_root() { Some(www_data_uid) } else { None },
             #[cfg(unix)]
@@ -674,6 +1186,12 @@ impl OnDemandHttpsServer {
             keep_alive_timeout: Duration::from_secs(5),
             keep_alive_max_requests: 100,
             minimum_http_version: HttpVersion::Http09,
+            vhosts: args.vhosts.clone(),
+            try_files: args.try_files.clone(),
+            file_cache_size: args.file_cache_size,
+            etag_mode: args.etag_mode,
+            etag_index: args.etag_index.clone(),
+            mime: args.mime.clone(),
         });
 
                #[cfg(target_os = "redox")]
@@ -683,16 +1201,15 @@ impl OnDemandHttpsServer {
                let allowed_ips = if let Some(ips_str) = &args.allowed_ips {
                    parse_allowed_ips(ips_str)?
                } else {
-                   println!("[{}] No allowed IPs specified, auto-detecting server IPs...",
-                 std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs());
+                   log::info!(target: "server", "no allowed IPs specified, auto-detecting server IPs...");
                    let detected_ips = detect_server_ips().unwrap_or_else(|e| {
-                       println!("Warning: Could not detect server IPs ({}), returning empty list", e);
+                       log::warn!(target: "server", "could not detect server IPs ({}), returning empty list", e);
                        Vec::new()
                    });
 
                    if detected_ips.is_empty() {
-                       eprintln!("Error: No valid IP addresses detected. Please specify --allowed-ips explicitly.");
-                       eprintln!("This is required for security - localhost addresses are not allowed for ACME requests.");
+                       log::error!(target: "server", "no valid IP addresses detected. Please specify --allowed-ips explicitly.");
+                       log::error!(target: "server", "this is required for security - localhost addresses are not allowed for ACME requests.");
                        std::process::exit(1);
                    }
 
@@ -704,62 +1221,86 @@ impl OnDemandHttpsServer {
                    // Ensure certificate cache directory has proper ownership before dropping privileges
                    #[cfg(unix)]
                    if let Err(e) = ensure_cert_cache_permissions(&args.cache_dir, www_data_uid, www_data_gid) {
-                       println!("Warning: Failed to set certificate cache permissions: {}", e);
+                       log::warn!(target: "tls", "failed to set certificate cache permissions: {}", e);
                        // Continue anyway - this is not a fatal error
                    }
 
                    // Ensure /tmp/acme_certs directory exists and is owned by www-data (acme-lib requirement)
                    #[cfg(unix)]
                    if let Err(e) = ensure_tmp_acme_permissions(www_data_uid, www_data_gid) {
-                       println!("Warning: Failed to set /tmp/acme_certs permissions: {}", e);
+                       log::warn!(target: "acme", "failed to set /tmp/acme_certs permissions: {}", e);
                        // Continue anyway - this is not a fatal error
                    }
 
                    // Ensure ACME cache directory is properly configured and accessible
-                   println!("ACME cache directory: {}", args.cache_dir);
+                   log::info!(target: "acme", "ACME cache directory: {}", args.cache_dir);
                    #[cfg(unix)]
                    if let Err(e) = ensure_acme_cache_directory(&args.cache_dir, www_data_uid, www_data_gid) {
-                       println!("Error: Failed to set up ACME cache directory: {}", e);
+                       log::error!(target: "acme", "failed to set up ACME cache directory: {}", e);
                        return Err(format!("ACME cache directory setup failed: {}", e).into());
                    }
 
 
                } else {
                    // For non-root users, just create the directory without privilege operations
-                   println!("ACME cache directory: {}", args.cache_dir);
+                   log::info!(target: "acme", "ACME cache directory: {}", args.cache_dir);
                    if let Err(e) = file_ops::create_dir_all(&args.cache_dir) {
-                       println!("Error: Failed to create ACME cache directory: {}", e);
+                       log::error!(target: "acme", "failed to create ACME cache directory: {}", e);
                        return Err(format!("ACME cache directory creation failed: {}", e).into());
                    }
 
                    // Create the acme_lib subdirectory
                    let acme_lib_dir = format!("{}/acme_lib", args.cache_dir);
                    if let Err(e) = file_ops::create_dir_all(&acme_lib_dir) {
-                       println!("Error: Failed to create ACME lib directory: {}", e);
+                       log::error!(target: "acme", "failed to create ACME lib directory: {}", e);
                        return Err(format!("ACME lib directory creation failed: {}", e).into());
                    }
 
-                   println!("ACME cache directory created: {}", args.cache_dir);
-                   println!("ACME lib directory created: {}", acme_lib_dir);
+                   log::info!(target: "acme", "ACME cache directory created: {}", args.cache_dir);
+                   log::info!(target: "acme", "ACME lib directory created: {}", acme_lib_dir);
                }
 
-               // Check if port 80 is available for ACME challenges
-               let port_80_available = is_port_80_available().await;
+               // ACME servers only validate on port 80 (HTTP-01) and port 443 (TLS-ALPN-01)
+               let acme_challenge = select_challenge(args.challenge_type, port_80_available && http_port == 80, final_https_port == 443);
 
-               if !port_80_available {
-                   println!("⚠️  Port 80 is not available. ACME certificate generation will not be possible.");
-                   println!("ℹ️  Using self-signed certificates instead. Run as root or ensure port 80 is available for real certificates.");
+               match acme_challenge {
+                   Some(kind) => log::info!(target: "acme", "answering {} ACME challenges (--challenge-type {})", kind, args.challenge_type),
+                   None => {
+                       log::warn!(target: "tls", "no port is available for {} ACME challenges. ACME certificate generation will not be possible.", args.challenge_type);
+                       log::info!(target: "tls", "using self-signed certificates instead. Run as root or ensure port 80 or 443 is available for real certificates.");
+                   }
                }
 
+               // Resolvers that watch their certificate files, polled by a background thread
+               let mut cert_reloaders: Vec<Weak<dyn ReloadCerts>> = Vec::new();
+
+               // DNS-01 records left behind by a crash are withdrawn before any new order
+               #[cfg(feature = "acme")]
+               let dns01_solver = match acme_challenge {
+                   Some(ChallengeKind::Dns01) => {
+                       let provider = args.dns01.build_provider()?;
+                       let state_file = PathBuf::from(format!("{}/dns01-records", args.cache_dir));
+                       let solver = Arc::new(Dns01Solver::new(provider, args.dns01.propagation_wait, Some(state_file)));
+                       let removed = solver.remove_stale().await;
+                       if removed > 0 {
+                           log::info!(target: "acme", "removed {} stale DNS-01 records", removed);
+                       }
+                       Some(solver)
+                   }
+                   _ => None,
+               };
+
                // Create certificate resolver with real ACME integration
                #[cfg(feature = "acme")]
-               let (cert_resolver, acme_client) = if args.test_mode || !port_80_available {
-                   if !port_80_available {
-                       // Port 80 not available, use self-signed certificates
-                       println!("🔒 Using self-signed certificates (port 80 not available for ACME challenges)");
-                       (Arc::new(TestCertResolver::new(allowed_ips.clone())?) as Arc<dyn ResolvesServerCert + Send + Sync>, None)
-                   } else {
-                       // In test mode with port 80 available, use staging ACME servers for real certificates
+               let (cert_resolver, acme_client) = if args.test_mode || acme_challenge.is_none() {
+                   match acme_challenge {
+                   None => {
+                       // No challenge can be answered, use self-signed certificates
+                       log::info!(target: "tls", "using self-signed certificates (ports 80 and 443 not available for ACME challenges)");
+                       (TestCertResolver::shared(allowed_ips.clone(), &mut cert_reloaders)?, None)
+                   }
+                   Some(challenge) => {
+                       // In test mode with a reachable challenge port, use staging ACME servers for real certificates
                        let directory_url = "https://acme-staging-v02.api.letsencrypt.org/directory".to_string();
 
                        // Determine email (prefer new --email over legacy --acme-email)
@@ -776,7 +1317,7 @@ impl OnDemandHttpsServer {
                            allowed_ips: allowed_ips.clone(),
                            cache_dir,
                            renewal_threshold_days: 30,
-                           challenge_type: ChallengeType::Http01("".to_string(), "".to_string()),
+                           challenge_type: acme_challenge_type(challenge),
                            is_staging: true, // Always staging in test mode
                            bogus_domain: args.bogus_domain.clone(),
                        };
@@ -787,9 +1328,9 @@ impl OnDemandHttpsServer {
                        // Warn about ACME limitations for non-root users
                        #[cfg(unix)]
                        if !is_running_as_root() {
-                           println!("⚠️  Running as non-root user. ACME certificate generation may not work properly.");
-                           println!("ℹ️  ACME challenges require binding to port 80, which needs root privileges.");
-                           println!("ℹ️  Consider running with sudo for production use or use --over-9000 for development.");
+                           log::warn!(target: "tls", "running as non-root user. ACME certificate generation may not work properly.");
+                           log::info!(target: "acme", "ACME challenges require binding to port 80, which needs root privileges.");
+                           log::info!(target: "server", "consider running with sudo for production use or use --over-9000 for development.");
                        }
 
                        let dns_validator = Arc::new(DnsValidator::new(allowed_ips.clone())?);
@@ -804,13 +1345,16 @@ impl OnDemandHttpsServer {
 
                        (cert_resolver as Arc<dyn ResolvesServerCert + Send + Sync>, Some(acme_client))
                    }
+                   }
                } else {
-                   if !port_80_available {
-                       // Port 80 not available, use self-signed certificates even in production mode
-                       println!("🔒 Using self-signed certificates (port 80 not available for ACME challenges)");
-                       (Arc::new(TestCertResolver::new(allowed_ips.clone())?) as Arc<dyn ResolvesServerCert + Send + Sync>, None)
-                   } else {
-                       // Port 80 available, use real ACME certificates
+                   match acme_challenge {
+                   None => {
+                       // No challenge can be answered, use self-signed certificates even in production mode
+                       log::info!(target: "tls", "using self-signed certificates (ports 80 and 443 not available for ACME challenges)");
+                       (TestCertResolver::shared(allowed_ips.clone(), &mut cert_reloaders)?, None)
+                   }
+                   Some(challenge) => {
+                       // A challenge port is available, use real ACME certificates
                        // Determine ACME directory URL
                        let directory_url = if args.staging {
                            "https://acme-staging-v02.api.letsencrypt.org/directory".to_string()
@@ -837,7 +1381,7 @@ impl OnDemandHttpsServer {
                            allowed_ips: allowed_ips.clone(),
                            cache_dir: cache_dir.clone(),
                            renewal_threshold_days: 30,
-                           challenge_type: ChallengeType::Http01("".to_string(), "".to_string()),
+                           challenge_type: acme_challenge_type(challenge),
                            is_staging: args.staging || args.acme_directory.contains("staging") || args.acme_directory.contains("stg"),
                            bogus_domain: args.bogus_domain.clone(),
                        };
@@ -847,19 +1391,19 @@ impl OnDemandHttpsServer {
                        // Warn about ACME limitations for non-root users
                        #[cfg(unix)]
                        if !is_running_as_root() {
-                           println!("⚠️  Running as non-root user. ACME certificate generation may not work properly.");
-                           println!("ℹ️  ACME challenges require binding to port 80, which needs root privileges.");
-                           println!("ℹ️  Consider running with sudo for production use or use --over-9000 for development.");
+                           log::warn!(target: "tls", "running as non-root user. ACME certificate generation may not work properly.");
+                           log::info!(target: "acme", "ACME challenges require binding to port 80, which needs root privileges.");
+                           log::info!(target: "server", "consider running with sudo for production use or use --over-9000 for development.");
                        }
 
                        // Restore from backup if requested
                        if args.restore_backup {
-                           println!("🔄 Restoring ACME certificates from backup...");
+                           log::info!(target: "acme", "restoring ACME certificates from backup...");
                            let acme_persist_dir = format!("{}/acme_lib", cache_dir.as_ref().unwrap());
                            if let Err(e) = acme_client.restore_acme_data(&acme_persist_dir) {
-                               println!("⚠️  Failed to restore from backup: {}", e);
+                               log::warn!(target: "acme", "failed to restore from backup: {}", e);
                            } else {
-                               println!("✅ ACME certificates restored from backup");
+                               log::info!(target: "acme", "ACME certificates restored from backup");
                            }
                        }
 
@@ -878,40 +1422,109 @@ impl OnDemandHttpsServer {
 
                        (cert_resolver as Arc<dyn ResolvesServerCert + Send + Sync>, Some(acme_client))
                    }
+                   }
                };
 
                #[cfg(not(feature = "acme"))]
                let (cert_resolver, acme_client) = {
                    // Fallback to test resolver if ACME feature not enabled
-                   (Arc::new(TestCertResolver::new(allowed_ips)?), None)
+                   (TestCertResolver::shared(allowed_ips.clone(), &mut cert_reloaders)?, None)
+               };
+
+               // Configured PEM certificates take priority; other names fall through to ACME.
+               // Certificates written to the ACME cache after startup (e.g. by --restore-backup
+               // in another process) are served without a restart.
+               let cert_resolver = if args.static_certs.is_empty() && args.default_cert.is_none() && acme_client.is_none() {
+                   cert_resolver
+               } else {
+                   let mut resolver = StaticCertResolver::load(
+                       &args.static_certs,
+                       args.default_cert.as_ref(),
+                       Some(cert_resolver),
+                       TestCertResolver::load_certificate_from_files,
+                   )?;
+                   // Registered before the cache directory so existing ACME certificates get stapled too
+                   let stapler = args.ocsp.enabled.then(|| OcspStapler::new(args.ocsp.clone()));
+                   if let Some(stapler) = &stapler {
+                       resolver = resolver.with_stapler(stapler.clone());
+                   }
+                   if acme_client.is_some() {
+                       let staging = args.test_mode || args.staging || args.acme_directory.contains("staging") || args.acme_directory.contains("stg");
+                       let subdir = if staging { "staging" } else { "production" };
+                       resolver = resolver.watch_cache_dir(format!("{}/{}", args.cache_dir, subdir));
+                   }
+                   // Started once every file is registered, so the first round fetches them all
+                   if let Some(stapler) = &stapler {
+                       stapler.spawn_refresher();
+                   }
+                   let resolver = Arc::new(resolver);
+                   let reloader: Arc<dyn ReloadCerts> = resolver.clone();
+                   cert_reloaders.push(Arc::downgrade(&reloader));
+                   resolver as Arc<dyn ResolvesServerCert + Send + Sync>
+               };
+               spawn_cert_reloader(cert_reloaders, RELOAD_INTERVAL);
+
+               // Challenges that are not answered on port 80 hook into the certificate resolver;
+               // the ACME client files each key authorization under its domain
+               let mut policy = args.tls.clone();
+               let cert_resolver = match (&acme_client, acme_challenge) {
+                   // TLS-ALPN-01 validation connections get a challenge certificate instead of the normal one
+                   (Some(acme_client), Some(ChallengeKind::TlsAlpn01)) => {
+                       let acme_client = acme_client.clone();
+                       let challenges = Arc::new(TlsAlpnChallenges::new().with_lookup(move |domain| {
+                           let handle = tokio::runtime::Handle::try_current().ok()?;
+                           tokio::task::block_in_place(|| handle.block_on(acme_client.get_challenge_response(domain)))
+                       }));
+                       policy.acme_tls_alpn = true;
+                       Arc::new(TlsAlpnResolver::new(challenges, cert_resolver)) as Arc<dyn ResolvesServerCert + Send + Sync>
+                   }
+                   // DNS-01 records are published while the ACME client has a key authorization
+                   // pending for a domain, and withdrawn once it is gone
+                   (Some(acme_client), Some(ChallengeKind::Dns01)) => {
+                       let solver = dns01_solver.clone().ok_or("DNS-01 solver missing")?;
+                       for domain in &args.domains {
+                           solver.watch(domain);
+                       }
+                       let acme_client = acme_client.clone();
+                       solver.spawn_watcher(move |domain| {
+                           let acme_client = acme_client.clone();
+                           async move { acme_client.get_challenge_response(&domain).await }
+                       });
+                       Arc::new(Dns01Resolver { solver, inner: cert_resolver }) as Arc<dyn ResolvesServerCert + Send + Sync>
+                   }
+                   _ => cert_resolver,
                };
 
+               // One ServerConfig (per client certificate mode) for the life of the server, so
+               // sessions resume across connections
+               let tls_configs = Arc::new(tls_policy::build_server_configs(&policy, cert_resolver, &args.client_auth)?);
+
                // Domain request logger removed - was unused dead code
 
                 // Initialize root extensions and admin system before dropping privileges
                 #[cfg(feature = "extensions")]
                 let extension_registry = {
-                    println!("Initializing root extensions and admin system...");
+                    log::info!(target: "admin", "initializing root extensions and admin system...");
                     let mut registry = ExtensionRegistry::new();
 
                     // Load existing admin keys from file
                     if let Err(e) = registry.load_existing_admin_keys() {
-                        println!("Warning: Failed to load existing admin keys: {}", e);
+                        log::warn!(target: "admin", "failed to load existing admin keys: {}", e);
                     }
 
                     // Generate missing admin keys and append to file
                     if let Err(e) = registry.generate_missing_admin_keys() {
-                        println!("Warning: Failed to generate missing admin keys: {}", e);
+                        log::warn!(target: "admin", "failed to generate missing admin keys: {}", e);
                     }
 
                     // Ensure admin file has correct permissions
                     if let Err(e) = registry.ensure_admin_file_permissions() {
-                        println!("Warning: Failed to set admin file permissions: {}", e);
+                        log::warn!(target: "admin", "failed to set admin file permissions: {}", e);
                     }
 
                     // Initialize root extensions
                     if let Err(e) = registry.initialize_root_extensions() {
-                        println!("Warning: Failed to initialize root extensions: {}", e);
+                        log::warn!(target: "extensions", "failed to initialize root extensions: {}", e);
                     }
 
                     Arc::new(Mutex::new(registry))
@@ -923,7 +1536,7 @@ impl OnDemandHttpsServer {
                // Drop privileges to unprivileged user after binding to privileged ports (only if running as root)
                if is_running_as_root() {
                    if let Err(e) = secure_file_server.drop_privileges() {
-                       println!("Warning: Failed to drop privileges: {}", e);
+                       log::warn!(target: "server", "failed to drop privileges: {}", e);
                        // Continue anyway - this is not a fatal error
                    } else {
                    // Verify that privileges were dropped successfully
@@ -931,18 +1544,18 @@ impl OnDemandHttpsServer {
                        Ok(output) => {
                            if output.status.success() {
                                let username = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                               println!("Server now running as user: {}", username);
+                               log::info!(target: "server", "server now running as user: {}", username);
                            } else {
-                               println!("Warning: Could not determine current user after privilege drop");
+                               log::warn!(target: "server", "could not determine current user after privilege drop");
                            }
                        }
                        Err(_) => {
-                           println!("Warning: Could not determine current user after privilege drop");
+                           log::warn!(target: "server", "could not determine current user after privilege drop");
                        }
                    }
                }
                } else {
-                   println!("Not running as root, skipping privilege dropping");
+                   log::info!(target: "server", "not running as root, skipping privilege dropping");
                }
 
 
@@ -954,10 +1567,18 @@ impl OnDemandHttpsServer {
                    Arc::new(Mutex::new(BTreeMap::new()))
                };
 
+               let reverse_proxy = Arc::new(args.proxy.clone());
+               // The upstreams admin panel reads the live group state from here
+               upstream::install_registry(reverse_proxy.upstreams.clone());
+               let fastcgi = Arc::new(args.fastcgi.clone());
+               let cgi = Arc::new(args.cgi.clone());
+               let websocket = Arc::new(args.websocket.clone());
+               let client_auth = Arc::new(args.client_auth.clone());
+
                Ok(Self {
                    http_listener,
                    https_listener,
-                   cert_resolver,
+                   tls_configs,
                    args,
                    http_challenges,
                    acme_client,
@@ -965,7 +1586,13 @@ impl OnDemandHttpsServer {
                    extension_registry,
                    secure_file_server,
                    stats_collector,
+                   reverse_proxy,
+                   fastcgi,
+                   cgi,
+                   websocket,
+                   client_auth,
                    port_80_available,
+                   acme_challenge,
                })
     }
 
@@ -987,7 +1614,7 @@ impl OnDemandHttpsServer {
             self.args.https_port
         };
 
-        println!("DEBUG: Port calculation - over_9000: {}, args.port: {}, args.https_port: {}, https_port: {}",
+        log::debug!(target: "server", "port calculation - over_9000: {}, args.port: {}, args.https_port: {}, https_port: {}",
                  self.args.over_9000, self.args.port, self.args.https_port, https_port);
 
         let final_https_port = if self.args.over_9000 {
@@ -998,107 +1625,147 @@ impl OnDemandHttpsServer {
             https_port
         };
 
-        println!("DEBUG: Final port calculation - final_https_port: {}", final_https_port);
-        println!("Starting easyp on-demand HTTPS server");
-        if self.port_80_available {
-            println!("HTTP listener on port {} (for ACME challenges)", http_port);
+        log::debug!(target: "server", "final port calculation - final_https_port: {}", final_https_port);
+        log::info!(target: "server", "starting easyp on-demand HTTPS server");
+        if self.acme_challenge == Some(ChallengeKind::Http01) {
+            log::info!(target: "acme", "HTTP listener on port {} (for ACME challenges)", http_port);
         } else {
-            println!("HTTP listener on port {} (for file serving only - no ACME challenges)", http_port);
+            log::info!(target: "acme", "HTTP listener on port {} (for file serving only - no ACME challenges)", http_port);
         }
-        println!("HTTPS listener on port {} (for HTTPS traffic)", final_https_port);
-        println!("Allowed IPs: {:?}", self.allowed_ips);
-        println!("ACME Directory: {}", if self.args.staging { "https://acme-staging-v02.api.letsencrypt.org/directory" } else { &self.args.acme_directory });
-        println!("Challenge Type: {}", self.args.challenge_type);
-        println!("Test Mode: {}", self.args.test_mode);
+        log::info!(target: "server", "HTTPS listener on port {} (for HTTPS traffic)", final_https_port);
+        log::info!(target: "server", "allowed IPs: {:?}", self.allowed_ips);
+        log::info!(target: "acme", "ACME Directory: {}", if self.args.staging { "https://acme-staging-v02.api.letsencrypt.org/directory" } else { &self.args.acme_directory });
+        match self.acme_challenge {
+            Some(kind) => log::info!(target: "acme", "challenge type: {} (--challenge-type {})", kind, self.args.challenge_type),
+            None => log::info!(target: "acme", "challenge type: none (--challenge-type {}), using self-signed certificates", self.args.challenge_type),
+        }
+        log::info!(target: "server", "test mode: {}", self.args.test_mode);
         if !self.args.domains.is_empty() {
-            println!("Domains: {:?}", self.args.domains);
+            log::info!(target: "server", "domains: {:?}", self.args.domains);
         }
         if let Some(ref email) = self.args.email.clone().or(self.args.acme_email.clone()) {
-            println!("Email: {}", email);
+            log::info!(target: "server", "email: {}", email);
+        }
+        log::info!(target: "server", "document root: {}", self.args.root);
+        log::info!(target: "server", "cache directory: {}", self.args.cache_dir);
+        for route in &self.reverse_proxy.routes {
+            log::info!(target: "server", "proxy: {}{} -> {:?}{}", route.host.as_deref().unwrap_or("*"), route.prefix, route.upstream.addr, route.upstream.path);
+        }
+        for group in self.reverse_proxy.upstreams.status() {
+            let members: Vec<&str> = group.servers.iter().map(|s| s.address.as_str()).collect();
+            log::info!(target: "server", "upstream group '{}' ({}): {}", group.name, group.policy, members.join(", "));
+        }
+        self.reverse_proxy.upstreams.spawn_health_checks();
+        for rule in &self.fastcgi.rules {
+            log::info!(target: "fastcgi", "FastCGI: {}{:?} -> {}", rule.host.as_deref().unwrap_or("*"), rule.pattern, rule.backend.addr);
+        }
+        if let Some(ref template) = self.cgi.dir_template {
+            log::info!(target: "cgi", "CGI scripts: /cgi-bin/ -> {}", template);
         }
-        println!("Document Root: {}", self.args.root);
-        println!("Cache Directory: {}", self.args.cache_dir);
 
         // Run test client if specified
         if let Some(ref test_client) = self.args.test_client {
             let test_client = test_client.clone();
-            println!("Running test client: {}", test_client);
+            log::info!(target: "server", "running test client: {}", test_client);
             std::thread::spawn(move || {
                 let output = std::process::Command::new(&test_client)
                     .output()
                     .expect("Failed to execute test client");
-                println!("Test client output: {}", String::from_utf8_lossy(&output.stdout));
+                log::info!(target: "server", "test client output: {}", String::from_utf8_lossy(&output.stdout));
                 if !output.stderr.is_empty() {
-                    eprintln!("Test client stderr: {}", String::from_utf8_lossy(&output.stderr));
+                    log::info!(target: "server", "test client stderr: {}", String::from_utf8_lossy(&output.stderr));
                 }
             });
         }
 
         let mut connections: Vec<ServerConnection> = Vec::new();
 
-        println!("🔍 Starting async server loop");
+        log::debug!(target: "server", "starting async server loop");
 
         // No more polling! The ACME client and HTTP server now share the same challenge storage
         // Challenges are automatically available to the HTTP server when created by the ACME client
 
-        println!("🔍 Starting main server loop - listening on HTTP port {} and HTTPS port {}", http_port, final_https_port);
+        // Readiness: listeners are bound and the resolver is installed by now
+        health::set_listener("http", self.http_listener.local_addr().ok());
+        health::set_listener("https", self.https_listener.local_addr().ok());
+        health::set_cert_resolver_ready(true);
+        let mut document_roots = vec![self.secure_file_server.config().document_root.clone()];
+        for domain in &self.args.domains {
+            let root = self.secure_file_server.get_domain_document_root(domain);
+            if !document_roots.contains(&root) {
+                document_roots.push(root);
+            }
+        }
+        health::set_document_roots(document_roots);
+
+        log::debug!(target: "server", "starting main server loop - listening on HTTP port {} and HTTPS port {}", http_port, final_https_port);
         loop {
         tokio::select! {
                 // Accept HTTP connections (port 80) for ACME challenges
                 result = self.http_listener.accept() => {
                     match result {
                         Ok((stream, addr)) => {
-                            println!("🔍 New HTTP connection from {} (ACME challenge)", addr);
+                            log::debug!(target: "acme", "new HTTP connection from {} (ACME challenge)", addr);
 
                             // Handle HTTP connection for ACME challenges and file serving
                             let acme_client = self.acme_client.clone();
                             let http_challenges = self.http_challenges.clone();
                             let secure_file_server = self.secure_file_server.clone();
                             let extension_registry = self.extension_registry.clone();
+                            let reverse_proxy = self.reverse_proxy.clone();
+                            let fastcgi = self.fastcgi.clone();
+                            let cgi = self.cgi.clone();
+                            let websocket = self.websocket.clone();
+                            let client_auth = self.client_auth.clone();
 
                     tokio::task::spawn_blocking(move || {
                         let rt = tokio::runtime::Handle::current();
                         rt.block_on(async {
-                            match Self::handle_http_connection(stream, acme_client, http_challenges, secure_file_server, extension_registry).await {
+                            match Self::handle_http_connection(stream, acme_client, http_challenges, secure_file_server, extension_registry, reverse_proxy, fastcgi, cgi, websocket, client_auth).await {
                                 Ok(()) => {},
                                 Err(e) => {
                                     let error_msg = format!("{}", e);
-                                    eprintln!("HTTP connection error: {}", error_msg);
+                                    log::info!(target: "server", "HTTP connection error: {}", error_msg);
                                 }
                             }
                         })
                     });
                         }
                         Err(e) => {
-                            eprintln!("❌ HTTP accept error: {}", e);
+                            log::error!(target: "server", "HTTP accept error: {}", e);
                         }
                     }
                 }
 
                 // Accept HTTPS connections (port 443) for HTTPS traffic
                 result = self.https_listener.accept() => {
-                    println!("🔍 HTTPS accept() returned: {:?}", result);
+                    log::debug!(target: "server", "HTTPS accept() returned: {:?}", result);
                     match result {
                         Ok((stream, addr)) => {
-                            println!("🔍 New HTTPS connection from {}", addr);
+                            log::debug!(target: "server", "new HTTPS connection from {}", addr);
 
                             // Handle HTTPS connection
-                            let cert_resolver = self.cert_resolver.clone();
+                            let tls_configs = self.tls_configs.clone();
                             let args = self.args.clone();
                             let acme_client = self.acme_client.clone();
                             let http_challenges = self.http_challenges.clone();
                             let extension_registry = self.extension_registry.clone();
                             let secure_file_server = self.secure_file_server.clone();
                             let stats_collector = self.stats_collector.clone();
+                            let reverse_proxy = self.reverse_proxy.clone();
+                            let fastcgi = self.fastcgi.clone();
+                            let cgi = self.cgi.clone();
+                            let websocket = self.websocket.clone();
+                            let client_auth = self.client_auth.clone();
 
                             tokio::spawn(async move {
-                                if let Err(e) = Self::handle_connection_static(stream, cert_resolver, args, extension_registry, http_challenges, secure_file_server, stats_collector).await {
-                                    eprintln!("HTTPS connection error: {}", e);
+                                if let Err(e) = Self::handle_connection_static(stream, tls_configs, args, extension_registry, http_challenges, secure_file_server, stats_collector, reverse_proxy, fastcgi, cgi, websocket, client_auth).await {
+                                    log::info!(target: "server", "HTTPS connection error: {}", e);
                                 }
                             });
                         }
                         Err(e) => {
-                            eprintln!("❌ HTTPS accept error: {}", e);
+                            log::error!(target: "server", "HTTPS accept error: {}", e);
                         }
                     }
             }
@@ -1124,13 +1791,26 @@ impl OnDemandHttpsServer {
         http_challenges: Arc<Mutex<BTreeMap<String, String>>>,
         secure_file_server: SecureFileServer,
         extension_registry: Arc<Mutex<ExtensionRegistry>>,
+        reverse_proxy: Arc<ProxyConfig>,
+        fastcgi: Arc<FastCgiConfig>,
+        cgi: Arc<CgiExecConfig>,
+        websocket: Arc<WsConfig>,
+        client_auth: Arc<ClientAuthConfig>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Every request on this connection is written to the access log
+        let remote = stream.peer_addr().map(|addr| addr.ip()).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let mut stream = AccessRecorder::new(stream, remote);
+        let _connection = metrics::track_connection("http");
+
         // Create connection policy for Keep-Alive decisions
         let connection_policy = ConnectionPolicy::default();
         let mut request_count = 0;
 
         // Keep-Alive loop: handle multiple requests on the same connection
         loop {
+        // The previous response (if any) is complete
+        stream.finish();
+
         // Read HTTP request
         let mut buffer = [0; 4096];
         let mut total_read = 0;
@@ -1140,7 +1820,7 @@ impl OnDemandHttpsServer {
             match stream.read(&mut buffer[total_read..]).await {
                     Ok(0) => {
                         // Connection closed by client
-                        println!("DEBUG: HTTP connection closed by client");
+                        log::debug!(target: "http", "HTTP connection closed by client");
                         return Ok(());
                     }
                 Ok(n) => {
@@ -1183,11 +1863,20 @@ impl OnDemandHttpsServer {
         // Extract domain from Host header
         let domain = extract_domain_from_host_header(&request);
 
-        println!("DEBUG: HTTP connection - total_read: {}", total_read);
-        println!("DEBUG: HTTP request (first 200 bytes): {}", &request[..std::cmp::min(200, request.len())]);
+        log::debug!(target: "http", "HTTP connection - total_read: {}", total_read);
+        // Header names only; values carry cookies and credentials
+        log::debug!(target: "http", "HTTP request headers: {}",
+            lines.iter().skip(1).take_while(|line| !line.is_empty()).filter_map(|line| line.split_once(':')).map(|(name, _)| name.trim()).collect::<Vec<_>>().join(", "));
 
         if let Some(first_line) = lines.first() {
-            println!("HTTP Request: {}", first_line);
+            log::debug!(target: "http", request:% = first_line; "HTTP request");
+
+            // Health and readiness probes are answered before anything else
+            if let Some(probe) = first_line.split_whitespace().nth(1).and_then(health::probe_for_path) {
+                stream.write_all(&health::http_response(probe, first_line.starts_with("HEAD "), false)).await?;
+                stream.flush().await?;
+                return Ok(());
+            }
 
             // Handle HTTP-01 ACME challenges
             if first_line.starts_with("GET /.well-known/acme-challenge/") {
@@ -1214,10 +1903,47 @@ impl OnDemandHttpsServer {
         } else {
             "/"
         };
-        println!("DEBUG: Full request: {}", request);
+
+        // Paths that need a client certificate cannot be served over plain HTTP
+        if client_auth.requires_certificate(domain.as_deref(), request_path) {
+            log::info!(target: "http", path:% = request_path; "HTTP request refused: client certificate required");
+            let mut response = HttpResponse::new(403, "Forbidden", b"403 Forbidden".to_vec());
+            response.set_content_type("text/plain");
+            response.set_content_length();
+            stream.write_all(&response.encode(&HttpVersion::Http11, false)).await?;
+            stream.flush().await?;
+            return Ok(());
+        }
 
         // Check for bin extension requests (CGI-like)
         if request_path.starts_with("/cgi-bin/") {
+            // Executables in the host's cgi-bin directory run as CGI/1.1 scripts;
+            // other names fall through to the compiled-in *.bin.rs handlers
+            if let Some(domain) = domain.as_deref() {
+                let request_target = lines.first().and_then(|line| line.split_whitespace().nth(1)).unwrap_or(request_path);
+                match cgi.find_script(domain, request_target) {
+                    Ok(Some(script)) => {
+                        let document_root = secure_file_server.get_domain_document_root(domain);
+                        let config = secure_file_server.config();
+                        let run_as = config.drop_to_uid.zip(config.drop_to_gid);
+                        let conn_info = ConnectionInfo { remote: stream.peer_addr()?, local: stream.local_addr()?, https: false };
+                        let outcome = run_cgi(&mut stream, &buffer[..total_read], &script, &document_root, &conn_info, run_as, &cgi).await?;
+                        log::debug!(target: "cgi", script:% = script.script_name, status = outcome.status, bytes = outcome.bytes_sent; "CGI HTTP request");
+                        return Ok(());
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        log::warn!(target: "cgi", "CGI script refused: {}", e);
+                        let mut response = HttpResponse::new(403, "Forbidden", b"403 Forbidden".to_vec());
+                        response.set_content_type("text/plain");
+                        response.set_content_length();
+                        stream.write_all(&response.encode(&HttpVersion::Http11, false)).await?;
+                        stream.flush().await?;
+                        return Ok(());
+                    }
+                }
+            }
+
             // Extract query string from the original request line
             let query_string = if let Some(first_line) = lines.first() {
                 if let Some(path_start) = first_line.find(' ') {
@@ -1252,6 +1978,7 @@ impl OnDemandHttpsServer {
                     headers.insert(header_name, header_value);
                 }
             }
+            client_auth::apply_headers(&mut headers, None);
 
             // Handle bin extension request
             let response = {
@@ -1284,15 +2011,26 @@ impl OnDemandHttpsServer {
         // Check for admin extension requests
         #[cfg(feature = "extensions")]
         {
-            println!("DEBUG: Checking for admin request with path: {}", request_path);
+            log::debug!(target: "admin", "checking for admin request with path: {}", request_path);
             // Use dynamic admin path checking instead of hardcoded paths
             let is_admin_request = {
                 let registry = extension_registry.lock().unwrap();
                 registry.is_admin_path(request_path)
             };
-            println!("DEBUG: Admin request check result: {}", is_admin_request);
+            log::debug!(target: "admin", "admin request check result: {}", is_admin_request);
             if is_admin_request {
-                println!("DEBUG: Admin request detected for path: {}", request_path);
+                log::debug!(target: "admin", "admin request detected for path: {}", request_path);
+
+                // Panels restricted to client certificates are HTTPS-only
+                if !client_auth.admin_allowed(None) {
+                    log::warn!(target: "admin", path:% = request_path; "HTTP admin request refused: client certificate required");
+                    let mut response = HttpResponse::new(403, "Forbidden", b"403 Forbidden".to_vec());
+                    response.set_content_type("text/plain");
+                    response.set_content_length();
+                    stream.write_all(&response.encode(&HttpVersion::Http11, false)).await?;
+                    stream.flush().await?;
+                    return Ok(());
+                }
 
                 // Extract HTTP method from the first line
                 let http_method = if let Some(first_line) = lines.first() {
@@ -1300,7 +2038,7 @@ impl OnDemandHttpsServer {
                 } else {
                     "GET"
                 };
-                println!("DEBUG: HTTP method: {}", http_method);
+                log::debug!(target: "http", "HTTP method: {}", http_method);
 
                 // Extract query string from the request
                 let query_string = if let Some(query_start) = request_path.find('?') {
@@ -1333,6 +2071,7 @@ impl OnDemandHttpsServer {
                         headers.insert(header_name, header_value);
                     }
                 }
+                client_auth::apply_headers(&mut headers, None);
 
                 // Remove trailing newline from body
                 if body.ends_with('\n') {
@@ -1364,7 +2103,64 @@ impl OnDemandHttpsServer {
             }
         }
 
-        println!("Requested path: {}", request_path);
+        // WebSocket extension endpoints (/ws/<name>) hold the connection until either side closes
+        #[cfg(feature = "extensions")]
+        if let Some(name) = websocket::endpoint_name(request_path).filter(|name| is_ws_endpoint(name)) {
+            let name = name.to_string();
+            let remote = stream.peer_addr()?;
+            let outcome = serve_websocket(&mut stream, &buffer[..total_read], remote, &websocket, move |channel| handle_ws_connection(name, channel)).await?;
+            log::debug!(target: "websocket", path:% = request_path, status = outcome.status, bytes = outcome.bytes_sent; "WebSocket HTTP connection finished");
+            return Ok(());
+        }
+
+        // Admin WebSocket endpoints (/ws/<name>_<key>) need the panel's admin key
+        #[cfg(feature = "extensions")]
+        if let Some(endpoint) = websocket::endpoint_name(request_path).filter(|endpoint| {
+            endpoint.split_once('_').is_some_and(|(name, _)| is_admin_ws_endpoint(name)) && extension_registry.lock().unwrap().is_valid_admin_key(endpoint)
+        }) {
+            if !client_auth.admin_allowed(None) {
+                log::warn!(target: "admin", path:% = request_path; "HTTP admin WebSocket refused: client certificate required");
+                let mut response = HttpResponse::new(403, "Forbidden", b"403 Forbidden".to_vec());
+                response.set_content_type("text/plain");
+                response.set_content_length();
+                stream.write_all(&response.encode(&HttpVersion::Http11, false)).await?;
+                stream.flush().await?;
+                return Ok(());
+            }
+            let name = endpoint.split('_').next().unwrap_or_default().to_string();
+            let remote = stream.peer_addr()?;
+            let outcome = serve_websocket(&mut stream, &buffer[..total_read], remote, &websocket, move |channel| handle_admin_ws_connection(name, channel)).await?;
+            log::debug!(target: "websocket", path:% = request_path, status = outcome.status, bytes = outcome.bytes_sent; "admin WebSocket HTTP connection finished");
+            return Ok(());
+        }
+
+        // Reverse proxy routes take over from static file serving (ACME, cgi-bin and admin come first)
+        let request_target = lines.first().and_then(|line| line.split_whitespace().nth(1)).unwrap_or(request_path);
+        if let Some(route) = reverse_proxy.find_route(domain.as_deref(), request_target) {
+            let forward = ForwardInfo { client_ip: stream.peer_addr()?.ip(), proto: "http" };
+            let outcome = proxy_request(&mut stream, &buffer[..total_read], route, &forward, &reverse_proxy).await?;
+            log::debug!(target: "proxy", path:% = request_target, upstream:? = route.upstream.addr, status = outcome.status, bytes = outcome.bytes_sent; "proxied HTTP request");
+            return Ok(());
+        }
+
+        // FastCGI scripts (e.g. *.php) run on their backend; the script path gets the usual sanitizing
+        if !fastcgi.rules.is_empty() {
+            let document_root = match domain.as_deref() {
+                Some(domain) => secure_file_server.get_domain_document_root(domain),
+                None => secure_file_server.config().document_root.clone(),
+            };
+            if let Some(script) = fastcgi.find(domain.as_deref(), request_target, &document_root) {
+                let script_filename = secure_file_server
+                    .sanitize_path_with_root(&script.script_target(), &document_root)
+                    .map_err(|e| e.to_string());
+                let conn_info = ConnectionInfo { remote: stream.peer_addr()?, local: stream.local_addr()?, https: false };
+                let outcome = fastcgi_request(&mut stream, &buffer[..total_read], &script, script_filename, &document_root, &conn_info, &fastcgi).await?;
+                log::debug!(target: "fastcgi", script:% = script.script_name, backend:% = script.backend.addr, status = outcome.status, bytes = outcome.bytes_sent; "FastCGI HTTP request");
+                return Ok(());
+            }
+        }
+
+        log::debug!(target: "files", path:% = request_path; "requested path");
 
         // Try to serve the requested file using secure file server with caching support
         let serve_result = secure_file_server.serve_file_with_domain_and_caching(
@@ -1379,26 +2175,45 @@ impl OnDemandHttpsServer {
                 // This is a complete HTTP response (with caching headers)
                 // Check if this is HTML content that needs extension processing
                 let final_response_bytes = if let Ok(response_string) = String::from_utf8(response_bytes.clone()) {
-                    println!("DEBUG: Response string length: {}", response_string.len());
-                    println!("DEBUG: Response contains 'text/html': {}", response_string.contains("text/html"));
+                    log::debug!(target: "http", "response string length: {}", response_string.len());
+                    log::debug!(target: "http", "response contains 'text/html': {}", response_string.contains("text/html"));
                     if response_string.contains("text/html") {
                         // Check if the HTML body contains #EXTEND: directives
                         if let Some(body_start) = response_string.find("\r\n\r\n") {
                             let body = &response_string[body_start + 4..];
-                            println!("DEBUG: Body length: {}", body.len());
-                            println!("DEBUG: Body contains '#EXTEND:': {}", body.contains("#EXTEND:"));
+                            log::debug!(target: "http", "body length: {}", body.len());
+                            log::debug!(target: "extensions", "body contains '#EXTEND:': {}", body.contains("#EXTEND:"));
                             if body.contains("#EXTEND:") {
-                                println!("DEBUG: Found #EXTEND: directive in HTML body, checking for constant extensions...");
+                                log::debug!(target: "extensions", "found #EXTEND: directive in HTML body, checking for constant extensions...");
 
                                 // Check if this page only contains constant extensions
                                 let has_constant_extensions_only = check_constant_extensions_only(body);
 
                                 if has_constant_extensions_only {
-                                    println!("DEBUG: Only constant extensions found, processing without breaking cache...");
+                                    log::debug!(target: "extensions", "only constant extensions found, processing without breaking cache...");
                                     // Process extensions but keep the original cache headers
                                     #[cfg(feature = "extensions")]
                                     {
-                                        let processed_html = extension_registry.lock().unwrap().process_html(body, request_path);
+                                        // Constant output only depends on the page, so full responses are cached by ETag
+                                        let page_cache = secure_file_server.content_cache();
+                                        let page_key = if response_string.starts_with("HTTP/1.1 200") || response_string.starts_with("HTTP/1.0 200") {
+                                            response_string[..body_start]
+                                                .lines()
+                                                .find_map(|line| line.strip_prefix("ETag: "))
+                                                .map(|etag| content_cache::ContentCache::page_key(domain.as_deref(), request_path, etag))
+                                        } else {
+                                            None
+                                        };
+                                        let processed_html = match page_key.as_deref().and_then(|key| page_cache.get_page(key)) {
+                                            Some(html) => html.to_string(),
+                                            None => {
+                                                let html = extension_registry.lock().unwrap().process_html(body, request_path);
+                                                if let Some(key) = page_key {
+                                                    page_cache.insert_page(key, html.clone());
+                                                }
+                                                html
+                                            }
+                                        };
                                         // Reconstruct response but preserve original cache headers
                                         let headers = &response_string[..body_start + 4];
                                         let new_content_length = processed_html.len();
@@ -1421,7 +2236,7 @@ impl OnDemandHttpsServer {
                                         response_bytes
                                     }
                                 } else {
-                                    println!("DEBUG: Dynamic extensions found, processing with no-cache headers...");
+                                    log::debug!(target: "extensions", "dynamic extensions found, processing with no-cache headers...");
                                     // Process extensions and add no-cache headers for dynamic content
                                     #[cfg(feature = "extensions")]
                                     {
@@ -1493,7 +2308,7 @@ impl OnDemandHttpsServer {
             Err(e) => {
                 // Security error or other error - check if this is a root request first
                 let error_msg = format!("{}", e);
-                println!("Request denied for {}: {}", request_path, error_msg);
+                log::info!(target: "files", path:% = request_path, error:% = error_msg; "request denied");
                 if secure_file_server.is_root_request(request_path) {
                     // Serve default informational page even for security errors on root
                     let default_page = secure_file_server.generate_default_page("localhost");
@@ -1520,12 +2335,12 @@ impl OnDemandHttpsServer {
 
         // Check if we should keep the connection alive for the next request
         if !should_keep_alive {
-            println!("DEBUG: Closing HTTP connection after {} requests (version: {})",
+            log::debug!(target: "http", "closing HTTP connection after {} requests (version: {})",
                 request_count, http_version);
             break; // Exit the Keep-Alive loop
         }
 
-        println!("DEBUG: Keeping HTTP connection alive for next request (version: {})",
+        log::debug!(target: "http", "keeping HTTP connection alive for next request (version: {})",
             http_version);
         }
 
@@ -1535,52 +2350,94 @@ impl OnDemandHttpsServer {
     /// Handle a single HTTPS connection (static version for threading)
     async fn handle_connection_static(
         stream: tokio::net::TcpStream,
-        cert_resolver: Arc<dyn ResolvesServerCert + Send + Sync>,
+        tls_configs: Arc<ServerConfigs>,
         args: Args,
         extension_registry: Arc<Mutex<ExtensionRegistry>>,
         http_challenges: Arc<Mutex<BTreeMap<String, String>>>,
         secure_file_server: SecureFileServer,
         stats_collector: Arc<HourlyStatsCollector>,
+        reverse_proxy: Arc<ProxyConfig>,
+        fastcgi: Arc<FastCgiConfig>,
+        cgi: Arc<CgiExecConfig>,
+        websocket: Arc<WsConfig>,
+        client_auth: Arc<ClientAuthConfig>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        println!("🔍 Starting HTTPS connection handling");
+        log::debug!(target: "tls", "starting HTTPS connection handling");
 
         // Use the new async HTTPS handler
         Self::handle_https_connection_async(
             stream,
-                cert_resolver,
+                tls_configs,
                 args,
                 extension_registry,
                 http_challenges,
                 secure_file_server,
                 stats_collector,
+                reverse_proxy,
+                fastcgi,
+                cgi,
+                websocket,
+                client_auth,
         ).await
     }
 
     /// Handle HTTPS connection using async tokio-rustls (for large file support)
     async fn handle_https_connection_async(
         stream: tokio::net::TcpStream,
-        cert_resolver: Arc<dyn ResolvesServerCert + Send + Sync>,
+        tls_configs: Arc<ServerConfigs>,
         args: Args,
         extension_registry: Arc<Mutex<ExtensionRegistry>>,
         http_challenges: Arc<Mutex<BTreeMap<String, String>>>,
         secure_file_server: SecureFileServer,
         stats_collector: Arc<HourlyStatsCollector>,
+        reverse_proxy: Arc<ProxyConfig>,
+        fastcgi: Arc<FastCgiConfig>,
+        cgi: Arc<CgiExecConfig>,
+        websocket: Arc<WsConfig>,
+        client_auth: Arc<ClientAuthConfig>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        println!("🔍 Starting async HTTPS connection handling");
-
-        // Create server config with our certificate resolver
-        let server_config = TokioServerConfig::builder_with_provider(
-            rustls::crypto::ring::default_provider().into()
-        )
-        .with_no_client_auth()
-        .with_cert_resolver(cert_resolver)?;
-
-        // Create tokio-rustls acceptor
-        let acceptor = TlsAcceptor::from(Arc::new(server_config));
-
-        // Perform TLS handshake
-        let mut tls_stream = acceptor.accept(stream).await?;
-        println!("🔍 TLS handshake completed");
+        log::debug!(target: "tls", "starting async HTTPS connection handling");
+
+        // Perform TLS handshake; every request on this connection is written to the access log
+        let remote = stream.peer_addr().map(|addr| addr.ip()).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let _connection = metrics::track_connection("https");
+        // The ClientHello's SNI name decides whether a client certificate is asked for; the
+        // shared configs keep the session cache and ticket keys across connections
+        let handshake = async {
+            let start = LazyConfigAcceptor::new(Acceptor::default(), stream).await?;
+            // ACME validation connections never present a client certificate
+            let mode = if tls_alpn::is_challenge_hello(start.client_hello()) {
+                None
+            } else {
+                client_auth.handshake_mode(start.client_hello().server_name().map(|name| name.as_ref()))
+            };
+            start.into_stream(tls_configs.for_mode(mode)).await
+        };
+        let tls_stream = match handshake.await {
+            Ok(tls_stream) => tls_stream,
+            Err(e) => {
+                metrics::record_tls_failure(&e);
+                return Err(e.into());
+            }
+        };
+        metrics::record_tls_handshake();
+        tls_policy::record_connection(tls_stream.get_ref().1);
+        // A TLS-ALPN-01 validation is complete once the challenge certificate was presented
+        if tls_stream.get_ref().1.alpn_protocol() == Some(ACME_TLS_ALPN_PROTOCOL) {
+            log::debug!(target: "acme", "TLS-ALPN-01 validation handshake completed");
+            let mut tls_stream = tls_stream;
+            let _ = tls_stream.shutdown().await;
+            return Ok(());
+        }
+        // The verifier has already checked the chain against the client CA bundle
+        let client_identity = tls_stream.get_ref().1.peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(|leaf| ClientIdentity::from_der(leaf.as_ref()).ok());
+        if let Some(identity) = &client_identity {
+            log::debug!(target: "tls", subject:% = identity.subject; "client certificate verified");
+        }
+        let mut tls_stream = AccessRecorder::new(tls_stream, remote);
+        log::debug!(target: "tls", "TLS handshake completed");
 
         // Handle the connection with Keep-Alive support
         let mut connection_policy = ConnectionPolicy::new(100, 300);
@@ -1588,7 +2445,7 @@ impl OnDemandHttpsServer {
 
         loop {
             request_count += 1;
-            println!("🔍 Processing async HTTPS request {} for domain", request_count);
+            log::debug!(target: "tls", "processing async HTTPS request {} for domain", request_count);
 
             // Process the request and get parsed version/connection info
             let (http_version, connection_header) = Self::process_https_request_async(
@@ -1597,7 +2454,14 @@ impl OnDemandHttpsServer {
                 &http_challenges,
                 &secure_file_server,
                 &stats_collector,
+                &reverse_proxy,
+                &fastcgi,
+                &cgi,
+                &websocket,
+                &client_auth,
+                client_identity.as_ref(),
             ).await?;
+            tls_stream.finish();
 
             // Determine if we should keep the connection alive
             let should_keep_alive = connection_policy.should_keep_alive(
@@ -1607,11 +2471,11 @@ impl OnDemandHttpsServer {
                 request_count,
             );
 
-            println!("🔍 Async HTTPS request {} completed (version: {}, keep_alive: {})",
+            log::debug!(target: "tls", "async HTTPS request {} completed (version: {}, keep_alive: {})",
                 request_count, http_version, should_keep_alive);
 
             if !should_keep_alive {
-                println!("DEBUG: Closing async HTTPS connection after {} requests (version: {})",
+                log::debug!(target: "tls", "closing async HTTPS connection after {} requests (version: {})",
                     request_count, http_version);
                 // Properly shut down the TLS connection
                 use tokio::io::AsyncWriteExt;
@@ -1619,7 +2483,7 @@ impl OnDemandHttpsServer {
                 break; // Exit the Keep-Alive loop
             }
 
-            println!("DEBUG: Keeping async HTTPS connection alive for next request (version: {})",
+            log::debug!(target: "tls", "keeping async HTTPS connection alive for next request (version: {})",
                 http_version);
         }
 
@@ -1628,11 +2492,17 @@ impl OnDemandHttpsServer {
 
     /// Process a single HTTPS request using async tokio-rustls
     async fn process_https_request_async(
-        tls_stream: &mut tokio_rustls::server::TlsStream<tokio::net::TcpStream>,
+        tls_stream: &mut AccessRecorder<tokio_rustls::server::TlsStream<tokio::net::TcpStream>>,
         extension_registry: &Arc<Mutex<ExtensionRegistry>>,
         http_challenges: &Arc<Mutex<BTreeMap<String, String>>>,
         secure_file_server: &SecureFileServer,
         stats_collector: &Arc<HourlyStatsCollector>,
+        reverse_proxy: &Arc<ProxyConfig>,
+        fastcgi: &Arc<FastCgiConfig>,
+        cgi: &Arc<CgiExecConfig>,
+        websocket: &Arc<WsConfig>,
+        client_auth: &ClientAuthConfig,
+        client_identity: Option<&ClientIdentity>,
     ) -> Result<(HttpVersion, Option<String>), Box<dyn std::error::Error + Send + Sync>> {
         use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
@@ -1644,7 +2514,7 @@ impl OnDemandHttpsServer {
         }
 
         let request = String::from_utf8_lossy(&buffer[..n]);
-        println!("🔍 Async HTTPS request received: {}", request.lines().next().unwrap_or(""));
+        log::debug!(target: "http", "async HTTPS request received: {}", request.lines().next().unwrap_or(""));
 
         // Parse the request
         let lines: Vec<&str> = request.lines().collect();
@@ -1669,13 +2539,10 @@ impl OnDemandHttpsServer {
         let http_version = HttpVersion::from_request_line(first_line);
 
         // Extract server name from Host header
-        let server_name = lines.iter()
-            .find(|line| line.starts_with("Host:"))
-            .and_then(|line| line.split(':').nth(1))
-            .map(|host| host.trim().to_string())
+        let server_name = extract_domain_from_host_header(&request)
             .unwrap_or_else(|| "localhost".to_string());
 
-        println!("🔍 Async HTTPS request: {} {} {} (server: {})", method, path, http_version_str, server_name);
+        log::debug!(target: "http", method:% = method, path:% = path, version:% = http_version_str, host:% = server_name; "HTTPS request");
 
         // Extract connection header from request
         let connection_header = lines.iter()
@@ -1699,17 +2566,41 @@ impl OnDemandHttpsServer {
             }
         }
 
+        // Client certificate rules cover everything below, admin panels included
+        if client_identity.is_none() && client_auth.requires_certificate(Some(&server_name), path) {
+            log::info!(target: "tls", path:% = path, host:% = server_name; "request refused: client certificate required");
+            let response = "HTTP/1.1 403 Forbidden\r\nContent-Type: text/plain\r\nContent-Length: 13\r\nConnection: close\r\n\r\n403 Forbidden";
+            tls_stream.write_all(response.as_bytes()).await?;
+            tls_stream.flush().await?;
+            return Ok((http_version.clone(), Some("close".to_string())));
+        }
+
+        // Health and readiness probes, if enabled on the site listeners
+        if let Some(probe) = health::probe_for_path(path) {
+            tls_stream.write_all(&health::http_response(probe, method == "HEAD", false)).await?;
+            tls_stream.flush().await?;
+            return Ok((HttpVersion::Http11, Some("close".to_string())));
+        }
+
         // Check for admin extension requests
         #[cfg(feature = "extensions")]
         {
-            println!("DEBUG: Async HTTPS - Checking for admin request with path: {}", path);
+            log::debug!(target: "admin", "async HTTPS - Checking for admin request with path: {}", path);
             let is_admin_request = {
                 let registry = extension_registry.lock().unwrap();
                 registry.is_admin_path(path)
             };
-            println!("DEBUG: Async HTTPS - Admin request check result: {}", is_admin_request);
+            log::debug!(target: "admin", "async HTTPS - Admin request check result: {}", is_admin_request);
             if is_admin_request {
-                println!("DEBUG: Async HTTPS - Admin request detected for path: {}", path);
+                log::debug!(target: "admin", "async HTTPS - Admin request detected for path: {}", path);
+
+                if !client_auth.admin_allowed(client_identity) {
+                    log::warn!(target: "admin", path:% = path, subject:? = client_identity.map(|identity| &identity.subject); "admin request refused: client certificate not allowed");
+                    let response = "HTTP/1.1 403 Forbidden\r\nContent-Type: text/plain\r\nContent-Length: 13\r\nConnection: close\r\n\r\n403 Forbidden";
+                    tls_stream.write_all(response.as_bytes()).await?;
+                    tls_stream.flush().await?;
+                    return Ok((http_version.clone(), Some("close".to_string())));
+                }
 
                 // Extract HTTP method
                 let http_method = method;
@@ -1737,6 +2628,7 @@ impl OnDemandHttpsServer {
                         headers.insert(key, value);
                     }
                 }
+                client_auth::apply_headers(&mut headers, client_identity);
 
                 // Read request body if present
                 let body = if let Some(content_length_line) = lines.iter().find(|line| line.to_lowercase().starts_with("content-length:")) {
@@ -1774,6 +2666,111 @@ impl OnDemandHttpsServer {
             }
         }
 
+        // WebSocket extension endpoints (/ws/<name>) hold the connection until either side closes
+        #[cfg(feature = "extensions")]
+        if let Some(name) = websocket::endpoint_name(path).filter(|name| is_ws_endpoint(name)) {
+            let name = name.to_string();
+            let remote = tls_stream.get_ref().0.peer_addr()?;
+            let outcome = serve_websocket(tls_stream, &buffer[..n], remote, websocket, move |channel| handle_ws_connection(name, channel)).await?;
+            log::debug!(target: "websocket", path:% = path, host:% = server_name, status = outcome.status, bytes = outcome.bytes_sent; "WebSocket HTTPS connection finished");
+            return Ok((http_version.clone(), Some("close".to_string())));
+        }
+
+        // Admin WebSocket endpoints (/ws/<name>_<key>) need the panel's admin key
+        #[cfg(feature = "extensions")]
+        if let Some(endpoint) = websocket::endpoint_name(path).filter(|endpoint| {
+            endpoint.split_once('_').is_some_and(|(name, _)| is_admin_ws_endpoint(name)) && extension_registry.lock().unwrap().is_valid_admin_key(endpoint)
+        }) {
+            if !client_auth.admin_allowed(client_identity) {
+                log::warn!(target: "admin", path:% = path, subject:? = client_identity.map(|identity| &identity.subject); "admin WebSocket refused: client certificate not allowed");
+                let response = "HTTP/1.1 403 Forbidden\r\nContent-Type: text/plain\r\nContent-Length: 13\r\nConnection: close\r\n\r\n403 Forbidden";
+                tls_stream.write_all(response.as_bytes()).await?;
+                tls_stream.flush().await?;
+                return Ok((http_version.clone(), Some("close".to_string())));
+            }
+            let name = endpoint.split('_').next().unwrap_or_default().to_string();
+            let remote = tls_stream.get_ref().0.peer_addr()?;
+            let outcome = serve_websocket(tls_stream, &buffer[..n], remote, websocket, move |channel| handle_admin_ws_connection(name, channel)).await?;
+            log::debug!(target: "websocket", path:% = path, host:% = server_name, status = outcome.status, bytes = outcome.bytes_sent; "admin WebSocket HTTPS connection finished");
+            return Ok((http_version.clone(), Some("close".to_string())));
+        }
+
+        // Executables in the host's cgi-bin directory run as CGI/1.1 scripts
+        if path.starts_with(cgi_exec::CGI_PREFIX) {
+            match cgi.find_script(&server_name, path) {
+                Ok(Some(script)) => {
+                    let document_root = secure_file_server.get_domain_document_root(&server_name);
+                    let config = secure_file_server.config();
+                    let run_as = config.drop_to_uid.zip(config.drop_to_gid);
+                    let tcp = tls_stream.get_ref().0;
+                    let conn_info = ConnectionInfo { remote: tcp.peer_addr()?, local: tcp.local_addr()?, https: true };
+                    let outcome = run_cgi(tls_stream, &buffer[..n], &script, &document_root, &conn_info, run_as, cgi).await?;
+                    log::debug!(target: "cgi", script:% = script.script_name, host:% = server_name, status = outcome.status, bytes = outcome.bytes_sent; "CGI HTTPS request");
+                    return Ok((http_version.clone(), Some("close".to_string())));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!(target: "cgi", "CGI script refused: {}", e);
+                    let response = "HTTP/1.1 403 Forbidden\r\nContent-Type: text/plain\r\nContent-Length: 13\r\nConnection: close\r\n\r\n403 Forbidden";
+                    tls_stream.write_all(response.as_bytes()).await?;
+                    tls_stream.flush().await?;
+                    return Ok((http_version.clone(), Some("close".to_string())));
+                }
+            }
+
+            // Then the compiled-in *.bin.rs handlers, which see the client certificate in their headers
+            let (bin_path, query_string) = path.split_once('?').unwrap_or((path, ""));
+            let mut headers = std::collections::HashMap::new();
+            for line in lines.iter().skip(1) {
+                if let Some(colon_pos) = line.find(':') {
+                    headers.insert(line[..colon_pos].trim().to_lowercase(), line[colon_pos + 1..].trim().to_string());
+                }
+            }
+            client_auth::apply_headers(&mut headers, client_identity);
+            let bin_response = extension_registry.lock().unwrap().handle_bin_request(bin_path, method, query_string, &headers);
+            match bin_response {
+                Ok(response) => {
+                    tls_stream.write_all(response.as_bytes()).await?;
+                    tls_stream.flush().await?;
+                    return Ok((http_version.clone(), Some("close".to_string())));
+                }
+                // Unknown names fall through to the handlers below
+                Err(e) if e.downcast_ref::<std::io::Error>().map(|e| e.kind()) == Some(std::io::ErrorKind::NotFound) => {}
+                Err(e) => {
+                    let mut response = HttpResponse::internal_server_error(format!("Error: {}", e).into_bytes());
+                    response.set_content_type("text/plain");
+                    response.set_content_length();
+                    tls_stream.write_all(&response.encode(&http_version, false)).await?;
+                    tls_stream.flush().await?;
+                    return Ok((http_version.clone(), Some("close".to_string())));
+                }
+            }
+        }
+
+        // Reverse proxy routes take over from static file serving; the certificate for
+        // server_name still came from the on-demand ACME resolver during the handshake
+        if let Some(route) = reverse_proxy.find_route(Some(&server_name), path) {
+            let forward = ForwardInfo { client_ip: tls_stream.get_ref().0.peer_addr()?.ip(), proto: "https" };
+            let outcome = proxy_request(tls_stream, &buffer[..n], route, &forward, reverse_proxy).await?;
+            log::debug!(target: "proxy", path:% = path, host:% = server_name, upstream:? = route.upstream.addr, status = outcome.status, bytes = outcome.bytes_sent; "proxied HTTPS request");
+            return Ok((http_version.clone(), Some("close".to_string())));
+        }
+
+        // FastCGI scripts (e.g. *.php) run on their backend; the script path gets the usual sanitizing
+        if !fastcgi.rules.is_empty() {
+            let document_root = secure_file_server.get_domain_document_root(&server_name);
+            if let Some(script) = fastcgi.find(Some(&server_name), path, &document_root) {
+                let script_filename = secure_file_server
+                    .sanitize_path_with_root(&script.script_target(), &document_root)
+                    .map_err(|e| e.to_string());
+                let tcp = tls_stream.get_ref().0;
+                let conn_info = ConnectionInfo { remote: tcp.peer_addr()?, local: tcp.local_addr()?, https: true };
+                let outcome = fastcgi_request(tls_stream, &buffer[..n], &script, script_filename, &document_root, &conn_info, fastcgi).await?;
+                log::debug!(target: "fastcgi", script:% = script.script_name, host:% = server_name, backend:% = script.backend.addr, status = outcome.status, bytes = outcome.bytes_sent; "FastCGI HTTPS request");
+                return Ok((http_version.clone(), Some("close".to_string())));
+            }
+        }
+
         // Determine if we should keep the connection alive (passed to response builder)
         // For now, we'll use a simple heuristic based on HTTP version
         let should_keep_alive_response = match &http_version {
@@ -1805,16 +2802,16 @@ impl OnDemandHttpsServer {
                         // Check if the HTML body contains #EXTEND: directives
                         if let Some(body_start) = response_string.find("\r\n\r\n") {
                             let body = &response_string[body_start + 4..];
-                            println!("DEBUG: Body length: {}", body.len());
-                            println!("DEBUG: Body contains '#EXTEND:': {}", body.contains("#EXTEND:"));
+                            log::debug!(target: "http", "body length: {}", body.len());
+                            log::debug!(target: "extensions", "body contains '#EXTEND:': {}", body.contains("#EXTEND:"));
                             if body.contains("#EXTEND:") {
-                                println!("DEBUG: Found #EXTEND: directive in HTML body, processing extensions...");
+                                log::debug!(target: "extensions", "found #EXTEND: directive in HTML body, processing extensions...");
                                 // Process extensions in the HTML content
                                 #[cfg(feature = "extensions")]
                                 {
-                                    println!("DEBUG: Extensions feature enabled, calling process_html...");
+                                    log::debug!(target: "extensions", "extensions feature enabled, calling process_html...");
                                     let processed_html = extension_registry.lock().unwrap().process_html(body, path);
-                                    println!("DEBUG: Extension processing completed, processed HTML length: {}", processed_html.len());
+                                    log::debug!(target: "extensions", "extension processing completed, processed HTML length: {}", processed_html.len());
                                     // Reconstruct the response with processed HTML
                                     let headers = &response_string[..body_start];
                                     let new_content_length = processed_html.len();
@@ -1849,7 +2846,7 @@ impl OnDemandHttpsServer {
                     response_bytes
                 };
 
-                println!("🔍 Serving {} bytes over async HTTPS", final_response_bytes.len());
+                log::debug!(target: "http", "serving {} bytes over async HTTPS", final_response_bytes.len());
 
                 // Write in chunks to handle large files properly with tokio-rustls
                 let chunk_size = 8192; // 8KB chunks
@@ -1863,7 +2860,7 @@ impl OnDemandHttpsServer {
 
                     // Log progress for large files
                     if final_response_bytes.len() > 100000 && offset % (chunk_size * 10) == 0 {
-                        println!("🔍 HTTPS progress: {}/{} bytes ({}%)",
+                        log::debug!(target: "http", "HTTPS progress: {}/{} bytes ({}%)",
                             offset, final_response_bytes.len(),
                             (offset * 100) / final_response_bytes.len());
                     }
@@ -1881,7 +2878,7 @@ impl OnDemandHttpsServer {
                             }
                             Err(e) => {
                                 retry_count += 1;
-                                eprintln!("Error writing chunk at offset {} (attempt {}): {}", offset, attempt + 1, e);
+                                log::error!(target: "http", "error writing chunk at offset {} (attempt {}): {}", offset, attempt + 1, e);
 
                                 if attempt < MAX_RETRIES - 1 {
                                     // Wait a bit before retrying
@@ -1899,12 +2896,12 @@ impl OnDemandHttpsServer {
 
                     // Flush after each chunk to ensure data is sent
                     if let Err(e) = tls_stream.flush().await {
-                        eprintln!("Error flushing after chunk at offset {}: {}", offset, e);
+                        log::error!(target: "http", "error flushing after chunk at offset {}: {}", offset, e);
                         return Err(e.into());
                     }
                 }
 
-                println!("🔍 Successfully served {} bytes over async HTTPS", final_response_bytes.len());
+                log::debug!(target: "files", "successfully served {} bytes over async HTTPS", final_response_bytes.len());
             }
             Ok(None) => {
                 // File not found - check if this is a root request (index.html missing)
@@ -1923,7 +2920,7 @@ impl OnDemandHttpsServer {
                 }
             }
             Err(error_msg) => {
-                eprintln!("{}", error_msg);
+                log::info!(target: "files", error:% = error_msg; "request failed");
                 let response = "HTTP/1.1 500 Internal Server Error\r\nContent-Type: text/plain\r\nContent-Length: 21\r\n\r\n500 Internal Server Error";
                 let response_bytes = response.as_bytes().to_vec();
                 tls_stream.write_all(&response_bytes).await?;
@@ -1945,7 +2942,7 @@ impl OnDemandHttpsServer {
         http_challenges: Arc<Mutex<BTreeMap<String, String>>>,
         secure_file_server: SecureFileServer,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        println!("🔍 Starting blocking HTTPS connection handling");
+        log::debug!(target: "tls", "starting blocking HTTPS connection handling");
 
         // Create server config with our certificate resolver
         // Use ring provider for better Safari 6-8 compatibility
@@ -1955,11 +2952,11 @@ impl OnDemandHttpsServer {
         .with_no_client_auth()
         .with_cert_resolver(cert_resolver)
         .map_err(|e| {
-            println!("❌ Failed to create server config: {}", e);
+            log::error!(target: "tls", "failed to create server config: {}", e);
             format!("Failed to create server config: {}", e)
         })?;
 
-        println!("✅ Server config created successfully");
+        log::debug!(target: "tls", "server config created successfully");
 
         // Create a new acceptor for this connection
         let mut acceptor = Acceptor::default();
@@ -1968,7 +2965,7 @@ impl OnDemandHttpsServer {
         let accepted = loop {
             match acceptor.read_tls(&mut stream) {
                 Ok(0) => {
-                    println!("🔍 Connection closed by client");
+                    log::debug!(target: "tls", "connection closed by client");
                     return Ok(()); // Connection closed
                 }
                         Ok(_) => {
@@ -1980,7 +2977,7 @@ impl OnDemandHttpsServer {
                         continue;
                     }
                         Err((e, mut alert)) => {
-                            println!("❌ Error accepting connection: {}", e);
+                            log::error!(target: "tls", "error accepting connection: {}", e);
                             let _ = alert.write_all(&mut stream);
                             return Err(format!("Error accepting connection: {}", e).into());
                         }
@@ -1991,11 +2988,11 @@ impl OnDemandHttpsServer {
                     continue;
                 }
                 Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                    println!("🔍 Client disconnected unexpectedly");
+                    log::debug!(target: "tls", "client disconnected unexpectedly");
                     return Ok(());
                 }
                 Err(e) => {
-                    println!("❌ Error reading TLS: {}", e);
+                    log::error!(target: "tls", "error reading TLS: {}", e);
                     return Err(e.into());
                 }
             }
@@ -2003,25 +3000,25 @@ impl OnDemandHttpsServer {
 
         // Get the server name from ClientHello
         let server_name = accepted.client_hello().server_name()
-            .map(|name| name.as_ref().to_string())
+            .and_then(|name| normalize_hostname(name.as_ref()).ok())
             .unwrap_or_else(|| "unknown".to_string());
 
         // Complete the TLS handshake
         let mut conn = match accepted.into_connection(Arc::new(server_config)) {
             Ok(conn) => {
-                println!("✅ TLS handshake completed successfully for domain: {}", server_name);
+                log::debug!(target: "tls", domain:% = server_name; "TLS handshake completed");
                 conn
             }
             Err((e, mut alert)) => {
-                println!("❌ Error completing TLS handshake: {}", e);
-                println!("🔍 Error details: {:?}", e);
+                log::error!(target: "tls", "error completing TLS handshake: {}", e);
+                log::debug!(target: "tls", "error details: {:?}", e);
                 let _ = alert.write_all(&mut stream);
                 return Err(format!("Error completing connection: {}", e).into());
             }
         };
 
         // Handle the connection with Keep-Alive support
-        println!("🔍 Starting HTTPS Keep-Alive loop for domain: {}", server_name);
+        log::debug!(target: "tls", "starting HTTPS Keep-Alive loop for domain: {}", server_name);
 
         let connection_policy = ConnectionPolicy::default();
         let mut request_count = 0;
@@ -2029,7 +3026,7 @@ impl OnDemandHttpsServer {
         loop {
             request_count += 1;
 
-            println!("🔍 Processing HTTPS request {} for domain: {}", request_count, server_name);
+            log::debug!(target: "tls", "processing HTTPS request {} for domain: {}", request_count, server_name);
 
             // Process the request and get parsed version/connection info
             let (http_version, connection_header) = self.process_https_request_static_blocking(&mut stream, &mut conn, &server_name, &extension_registry, &http_challenges, &secure_file_server)?;
@@ -2042,16 +3039,16 @@ impl OnDemandHttpsServer {
                 request_count,
             );
 
-            println!("🔍 HTTPS request {} completed (version: {}, keep_alive: {})",
+            log::debug!(target: "tls", "HTTPS request {} completed (version: {}, keep_alive: {})",
                 request_count, http_version, should_keep_alive);
 
             if !should_keep_alive {
-                println!("DEBUG: Closing HTTPS connection after {} requests (version: {})",
+                log::debug!(target: "tls", "closing HTTPS connection after {} requests (version: {})",
                     request_count, http_version);
                 break; // Exit the Keep-Alive loop
             }
 
-            println!("DEBUG: Keeping HTTPS connection alive for next request (version: {})",
+            log::debug!(target: "tls", "keeping HTTPS connection alive for next request (version: {})",
                 http_version);
         }
 
@@ -2070,7 +3067,7 @@ impl OnDemandHttpsServer {
         lines: &[&str],
         extension_registry: &Arc<Mutex<ExtensionRegistry>>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        println!("DEBUG: Admin request detected for path: {}", request_path);
+        log::debug!(target: "admin", "admin request detected for path: {}", request_path);
 
         // Extract HTTP method from the first line
         let http_method = if let Some(first_line) = lines.first() {
@@ -2078,7 +3075,7 @@ impl OnDemandHttpsServer {
         } else {
             "GET"
         };
-        println!("DEBUG: HTTP method: {}", http_method);
+        log::debug!(target: "admin", "HTTP method: {}", http_method);
 
         // Extract query string from the request
         let query_string = if let Some(query_start) = request_path.find('?') {
@@ -2153,18 +3150,18 @@ impl OnDemandHttpsServer {
         http_challenges: &Arc<Mutex<BTreeMap<String, String>>>,
         secure_file_server: &SecureFileServer,
     ) -> Result<(HttpVersion, Option<String>), Box<dyn std::error::Error + Send + Sync>> {
-        println!("🔍 process_https_request_static started for domain: {}", server_name);
+        log::debug!(target: "http", "process_https_request_static started for domain: {}", server_name);
         // TLS handshake is already completed in the main connection handler
-        println!("🔍 TLS handshake already completed for domain: {}", server_name);
+        log::debug!(target: "tls", "TLS handshake already completed for domain: {}", server_name);
 
         // Read HTTP request using the TLS connection
         let mut buffer = [0; 4096];
         let mut total_read = 0;
 
-        println!("🔍 Starting to read HTTP request for domain: {}", server_name);
+        log::debug!(target: "http", "starting to read HTTP request for domain: {}", server_name);
 
         // TLS I/O is already completed in the main connection handler
-        println!("🔍 TLS I/O already completed for domain: {}", server_name);
+        log::debug!(target: "tls", "TLS I/O already completed for domain: {}", server_name);
 
         // Read data in a loop to handle partial reads
         let mut read_attempts = 0;
@@ -2173,7 +3170,7 @@ impl OnDemandHttpsServer {
         loop {
             read_attempts += 1;
             if read_attempts > MAX_READ_ATTEMPTS {
-                println!("🔍 Timeout waiting for HTTP request from domain: {}", server_name);
+                log::debug!(target: "http", "timeout waiting for HTTP request from domain: {}", server_name);
                 return Err("Timeout waiting for HTTP request".into());
             }
 
@@ -2182,40 +3179,39 @@ impl OnDemandHttpsServer {
             // First, try to complete any pending TLS I/O
             match conn.complete_io(stream) {
                 Ok((0, 0)) => {
-                    println!("🔍 Connection closed for domain: {}", server_name);
+                    log::debug!(target: "http", "connection closed for domain: {}", server_name);
                     break; // Connection closed
                 }
                 Ok(_) => {
                     // TLS I/O completed, now try to read data
                     match conn.reader().read(&mut buffer[total_read..]) {
                         Ok(0) => {
-                            println!("🔍 Connection closed for domain: {}", server_name);
+                            log::debug!(target: "http", "connection closed for domain: {}", server_name);
                             break; // Connection closed
                         }
                         Ok(n) => {
-                            println!("🔍 Read {} bytes for domain: {}", n, server_name);
+                            log::debug!(target: "http", "read {} bytes for domain: {}", n, server_name);
                             total_read += n;
                             if total_read >= buffer.len() {
-                                println!("🔍 Buffer full for domain: {}", server_name);
+                                log::debug!(target: "http", "buffer full for domain: {}", server_name);
                                 break; // Buffer full
                             }
                             // Check if we have a complete HTTP request
                             if let Ok(request_str) = std::str::from_utf8(&buffer[..total_read]) {
-                                println!("🔍 Current request data for domain {}: {}", server_name, request_str);
                                 if request_str.contains("\r\n\r\n") {
-                                    println!("🔍 Complete HTTP request received for domain: {}", server_name);
+                                    log::debug!(target: "http", "complete HTTP request received for domain: {}", server_name);
                                     break; // Complete HTTP request received
                                 }
                             }
                         }
                         Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                            println!("🔍 WouldBlock error for domain: {}, waiting... (attempt {})", server_name, read_attempts);
+                            log::debug!(target: "http", "WouldBlock error for domain: {}, waiting... (attempt {})", server_name, read_attempts);
                             // No data available, wait a bit
                             std::thread::sleep(std::time::Duration::from_millis(10));
                             continue;
                         }
                         Err(e) => {
-                            println!("🔍 Read error for domain {}: {}", server_name, e);
+                            log::debug!(target: "http", "read error for domain {}: {}", server_name, e);
                             return Err(e.into());
                         }
                     }
@@ -2227,7 +3223,7 @@ impl OnDemandHttpsServer {
                     continue;
                 }
                 Err(e) => {
-                    println!("🔍 TLS I/O error for domain {}: {}", server_name, e);
+                    log::debug!(target: "tls", "TLS I/O error for domain {}: {}", server_name, e);
                     return Err(e.into());
                 }
             }
@@ -2243,7 +3239,7 @@ impl OnDemandHttpsServer {
         let (http_version, connection_header) = ConnectionPolicy::parse_request_info(&request);
 
         if let Some(first_line) = lines.first() {
-            println!("HTTP Request: {}", first_line);
+            log::debug!(target: "http", request:% = first_line; "HTTP request");
 
                    // Handle HTTP-01 ACME challenges
                    if first_line.starts_with("GET /.well-known/acme-challenge/") {
@@ -2277,19 +3273,19 @@ impl OnDemandHttpsServer {
         // Record request for stats collection
         self.stats_collector.record_request();
 
-        println!("Requested path: {}", request_path);
+        log::debug!(target: "files", path:% = request_path; "requested path");
 
         // Check for admin extension requests
         #[cfg(feature = "extensions")]
         {
-            println!("DEBUG: HTTPS - Checking for admin request with path: {}", request_path);
+            log::debug!(target: "admin", "HTTPS - Checking for admin request with path: {}", request_path);
             let is_admin_request = {
                 let registry = extension_registry.lock().unwrap();
                 registry.is_admin_path(request_path)
             };
-            println!("DEBUG: HTTPS - Admin request check result: {}", is_admin_request);
+            log::debug!(target: "admin", "HTTPS - Admin request check result: {}", is_admin_request);
             if is_admin_request {
-                println!("DEBUG: HTTPS - Admin request detected for path: {}", request_path);
+                log::debug!(target: "admin", "HTTPS - Admin request detected for path: {}", request_path);
                 Self::handle_admin_request_https(stream, conn, request_path, &lines, extension_registry)?;
                 return Ok((http_version.clone(), connection_header.clone()));
             }
@@ -2330,6 +3326,7 @@ impl OnDemandHttpsServer {
                     headers.insert(header_name, header_value);
                 }
             }
+            client_auth::apply_headers(&mut headers, None);
 
             // Handle bin extension request
             let response = {
@@ -2415,7 +3412,7 @@ impl OnDemandHttpsServer {
             }
             Err(e) => {
                 // Security error or other error - check if this is a root request first
-                println!("Request denied for {}: {}", request_path, e);
+                log::info!(target: "files", path:% = request_path, error:% = e; "request denied");
                 if secure_file_server.is_root_request(request_path) {
                     // Serve default informational page even for security errors on root
                     let default_page = secure_file_server.generate_default_page(server_name);
@@ -2488,7 +3485,7 @@ impl OnDemandHttpsServer {
         let lines: Vec<&str> = request.lines().collect();
 
         if let Some(first_line) = lines.first() {
-            println!("HTTP Request: {}", first_line);
+            log::debug!(target: "http", request:% = first_line; "HTTP request");
 
                    // Handle HTTP-01 ACME challenges
                    if first_line.starts_with("GET /.well-known/acme-challenge/") {
@@ -2511,7 +3508,7 @@ impl OnDemandHttpsServer {
             "/"
         };
 
-        println!("Requested path: {}", request_path);
+        log::debug!(target: "files", path:% = request_path; "requested path");
 
         // Try to serve the requested file using secure file server with caching support
         match self.secure_file_server.serve_file_with_domain_and_caching(
@@ -2534,7 +3531,7 @@ impl OnDemandHttpsServer {
             }
             Err(e) => {
                 // Security error or other error, send 403 or 404 for security
-                println!("Request denied for {}: {}", request_path, e);
+                log::info!(target: "files", path:% = request_path, error:% = e; "request denied");
                 Self::send_error_response(conn, stream, 404, "Not Found")?;
             }
         }
@@ -2624,7 +3621,7 @@ impl OnDemandHttpsServer {
 
     /// Handle HTTP-01 ACME challenge over HTTP (port 80)
     async fn handle_acme_challenge_http(
-        mut stream: tokio::net::TcpStream,
+        mut stream: AccessRecorder<tokio::net::TcpStream>,
         request_line: &str,
         acme_client: &Option<Arc<AcmeClient>>,
         http_challenges: &Arc<Mutex<BTreeMap<String, String>>>,
@@ -2634,13 +3631,14 @@ impl OnDemandHttpsServer {
         let path = request_line.split_whitespace().nth(1).unwrap_or("");
         let token = path.strip_prefix("/.well-known/acme-challenge/").unwrap_or("");
 
-        println!("ACME HTTP-01 challenge request for token: {}", token);
+        log::debug!(target: "acme", challenge:% = token; "ACME HTTP-01 challenge request");
 
         // Look up the key authorization for this token
         let key_authorization = Self::get_challenge_response_from_params(acme_client, http_challenges, token);
+        metrics::record_acme_challenge(key_authorization.is_some());
 
         let response = if let Some(key_auth) = key_authorization {
-            println!("Serving challenge response for token: {}", token);
+            log::debug!(target: "acme", challenge:% = token; "serving challenge response");
 
             // Use HttpResponse builder for version-aware response (default to HTTP/1.1 for HTTP)
             let mut response = HttpResponse::ok(key_auth.as_bytes().to_vec());
@@ -2651,7 +3649,7 @@ impl OnDemandHttpsServer {
             let response_bytes = response.encode(&HttpVersion::Http11, false); // Default to close for HTTP
             response_bytes
         } else {
-            println!("Challenge token not found: {}", token);
+            log::info!(target: "acme", challenge:% = token; "challenge token not found");
 
             // Use HttpResponse builder for version-aware response (default to HTTP/1.1 for HTTP)
             let mut response = HttpResponse::not_found(b"Not Found".to_vec());
@@ -2681,13 +3679,14 @@ impl OnDemandHttpsServer {
         let path = request_line.split_whitespace().nth(1).unwrap_or("");
         let token = path.strip_prefix("/.well-known/acme-challenge/").unwrap_or("");
 
-        println!("ACME HTTP-01 challenge request for token: {}", token);
+        log::debug!(target: "acme", challenge:% = token; "ACME HTTP-01 challenge request");
 
         // Look up the key authorization for this token
         let key_authorization = Self::get_challenge_response_from_params_static(acme_client, http_challenges, token);
+        metrics::record_acme_challenge(key_authorization.is_some());
 
         let response = if let Some(key_auth) = key_authorization {
-            println!("Serving challenge response for token: {}", token);
+            log::debug!(target: "acme", challenge:% = token; "serving challenge response");
 
             // Use HttpResponse builder for version-aware response (default to HTTP/1.1 for HTTPS)
             let mut response = HttpResponse::ok(key_auth.as_bytes().to_vec());
@@ -2698,7 +3697,7 @@ impl OnDemandHttpsServer {
             let response_bytes = response.encode(&HttpVersion::Http11, false); // Default to close for HTTPS
             response_bytes
         } else {
-            println!("Challenge token not found: {}", token);
+            log::info!(target: "acme", challenge:% = token; "challenge token not found");
 
             // Use HttpResponse builder for version-aware response (default to HTTP/1.1 for HTTPS)
             let mut response = HttpResponse::not_found(b"Not Found".to_vec());
@@ -2729,13 +3728,14 @@ impl OnDemandHttpsServer {
         let path = request_line.split_whitespace().nth(1).unwrap_or("");
         let token = path.strip_prefix("/.well-known/acme-challenge/").unwrap_or("");
 
-        println!("ACME HTTP-01 challenge request for token: {}", token);
+        log::debug!(target: "acme", challenge:% = token; "ACME HTTP-01 challenge request");
 
         // Look up the key authorization for this token
         let key_authorization = Self::get_challenge_response_from_params(acme_client, http_challenges, token);
+        metrics::record_acme_challenge(key_authorization.is_some());
 
         let response = if let Some(key_auth) = key_authorization {
-            println!("Serving challenge response for token: {}", token);
+            log::debug!(target: "acme", challenge:% = token; "serving challenge response");
 
             // Use HttpResponse builder for version-aware response (default to HTTP/1.1 for HTTPS)
             let mut response = HttpResponse::ok(key_auth.as_bytes().to_vec());
@@ -2746,7 +3746,7 @@ impl OnDemandHttpsServer {
             let response_bytes = response.encode(&HttpVersion::Http11, false); // Default to close for HTTPS
             response_bytes
         } else {
-            println!("Challenge token not found: {}", token);
+            log::info!(target: "acme", challenge:% = token; "challenge token not found");
 
             // Use HttpResponse builder for version-aware response (default to HTTP/1.1 for HTTPS)
             let mut response = HttpResponse::not_found(b"Not Found".to_vec());
@@ -2892,12 +3892,70 @@ fn ensure_tmp_acme_permissions(uid: u32, gid: u32) -> Result<(), Box<dyn std::er
         return Err(format!("Failed to set permissions of /tmp/acme_certs directory: {}", String::from_utf8_lossy(&output.stderr)).into());
     }
 
-    println!("Certificate cache directory permissions set: {} (owner: {})", tmp_acme_dir, uid);
+    log::info!(target: "tls", "certificate cache directory permissions set: {} (owner: {})", tmp_acme_dir, uid);
     Ok(())
 }
 
 // DomainRequestLogger removed - was unused dead code
 
+/// Publish the notAfter time of every certificate found under the given directories
+///
+/// Looks at `*.pem`, `*.crt` and rustls-acme `cached_cert_*` files (two levels deep) and
+/// labels each by its first DNS name, falling back to the directory name.
+///
+/// # Arguments
+/// * `dirs` - Certificate and ACME cache directories to scan
+/// * `files` - Individual certificate files (from `--cert` and `--default-cert`)
+fn update_certificate_expiry(dirs: &[String], files: &[PathBuf]) {
+    fn scan(dir: &std::path::Path, depth: usize) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                if depth > 0 {
+                    scan(&path, depth - 1);
+                }
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !(name.ends_with(".pem") || name.ends_with(".crt") || name.starts_with("cached_cert")) || name.contains("key") {
+                continue;
+            }
+            if let Some((domain, not_after)) = certificate_expiry(&path) {
+                metrics::set_certificate_expiry(&domain, not_after);
+            }
+        }
+    }
+
+    for dir in dirs {
+        scan(std::path::Path::new(dir), 2);
+    }
+    // Statically configured certificates live outside the cache directories
+    for file in files {
+        if let Some((domain, not_after)) = certificate_expiry(file) {
+            metrics::set_certificate_expiry(&domain, not_after);
+        }
+    }
+}
+
+/// Read the leaf certificate of a PEM file and return (domain, notAfter as Unix time)
+fn certificate_expiry(path: &std::path::Path) -> Option<(String, i64)> {
+    let file = std::fs::File::open(path).ok()?;
+    let der = rustls_pemfile::certs(&mut std::io::BufReader::new(file)).next()?.ok()?;
+    let params = rcgen::CertificateParams::from_ca_cert_der(&der).ok()?;
+    let domain = params
+        .subject_alt_names
+        .iter()
+        .find_map(|san| match san {
+            rcgen::SanType::DnsName(name) => Some(name.as_str().to_string()),
+            _ => None,
+        })
+        .or_else(|| path.parent()?.file_name().map(|dir| dir.to_string_lossy().to_string()))?;
+    Some((domain, params.not_after.unix_timestamp()))
+}
+
 
 /// Ensure certificate cache directory has proper ownership for www-data
 fn ensure_cert_cache_permissions(cache_dir: &str, uid: u32, gid: u32) -> Result<(), Box<dyn std::error::Error>> {
@@ -2944,7 +4002,7 @@ fn ensure_cert_cache_permissions(cache_dir: &str, uid: u32, gid: u32) -> Result<
         return Err(format!("Failed to set permissions of certificate cache directory: {}", String::from_utf8_lossy(&output.stderr)).into());
     }
 
-    println!("Certificate cache directory permissions set: {} (owner: {})", cache_dir, uid);
+    log::info!(target: "tls", "certificate cache directory permissions set: {} (owner: {})", cache_dir, uid);
     Ok(())
 }
 
@@ -2978,8 +4036,8 @@ fn ensure_acme_cache_directory(cache_dir: &str, uid: u32, gid: u32) -> Result<()
         return Err(format!("Failed to set permissions of ACME cache directory '{}': {}", cache_dir, String::from_utf8_lossy(&output.stderr)).into());
     }
 
-    println!("ACME cache directory permissions set: {} (owner: {})", cache_dir, uid);
-    println!("ACME lib directory created: {}", acme_lib_dir);
+    log::info!(target: "acme", "ACME cache directory permissions set: {} (owner: {})", cache_dir, uid);
+    log::info!(target: "acme", "ACME lib directory created: {}", acme_lib_dir);
     Ok(())
 }
 
@@ -3038,7 +4096,7 @@ fn ensure_www_data_user() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    println!("www-data user not found, creating...");
+    log::info!(target: "server", "www-data user not found, creating...");
 
     // Create www-data group first
     #[cfg(not(target_os = "redox"))]
@@ -3069,20 +4127,20 @@ fn ensure_www_data_user() -> Result<(), Box<dyn std::error::Error>> {
 
     // then sleep for a second
     //std::thread::sleep(std::time::Duration::from_secs(10));
-    println!("user_output: {:?}", user_output);
-    println!("user_output.status: {:?}", user_output.status);
-    println!("user_output.stdout: {:?}", user_output.stdout);
-    println!("user_output.stderr: {:?}", user_output.stderr);
+    log::debug!(target: "server", "user_output: {:?}", user_output);
+    log::debug!(target: "server", "user_output.status: {:?}", user_output.status);
+    log::debug!(target: "server", "user_output.stdout: {:?}", user_output.stdout);
+    log::debug!(target: "server", "user_output.stderr: {:?}", user_output.stderr);
 
     // Print the contents of /etc/passwd for diagnostic purposes
 
         use std::fs;
         match fs::read_to_string("/etc/passwd") {
             Ok(contents) => {
-                println!("Contents of /etc/passwd:\n{}", contents);
+                log::debug!(target: "server", "contents of /etc/passwd:\n{}", contents);
             }
             Err(e) => {
-                println!("Failed to read /etc/passwd: {}", e);
+                log::warn!(target: "server", "failed to read /etc/passwd: {}", e);
             }
         }
     }
@@ -3097,7 +4155,7 @@ fn ensure_www_data_user() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    println!("✅ www-data user created successfully");
+    log::info!(target: "server", "www-data user created successfully");
     Ok(())
 }
 
@@ -3152,6 +4210,16 @@ async fn is_port_80_available() -> bool {
     }
 }
 
+/// Map the selected challenge onto the ACME client's challenge type
+#[cfg(feature = "acme")]
+fn acme_challenge_type(kind: ChallengeKind) -> ChallengeType {
+    match kind {
+        ChallengeKind::Http01 => ChallengeType::Http01("".to_string(), "".to_string()),
+        ChallengeKind::TlsAlpn01 => ChallengeType::TlsAlpn01("".to_string(), "".to_string()),
+        ChallengeKind::Dns01 => ChallengeType::Dns01("".to_string(), "".to_string()),
+    }
+}
+
 /// Look up the UID and GID for the www-data user
 #[cfg(unix)]
 fn get_www_data_uid_gid() -> Result<(u32, u32), Box<dyn std::error::Error>> {
@@ -3200,7 +4268,7 @@ fn get_www_data_uid_gid() -> Result<(u32, u32), Box<dyn std::error::Error>> {
 /// Fallback for non-UNIX systems - returns dummy values
 #[cfg(not(unix))]
 fn get_www_data_uid_gid() -> Result<(u32, u32), Box<dyn std::error::Error>> {
-    println!("Warning: Running without unix feature - using dummy UID/GID values");
+    log::warn!(target: "server", "running without unix feature - using dummy UID/GID values");
     Ok((1000, 1000)) // Dummy values for non-UNIX systems
 }
 
@@ -3231,7 +4299,7 @@ fn detect_server_ips() -> Result<Vec<IpAddr>, Box<dyn std::error::Error>> {
         }
     }
 
-    println!("Detected {} IP addresses: {:?}", ip_addresses.len(), ip_addresses);
+    log::info!(target: "server", "detected {} IP addresses: {:?}", ip_addresses.len(), ip_addresses);
     Ok(ip_addresses)
 }
 
@@ -3265,8 +4333,8 @@ fn get_admin_keys() -> Result<std::collections::HashSet<String>, Box<dyn std::er
     let admin_keys_file = std::path::Path::new(&admin_keys_path);
     let mut admin_keys = std::collections::HashSet::new();
 
-    println!("DEBUG: Checking admin keys file: {:?}", admin_keys_file);
-    println!("DEBUG: File exists: {}", admin_keys_file.exists());
+    log::debug!(target: "admin", "checking admin keys file: {:?}", admin_keys_file);
+    log::debug!(target: "admin", "file exists: {}", admin_keys_file.exists());
 
     if admin_keys_file.exists() {
         if let Ok(content) = file_ops::read_to_string(admin_keys_file) {
@@ -3320,6 +4388,12 @@ fn get_domains(cache_dir: &str) -> Result<Vec<String>, Box<dyn std::error::Error
                                         file_name.trim_end_matches(".crt").trim_end_matches(".pem").to_string()
                                     };
 
+                                    // Normalise (lowercase, punycode) and skip anything that isn't a host name
+                                    let domain = match normalize_hostname(&domain) {
+                                        Ok(domain) => domain,
+                                        Err(_) => continue,
+                                    };
+
                                     // Filter out non-domain filenames (like "fullchain", "privkey", etc.)
                                     if !domain.is_empty()
                                         && !domains.contains(&domain)
@@ -3378,13 +4452,13 @@ fn check_constant_extensions_only(body: &str) -> bool {
 
                     // Check if this is a dynamic extension
                     if dynamic_extensions.contains(&ext_name) {
-                        println!("DEBUG: Found dynamic extension: {}", ext_name);
+                        log::debug!(target: "extensions", "found dynamic extension: {}", ext_name);
                         return false;
                     }
 
                     // If it's not in our constant list, assume it's dynamic for safety
                     if !constant_extensions.contains(&ext_name) {
-                        println!("DEBUG: Unknown extension '{}', assuming dynamic", ext_name);
+                        log::debug!(target: "extensions", "unknown extension '{}', assuming dynamic", ext_name);
                         return false;
                     }
                 }
@@ -3394,7 +4468,7 @@ fn check_constant_extensions_only(body: &str) -> bool {
         start = full_pos + 1;
     }
 
-    println!("DEBUG: Only constant extensions found");
+    log::debug!(target: "extensions", "only constant extensions found");
     true
 }
 
@@ -3405,8 +4479,6 @@ fn print_admin_urls(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
 
     // Generate keys for any missing extensions
     let available_extensions = get_available_admin_extensions()?;
-    println!("DEBUG: Available extensions: {:?}", available_extensions);
-    println!("DEBUG: Current admin keys: {:?}", admin_keys.iter().collect::<Vec<_>>());
     let mut needs_update = false;
 
     for ext_name in available_extensions {
@@ -3487,15 +4559,13 @@ fn save_admin_keys(admin_keys: &std::collections::HashSet<String>) -> Result<(),
     }
 
     file_ops::write(admin_keys_file, content)?;
-    println!("Admin keys saved to file");
+    log::info!(target: "admin", "admin keys saved to file");
 
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    debug_extensions_enabled();
-        println!("🚀 easyp HTTPS Server starting - Debug version with enhanced ACME integration");
     let args = Args::parse().unwrap_or_else(|e| {
         eprintln!("Error parsing arguments: {}", e);
         eprintln!("Use --help for usage information");
@@ -3508,15 +4578,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
-    // Initialize simple logging
-    if args.verbose {
-        log::set_max_level(log::LevelFilter::Debug);
+    // Route all diagnostics through the structured logger; --verbose raises the default to debug
+    let log_levels = if args.verbose {
+        args.log_levels.clone().with_default(log::LevelFilter::Debug)
     } else {
-        log::set_max_level(log::LevelFilter::Info);
+        args.log_levels.clone()
+    };
+    if let Err(e) = init_logging(log_levels, args.log_output) {
+        eprintln!("Error: {}", e);
     }
-
-    // Simple console logger without regex dependency
-    log::set_logger(&SimpleLogger).unwrap();
+    #[cfg(unix)]
+    logging::spawn_debug_toggle_on_sigusr2();
+    log::info!(target: "server", version = env!("CARGO_PKG_VERSION"); "easyp HTTPS server starting");
+    debug_extensions_enabled();
 
     // Initialize file logging with per-user directories
     let log_file_path = if is_running_as_root() {
@@ -3527,21 +4601,79 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         format!("{}/.local/share/easyp/server.log", home_dir)
     };
 
-    println!("DEBUG: Attempting to initialize file logger at: {}", log_file_path);
-    if let Err(e) = init_file_logger(&log_file_path) {
-        eprintln!("Warning: Failed to initialize file logger: {}", e);
+    log::debug!(target: "server", "attempting to initialize file logger at: {}", log_file_path);
+    if let Err(e) = init_file_logger(&log_file_path, args.log_rotation.clone()) {
+        log::warn!(target: "server", "failed to initialize file logger: {}", e);
         // Fallback to /tmp if user directory is not accessible
         let fallback_path = "/tmp/easyp.log";
-        println!("DEBUG: Attempting fallback file logger at: {}", fallback_path);
-        if let Err(e2) = init_file_logger(fallback_path) {
-            eprintln!("Warning: Failed to initialize fallback file logger: {}", e2);
+        log::debug!(target: "server", "attempting fallback file logger at: {}", fallback_path);
+        if let Err(e2) = init_file_logger(fallback_path, args.log_rotation.clone()) {
+            log::warn!(target: "server", "failed to initialize fallback file logger: {}", e2);
         } else {
-            println!("📝 File logging initialized: {}", fallback_path);
+            log::info!(target: "server", "file logging initialized: {}", fallback_path);
         }
     } else {
-        println!("📝 File logging initialized: {}", log_file_path);
+        log::info!(target: "server", "file logging initialized: {}", log_file_path);
+    }
+
+    // Access log goes next to the server log unless a path is given or it is turned off
+    let mut access_log = args.access_log.clone();
+    access_log.rotation = args.log_rotation.clone();
+    access_log.vhosts = args.vhosts.clone();
+    if access_log.path.is_none() && !args.access_log_off {
+        access_log.path = Some(std::path::Path::new(&log_file_path).with_file_name("access.log"));
+    }
+    if let Some(ref path) = access_log.path {
+        log::info!(target: "server", "access log: {}", path.display());
+    }
+    if let Err(e) = init_access_log(access_log) {
+        log::warn!(target: "server", "failed to initialize access log: {}", e);
+    }
+    #[cfg(unix)]
+    log_rotation::spawn_reopen_on_sigusr1();
+
+    // Health probes: path on the site listeners, optional separate listener
+    health::set_path_prefix(args.health_path.clone());
+    health::spawn_heartbeat();
+    if let Some(addr) = args.health_listen {
+        tokio::spawn(async move {
+            if let Err(e) = health::run_health_listener(addr).await {
+                log::warn!(target: "server", "health listener on {} failed: {}", addr, e);
+            }
+        });
     }
 
+    // Prometheus metrics: optional plain listener plus periodic certificate expiry scan
+    if let Some(addr) = args.metrics_listen {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::run_metrics_listener(addr).await {
+                log::warn!(target: "server", "metrics listener on {} failed: {}", addr, e);
+            }
+        });
+    }
+    let cert_files: Vec<PathBuf> = args
+        .static_certs
+        .iter()
+        .map(|spec| spec.cert.clone())
+        .chain(args.default_cert.iter().map(|(cert, _)| cert.clone()))
+        .collect();
+    let cert_dirs = vec![
+        if is_running_as_root() {
+            "/var/lib/easyp/certs".to_string()
+        } else {
+            format!("{}/.local/share/easyp/certs", std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string()))
+        },
+        args.cache_dir.clone(),
+    ];
+    tokio::spawn(async move {
+        loop {
+            let dirs = cert_dirs.clone();
+            let files = cert_files.clone();
+            let _ = tokio::task::spawn_blocking(move || update_certificate_expiry(&dirs, &files)).await;
+            tokio::time::sleep(Duration::from_secs(600)).await;
+        }
+    });
+
     // Initialize hourly stats collector with per-user directories
     let stats_file = if is_running_as_root() {
         "/var/lib/easyp/stats/hourly_stats.json".to_string()
@@ -3554,10 +4686,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Create stats directory if it doesn't exist
     if let Some(parent) = std::path::Path::new(&stats_file).parent() {
         if let Err(e) = std::fs::create_dir_all(parent) {
-            eprintln!("⚠️  Warning: Failed to create stats directory '{}': {}", parent.display(), e);
-            eprintln!("ℹ️  Hourly statistics collection may not work properly");
+            log::warn!(target: "stats", "failed to create stats directory '{}': {}", parent.display(), e);
+            log::warn!(target: "stats", "hourly statistics collection may not work properly");
         } else {
-            println!("📊 Stats directory ready: {}", parent.display());
+            log::info!(target: "stats", "stats directory ready: {}", parent.display());
 
             // Set ownership to www-data if running as root (before dropping privileges)
             #[cfg(unix)]
@@ -3565,21 +4697,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 // We need to get www-data UID/GID here since they're not in scope
                 if let Ok((www_data_uid, www_data_gid)) = get_www_data_uid_gid() {
                     if let Err(e) = std::os::unix::fs::chown(parent, Some(www_data_uid), Some(www_data_gid)) {
-                        eprintln!("⚠️  Warning: Failed to set stats directory ownership: {}", e);
+                        log::warn!(target: "stats", "failed to set stats directory ownership: {}", e);
                     } else {
-                        println!("📊 Stats directory ownership set to www-data: {}", parent.display());
+                        log::info!(target: "stats", "stats directory ownership set to www-data: {}", parent.display());
                     }
                 } else {
-                    eprintln!("⚠️  Warning: Could not get www-data UID/GID for stats directory ownership");
+                    log::warn!(target: "stats", "could not get www-data UID/GID for stats directory ownership");
                 }
             }
         }
     }
 
 
-    println!("DEBUG: Attempting to initialize stats collector at: {}", stats_file);
-    let stats_collector = Arc::new(HourlyStatsCollector::new(stats_file));
-    println!("DEBUG: Stats collector initialized successfully");
+    log::debug!(target: "stats", "attempting to initialize stats collector at: {}", stats_file);
+    let stats_collector = Arc::new(HourlyStatsCollector::new(stats_file.clone()));
+    log::debug!(target: "stats", "stats collector initialized successfully");
 
     // Start background stats collection task
     let stats_collector_clone = stats_collector.clone();
@@ -3587,11 +4719,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         start_stats_collection_task(stats_collector_clone).await;
     });
 
+    // Per-domain traffic rollups live next to the hourly samples
+    let traffic_file = std::path::Path::new(&stats_file).with_file_name("traffic.stats");
+    health::add_stats_file(std::path::PathBuf::from(&stats_file));
+    health::add_stats_file(traffic_file.clone());
+    init_traffic_stats(Some(traffic_file), args.stats_retention.clone());
+    tokio::spawn(start_traffic_stats_task());
+
     // Create and run server
-    println!("DEBUG: Attempting to create OnDemandHttpsServer");
+    log::debug!(target: "server", "attempting to create OnDemandHttpsServer");
     let server = OnDemandHttpsServer::new(args, stats_collector).await?;
-    println!("DEBUG: OnDemandHttpsServer created successfully");
-    println!("DEBUG: Starting server run loop");
+    log::debug!(target: "server", "OnDemandHttpsServer created successfully");
+    log::debug!(target: "server", "starting server run loop");
     server.run().await?;
 
     Ok(())
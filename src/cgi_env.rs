@@ -1,5 +1,8 @@
 // cgi_env.rs - Minimal CGI environment for extensions
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+
+use super::client_auth::is_client_cert_header;
 
 #[derive(Debug)]
 pub struct CgiEnv {
@@ -38,6 +41,174 @@ impl CgiEnv {
     }
 }
 
+/// Builder for the RFC 3875 meta-variables passed to external CGI/FastCGI programs
+#[derive(Debug, Clone)]
+pub struct CgiVarsBuilder {
+    vars: BTreeMap<String, String>,
+}
+
+impl CgiVarsBuilder {
+    /// Start from a request: method, URI, query, host and `HTTP_*` header variables
+    pub fn new(env: &CgiEnv) -> Self {
+        let mut vars = BTreeMap::new();
+        vars.insert("GATEWAY_INTERFACE".to_string(), "CGI/1.1".to_string());
+        vars.insert("SERVER_SOFTWARE".to_string(), format!("easyp/{}", env!("CARGO_PKG_VERSION")));
+        vars.insert("SERVER_PROTOCOL".to_string(), "HTTP/1.1".to_string());
+        vars.insert("REQUEST_METHOD".to_string(), env.method.clone());
+        vars.insert("REQUEST_URI".to_string(), env.request_uri.clone());
+        vars.insert("QUERY_STRING".to_string(), env.query_string.clone());
+        vars.insert("SERVER_NAME".to_string(), env.host.clone());
+        vars.insert("REQUEST_SCHEME".to_string(), "http".to_string());
+
+        for (name, value) in &env.headers {
+            let name = name.to_ascii_lowercase();
+            match name.as_str() {
+                "content-type" => {
+                    vars.insert("CONTENT_TYPE".to_string(), value.clone());
+                }
+                "content-length" => {
+                    vars.insert("CONTENT_LENGTH".to_string(), value.clone());
+                }
+                // Never expose a client Proxy header as HTTP_PROXY (httpoxy)
+                "proxy" => {}
+                // Nor a client certificate the TLS layer did not verify
+                name if is_client_cert_header(name) => {}
+                _ => {
+                    let var = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+                    vars.insert(var, value.clone());
+                }
+            }
+        }
+
+        Self { vars }
+    }
+
+    /// Set the script location: SCRIPT_NAME, SCRIPT_FILENAME, PATH_INFO, PATH_TRANSLATED, DOCUMENT_ROOT
+    pub fn script(mut self, script_name: &str, script_filename: &str, path_info: &str, document_root: &str) -> Self {
+        self.vars.insert("SCRIPT_NAME".to_string(), script_name.to_string());
+        self.vars.insert("SCRIPT_FILENAME".to_string(), script_filename.to_string());
+        self.vars.insert("DOCUMENT_ROOT".to_string(), document_root.to_string());
+        self.vars.insert("PATH_INFO".to_string(), path_info.to_string());
+        if !path_info.is_empty() {
+            self.vars
+                .insert("PATH_TRANSLATED".to_string(), format!("{}{}", document_root.trim_end_matches('/'), path_info));
+        }
+        self
+    }
+
+    /// Set REMOTE_ADDR and REMOTE_PORT
+    pub fn remote(mut self, addr: SocketAddr) -> Self {
+        self.vars.insert("REMOTE_ADDR".to_string(), addr.ip().to_string());
+        self.vars.insert("REMOTE_PORT".to_string(), addr.port().to_string());
+        self
+    }
+
+    /// Set SERVER_ADDR, SERVER_PORT and, for TLS connections, HTTPS and REQUEST_SCHEME
+    pub fn server(mut self, addr: SocketAddr, https: bool) -> Self {
+        self.vars.insert("SERVER_ADDR".to_string(), addr.ip().to_string());
+        self.vars.insert("SERVER_PORT".to_string(), addr.port().to_string());
+        if https {
+            self.vars.insert("HTTPS".to_string(), "on".to_string());
+            self.vars.insert("REQUEST_SCHEME".to_string(), "https".to_string());
+        }
+        self
+    }
+
+    /// Set SERVER_PROTOCOL (e.g. `HTTP/1.0`)
+    pub fn protocol(mut self, protocol: &str) -> Self {
+        self.vars.insert("SERVER_PROTOCOL".to_string(), protocol.to_string());
+        self
+    }
+
+    /// Set any other variable
+    pub fn var(mut self, name: &str, value: &str) -> Self {
+        self.vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Finish, returning the variables sorted by name
+    pub fn build(self) -> Vec<(String, String)> {
+        self.vars.into_iter().collect()
+    }
+}
+
+/// Find the end of the CGI header block, accepting `\r\n\r\n` or `\n\n`
+pub fn find_cgi_head_end(buf: &[u8]) -> Option<(usize, usize)> {
+    let crlf = buf.windows(4).position(|w| w == b"\r\n\r\n").map(|pos| (pos, 4));
+    let lf = buf.windows(2).position(|w| w == b"\n\n").map(|pos| (pos, 2));
+    match (crlf, lf) {
+        (Some(a), Some(b)) => Some(if a.0 <= b.0 { a } else { b }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Translate CGI response headers (`Status`, `Location`, ...) into an HTTP response head
+///
+/// # Returns
+/// * `(u16, String)` - Status code and the head to send, ending in `Connection: close` and a blank line
+pub fn cgi_response_head(cgi_head: &str) -> (u16, String) {
+    let mut status = None;
+    let mut location = false;
+    let mut headers = String::new();
+    for line in cgi_head.lines() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, value) = (name.trim(), value.trim());
+        match name.to_ascii_lowercase().as_str() {
+            "status" => {
+                let mut parts = value.splitn(2, ' ');
+                let code = parts.next().and_then(|c| c.parse::<u16>().ok());
+                let reason = parts.next().unwrap_or("").trim().to_string();
+                status = code.map(|code| (code, reason));
+            }
+            // Hop-by-hop headers are ours to set
+            "connection" | "keep-alive" | "transfer-encoding" => {}
+            lower => {
+                location |= lower == "location";
+                headers.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+    }
+
+    let (code, reason) = match status {
+        Some((code, reason)) if (100..=599).contains(&code) => {
+            let reason = if reason.is_empty() { default_reason(code).to_string() } else { reason };
+            (code, reason)
+        }
+        _ if location => (302, "Found".to_string()),
+        _ => (200, "OK".to_string()),
+    };
+    if !headers.to_ascii_lowercase().contains("content-type:") && code != 204 && code != 304 && !location {
+        headers.push_str("Content-Type: text/html; charset=utf-8\r\n");
+    }
+
+    (code, format!("HTTP/1.1 {} {}\r\n{}Connection: close\r\n\r\n", code, reason, headers))
+}
+
+/// Reason phrase for a status the script gave without one
+fn default_reason(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
 pub fn url_decode(s: &str) -> String {
     // Simple URL decoding - replace %20 with space, etc.
     s.replace("%20", " ")
@@ -0,0 +1,852 @@
+//! Access Logging
+//!
+//! This module writes one line per HTTP request, on every transport, to an access log.
+//! It provides:
+//! - Common Log Format, Combined Log Format (readable by GoAccess and friends), JSON
+//!   lines, or a custom Apache-style format string (`%h %t "%r" %>s %b %{Referer}i` ...)
+//! - Per-domain log files from a path template (`/var/log/easyp/{domain}.access.log`)
+//!   for configured vhosts; other hosts stay in the main log
+//! - Buffered writes on a background thread, so request handlers never touch the disk
+//! - Optional client IP anonymisation (IPv4 /24, IPv6 /48)
+//! - Size or daily rotation of every file, shared with the server log (see `log_rotation`)
+//!
+//! Requests are observed by [`AccessRecorder`], a stream wrapper that sees the request
+//! head as it is read and the status line and body size as the response is written.
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::hostname::{is_valid_hostname, parse_host};
+use super::log_rotation::{RotatingFile, RotationPolicy};
+use super::vhost::VhostConfig;
+
+/// Largest request head kept for logging
+const MAX_REQUEST_HEAD: usize = 16 * 1024;
+
+/// Largest response head inspected for the status line and body start
+const MAX_RESPONSE_HEAD: usize = 16 * 1024;
+
+/// Log files kept open at once; beyond this all are flushed and closed
+const MAX_OPEN_FILES: usize = 256;
+
+/// How often buffered lines are flushed when traffic is light
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// One element of a custom format string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatItem {
+    Literal(String),
+    /// `%h` client address
+    RemoteHost,
+    /// `%l` and `%u` (always `-`)
+    Dash,
+    /// `%t` request time in CLF form
+    Time,
+    /// `%r` request line
+    RequestLine,
+    /// `%s` or `%>s` status code
+    Status,
+    /// `%b` body bytes, `-` for zero
+    BodyBytesClf,
+    /// `%B` body bytes
+    BodyBytes,
+    /// `%{Name}i` request header
+    Header(String),
+    /// `%m` method
+    Method,
+    /// `%U` path without query
+    Path,
+    /// `%q` query string including `?`, or empty
+    Query,
+    /// `%H` protocol
+    Protocol,
+    /// `%v` host
+    Host,
+    /// `%D` duration in microseconds
+    Micros,
+    /// `%T` duration in seconds
+    Seconds,
+}
+
+/// Access log line format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `%h %l %u %t "%r" %>s %b`
+    Common,
+    /// Common plus `"%{Referer}i" "%{User-Agent}i"`
+    Combined,
+    /// One JSON object per line
+    Json,
+    /// Apache-style format string
+    Custom(Vec<FormatItem>),
+}
+
+impl LogFormat {
+    /// Parse a format from its command line form
+    ///
+    /// # Arguments
+    /// * `value` - `common`, `combined`, `json` or a format string using `%` directives
+    ///
+    /// # Returns
+    /// * `Result<LogFormat, String>` - The parsed format
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "common" => return Ok(LogFormat::Common),
+            "combined" => return Ok(LogFormat::Combined),
+            "json" => return Ok(LogFormat::Json),
+            _ => {}
+        }
+
+        let mut items = Vec::new();
+        let mut literal = String::new();
+        let mut chars = value.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let item = match chars.next() {
+                Some('%') => {
+                    literal.push('%');
+                    continue;
+                }
+                Some('>') if chars.next_if_eq(&'s').is_some() => FormatItem::Status,
+                Some('{') => {
+                    let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    match chars.next() {
+                        Some('i') if !name.is_empty() => FormatItem::Header(name.to_lowercase()),
+                        _ => return Err(format!("invalid directive '%{{{}}}' in log format (only %{{Header}}i is supported)", name)),
+                    }
+                }
+                Some('h') => FormatItem::RemoteHost,
+                Some('l') | Some('u') => FormatItem::Dash,
+                Some('t') => FormatItem::Time,
+                Some('r') => FormatItem::RequestLine,
+                Some('s') => FormatItem::Status,
+                Some('b') => FormatItem::BodyBytesClf,
+                Some('B') => FormatItem::BodyBytes,
+                Some('m') => FormatItem::Method,
+                Some('U') => FormatItem::Path,
+                Some('q') => FormatItem::Query,
+                Some('H') => FormatItem::Protocol,
+                Some('v') => FormatItem::Host,
+                Some('D') => FormatItem::Micros,
+                Some('T') => FormatItem::Seconds,
+                Some(other) => return Err(format!("unknown directive '%{}' in log format", other)),
+                None => return Err("log format ends with '%'".to_string()),
+            };
+            if !literal.is_empty() {
+                items.push(FormatItem::Literal(std::mem::take(&mut literal)));
+            }
+            items.push(item);
+        }
+        if !literal.is_empty() {
+            items.push(FormatItem::Literal(literal));
+        }
+        Ok(LogFormat::Custom(items))
+    }
+
+    /// Render one log line (without the trailing newline)
+    pub fn render(&self, entry: &AccessEntry) -> String {
+        match self {
+            LogFormat::Common => format!(
+                "{} - - {} \"{}\" {} {}",
+                entry.remote,
+                clf_time(entry.time),
+                escape_clf(&entry.request_line()),
+                entry.status,
+                clf_bytes(entry.body_bytes)
+            ),
+            LogFormat::Combined => format!(
+                "{} - - {} \"{}\" {} {} \"{}\" \"{}\"",
+                entry.remote,
+                clf_time(entry.time),
+                escape_clf(&entry.request_line()),
+                entry.status,
+                clf_bytes(entry.body_bytes),
+                escape_clf(entry.header("referer").unwrap_or("-")),
+                escape_clf(entry.header("user-agent").unwrap_or("-"))
+            ),
+            LogFormat::Json => {
+                let (path, query) = entry.target.split_once('?').unwrap_or((&entry.target, ""));
+                format!(
+                    "{{\"time\":\"{}\",\"remote\":\"{}\",\"host\":{},\"method\":\"{}\",\"path\":\"{}\",\"query\":\"{}\",\"protocol\":\"{}\",\"status\":{},\"bytes\":{},\"referer\":{},\"user_agent\":{},\"duration_ms\":{:.3}}}",
+                    iso_time(entry.time),
+                    entry.remote,
+                    json_opt(entry.host.as_deref()),
+                    json_escape(&entry.method),
+                    json_escape(path),
+                    json_escape(query),
+                    json_escape(&entry.protocol),
+                    entry.status,
+                    entry.body_bytes,
+                    json_opt(entry.header("referer")),
+                    json_opt(entry.header("user-agent")),
+                    entry.duration.as_secs_f64() * 1000.0
+                )
+            }
+            LogFormat::Custom(items) => {
+                let mut line = String::new();
+                for item in items {
+                    match item {
+                        FormatItem::Literal(text) => line.push_str(text),
+                        FormatItem::RemoteHost => line.push_str(&entry.remote.to_string()),
+                        FormatItem::Dash => line.push('-'),
+                        FormatItem::Time => line.push_str(&clf_time(entry.time)),
+                        FormatItem::RequestLine => line.push_str(&escape_clf(&entry.request_line())),
+                        FormatItem::Status => line.push_str(&entry.status.to_string()),
+                        FormatItem::BodyBytesClf => line.push_str(&clf_bytes(entry.body_bytes)),
+                        FormatItem::BodyBytes => line.push_str(&entry.body_bytes.to_string()),
+                        FormatItem::Header(name) => line.push_str(&escape_clf(entry.header(name).unwrap_or("-"))),
+                        FormatItem::Method => line.push_str(&escape_clf(&entry.method)),
+                        FormatItem::Path => line.push_str(&escape_clf(entry.target.split('?').next().unwrap_or(""))),
+                        FormatItem::Query => {
+                            if let Some((_, query)) = entry.target.split_once('?') {
+                                line.push('?');
+                                line.push_str(&escape_clf(query));
+                            }
+                        }
+                        FormatItem::Protocol => line.push_str(&escape_clf(&entry.protocol)),
+                        FormatItem::Host => line.push_str(&escape_clf(entry.host.as_deref().unwrap_or("-"))),
+                        FormatItem::Micros => line.push_str(&entry.duration.as_micros().to_string()),
+                        FormatItem::Seconds => line.push_str(&entry.duration.as_secs().to_string()),
+                    }
+                }
+                line
+            }
+        }
+    }
+}
+
+/// One completed request
+#[derive(Debug, Clone)]
+pub struct AccessEntry {
+    /// Client address
+    pub remote: IpAddr,
+    /// When the request started
+    pub time: SystemTime,
+    /// Request method
+    pub method: String,
+    /// Request target (path and query)
+    pub target: String,
+    /// Protocol from the request line
+    pub protocol: String,
+    /// Host header without port
+    pub host: Option<String>,
+    /// Request headers, names lowercased
+    pub headers: Vec<(String, String)>,
+    /// Response status (499 when the client left before a response)
+    pub status: u16,
+    /// Response body bytes (excluding the head)
+    pub body_bytes: u64,
+    /// Time from the first request byte to the last response byte
+    pub duration: Duration,
+}
+
+impl AccessEntry {
+    /// Look up a request header by lowercase name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    fn request_line(&self) -> String {
+        if self.method.is_empty() {
+            "-".to_string()
+        } else {
+            format!("{} {} {}", self.method, self.target, self.protocol)
+        }
+    }
+}
+
+/// Access log configuration
+#[derive(Debug, Clone)]
+pub struct AccessLogConfig {
+    /// Main access log (None = only per-domain files, or logging off)
+    pub path: Option<PathBuf>,
+    /// Per-domain log path, `{domain}` is replaced by the site serving the request
+    pub domain_template: Option<String>,
+    /// Sites that get per-domain files; lines for any other host go to the main log
+    pub vhosts: VhostConfig,
+    /// Line format
+    pub format: LogFormat,
+    /// Truncate client addresses before writing them
+    pub anonymize_ip: bool,
+    /// Entries queued for the writer before new ones are dropped
+    pub queue_size: usize,
+    /// Rotation of the main and per-domain files
+    pub rotation: RotationPolicy,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            domain_template: None,
+            vhosts: VhostConfig::default(),
+            format: LogFormat::Combined,
+            anonymize_ip: false,
+            queue_size: 8192,
+            rotation: RotationPolicy::default(),
+        }
+    }
+}
+
+impl AccessLogConfig {
+    /// Whether any access log output is configured
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some() || self.domain_template.is_some()
+    }
+
+    /// The file a line for this host goes to
+    ///
+    /// Only hosts a vhost serves get their own file (aliases share their site's),
+    /// so arbitrary Host headers cannot create files.
+    fn path_for(&self, host: Option<&str>) -> Option<PathBuf> {
+        if let (Some(template), Some(host)) = (&self.domain_template, host) {
+            let host = host.to_lowercase();
+            if is_valid_hostname(&host) {
+                if let Some(site) = self.vhosts.site_for(&host) {
+                    return Some(PathBuf::from(template.replace("{domain}", &site)));
+                }
+            }
+        }
+        self.path.clone()
+    }
+}
+
+/// Handle to the background writer
+struct AccessLogger {
+    sender: SyncSender<AccessEntry>,
+    dropped: AtomicU64,
+}
+
+static ACCESS_LOGGER: OnceLock<AccessLogger> = OnceLock::new();
+
+/// Start the access log writer
+///
+/// # Arguments
+/// * `config` - Where and how to write
+///
+/// # Returns
+/// * `Result<(), String>` - Error if the main log cannot be opened or a writer is already running
+pub fn init_access_log(config: AccessLogConfig) -> Result<(), String> {
+    if !config.is_enabled() {
+        return Ok(());
+    }
+    // Fail early on an unusable main log rather than dropping every line later
+    if let Some(path) = &config.path {
+        RotatingFile::open(path, config.rotation.clone())
+            .map_err(|e| format!("cannot open access log {}: {}", path.display(), e))?;
+    }
+
+    let (sender, receiver) = mpsc::sync_channel(config.queue_size);
+    ACCESS_LOGGER
+        .set(AccessLogger { sender, dropped: AtomicU64::new(0) })
+        .map_err(|_| "access log already initialized".to_string())?;
+    std::thread::Builder::new()
+        .name("access-log".to_string())
+        .spawn(move || run_writer(config, receiver))
+        .map_err(|e| format!("cannot start access log writer: {}", e))?;
+    Ok(())
+}
+
+/// Queue an entry for the access log (no-op when logging is off)
+pub fn log_access(entry: AccessEntry) {
+    if let Some(logger) = ACCESS_LOGGER.get() {
+        if let Err(TrySendError::Full(_)) = logger.sender.try_send(entry) {
+            logger.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Entries dropped because the writer fell behind
+pub fn dropped_entries() -> u64 {
+    ACCESS_LOGGER.get().map(|logger| logger.dropped.load(Ordering::Relaxed)).unwrap_or(0)
+}
+
+/// Writer thread: render, route and buffer lines until the channel closes
+fn run_writer(config: AccessLogConfig, receiver: mpsc::Receiver<AccessEntry>) {
+    let mut files: HashMap<PathBuf, BufWriter<RotatingFile>> = HashMap::new();
+    loop {
+        let mut entry = match receiver.recv_timeout(FLUSH_INTERVAL) {
+            Ok(entry) => entry,
+            Err(RecvTimeoutError::Timeout) => {
+                flush_all(&mut files);
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if config.anonymize_ip {
+            entry.remote = anonymize_ip(entry.remote);
+        }
+        let Some(path) = config.path_for(entry.host.as_deref()) else {
+            continue;
+        };
+
+        if !files.contains_key(&path) {
+            if files.len() >= MAX_OPEN_FILES {
+                flush_all(&mut files);
+                files.clear();
+            }
+            match RotatingFile::open(&path, config.rotation.clone()) {
+                Ok(file) => {
+                    files.insert(path.clone(), BufWriter::new(file));
+                }
+                Err(e) => {
+                    log::error!(target: "http", "cannot open access log {}: {}", path.display(), e);
+                    continue;
+                }
+            }
+        }
+        if let Some(writer) = files.get_mut(&path) {
+            let line = config.format.render(&entry);
+            if let Err(e) = writeln!(writer, "{}", line) {
+                log::error!(target: "http", "access log write to {} failed: {}", path.display(), e);
+                files.remove(&path);
+            }
+        }
+    }
+    flush_all(&mut files);
+}
+
+fn flush_all(files: &mut HashMap<PathBuf, BufWriter<RotatingFile>>) {
+    for (path, writer) in files.iter_mut() {
+        if let Err(e) = writer.flush() {
+            log::error!(target: "http", "access log flush to {} failed: {}", path.display(), e);
+        }
+    }
+}
+
+/// Truncate an address: IPv4 to /24, IPv6 to /48
+pub fn anonymize_ip(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(v6) => {
+            let s = v6.segments();
+            IpAddr::V6(Ipv6Addr::new(s[0], s[1], s[2], 0, 0, 0, 0, 0))
+        }
+    }
+}
+
+/// Split a Unix timestamp into UTC (year, month, day, hour, minute, second)
+pub(crate) fn utc_parts(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+/// `[10/Oct/2000:13:55:36 +0000]`
+fn clf_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = utc_parts(time);
+    format!(
+        "[{:02}/{}/{}:{:02}:{:02}:{:02} +0000]",
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        hour,
+        minute,
+        second
+    )
+}
+
+/// `2000-10-10T13:55:36Z`
+fn iso_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = utc_parts(time);
+    format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+}
+
+fn clf_bytes(bytes: u64) -> String {
+    if bytes == 0 {
+        "-".to_string()
+    } else {
+        bytes.to_string()
+    }
+}
+
+/// Escape quotes, backslashes and control characters the way Apache does
+fn escape_clf(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32 & 0xFF)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_opt(value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("\"{}\"", json_escape(value)),
+        None => "null".to_string(),
+    }
+}
+
+/// Stream wrapper that records each request/response pair for the access log
+///
+/// Reads are inspected until the request head is complete; writes are inspected for
+/// the status line and counted after the response head. Call [`AccessRecorder::finish`]
+/// when a response is done (keep-alive loops call it before reading the next request);
+/// whatever is still open is logged when the recorder is dropped. The wrapper derefs to
+/// the inner stream, so address and TLS accessors keep working.
+pub struct AccessRecorder<S> {
+    inner: S,
+    remote: IpAddr,
+    started: Option<(SystemTime, Instant)>,
+    request_head: Vec<u8>,
+    request_done: bool,
+    response_head: Vec<u8>,
+    response_done: bool,
+    body_bytes: u64,
+    last_write: Option<Instant>,
+}
+
+impl<S> AccessRecorder<S> {
+    /// Wrap a client stream
+    pub fn new(inner: S, remote: IpAddr) -> Self {
+        Self {
+            inner,
+            remote,
+            started: None,
+            request_head: Vec::new(),
+            request_done: false,
+            response_head: Vec::new(),
+            response_done: false,
+            body_bytes: 0,
+            last_write: None,
+        }
+    }
+
+    /// Log the current request, if any, and start watching for the next one
+    pub fn finish(&mut self) {
+        if let Some(entry) = self.take_entry() {
+            super::metrics::record_request(
+                entry.host.as_deref(),
+                &entry.method,
+                entry.status,
+                entry.body_bytes,
+                entry.duration,
+            );
+            super::traffic_stats::record_request(
+                entry.time,
+                entry.host.as_deref(),
+                entry.status,
+                entry.body_bytes,
+                entry.duration,
+                entry.remote,
+            );
+            log_access(entry);
+        }
+    }
+
+    /// Build the entry for the current request and reset
+    fn take_entry(&mut self) -> Option<AccessEntry> {
+        let (time, started) = self.started.take()?;
+        let head_end = find_head_end(&self.request_head).unwrap_or(self.request_head.len());
+        let head = String::from_utf8_lossy(&self.request_head[..head_end]).to_string();
+        let mut lines = head.split("\r\n");
+        let mut parts = lines.next().unwrap_or("").split_whitespace();
+        let method = parts.next().unwrap_or("").to_string();
+        let target = parts.next().unwrap_or("").to_string();
+        let protocol = parts.next().unwrap_or("").to_string();
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect();
+        // Malformed Host headers are logged as sent
+        let host = headers.iter().find(|(n, _)| n == "host").map(|(_, value)| match parse_host(value) {
+            Ok((host, _)) => host.to_string(),
+            Err(_) => value.clone(),
+        });
+
+        let status = parse_status(&self.response_head).unwrap_or(499);
+        let duration = self.last_write.unwrap_or_else(Instant::now).saturating_duration_since(started);
+        let entry = AccessEntry {
+            remote: self.remote,
+            time,
+            method,
+            target,
+            protocol,
+            host,
+            headers,
+            status,
+            body_bytes: self.body_bytes,
+            duration,
+        };
+
+        self.request_head.clear();
+        self.request_done = false;
+        self.response_head.clear();
+        self.response_done = false;
+        self.body_bytes = 0;
+        self.last_write = None;
+        Some(entry)
+    }
+
+    fn observe_read(&mut self, data: &[u8]) {
+        if data.is_empty() || self.request_done {
+            return;
+        }
+        if self.started.is_none() {
+            self.started = Some((SystemTime::now(), Instant::now()));
+        }
+        let room = MAX_REQUEST_HEAD.saturating_sub(self.request_head.len());
+        self.request_head.extend_from_slice(&data[..data.len().min(room)]);
+        if find_head_end(&self.request_head).is_some() || self.request_head.len() >= MAX_REQUEST_HEAD {
+            self.request_done = true;
+        }
+    }
+
+    fn observe_write(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        self.last_write = Some(Instant::now());
+        if self.response_done {
+            self.body_bytes += data.len() as u64;
+            return;
+        }
+        let before = self.response_head.len();
+        let room = MAX_RESPONSE_HEAD.saturating_sub(before);
+        self.response_head.extend_from_slice(&data[..data.len().min(room)]);
+        match find_head_end(&self.response_head) {
+            Some(end) => {
+                self.response_done = true;
+                self.body_bytes += (before + data.len()).saturating_sub(end + 4) as u64;
+                self.response_head.truncate(end);
+            }
+            None if self.response_head.len() >= MAX_RESPONSE_HEAD => {
+                self.response_done = true;
+                self.body_bytes += (before + data.len() - MAX_RESPONSE_HEAD) as u64;
+            }
+            None => {}
+        }
+    }
+}
+
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+/// Status code from an `HTTP/1.x NNN ...` status line
+fn parse_status(head: &[u8]) -> Option<u16> {
+    let line = head.split(|&b| b == b'\r').next()?;
+    let line = std::str::from_utf8(line).ok()?;
+    let mut parts = line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+impl<S> Drop for AccessRecorder<S> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl<S> Deref for AccessRecorder<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S> DerefMut for AccessRecorder<S> {
+    fn deref_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for AccessRecorder<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            this.observe_read(&buf.filled()[before..]);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for AccessRecorder<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            this.observe_write(&buf[..n]);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn entry() -> AccessEntry {
+        AccessEntry {
+            remote: "203.0.113.9".parse().unwrap(),
+            // 2000-10-10 13:55:36 UTC
+            time: UNIX_EPOCH + Duration::from_secs(971186136),
+            method: "GET".to_string(),
+            target: "/apache_pb.gif?x=1".to_string(),
+            protocol: "HTTP/1.1".to_string(),
+            host: Some("example.com".to_string()),
+            headers: vec![
+                ("referer".to_string(), "http://www.example.com/start.html".to_string()),
+                ("user-agent".to_string(), "Mozilla/4.08 \"quoted\"".to_string()),
+            ],
+            status: 200,
+            body_bytes: 2326,
+            duration: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn test_builtin_formats() {
+        let entry = entry();
+        assert_eq!(
+            LogFormat::Common.render(&entry),
+            "203.0.113.9 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif?x=1 HTTP/1.1\" 200 2326"
+        );
+        assert_eq!(
+            LogFormat::Combined.render(&entry),
+            "203.0.113.9 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif?x=1 HTTP/1.1\" 200 2326 \"http://www.example.com/start.html\" \"Mozilla/4.08 \\\"quoted\\\"\""
+        );
+        let json = LogFormat::Json.render(&entry);
+        assert!(json.starts_with("{\"time\":\"2000-10-10T13:55:36Z\",\"remote\":\"203.0.113.9\",\"host\":\"example.com\",\"method\":\"GET\",\"path\":\"/apache_pb.gif\",\"query\":\"x=1\""));
+        assert!(json.ends_with("\"user_agent\":\"Mozilla/4.08 \\\"quoted\\\"\",\"duration_ms\":1.500}"));
+    }
+
+    #[test]
+    fn test_custom_format() {
+        let format = LogFormat::parse("%v %h %m %U%q %>s %B %D 100%% %{User-Agent}i %{X-Missing}i").unwrap();
+        assert_eq!(
+            format.render(&entry()),
+            "example.com 203.0.113.9 GET /apache_pb.gif?x=1 200 2326 1500 100% Mozilla/4.08 \\\"quoted\\\" -"
+        );
+        assert!(LogFormat::parse("%Z").is_err());
+        assert!(LogFormat::parse("%{Referer}o").is_err());
+        assert!(LogFormat::parse("trailing %").is_err());
+        assert_eq!(LogFormat::parse("json").unwrap(), LogFormat::Json);
+    }
+
+    #[test]
+    fn test_anonymize_and_routing() {
+        assert_eq!(anonymize_ip("203.0.113.9".parse().unwrap()), "203.0.113.0".parse::<IpAddr>().unwrap());
+        assert_eq!(anonymize_ip("2001:db8:abcd:12::1".parse().unwrap()), "2001:db8:abcd::".parse::<IpAddr>().unwrap());
+        assert_eq!(anonymize_ip("::ffff:198.51.100.7".parse().unwrap()), "198.51.100.0".parse::<IpAddr>().unwrap());
+
+        let sites = std::env::temp_dir().join(format!("easyp_access_sites_{}", std::process::id()));
+        std::fs::create_dir_all(sites.join("example.com")).unwrap();
+        let mut vhosts = VhostConfig { root_template: format!("{}/{{domain}}", sites.display()), ..VhostConfig::default() };
+        vhosts.add_alias_spec("example.com=www.example.com").unwrap();
+        let config = AccessLogConfig {
+            path: Some(PathBuf::from("/logs/access.log")),
+            domain_template: Some("/logs/{domain}.log".to_string()),
+            vhosts,
+            ..AccessLogConfig::default()
+        };
+        assert_eq!(config.path_for(Some("Example.COM")), Some(PathBuf::from("/logs/example.com.log")));
+        assert_eq!(config.path_for(Some("www.example.com")), Some(PathBuf::from("/logs/example.com.log")));
+        assert_eq!(config.path_for(Some("random.example.org")), Some(PathBuf::from("/logs/access.log")));
+        assert_eq!(config.path_for(Some("../etc")), Some(PathBuf::from("/logs/access.log")));
+        assert_eq!(config.path_for(None), Some(PathBuf::from("/logs/access.log")));
+        let _ = std::fs::remove_dir_all(&sites);
+    }
+
+    #[test]
+    fn test_utc_parts() {
+        assert_eq!(utc_parts(UNIX_EPOCH), (1970, 1, 1, 0, 0, 0));
+        // 2024-02-29 23:59:59 UTC
+        assert_eq!(utc_parts(UNIX_EPOCH + Duration::from_secs(1709251199)), (2024, 2, 29, 23, 59, 59));
+    }
+
+    #[tokio::test]
+    async fn test_recorder_keep_alive() {
+        let (mut client, server_side) = tokio::io::duplex(64 * 1024);
+        let mut recorder = AccessRecorder::new(server_side, "198.51.100.7".parse().unwrap());
+
+        client
+            .write_all(b"GET /a HTTP/1.1\r\nHost: Example.com:8080\r\nReferer: /r\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0u8; 1024];
+        let _ = recorder.read(&mut buf).await.unwrap();
+        recorder.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 5\r\n").await.unwrap();
+        recorder.write_all(b"\r\nnope!").await.unwrap();
+        let first = recorder.take_entry().unwrap();
+        assert_eq!((first.method.as_str(), first.target.as_str(), first.status, first.body_bytes), ("GET", "/a", 404, 5));
+        assert_eq!(first.host.as_deref(), Some("example.com"));
+        assert_eq!(first.header("referer"), Some("/r"));
+
+        // Nothing read since: nothing to log
+        assert!(recorder.take_entry().is_none());
+
+        // A request the client abandoned before any response
+        client.write_all(b"POST /b HTTP/1.1\r\nHost: [2001:db8::1]:443\r\n\r\nbody").await.unwrap();
+        let _ = recorder.read(&mut buf).await.unwrap();
+        let second = recorder.take_entry().unwrap();
+        assert_eq!((second.method.as_str(), second.status, second.body_bytes), ("POST", 499, 0));
+        assert_eq!(second.host.as_deref(), Some("[2001:db8::1]"));
+    }
+}
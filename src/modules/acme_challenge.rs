@@ -0,0 +1,124 @@
+//! ACME Challenge Selection
+//!
+//! This module chooses how the ACME server is asked to validate domain ownership. It
+//! provides:
+//! - [`ChallengeKind`]: HTTP-01 (port 80), TLS-ALPN-01 (port 443, RFC 8737) or DNS-01
+//! - [`ChallengePreference`]: the `--challenge-type` value, `auto` by default
+//! - [`select_challenge`]: the challenge to use given which ports the ACME server can reach
+//! - [`acme_identifier`]: the digest of a key authorization that TLS-ALPN-01 certificates carry
+//!
+//! With `auto`, HTTP-01 is used while port 80 is ours and TLS-ALPN-01 when only port 443
+//! is, so hosts with port 80 firewalled still get real certificates. ACME servers always
+//! validate on the standard ports, so a listener moved by `--over-9000` does not count.
+
+use std::fmt;
+
+use super::digest::sha256;
+
+/// ALPN protocol name of TLS-ALPN-01 validation connections
+pub const ACME_TLS_ALPN_PROTOCOL: &[u8] = b"acme-tls/1";
+
+/// A validation method defined by RFC 8555 / RFC 8737
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeKind {
+    Http01,
+    TlsAlpn01,
+    Dns01,
+}
+
+impl ChallengeKind {
+    /// Name as used by ACME servers (`http-01`, `tls-alpn-01`, `dns-01`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChallengeKind::Http01 => "http-01",
+            ChallengeKind::TlsAlpn01 => "tls-alpn-01",
+            ChallengeKind::Dns01 => "dns-01",
+        }
+    }
+}
+
+impl fmt::Display for ChallengeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The `--challenge-type` setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChallengePreference {
+    /// HTTP-01 if port 80 is reachable, otherwise TLS-ALPN-01
+    #[default]
+    Auto,
+    Only(ChallengeKind),
+}
+
+impl ChallengePreference {
+    /// Parse `auto`, `http01`, `tls-alpn01` or `dns01` (with or without dashes)
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let normalized: String = value.trim().to_ascii_lowercase().chars().filter(|c| *c != '-' && *c != '_').collect();
+        match normalized.as_str() {
+            "auto" => Ok(ChallengePreference::Auto),
+            "http01" | "http" => Ok(ChallengePreference::Only(ChallengeKind::Http01)),
+            "tlsalpn01" | "tlsalpn" | "alpn" => Ok(ChallengePreference::Only(ChallengeKind::TlsAlpn01)),
+            "dns01" | "dns" => Ok(ChallengePreference::Only(ChallengeKind::Dns01)),
+            _ => Err(format!("invalid challenge type '{}': expected auto, http01, tls-alpn01 or dns01", value)),
+        }
+    }
+}
+
+impl fmt::Display for ChallengePreference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChallengePreference::Auto => f.write_str("auto"),
+            ChallengePreference::Only(kind) => kind.fmt(f),
+        }
+    }
+}
+
+/// Pick the challenge for this server
+///
+/// # Arguments
+/// * `preference` - The `--challenge-type` setting
+/// * `http_reachable` - Whether we listen on port 80
+/// * `https_reachable` - Whether we listen on port 443
+///
+/// # Returns
+/// * `Option<ChallengeKind>` - None if the preferred challenge cannot be answered
+pub fn select_challenge(preference: ChallengePreference, http_reachable: bool, https_reachable: bool) -> Option<ChallengeKind> {
+    match preference {
+        ChallengePreference::Auto if http_reachable => Some(ChallengeKind::Http01),
+        ChallengePreference::Auto if https_reachable => Some(ChallengeKind::TlsAlpn01),
+        ChallengePreference::Auto => None,
+        ChallengePreference::Only(ChallengeKind::Http01) => http_reachable.then_some(ChallengeKind::Http01),
+        ChallengePreference::Only(ChallengeKind::TlsAlpn01) => https_reachable.then_some(ChallengeKind::TlsAlpn01),
+        // Answered in DNS, so no port needs to be reachable
+        ChallengePreference::Only(ChallengeKind::Dns01) => Some(ChallengeKind::Dns01),
+    }
+}
+
+/// Contents of the acmeIdentifier extension for a key authorization (RFC 8737 section 3)
+pub fn acme_identifier(key_authorization: &str) -> [u8; 32] {
+    sha256(key_authorization.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_challenge() {
+        assert_eq!(ChallengePreference::parse("TLS-ALPN-01"), Ok(ChallengePreference::Only(ChallengeKind::TlsAlpn01)));
+        assert_eq!(ChallengePreference::parse("http01"), Ok(ChallengePreference::Only(ChallengeKind::Http01)));
+        assert_eq!(ChallengePreference::parse("dns-01"), Ok(ChallengePreference::Only(ChallengeKind::Dns01)));
+        assert!(ChallengePreference::parse("smtp01").is_err());
+        assert_eq!(ChallengePreference::default().to_string(), "auto");
+
+        let auto = ChallengePreference::Auto;
+        assert_eq!(select_challenge(auto, true, true), Some(ChallengeKind::Http01));
+        assert_eq!(select_challenge(auto, false, true), Some(ChallengeKind::TlsAlpn01));
+        assert_eq!(select_challenge(auto, false, false), None);
+        assert_eq!(select_challenge(ChallengePreference::Only(ChallengeKind::TlsAlpn01), true, false), None);
+        assert_eq!(select_challenge(ChallengePreference::Only(ChallengeKind::Http01), false, true), None);
+        assert_eq!(select_challenge(ChallengePreference::Only(ChallengeKind::Dns01), false, false), Some(ChallengeKind::Dns01));
+    }
+}
@@ -0,0 +1,541 @@
+//! External CGI Execution
+//!
+//! This module runs executables from a per-domain cgi-bin directory as CGI/1.1
+//! processes (RFC 3875). It is opt-in: nothing runs unless a directory template is
+//! configured, and requests for names that are not found there fall through to the
+//! compiled-in `*.bin.rs` handlers. It provides:
+//! - Script lookup confined to the cgi-bin directory (no hidden files, no traversal,
+//!   symlinks must stay inside, the file must be executable)
+//! - The full meta-variable set with a clean environment
+//! - The request body on stdin and `Status`/`Content-Type`/`Location` output headers
+//! - A wall-clock timeout and an output size cap
+//! - stderr lines written to the server log (and so to the logs admin panel)
+//!
+//! Scripts run with the server's dropped privileges; when the server still runs as
+//! root the configured drop user is applied to the child, and without one nothing runs.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::cgi_env::{cgi_response_head, find_cgi_head_end, CgiEnv, CgiVarsBuilder};
+use super::fastcgi::ConnectionInfo;
+use super::hostname::is_valid_hostname;
+use super::reverse_proxy::{read_head, MessageHead, ProxyOutcome};
+
+/// URI prefix for CGI scripts
+pub const CGI_PREFIX: &str = "/cgi-bin/";
+
+/// Default wall-clock limit for one script run
+pub const DEFAULT_CGI_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default cap on script output (headers and body)
+pub const DEFAULT_CGI_MAX_OUTPUT: usize = 16 * 1024 * 1024;
+
+/// PATH given to scripts, which otherwise get no inherited environment
+const CGI_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Cap on captured stderr per run
+const MAX_STDERR: usize = 64 * 1024;
+
+/// External CGI configuration
+#[derive(Debug, Clone)]
+pub struct CgiExecConfig {
+    /// cgi-bin directory per domain, `{domain}` is replaced (None = disabled)
+    pub dir_template: Option<String>,
+    /// Wall-clock limit for one script run
+    pub timeout: Duration,
+    /// Maximum bytes of output accepted from a script
+    pub max_output: usize,
+}
+
+impl Default for CgiExecConfig {
+    fn default() -> Self {
+        Self {
+            dir_template: None,
+            timeout: DEFAULT_CGI_TIMEOUT,
+            max_output: DEFAULT_CGI_MAX_OUTPUT,
+        }
+    }
+}
+
+/// A script found in a cgi-bin directory
+#[derive(Debug, Clone)]
+pub struct CgiScript {
+    /// URI path of the script (SCRIPT_NAME)
+    pub script_name: String,
+    /// Extra path after the script (PATH_INFO)
+    pub path_info: String,
+    /// Canonical path of the executable
+    pub filename: PathBuf,
+    /// Canonical cgi-bin directory (the working directory of the script)
+    pub dir: PathBuf,
+}
+
+impl CgiExecConfig {
+    /// The cgi-bin directory for a domain, if external CGI is enabled
+    pub fn cgi_dir(&self, domain: &str) -> Option<PathBuf> {
+        let template = self.dir_template.as_ref()?;
+        let domain = domain.to_lowercase();
+        if !is_valid_hostname(&domain) {
+            return None;
+        }
+        Some(PathBuf::from(template.replace("{domain}", &domain)))
+    }
+
+    /// Find the executable for a `/cgi-bin/` request
+    ///
+    /// # Arguments
+    /// * `domain` - Request host
+    /// * `path` - Request target (a query string is ignored)
+    ///
+    /// # Returns
+    /// * `Ok(Some(script))` - Run this script
+    /// * `Ok(None)` - Not an external script; fall back to the compiled-in handlers
+    /// * `Err(reason)` - The name exists but must not be run (403)
+    pub fn find_script(&self, domain: &str, path: &str) -> Result<Option<CgiScript>, String> {
+        let Some(dir) = self.cgi_dir(domain) else {
+            return Ok(None);
+        };
+        let path = path.split('?').next().unwrap_or(path);
+        let Some(rest) = path.strip_prefix(CGI_PREFIX) else {
+            return Ok(None);
+        };
+        let (name, path_info) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, ""),
+        };
+
+        let name = urlencoding::decode(name).map_err(|_| "invalid script name encoding".to_string())?;
+        if name.is_empty() {
+            return Ok(None);
+        }
+        if name.starts_with('.') || name.contains(['/', '\\', '\0']) {
+            return Err(format!("script name '{}' not allowed", name));
+        }
+
+        let candidate = dir.join(name.as_ref());
+        if !candidate.is_file() {
+            return Ok(None);
+        }
+
+        // Symlinks may point anywhere; only run what resolves inside the directory
+        let dir = dir.canonicalize().map_err(|e| format!("cgi-bin directory unavailable: {}", e))?;
+        let filename = candidate.canonicalize().map_err(|e| format!("script unavailable: {}", e))?;
+        if !filename.starts_with(&dir) {
+            return Err(format!("script '{}' resolves outside the cgi-bin directory", name));
+        }
+        if !is_executable(&filename) {
+            return Err(format!("script '{}' is not executable", name));
+        }
+
+        Ok(Some(CgiScript {
+            script_name: format!("{}{}", CGI_PREFIX, name),
+            path_info: path_info.to_string(),
+            filename,
+            dir,
+        }))
+    }
+}
+
+/// Whether a file has an execute bit set
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|m| m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Errors that map to error responses before anything was sent to the client
+#[derive(Debug)]
+enum CgiError {
+    /// Script could not run or produced unusable output (500)
+    Script(String),
+    /// Script ran past the timeout (504)
+    Timeout(String),
+    /// Client sent something we cannot pass on (400/411)
+    Request(u16, &'static str, String),
+}
+
+impl std::fmt::Display for CgiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CgiError::Script(msg) => write!(f, "script error: {}", msg),
+            CgiError::Timeout(msg) => write!(f, "timeout: {}", msg),
+            CgiError::Request(code, _, msg) => write!(f, "{}: {}", code, msg),
+        }
+    }
+}
+
+/// Run a CGI script for a request and send its response
+///
+/// # Arguments
+/// * `client` - Client connection (plain TCP or TLS)
+/// * `initial` - Bytes already read from the client (at least part of the request head)
+/// * `script` - Script found by [`CgiExecConfig::find_script`]
+/// * `document_root` - Document root of the host
+/// * `conn_info` - Connection facts for the CGI variables
+/// * `run_as` - UID and GID for the child when the server still runs as root
+/// * `config` - Timeout and output cap
+///
+/// # Returns
+/// * `Result<ProxyOutcome, Box<dyn std::error::Error + Send + Sync>>` - Status and size of the response;
+///   the caller should close the client connection afterwards
+pub async fn run_cgi<C>(
+    client: &mut C,
+    initial: &[u8],
+    script: &CgiScript,
+    document_root: &Path,
+    conn_info: &ConnectionInfo,
+    run_as: Option<(u32, u32)>,
+    config: &CgiExecConfig,
+) -> Result<ProxyOutcome, Box<dyn std::error::Error + Send + Sync>>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let response = match cgi_exchange(client, initial, script, document_root, conn_info, run_as, config).await {
+        Ok((status, response)) => {
+            client.write_all(&response).await?;
+            client.flush().await?;
+            return Ok(ProxyOutcome { status, bytes_sent: response.len() as u64 });
+        }
+        Err(error) => error,
+    };
+
+    let (status, reason) = match &response {
+        CgiError::Script(_) => (500, "Internal Server Error"),
+        CgiError::Timeout(_) => (504, "Gateway Timeout"),
+        CgiError::Request(code, reason, _) => (*code, *reason),
+    };
+    log::error!(target: "cgi", "CGI error for {}: {}", script.script_name, response);
+    let body = format!("{} {}", status, reason);
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+    client.write_all(response.as_bytes()).await?;
+    client.flush().await?;
+    Ok(ProxyOutcome { status, bytes_sent: response.len() as u64 })
+}
+
+/// Run the script and build the complete response
+async fn cgi_exchange<C>(
+    client: &mut C,
+    initial: &[u8],
+    script: &CgiScript,
+    document_root: &Path,
+    conn_info: &ConnectionInfo,
+    run_as: Option<(u32, u32)>,
+    config: &CgiExecConfig,
+) -> Result<(u16, Vec<u8>), CgiError>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    // Complete the request head if the caller's buffer ended inside it
+    let mut buffered = initial.to_vec();
+    let bad_request = |e: String| CgiError::Request(400, "Bad Request", e);
+    let head_end = read_head(client, &mut buffered, config.timeout).await.map_err(bad_request)?;
+    let request = MessageHead::parse(&buffered[..head_end]).map_err(bad_request)?;
+    let body_start = head_end + 4;
+
+    let mut parts = request.start_line.split_whitespace();
+    let method = parts.next().unwrap_or("GET").to_string();
+    let target = parts.next().unwrap_or("/").to_string();
+    let protocol = parts.next().unwrap_or("HTTP/1.1").to_string();
+    let query = target.split_once('?').map(|(_, q)| q).unwrap_or("");
+
+    if request.get("transfer-encoding").is_some() {
+        return Err(CgiError::Request(411, "Length Required", "chunked request bodies are not supported".to_string()));
+    }
+    let content_length: u64 = match request.get("content-length") {
+        Some(length) => length.parse().map_err(|_| bad_request(format!("invalid Content-Length '{}'", length)))?,
+        None => 0,
+    };
+
+    let headers = request
+        .headers
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), value.clone()))
+        .collect();
+    let host = request.get("host").unwrap_or("localhost");
+    let server_name = host.rsplit_once(':').filter(|(_, port)| port.parse::<u16>().is_ok()).map(|(h, _)| h).unwrap_or(host);
+    let env = CgiEnv::from_request(&method, &target, server_name, query, &headers);
+    let vars = CgiVarsBuilder::new(&env)
+        .script(
+            &script.script_name,
+            &script.filename.to_string_lossy(),
+            &script.path_info,
+            &document_root.to_string_lossy(),
+        )
+        .remote(conn_info.remote)
+        .server(conn_info.local, conn_info.https)
+        .protocol(&protocol)
+        .build();
+
+    let mut command = tokio::process::Command::new(&script.filename);
+    command
+        .env_clear()
+        .envs(vars)
+        .env("PATH", CGI_PATH)
+        .current_dir(&script.dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    {
+        // Its own process group, so whatever the script starts can be killed with it
+        command.process_group(0);
+        if unsafe { libc::geteuid() } == 0 {
+            match run_as {
+                Some((uid, gid)) => {
+                    command.uid(uid).gid(gid);
+                }
+                None => return Err(CgiError::Script("refusing to run CGI scripts as root".to_string())),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = run_as;
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| CgiError::Script(format!("failed to start {}: {}", script.filename.display(), e)))?;
+    let mut stdin = child.stdin.take();
+    let mut stdout = child.stdout.take().ok_or_else(|| CgiError::Script("no stdout".to_string()))?;
+    let mut stderr = child.stderr.take().ok_or_else(|| CgiError::Script("no stderr".to_string()))?;
+
+    // Request body -> stdin. A script that ignores its input may close stdin early.
+    let feed = async {
+        let already = &buffered[body_start..];
+        let first = (already.len() as u64).min(content_length) as usize;
+        let mut remaining = content_length - first as u64;
+        if let Some(pipe) = stdin.as_mut() {
+            if pipe.write_all(&already[..first]).await.is_err() {
+                return Ok(());
+            }
+        }
+        let mut chunk = vec![0u8; 16 * 1024];
+        while remaining > 0 {
+            let want = remaining.min(chunk.len() as u64) as usize;
+            let n = client.read(&mut chunk[..want]).await.map_err(|e| e.to_string())?;
+            if n == 0 {
+                return Err("client closed during request body".to_string());
+            }
+            if let Some(pipe) = stdin.as_mut() {
+                if pipe.write_all(&chunk[..n]).await.is_err() {
+                    stdin = None;
+                }
+            }
+            remaining -= n as u64;
+        }
+        drop(stdin.take());
+        Ok(())
+    };
+    // stdout, up to the cap
+    let limit = config.max_output;
+    let collect = async {
+        let mut output = Vec::new();
+        (&mut stdout).take(limit as u64 + 1).read_to_end(&mut output).await.map(|_| output)
+    };
+    // stderr, logged after the run; the rest is drained so the script never blocks on it
+    let errors = async {
+        let mut captured = Vec::new();
+        let _ = (&mut stderr).take(MAX_STDERR as u64).read_to_end(&mut captured).await;
+        let _ = tokio::io::copy(&mut stderr, &mut tokio::io::sink()).await;
+        captured
+    };
+
+    let run = async {
+        let output = async {
+            let output = collect.await;
+            // A script still writing past the cap would block on the full pipe until the timeout
+            if output.as_ref().map_or(true, |output| output.len() > limit) {
+                kill_script(&mut child);
+            }
+            output
+        };
+        let (fed, output, captured) = tokio::join!(feed, output, errors);
+        let status = child.wait().await;
+        (fed, output, captured, status)
+    };
+    let (fed, output, captured, status) = match tokio::time::timeout(config.timeout, run).await {
+        Ok(result) => result,
+        Err(_) => {
+            kill_script(&mut child);
+            let _ = child.wait().await;
+            return Err(CgiError::Timeout(format!("script ran longer than {:?}", config.timeout)));
+        }
+    };
+
+    log_stderr(&script.script_name, &captured);
+    fed.map_err(bad_request)?;
+    let output = output.map_err(|e| CgiError::Script(format!("reading output failed: {}", e)))?;
+    if output.len() > limit {
+        return Err(CgiError::Script(format!("output exceeded {} bytes", limit)));
+    }
+    if let Ok(status) = status {
+        if !status.success() {
+            log::warn!(target: "cgi", "CGI {} exited with {}", script.script_name, status);
+        }
+    }
+
+    let (header_len, separator_len) =
+        find_cgi_head_end(&output).ok_or_else(|| CgiError::Script("malformed header from script".to_string()))?;
+    let cgi_head = String::from_utf8_lossy(&output[..header_len]).to_string();
+    let (code, head) = cgi_response_head(&cgi_head);
+
+    let mut response = head.into_bytes();
+    response.extend_from_slice(&output[header_len + separator_len..]);
+    Ok((code, response))
+}
+
+/// Kill a script together with anything it started, which may still hold its pipes
+fn kill_script(child: &mut tokio::process::Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: plain syscall; the script leads its own process group
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+        }
+    }
+    let _ = child.start_kill();
+}
+
+/// Write what a script sent to stderr into the server log
+fn log_stderr(script: &str, captured: &[u8]) {
+    for line in String::from_utf8_lossy(captured).lines().filter(|l| !l.trim().is_empty()) {
+        log::warn!(target: "cgi", "CGI stderr ({}): {}", script, line);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn cgi_site(name: &str) -> (PathBuf, CgiExecConfig) {
+        let base = std::env::temp_dir().join(format!("easyp_cgi_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("example.com/cgi-bin")).unwrap();
+        let config = CgiExecConfig {
+            dir_template: Some(format!("{}/{{domain}}/cgi-bin", base.display())),
+            ..CgiExecConfig::default()
+        };
+        (base, config)
+    }
+
+    fn write_script(base: &Path, name: &str, body: &str, mode: u32) {
+        let path = base.join("example.com/cgi-bin").join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    async fn run(config: &CgiExecConfig, script: &CgiScript, request: &[u8], extra: &[u8]) -> (u16, String) {
+        let (mut client, mut server_side) = tokio::io::duplex(64 * 1024);
+        client.write_all(extra).await.unwrap();
+        let outcome = run_cgi(
+            &mut server_side,
+            request,
+            script,
+            Path::new("/srv"),
+            &ConnectionInfo {
+                remote: "203.0.113.9:5555".parse().unwrap(),
+                local: "127.0.0.1:80".parse().unwrap(),
+                https: false,
+            },
+            Some((65534, 65534)),
+            config,
+        )
+        .await
+        .unwrap();
+        drop(server_side);
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        (outcome.status, response)
+    }
+
+    #[test]
+    fn test_find_script() {
+        let (base, config) = cgi_site("find");
+        write_script(&base, "hello", "echo", 0o755);
+        write_script(&base, "data", "echo", 0o644);
+        fs::write(base.join("example.com/secret"), "x").unwrap();
+        std::os::unix::fs::symlink(base.join("example.com/secret"), base.join("example.com/cgi-bin/escape")).unwrap();
+
+        let script = config.find_script("example.com", "/cgi-bin/hello/extra/path?x=1").unwrap().unwrap();
+        assert_eq!(script.script_name, "/cgi-bin/hello");
+        assert_eq!(script.path_info, "/extra/path");
+
+        assert!(config.find_script("example.com", "/cgi-bin/missing").unwrap().is_none());
+        assert!(config.find_script("other.com", "/cgi-bin/hello").unwrap().is_none());
+        assert!(config.find_script("example.com", "/cgi-bin/data").is_err());
+        assert!(config.find_script("example.com", "/cgi-bin/escape").is_err());
+        assert!(config.find_script("example.com", "/cgi-bin/%2e%2e%2fsecret").is_err());
+        assert!(config.find_script("example.com", "/cgi-bin/.hidden").is_err());
+        assert!(config.find_script("../etc", "/cgi-bin/hello").unwrap().is_none());
+        assert!(CgiExecConfig::default().find_script("example.com", "/cgi-bin/hello").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_run_script_with_body_and_stderr() {
+        let (base, config) = cgi_site("run");
+        write_script(
+            &base,
+            "echo",
+            "echo 'Status: 201 Created'\necho 'Content-Type: text/plain'\necho\necho \"$REQUEST_METHOD $PATH_INFO $QUERY_STRING $REMOTE_ADDR\"\ncat\necho oops >&2\n",
+            0o755,
+        );
+        let script = config.find_script("example.com", "/cgi-bin/echo/x?a=1").unwrap().unwrap();
+        let request = b"POST /cgi-bin/echo/x?a=1 HTTP/1.1\r\nHost: example.com\r\nContent-Length: 11\r\n\r\nhello";
+        let (status, response) = run(&config, &script, request, b" world").await;
+        assert_eq!(status, 201);
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\nContent-Type: text/plain\r\n"));
+        assert!(response.ends_with("\r\n\r\nPOST /x a=1 203.0.113.9\nhello world"));
+    }
+
+    #[tokio::test]
+    async fn test_location_timeout_and_output_cap() {
+        let (base, mut config) = cgi_site("limits");
+        write_script(&base, "redirect", "printf 'Location: /elsewhere\\r\\n\\r\\n'\n", 0o755);
+        write_script(&base, "slow", "sleep 5\n", 0o755);
+        write_script(&base, "big", "echo 'Content-Type: text/plain'\necho\nhead -c 1000000 /dev/zero\n", 0o755);
+        write_script(&base, "chatty", "head -c 1000000 /dev/zero >&2\necho 'Content-Type: text/plain'\necho\necho done\n", 0o755);
+        write_script(&base, "noheader", "echo plain text\n", 0o755);
+        let get = |name: &str| format!("GET /cgi-bin/{} HTTP/1.1\r\nHost: example.com\r\n\r\n", name);
+
+        let script = config.find_script("example.com", "/cgi-bin/redirect").unwrap().unwrap();
+        let (status, response) = run(&config, &script, get("redirect").as_bytes(), b"").await;
+        assert_eq!(status, 302);
+        assert!(response.contains("Location: /elsewhere\r\n"));
+
+        let script = config.find_script("example.com", "/cgi-bin/noheader").unwrap().unwrap();
+        assert_eq!(run(&config, &script, get("noheader").as_bytes(), b"").await.0, 500);
+
+        // Neither a full stdout nor a full stderr pipe leaves the script hanging until the timeout
+        config.max_output = 1000;
+        config.timeout = Duration::from_secs(10);
+        let script = config.find_script("example.com", "/cgi-bin/big").unwrap().unwrap();
+        assert_eq!(run(&config, &script, get("big").as_bytes(), b"").await.0, 500);
+        let script = config.find_script("example.com", "/cgi-bin/chatty").unwrap().unwrap();
+        let (status, response) = run(&config, &script, get("chatty").as_bytes(), b"").await;
+        assert_eq!(status, 200);
+        assert!(response.ends_with("\r\n\r\ndone\n"));
+
+        config.timeout = Duration::from_millis(200);
+        let script = config.find_script("example.com", "/cgi-bin/slow").unwrap().unwrap();
+        assert_eq!(run(&config, &script, get("slow").as_bytes(), b"").await.0, 504);
+    }
+}
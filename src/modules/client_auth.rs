@@ -0,0 +1,375 @@
+//! Client Certificate Authentication
+//!
+//! This module decides when HTTPS clients must present a certificate issued by a
+//! configured CA, and describes the certificate they presented. It provides:
+//! - [`ClientAuthRule`]: one `--client-auth MODE:TARGET` entry, where TARGET is a host
+//!   name (or `*.` wildcard), a path prefix, or both (`admin.example.com/panel`)
+//! - [`ClientAuthConfig`]: the CA bundle, the rules and the identities allowed to use the
+//!   admin panels
+//! - [`ClientIdentity`]: subject, common name and subjectAltName entries of a verified
+//!   certificate, and the request headers that hand them to extensions
+//!
+//! The certificate is asked for during the handshake, before the request path is known,
+//! so the handshake only refuses clients for host-wide `required` rules. Path rules (and
+//! admin identities) make the handshake ask for a certificate without insisting, and the
+//! request is refused with 403 afterwards if a `required` rule matches and none was
+//! presented. Chains are verified by rustls against the CA bundle; what arrives here has
+//! already passed that check.
+//!
+//! Request headers named `x-client-cert-*` (or `x_client_cert_*`) are always removed, so a
+//! client cannot supply its own identity, over HTTPS or plain HTTP. Extensions get the
+//! verified values instead; proxied upstreams and CGI/FastCGI programs get none.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::hostname::normalize_hostname;
+use super::x509::{common_name, format_name, parse_certificate};
+
+/// Prefix of the request headers that carry the client certificate
+pub const HEADER_PREFIX: &str = "x-client-cert-";
+
+/// How strongly a rule asks for a client certificate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ClientAuthMode {
+    /// Ask for a certificate, but serve clients without one
+    Optional,
+    /// Refuse clients without a valid certificate
+    Required,
+}
+
+/// One `--client-auth` entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientAuthRule {
+    pub mode: ClientAuthMode,
+    /// Host name or `*.` wildcard, lowercase; None matches every host
+    pub host: Option<String>,
+    /// Path prefix, matched on whole segments; None matches every path
+    pub path: Option<String>,
+}
+
+impl ClientAuthRule {
+    /// Parse `MODE:TARGET`
+    ///
+    /// # Arguments
+    /// * `value` - Command-line value, e.g. `required:admin.example.com`,
+    ///   `optional:/downloads` or `required:example.com/private`; `*` targets everything
+    ///
+    /// # Returns
+    /// * `Result<Self, String>` - The rule, or why the value is malformed
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (mode, target) = value
+            .split_once(':')
+            .ok_or_else(|| format!("invalid client auth rule '{}': expected MODE:TARGET", value))?;
+        let mode = match mode.trim().to_ascii_lowercase().as_str() {
+            "required" | "require" => ClientAuthMode::Required,
+            "optional" | "request" => ClientAuthMode::Optional,
+            other => return Err(format!("invalid client auth mode '{}': expected required or optional", other)),
+        };
+
+        let target = target.trim();
+        if target.is_empty() {
+            return Err(format!("invalid client auth rule '{}': missing host or path", value));
+        }
+        if target == "*" {
+            return Ok(Self { mode, host: None, path: None });
+        }
+        let (host, path) = match target.find('/') {
+            Some(0) => ("", target),
+            Some(pos) => (&target[..pos], &target[pos..]),
+            None => (target, ""),
+        };
+
+        let host = match host {
+            "" | "*" => None,
+            host => {
+                let normalized = match host.strip_prefix("*.") {
+                    Some(base) => normalize_hostname(base).map(|base| format!("*.{}", base)),
+                    None => normalize_hostname(host),
+                };
+                Some(normalized.map_err(|e| format!("invalid client auth host '{}': {}", host, e))?)
+            }
+        };
+        let path = Some(canonical_path(path)).filter(|path| path != "/");
+        Ok(Self { mode, host, path })
+    }
+
+    /// Whether the rule covers a host, None being a client that sent no name
+    fn matches_host(&self, host: Option<&str>) -> bool {
+        let Some(pattern) = &self.host else {
+            return true;
+        };
+        let Some(host) = host.and_then(|host| normalize_hostname(host).ok()) else {
+            return false;
+        };
+        match pattern.strip_prefix("*.") {
+            Some(base) => host.split_once('.').is_some_and(|(_, parent)| parent == base),
+            None => host == *pattern,
+        }
+    }
+
+    /// Whether the rule covers a request
+    fn matches(&self, host: Option<&str>, path: &str) -> bool {
+        self.matches_host(host) && self.path.as_deref().is_none_or(|prefix| path_has_prefix(&canonical_path(path), prefix))
+    }
+}
+
+/// Decode a request path and resolve `.`, `..` and repeated slashes, so that
+/// `/a/../%70rivate//x` is checked as `/private/x`
+fn canonical_path(path: &str) -> String {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let decoded = urlencoding::decode(path).map(|path| path.into_owned()).unwrap_or_else(|_| path.to_string());
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split(['/', '\\']) {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+/// Prefix match on whole segments: `/private` covers `/private/x` but not `/privateer`
+fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Client certificate settings
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientAuthConfig {
+    /// PEM bundle of the CAs that issue client certificates; None disables the feature
+    pub ca_file: Option<PathBuf>,
+    pub rules: Vec<ClientAuthRule>,
+    /// Identities allowed to use the admin panels (common name, DNS or e-mail
+    /// subjectAltName, or the full subject); empty leaves the panels to their keys
+    pub admin_identities: Vec<String>,
+}
+
+impl ClientAuthConfig {
+    /// Whether client certificates are verified at all
+    pub fn is_enabled(&self) -> bool {
+        self.ca_file.is_some()
+    }
+
+    /// Check that rules and admin identities come with a CA bundle
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ca_file.is_none() && (!self.rules.is_empty() || !self.admin_identities.is_empty()) {
+            return Err("--client-auth and --admin-client-cert need --client-ca".to_string());
+        }
+        Ok(())
+    }
+
+    /// How the handshake asks for a certificate, for the name the client sent (SNI)
+    ///
+    /// # Returns
+    /// * `Option<ClientAuthMode>` - None to not ask at all; `Required` only when a
+    ///   host-wide required rule covers the name
+    pub fn handshake_mode(&self, server_name: Option<&str>) -> Option<ClientAuthMode> {
+        if !self.is_enabled() {
+            return None;
+        }
+        // Admin panels can be on any host
+        let mut mode = (!self.admin_identities.is_empty()).then_some(ClientAuthMode::Optional);
+        for rule in self.rules.iter().filter(|rule| rule.matches_host(server_name)) {
+            let rule_mode = if rule.path.is_none() { rule.mode } else { ClientAuthMode::Optional };
+            mode = mode.max(Some(rule_mode));
+        }
+        mode
+    }
+
+    /// Whether a request must come with a verified client certificate
+    ///
+    /// # Arguments
+    /// * `host` - Host the request is for
+    /// * `path` - Request path, possibly with a query string
+    pub fn requires_certificate(&self, host: Option<&str>, path: &str) -> bool {
+        self.rules.iter().any(|rule| rule.mode == ClientAuthMode::Required && rule.matches(host, path))
+    }
+
+    /// Whether a client may use the admin panels
+    pub fn admin_allowed(&self, identity: Option<&ClientIdentity>) -> bool {
+        self.admin_identities.is_empty()
+            || identity.is_some_and(|identity| self.admin_identities.iter().any(|wanted| identity.matches(wanted)))
+    }
+}
+
+/// A verified client certificate, as exposed to extensions
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientIdentity {
+    /// RFC 4514 subject, e.g. `CN=alice,O=Example\, Inc,C=AU`
+    pub subject: String,
+    pub common_name: Option<String>,
+    pub dns_names: Vec<String>,
+    pub email_names: Vec<String>,
+    /// Serial number in uppercase hex
+    pub serial: String,
+}
+
+impl ClientIdentity {
+    /// Describe the leaf certificate of a verified chain
+    pub fn from_der(der: &[u8]) -> Result<Self, String> {
+        let cert = parse_certificate(der)?;
+        let serial = cert.serial.iter().skip_while(|byte| **byte == 0).map(|byte| format!("{:02X}", byte)).collect::<String>();
+        Ok(Self {
+            subject: format_name(&cert.subject),
+            common_name: common_name(&cert.subject),
+            dns_names: cert.dns_names,
+            email_names: cert.email_names,
+            serial: if serial.is_empty() { "00".to_string() } else { serial },
+        })
+    }
+
+    /// Whether the certificate has the identity named in `--admin-client-cert`
+    pub fn matches(&self, wanted: &str) -> bool {
+        let wanted = wanted.trim();
+        self.subject == wanted
+            || self.common_name.as_deref() == Some(wanted)
+            || self.dns_names.iter().any(|name| name.eq_ignore_ascii_case(wanted))
+            || self.email_names.iter().any(|email| email.eq_ignore_ascii_case(wanted))
+    }
+
+    /// subjectAltName entries as `DNS:name, email:address`
+    pub fn subject_alt_names(&self) -> String {
+        let dns = self.dns_names.iter().map(|name| format!("DNS:{}", name));
+        let email = self.email_names.iter().map(|email| format!("email:{}", email));
+        dns.chain(email).collect::<Vec<_>>().join(", ")
+    }
+
+    /// Add the `x-client-cert-*` headers to a request's (lowercase) header map
+    pub fn add_headers(&self, headers: &mut HashMap<String, String>) {
+        headers.insert(format!("{}verify", HEADER_PREFIX), "SUCCESS".to_string());
+        headers.insert(format!("{}subject", HEADER_PREFIX), self.subject.clone());
+        if let Some(common_name) = &self.common_name {
+            headers.insert(format!("{}cn", HEADER_PREFIX), common_name.clone());
+        }
+        headers.insert(format!("{}san", HEADER_PREFIX), self.subject_alt_names());
+        headers.insert(format!("{}serial", HEADER_PREFIX), self.serial.clone());
+    }
+}
+
+/// Whether a request header is one of the `x-client-cert-*` headers, in any case and
+/// with underscores for dashes (which some backends treat alike)
+pub fn is_client_cert_header(name: &str) -> bool {
+    name.len() > HEADER_PREFIX.len()
+        && name.is_char_boundary(HEADER_PREFIX.len())
+        && name[..HEADER_PREFIX.len()].replace('_', "-").eq_ignore_ascii_case(HEADER_PREFIX)
+}
+
+/// Replace any client-supplied `x-client-cert-*` headers with the verified identity
+///
+/// # Arguments
+/// * `headers` - Request headers with lowercase names
+/// * `identity` - The verified certificate, None for clients without one (and plain HTTP)
+pub fn apply_headers(headers: &mut HashMap<String, String>, identity: Option<&ClientIdentity>) {
+    headers.retain(|name, _| !is_client_cert_header(name));
+    match identity {
+        Some(identity) => identity.add_headers(headers),
+        None => {
+            headers.insert(format!("{}verify", HEADER_PREFIX), "NONE".to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT_CERT: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIIBkTCCATegAwIBAgICHy4wCgYIKoZIzj0EAwIwNDELMAkGA1UEBhMCQVUxFTAT\n\
+BgNVBAoMDEV4YW1wbGUsIEluYzEOMAwGA1UEAwwFYWxpY2UwIBcNMjYwMTAxMDAw\n\
+MDAwWhgPMjA5OTAxMDEwMDAwMDBaMDQxCzAJBgNVBAYTAkFVMRUwEwYDVQQKDAxF\n\
+eGFtcGxlLCBJbmMxDjAMBgNVBAMMBWFsaWNlMFkwEwYHKoZIzj0CAQYIKoZIzj0D\n\
+AQcDQgAEKb8IIo1QSjWSuKrYmmUttJAabDhOhcfUI2clVc75esx7q5z8cbgK/cN5\n\
+LzZlt00Vo2DFzqoY6yiwmloGhle2W6M3MDUwMwYDVR0RBCwwKoERYWxpY2VAZXhh\n\
+bXBsZS5jb22CFWFsaWNlLmNsaWVudHMuZXhhbXBsZTAKBggqhkjOPQQDAgNIADBF\n\
+AiAetSCU6zr1nBCNfUmtGtZKnse180ydf24wg6qRgfYIRQIhAIrGA3kD5rAB1Tsn\n\
+cj369Mx4AFWhC8SwMQYnAP9TNz0c\n\
+-----END CERTIFICATE-----\n";
+
+    fn config(rules: &[&str], admin_identities: &[&str]) -> ClientAuthConfig {
+        ClientAuthConfig {
+            ca_file: Some(PathBuf::from("/etc/easyp/client-ca.pem")),
+            rules: rules.iter().map(|rule| ClientAuthRule::parse(rule).unwrap()).collect(),
+            admin_identities: admin_identities.iter().map(|identity| identity.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        let rule = ClientAuthRule::parse("required:Admin.Example.com").unwrap();
+        assert_eq!(rule, ClientAuthRule { mode: ClientAuthMode::Required, host: Some("admin.example.com".to_string()), path: None });
+        let rule = ClientAuthRule::parse("optional:*.example.com/private/").unwrap();
+        assert_eq!(rule.host.as_deref(), Some("*.example.com"));
+        assert_eq!(rule.path.as_deref(), Some("/private"));
+        let rule = ClientAuthRule::parse("required:/downloads").unwrap();
+        assert_eq!((rule.host, rule.path.as_deref()), (None, Some("/downloads")));
+        assert_eq!(ClientAuthRule::parse("required:*").unwrap().path, None);
+
+        assert!(ClientAuthRule::parse("admin.example.com").is_err());
+        assert!(ClientAuthRule::parse("sometimes:/x").is_err());
+        assert!(ClientAuthRule::parse("required:").is_err());
+        assert!(ClientAuthRule::parse("required:bad_host!/x").is_err());
+    }
+
+    #[test]
+    fn test_handshake_and_request_rules() {
+        let config = config(&["required:secure.example.com", "required:www.example.com/private", "optional:*.example.org"], &[]);
+        assert_eq!(config.handshake_mode(Some("secure.example.com")), Some(ClientAuthMode::Required));
+        assert_eq!(config.handshake_mode(Some("www.example.com")), Some(ClientAuthMode::Optional));
+        assert_eq!(config.handshake_mode(Some("a.example.org")), Some(ClientAuthMode::Optional));
+        assert_eq!(config.handshake_mode(Some("example.org")), None);
+        assert_eq!(config.handshake_mode(None), None);
+
+        assert!(config.requires_certificate(Some("SECURE.example.com"), "/"));
+        assert!(config.requires_certificate(Some("www.example.com"), "/private"));
+        assert!(config.requires_certificate(Some("www.example.com"), "/private/report.pdf?x=1"));
+        assert!(config.requires_certificate(Some("www.example.com"), "/public/../%70rivate//x"));
+        assert!(!config.requires_certificate(Some("www.example.com"), "/privateer"));
+        assert!(!config.requires_certificate(Some("other.example.com"), "/private"));
+        assert!(!config.requires_certificate(Some("a.example.org"), "/"));
+
+        // Admin identities ask everywhere; nothing is asked without a CA bundle
+        assert_eq!(self::config(&[], &["alice"]).handshake_mode(None), Some(ClientAuthMode::Optional));
+        let disabled = ClientAuthConfig { ca_file: None, ..self::config(&["required:*"], &[]) };
+        assert_eq!(disabled.handshake_mode(Some("a.example")), None);
+        assert!(disabled.validate().is_err());
+    }
+
+    #[test]
+    fn test_client_identity() {
+        let der = rustls_pemfile::certs(&mut CLIENT_CERT.as_bytes()).next().unwrap().unwrap();
+        let identity = ClientIdentity::from_der(&der).unwrap();
+        assert_eq!(identity.subject, "CN=alice,O=Example\\, Inc,C=AU");
+        assert_eq!(identity.common_name.as_deref(), Some("alice"));
+        assert_eq!(identity.serial, "1F2E");
+        assert_eq!(identity.subject_alt_names(), "DNS:alice.clients.example, email:alice@example.com");
+
+        assert!(identity.matches("alice"));
+        assert!(identity.matches("Alice@Example.com"));
+        assert!(identity.matches("CN=alice,O=Example\\, Inc,C=AU"));
+        assert!(!identity.matches("bob"));
+
+        assert!(config(&[], &[]).admin_allowed(None));
+        assert!(config(&[], &["bob", "alice.clients.example"]).admin_allowed(Some(&identity)));
+        assert!(!config(&[], &["bob"]).admin_allowed(Some(&identity)));
+        assert!(!config(&[], &["alice"]).admin_allowed(None));
+
+        let mut headers = HashMap::new();
+        headers.insert("x-client-cert-subject".to_string(), "CN=mallory".to_string());
+        headers.insert("x_client_cert_cn".to_string(), "mallory".to_string());
+        headers.insert("host".to_string(), "example.com".to_string());
+        apply_headers(&mut headers, None);
+        assert_eq!(headers.get("x-client-cert-verify").map(String::as_str), Some("NONE"));
+        assert!(!headers.contains_key("x-client-cert-subject") && !headers.contains_key("x_client_cert_cn"));
+        assert!(is_client_cert_header("X-Client-Cert-CN") && !is_client_cert_header("x-client-certificate"));
+
+        apply_headers(&mut headers, Some(&identity));
+        assert_eq!(headers.get("x-client-cert-verify").map(String::as_str), Some("SUCCESS"));
+        assert_eq!(headers.get("x-client-cert-cn").map(String::as_str), Some("alice"));
+        assert_eq!(headers.get("host").map(String::as_str), Some("example.com"));
+    }
+}
@@ -0,0 +1,630 @@
+//! In-Memory Content Cache
+//!
+//! This module keeps small, frequently requested files in memory so repeat requests skip
+//! `metadata`, `open` and `read`. It provides:
+//! - A byte-budgeted LRU shared by all clones of a [`ContentCache`]
+//! - Cached file bodies together with their metadata and ETag
+//! - Cached processed HTML for pages that only use constant extensions, keyed by
+//!   domain, request path and the file's ETag
+//! - Invalidation through inotify watches on the directories holding cached files
+//!   (Linux), with an mtime and length check as fallback
+//! - Process-wide hit, miss, eviction and invalidation counters for the stats panel
+//!
+//! Directories are watched lazily when the first file in them is cached, so only the
+//! hot parts of the document roots hold watches. Even watched entries are re-checked
+//! with a `stat` every [`WATCHED_REVALIDATE`] in case an event was missed (network
+//! filesystems and bind mounts do not always report changes).
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::file_cache::FileCacheInfo;
+
+/// Default cache budget in bytes (`--file-cache-size`)
+pub const DEFAULT_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Largest single entry cached; bigger files are always read from disk
+pub const MAX_ENTRY_SIZE: u64 = 1024 * 1024;
+
+/// How long a watched entry is trusted before it is re-checked with `stat`
+pub const WATCHED_REVALIDATE: Duration = Duration::from_secs(5);
+
+/// Approximate bookkeeping cost per entry, counted against the budget
+const ENTRY_OVERHEAD: usize = 128;
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+static EVICTIONS: AtomicU64 = AtomicU64::new(0);
+static INVALIDATIONS: AtomicU64 = AtomicU64::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+static ENTRIES: AtomicU64 = AtomicU64::new(0);
+static CAPACITY: AtomicU64 = AtomicU64::new(0);
+
+/// Snapshot of the process-wide cache counters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub invalidations: u64,
+    pub bytes: u64,
+    pub entries: u64,
+    pub capacity: u64,
+}
+
+impl CacheStats {
+    /// Fraction of lookups answered from memory, if there were any
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            None
+        } else {
+            Some(self.hits as f64 / total as f64)
+        }
+    }
+}
+
+/// Read the process-wide cache counters
+pub fn stats() -> CacheStats {
+    CacheStats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+        evictions: EVICTIONS.load(Ordering::Relaxed),
+        invalidations: INVALIDATIONS.load(Ordering::Relaxed),
+        bytes: BYTES.load(Ordering::Relaxed),
+        entries: ENTRIES.load(Ordering::Relaxed),
+        capacity: CAPACITY.load(Ordering::Relaxed),
+    }
+}
+
+fn record_lookup(cache: &'static str, hit: bool) {
+    if hit {
+        HITS.fetch_add(1, Ordering::Relaxed);
+    } else {
+        MISSES.fetch_add(1, Ordering::Relaxed);
+    }
+    super::metrics::record_cache(cache, hit);
+}
+
+/// A file body held in memory with everything needed to answer a request for it
+#[derive(Debug, Clone)]
+pub struct CachedFile {
+    pub contents: Arc<Vec<u8>>,
+    pub info: FileCacheInfo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum CacheKey {
+    File(PathBuf),
+    Page(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Validator {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Validator {
+    fn from_metadata(metadata: &std::fs::Metadata) -> Self {
+        Self { modified: metadata.modified().ok(), len: metadata.len() }
+    }
+}
+
+enum Value {
+    File { file: CachedFile, validator: Validator, checked: Instant },
+    Page(Arc<String>),
+}
+
+struct Entry {
+    value: Value,
+    /// Source file, used to drop the entry when the file or its directory changes
+    path: Option<PathBuf>,
+    size: usize,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<CacheKey, Entry>,
+    lru: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    bytes: usize,
+    /// Watched directory -> inotify watch descriptor
+    watched: HashMap<PathBuf, i32>,
+    /// inotify watch descriptor -> watched directory
+    watch_dirs: HashMap<i32, PathBuf>,
+}
+
+impl Inner {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn touch(&mut self, key: &CacheKey) {
+        let tick = self.next_tick();
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.lru.remove(&entry.tick);
+            entry.tick = tick;
+            self.lru.insert(tick, key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> bool {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                self.lru.remove(&entry.tick);
+                self.bytes -= entry.size;
+                BYTES.fetch_sub(entry.size as u64, Ordering::Relaxed);
+                ENTRIES.fetch_sub(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, key: CacheKey, value: Value, path: Option<PathBuf>, size: usize, max_bytes: usize) {
+        self.remove(&key);
+        while self.bytes + size > max_bytes {
+            let Some((_, oldest)) = self.lru.pop_first() else { break };
+            // pop_first already dropped the LRU slot; remove() tolerates that
+            if self.remove(&oldest) {
+                EVICTIONS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let tick = self.next_tick();
+        self.lru.insert(tick, key.clone());
+        self.entries.insert(key, Entry { value, path, size, tick });
+        self.bytes += size;
+        BYTES.fetch_add(size as u64, Ordering::Relaxed);
+        ENTRIES.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Drop every entry whose source path is `path` or lies below it
+    fn invalidate_under(&mut self, path: &Path) -> usize {
+        let stale: Vec<CacheKey> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.path.as_deref().is_some_and(|p| p.starts_with(path)))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &stale {
+            self.remove(key);
+        }
+        INVALIDATIONS.fetch_add(stale.len() as u64, Ordering::Relaxed);
+        stale.len()
+    }
+
+    fn clear(&mut self) {
+        let count = self.entries.len() as u64;
+        BYTES.fetch_sub(self.bytes as u64, Ordering::Relaxed);
+        ENTRIES.fetch_sub(count, Ordering::Relaxed);
+        INVALIDATIONS.fetch_add(count, Ordering::Relaxed);
+        self.entries.clear();
+        self.lru.clear();
+        self.bytes = 0;
+    }
+}
+
+struct Shared {
+    max_bytes: usize,
+    inner: Mutex<Inner>,
+    /// inotify descriptor; owned and closed by the watcher thread
+    watch_fd: Option<i32>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let inner = self.inner.get_mut().unwrap_or_else(|e| e.into_inner());
+        BYTES.fetch_sub(inner.bytes as u64, Ordering::Relaxed);
+        ENTRIES.fetch_sub(inner.entries.len() as u64, Ordering::Relaxed);
+        CAPACITY.fetch_sub(self.max_bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// Byte-budgeted LRU of file bodies and processed pages
+///
+/// Clones share the same storage, so every connection handler sees one cache.
+#[derive(Clone)]
+pub struct ContentCache {
+    shared: Arc<Shared>,
+}
+
+impl std::fmt::Debug for ContentCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ContentCache")
+            .field("max_bytes", &self.shared.max_bytes)
+            .field("watching", &self.shared.watch_fd.is_some())
+            .finish()
+    }
+}
+
+impl ContentCache {
+    /// Create a cache holding at most `max_bytes`; 0 disables caching
+    ///
+    /// # Arguments
+    /// * `max_bytes` - Memory budget for cached bodies and pages
+    ///
+    /// # Returns
+    /// * `ContentCache` - The cache, with its inotify watcher running when available
+    pub fn new(max_bytes: u64) -> Self {
+        let max_bytes = usize::try_from(max_bytes).unwrap_or(usize::MAX);
+        let watch_fd = if max_bytes > 0 { watch::init() } else { None };
+        let shared = Arc::new(Shared { max_bytes, inner: Mutex::new(Inner::default()), watch_fd });
+        CAPACITY.fetch_add(max_bytes as u64, Ordering::Relaxed);
+        if let Some(fd) = watch_fd {
+            watch::spawn(fd, Arc::downgrade(&shared));
+        }
+        Self { shared }
+    }
+
+    /// Whether the cache stores anything at all
+    pub fn is_enabled(&self) -> bool {
+        self.shared.max_bytes > 0
+    }
+
+    /// Configured budget in bytes
+    pub fn max_bytes(&self) -> u64 {
+        self.shared.max_bytes as u64
+    }
+
+    /// Largest body this cache will hold
+    pub fn max_entry_size(&self) -> u64 {
+        MAX_ENTRY_SIZE.min(self.shared.max_bytes as u64 / 8)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.shared.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Look up a cached file, re-checking it against the filesystem when due
+    ///
+    /// Counts a hit when an entry is returned. Misses are counted by
+    /// [`ContentCache::insert_file`] once the caller has read an eligible file.
+    ///
+    /// # Arguments
+    /// * `path` - Resolved path of the file on disk
+    ///
+    /// # Returns
+    /// * `Option<CachedFile>` - The cached file, or None if absent or stale
+    pub fn get_file(&self, path: &Path) -> Option<CachedFile> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let key = CacheKey::File(path.to_path_buf());
+        let (file, validator) = {
+            let mut inner = self.lock();
+            let watched = path.parent().is_some_and(|dir| inner.watched.contains_key(dir));
+            let Some(Entry { value: Value::File { file, validator, checked }, .. }) = inner.entries.get(&key) else {
+                return None;
+            };
+            // Without a watch every hit is re-validated; that is still one stat instead of stat+open+read
+            if watched && checked.elapsed() < WATCHED_REVALIDATE {
+                let file = file.clone();
+                inner.touch(&key);
+                record_lookup("file", true);
+                return Some(file);
+            }
+            (file.clone(), *validator)
+        };
+
+        let current = std::fs::metadata(path).ok().map(|m| Validator::from_metadata(&m));
+        let mut inner = self.lock();
+        if current == Some(validator) {
+            if let Some(Entry { value: Value::File { checked, .. }, .. }) = inner.entries.get_mut(&key) {
+                *checked = Instant::now();
+            }
+            inner.touch(&key);
+            record_lookup("file", true);
+            Some(file)
+        } else {
+            log::debug!(target: "files", "cached copy of {} is stale", path.display());
+            if inner.remove(&key) {
+                INVALIDATIONS.fetch_add(1, Ordering::Relaxed);
+            }
+            None
+        }
+    }
+
+    /// Store a file that was just read from disk and count the miss
+    ///
+    /// Files larger than [`ContentCache::max_entry_size`] are passed through uncached
+    /// and are not counted, so the hit ratio only covers files the cache could hold.
+    ///
+    /// # Arguments
+    /// * `path` - Resolved path of the file on disk
+    /// * `metadata` - Metadata taken before the file was read
+    /// * `contents` - File body
+    /// * `info` - Cache validators derived from `metadata`
+    ///
+    /// # Returns
+    /// * `CachedFile` - The file, shared with the cache when it was stored
+    pub fn insert_file(
+        &self,
+        path: &Path,
+        metadata: &std::fs::Metadata,
+        contents: Vec<u8>,
+        info: FileCacheInfo,
+    ) -> CachedFile {
+        let file = CachedFile { contents: Arc::new(contents), info };
+        let validator = Validator::from_metadata(metadata);
+        // A file that changed while it was read must not be cached under the old validator
+        if !self.is_enabled() || file.contents.len() as u64 > self.max_entry_size() || file.contents.len() as u64 != validator.len {
+            return file;
+        }
+        record_lookup("file", false);
+
+        let size = file.contents.len() + file.info.etag.len() + path.as_os_str().len() + ENTRY_OVERHEAD;
+        let value = Value::File { file: file.clone(), validator, checked: Instant::now() };
+        let mut inner = self.lock();
+        if let (Some(fd), Some(dir)) = (self.shared.watch_fd, path.parent()) {
+            watch::add(fd, &mut inner, dir);
+        }
+        inner.insert(CacheKey::File(path.to_path_buf()), value, Some(path.to_path_buf()), size, self.shared.max_bytes);
+        file
+    }
+
+    /// Build the key for a processed page
+    ///
+    /// The ETag is part of the key, so an edited file never matches an older page.
+    pub fn page_key(domain: Option<&str>, request_path: &str, etag: &str) -> String {
+        format!("{}|{}|{}", domain.unwrap_or(""), request_path, etag)
+    }
+
+    /// Look up processed HTML, counting a hit or a miss
+    pub fn get_page(&self, key: &str) -> Option<Arc<String>> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let key = CacheKey::Page(key.to_string());
+        let mut inner = self.lock();
+        let page = match inner.entries.get(&key) {
+            Some(Entry { value: Value::Page(html), .. }) => Some(html.clone()),
+            _ => None,
+        };
+        if page.is_some() {
+            inner.touch(&key);
+        }
+        record_lookup("page", page.is_some());
+        page
+    }
+
+    /// Store processed HTML for a page that only uses constant extensions
+    pub fn insert_page(&self, key: String, html: String) -> Arc<String> {
+        let html = Arc::new(html);
+        if !self.is_enabled() || html.len() as u64 > self.max_entry_size() {
+            return html;
+        }
+        let size = html.len() + key.len() + ENTRY_OVERHEAD;
+        self.lock().insert(CacheKey::Page(key), Value::Page(html.clone()), None, size, self.shared.max_bytes);
+        html
+    }
+
+    /// Drop cached files at or below `path`
+    pub fn invalidate(&self, path: &Path) -> usize {
+        self.lock().invalidate_under(path)
+    }
+
+    /// Drop everything
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Bytes currently held by this cache
+    pub fn bytes(&self) -> u64 {
+        self.lock().bytes as u64
+    }
+
+    /// Entries currently held by this cache
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Whether this cache currently holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod watch {
+    use super::{Inner, Shared};
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::sync::Weak;
+
+    const MASK: u32 = libc::IN_MODIFY
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_ATTRIB
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_DELETE_SELF
+        | libc::IN_MOVE_SELF;
+
+    /// Size of `struct inotify_event` without the trailing name
+    const EVENT_HEADER: usize = 16;
+
+    pub(super) fn init() -> Option<i32> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            log::warn!(target: "files", "inotify unavailable, file cache falls back to mtime checks: {}", std::io::Error::last_os_error());
+            return None;
+        }
+        Some(fd)
+    }
+
+    pub(super) fn add(fd: i32, inner: &mut Inner, dir: &Path) {
+        if inner.watched.contains_key(dir) {
+            return;
+        }
+        let Ok(c_path) = CString::new(dir.as_os_str().as_bytes()) else { return };
+        let wd = unsafe { libc::inotify_add_watch(fd, c_path.as_ptr(), MASK) };
+        if wd < 0 {
+            // Usually fs.inotify.max_user_watches; entries here keep using mtime checks
+            log::debug!(target: "files", "cannot watch {}: {}", dir.display(), std::io::Error::last_os_error());
+            return;
+        }
+        inner.watched.insert(dir.to_path_buf(), wd);
+        inner.watch_dirs.insert(wd, dir.to_path_buf());
+    }
+
+    /// Read events until the cache is dropped, then close the descriptor
+    pub(super) fn spawn(fd: i32, shared: Weak<Shared>) {
+        let result = std::thread::Builder::new().name("file-cache-watch".to_string()).spawn(move || {
+            let mut buffer = [0u8; 8192];
+            loop {
+                let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+                let ready = unsafe { libc::poll(&mut pollfd, 1, 1000) };
+                let Some(shared) = shared.upgrade() else { break };
+                if ready <= 0 {
+                    continue;
+                }
+                let n = unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+                if n <= 0 {
+                    continue;
+                }
+                let mut inner = shared.inner.lock().unwrap_or_else(|e| e.into_inner());
+                handle_events(fd, &mut inner, &buffer[..n as usize]);
+            }
+            unsafe { libc::close(fd) };
+        });
+        if let Err(e) = result {
+            log::warn!(target: "files", "cannot start file cache watcher: {}", e);
+            unsafe { libc::close(fd) };
+        }
+    }
+
+    fn handle_events(fd: i32, inner: &mut Inner, mut events: &[u8]) {
+        while events.len() >= EVENT_HEADER {
+            let wd = i32::from_ne_bytes(events[0..4].try_into().unwrap());
+            let mask = u32::from_ne_bytes(events[4..8].try_into().unwrap());
+            let len = u32::from_ne_bytes(events[12..16].try_into().unwrap()) as usize;
+            let end = (EVENT_HEADER + len).min(events.len());
+            let name = &events[EVENT_HEADER..end];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            events = &events[end..];
+
+            if mask & libc::IN_Q_OVERFLOW != 0 {
+                log::debug!(target: "files", "inotify queue overflowed, clearing file cache");
+                inner.clear();
+                continue;
+            }
+            let Some(dir) = inner.watch_dirs.get(&wd).cloned() else { continue };
+            if mask & (libc::IN_IGNORED | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF) != 0 {
+                inner.invalidate_under(&dir);
+                inner.watch_dirs.remove(&wd);
+                inner.watched.remove(&dir);
+                if mask & libc::IN_IGNORED == 0 {
+                    unsafe { libc::inotify_rm_watch(fd, wd) };
+                }
+                continue;
+            }
+            if !name.is_empty() {
+                let path = dir.join(std::ffi::OsStr::from_bytes(name));
+                let dropped = inner.invalidate_under(&path);
+                if dropped > 0 {
+                    log::trace!(target: "files", "{} changed, dropped {} cached entries", path.display(), dropped);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod watch {
+    use super::{Inner, Shared};
+    use std::path::Path;
+    use std::sync::Weak;
+
+    pub(super) fn init() -> Option<i32> {
+        None
+    }
+
+    pub(super) fn add(_fd: i32, _inner: &mut Inner, _dir: &Path) {}
+
+    pub(super) fn spawn(_fd: i32, _shared: Weak<Shared>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn cache_file(cache: &ContentCache, path: &Path) -> CachedFile {
+        let metadata = std::fs::metadata(path).unwrap();
+        let contents = std::fs::read(path).unwrap();
+        let info = FileCacheInfo::from_metadata(&metadata);
+        cache.insert_file(path, &metadata, contents, info)
+    }
+
+    #[test]
+    fn test_lru_eviction_respects_budget() {
+        let cache = ContentCache::new(64 * 1024);
+        for i in 0..20 {
+            cache.insert_page(format!("page{}", i), "x".repeat(4000));
+        }
+        assert!(cache.bytes() <= 64 * 1024);
+        assert!(cache.len() < 20);
+        // The most recent pages survive, the oldest were evicted
+        assert!(cache.get_page("page19").is_some());
+        assert!(cache.get_page("page0").is_none());
+
+        // Touching an entry protects it from the next eviction
+        let survivor = (0..20).map(|i| format!("page{}", i)).find(|k| cache.get_page(k).is_some()).unwrap();
+        cache.insert_page("page20".to_string(), "x".repeat(4000));
+        assert!(cache.get_page(&survivor).is_some());
+
+        // Oversized entries pass through without displacing anything
+        let before = cache.len();
+        cache.insert_page("huge".to_string(), "x".repeat(10_000));
+        assert!(cache.get_page("huge").is_none());
+        assert_eq!(cache.len(), before);
+    }
+
+    #[test]
+    fn test_disabled_cache_stores_nothing() {
+        let cache = ContentCache::new(0);
+        assert!(!cache.is_enabled());
+        cache.insert_page("a".to_string(), "body".to_string());
+        assert!(cache.get_page("a").is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_file_change_invalidates_entry() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("index.html");
+        std::fs::write(&path, "first").unwrap();
+
+        let cache = ContentCache::new(1024 * 1024);
+        cache_file(&cache, &path);
+        let hit = cache.get_file(&path).expect("cached file");
+        assert_eq!(hit.contents.as_slice(), b"first");
+
+        let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b" and second").unwrap();
+        drop(file);
+
+        // inotify drops the entry shortly; the mtime/length check catches it once due
+        let deadline = Instant::now() + WATCHED_REVALIDATE + Duration::from_secs(2);
+        while cache.get_file(&path).is_some() {
+            assert!(Instant::now() < deadline, "stale entry was never invalidated");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        let fresh = cache_file(&cache, &path);
+        assert_eq!(fresh.contents.as_slice(), b"first and second");
+        assert!(cache.get_file(&path).is_some());
+
+        cache.invalidate(dir);
+        assert!(cache.get_file(&path).is_none());
+    }
+}
@@ -0,0 +1,74 @@
+//! Digests and Base64
+//!
+//! This module gives the server's few digest and base64 needs one home, backed by
+//! ring and the base64 crate. It provides:
+//! - [`sha1`]: the WebSocket handshake and OCSP CertIDs, neither security relevant
+//! - [`sha256`]: ACME key authorization digests
+//! - [`base64_encode`] and [`base64_decode`]: standard base64 out, standard or
+//!   URL-safe in (TSIG secrets)
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use ring::digest;
+
+/// SHA-1 (FIPS 180-4)
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, data).as_ref().try_into().expect("SHA-1 digests are 20 bytes")
+}
+
+/// SHA-256 (FIPS 180-4)
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    digest::digest(&digest::SHA256, data).as_ref().try_into().expect("SHA-256 digests are 32 bytes")
+}
+
+/// Standard base64 with padding
+pub fn base64_encode(data: &[u8]) -> String {
+    STANDARD.encode(data)
+}
+
+/// Decode standard or URL-safe base64, with or without padding
+///
+/// # Returns
+/// * `Option<Vec<u8>>` - None if the text is not base64 in either alphabet
+pub fn base64_decode(value: &str) -> Option<Vec<u8>> {
+    let standard = value.trim().trim_end_matches('=').replace('-', "+").replace('_', "/");
+    STANDARD_NO_PAD.decode(standard).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex(&sha1(&[b'a'; 1000])), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(hex(&sha256(b"")), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex(&sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_decode("Zm9vYg==").unwrap(), b"foob");
+        assert_eq!(base64_decode("47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU").unwrap(), sha256(b""));
+        assert_eq!(base64_decode(&base64_encode(&[0xfb, 0xff, 0xfe])).unwrap(), [0xfb, 0xff, 0xfe]);
+        assert!(base64_decode("not base64!").is_none());
+    }
+}
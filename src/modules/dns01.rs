@@ -0,0 +1,922 @@
+//! DNS-01 Challenges
+//!
+//! This module answers ACME DNS-01 validation (RFC 8555 section 8.4) by publishing
+//! `_acme-challenge` TXT records through a pluggable [`DnsProvider`]. It provides:
+//! - [`txt_record_name`] / [`txt_record_value`]: the record a key authorization needs
+//! - [`Rfc2136Provider`]: dynamic updates (RFC 2136) sent to the primary nameserver over
+//!   UDP, signed with TSIG HMAC-SHA256 (RFC 8945) when a key is configured
+//! - [`ExecHookProvider`]: runs `SCRIPT add|remove NAME VALUE` for any other DNS API
+//! - [`ZoneFileProvider`]: keeps the records in a zone fragment for a nameserver that
+//!   includes and reloads it
+//! - [`Dns01Solver`]: asks the ACME client for pending DNS-01 key authorizations,
+//!   publishes each record in its own task, waits for it to propagate and withdraws it
+//!   once the challenge is gone
+//!
+//! Every record published is also written to a state file, so records left behind by a
+//! crash or restart are removed on the next start. DNS-01 needs no open port, and is the
+//! only challenge that can validate wildcard names.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::future::Future;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::hmac;
+
+use super::digest::{base64_decode, sha256};
+use super::hostname::normalize_hostname;
+
+const UPDATE_TIMEOUT: Duration = Duration::from_secs(5);
+const UPDATE_ATTEMPTS: usize = 3;
+
+/// How long a DNS hook may run before it is killed
+const HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Hook stderr kept for the error message
+const MAX_HOOK_STDERR: u64 = 4096;
+
+/// Allowed clock difference for TSIG signatures, in seconds
+const TSIG_FUDGE: u16 = 300;
+const TSIG_ALGORITHM: &str = "hmac-sha256";
+
+const OPCODE_UPDATE: u16 = 5;
+const TYPE_SOA: u16 = 6;
+const TYPE_TXT: u16 = 16;
+const TYPE_TSIG: u16 = 250;
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+
+/// Owner name of the TXT record for a domain (wildcards are validated at their base name)
+pub fn txt_record_name(domain: &str) -> Result<String, String> {
+    let domain = domain.strip_prefix("*.").unwrap_or(domain);
+    Ok(format!("_acme-challenge.{}", normalize_hostname(domain)?))
+}
+
+/// TXT record value for a key authorization: base64url(SHA-256(key authorization))
+pub fn txt_record_value(key_authorization: &str) -> String {
+    URL_SAFE_NO_PAD.encode(sha256(key_authorization.as_bytes()))
+}
+
+/// Publishes and withdraws TXT records
+pub trait DnsProvider: Send + Sync + std::fmt::Debug {
+    /// Add a TXT record, keeping other values for the same name
+    fn add_txt(&self, name: &str, value: &str) -> Result<(), String>;
+
+    /// Remove one TXT record added by [`DnsProvider::add_txt`]
+    fn remove_txt(&self, name: &str, value: &str) -> Result<(), String>;
+}
+
+/// `--dns-provider` backend
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProviderSpec {
+    /// Primary nameserver accepting dynamic updates, `HOST[:PORT]`
+    Rfc2136(String),
+    /// Script called with `add|remove NAME VALUE`
+    Exec(PathBuf),
+    /// Zone fragment rewritten on every change
+    ZoneFile(PathBuf),
+}
+
+impl ProviderSpec {
+    /// Parse `rfc2136:SERVER[:PORT]`, `exec:SCRIPT` or `zonefile:PATH`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid --dns-provider '{}': expected rfc2136:SERVER, exec:SCRIPT or zonefile:PATH", value);
+        let (kind, target) = value.split_once(':').ok_or_else(invalid)?;
+        if target.is_empty() {
+            return Err(invalid());
+        }
+        match kind.to_ascii_lowercase().as_str() {
+            "rfc2136" | "nsupdate" => Ok(ProviderSpec::Rfc2136(target.to_string())),
+            "exec" | "hook" => Ok(ProviderSpec::Exec(PathBuf::from(target))),
+            "zonefile" | "file" => Ok(ProviderSpec::ZoneFile(PathBuf::from(target))),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// DNS-01 settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dns01Config {
+    pub provider: Option<ProviderSpec>,
+    /// Zone that dynamic updates are addressed to
+    pub zone: Option<String>,
+    pub tsig_key: Option<TsigKey>,
+    /// TTL of the published records
+    pub ttl: u32,
+    /// Time allowed for a new record to reach every authoritative nameserver
+    pub propagation_wait: Duration,
+}
+
+impl Default for Dns01Config {
+    fn default() -> Self {
+        Self { provider: None, zone: None, tsig_key: None, ttl: 60, propagation_wait: Duration::from_secs(30) }
+    }
+}
+
+impl Dns01Config {
+    /// Check that the flags given fit together
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.provider {
+            Some(ProviderSpec::Rfc2136(_)) if self.zone.is_none() => Err("--dns-provider rfc2136 needs --dns-zone".to_string()),
+            Some(ProviderSpec::Rfc2136(_)) => Ok(()),
+            _ if self.tsig_key.is_some() => Err("--dns-tsig-key is only used with --dns-provider rfc2136".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Create the configured provider
+    ///
+    /// # Returns
+    /// * `Result<Arc<dyn DnsProvider>, String>` - An error if no provider is configured or the server does not resolve
+    pub fn build_provider(&self) -> Result<Arc<dyn DnsProvider>, String> {
+        match &self.provider {
+            None => Err("DNS-01 challenges need a --dns-provider".to_string()),
+            Some(ProviderSpec::Rfc2136(server)) => {
+                let zone = self.zone.clone().ok_or("--dns-provider rfc2136 needs --dns-zone")?;
+                Ok(Arc::new(Rfc2136Provider::new(resolve_server(server)?, &zone, self.ttl, self.tsig_key.clone())?))
+            }
+            Some(ProviderSpec::Exec(script)) => Ok(Arc::new(ExecHookProvider::new(script.clone()))),
+            Some(ProviderSpec::ZoneFile(path)) => Ok(Arc::new(ZoneFileProvider::new(path.clone(), self.ttl))),
+        }
+    }
+}
+
+/// `HOST`, `HOST:PORT`, `IP` or `[IPV6]:PORT`, port 53 by default
+fn resolve_server(server: &str) -> Result<SocketAddr, String> {
+    if let Ok(addr) = server.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    if let Ok(ip) = server.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    let with_port = if server.contains(':') { server.to_string() } else { format!("{}:53", server) };
+    with_port
+        .to_socket_addrs()
+        .map_err(|e| format!("Cannot resolve DNS server {}: {}", server, e))?
+        .next()
+        .ok_or_else(|| format!("Cannot resolve DNS server {}", server))
+}
+
+/// A TSIG key for HMAC-SHA256
+#[derive(Clone, PartialEq)]
+pub struct TsigKey {
+    pub name: String,
+    pub secret: Vec<u8>,
+}
+
+impl std::fmt::Debug for TsigKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TsigKey").field("name", &self.name).finish_non_exhaustive()
+    }
+}
+
+impl TsigKey {
+    /// Parse `[hmac-sha256:]NAME:SECRET`, the secret in base64 as printed by `tsig-keygen`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || "Invalid --dns-tsig-key: expected [hmac-sha256:]NAME:BASE64-SECRET".to_string();
+        let (name, secret) = value.rsplit_once(':').ok_or_else(invalid)?;
+        let name = match name.split_once(':') {
+            Some((algorithm, name)) if algorithm.eq_ignore_ascii_case(TSIG_ALGORITHM) => name,
+            Some((algorithm, _)) => return Err(format!("Unsupported TSIG algorithm '{}': only hmac-sha256 is supported", algorithm)),
+            None => name,
+        };
+        let secret = base64_decode(secret).ok_or_else(invalid)?;
+        if name.is_empty() || secret.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self { name: name.trim_end_matches('.').to_ascii_lowercase(), secret })
+    }
+}
+
+/// Append a domain name in wire format (lowercased, as TSIG requires)
+fn push_name(out: &mut Vec<u8>, name: &str) -> Result<(), String> {
+    for label in name.trim_end_matches('.').split('.').filter(|label| !label.is_empty()) {
+        if label.len() > 63 {
+            return Err(format!("DNS label too long in {}", name));
+        }
+        out.push(label.len() as u8);
+        out.extend(label.bytes().map(|b| b.to_ascii_lowercase()));
+    }
+    out.push(0);
+    Ok(())
+}
+
+/// Build an UPDATE message adding (or deleting) one TXT record in `zone`
+fn update_message(id: u16, zone: &str, name: &str, value: &str, ttl: u32, add: bool) -> Result<Vec<u8>, String> {
+    if value.len() > 255 {
+        return Err(format!("TXT value for {} is too long", name));
+    }
+    let mut message = Vec::with_capacity(128);
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&(OPCODE_UPDATE << 11).to_be_bytes());
+    // One zone, no prerequisites, one update, no additional records (yet)
+    for count in [1u16, 0, 1, 0] {
+        message.extend_from_slice(&count.to_be_bytes());
+    }
+    push_name(&mut message, zone)?;
+    message.extend_from_slice(&TYPE_SOA.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    // RFC 2136 section 2.5: add to an RRset, or delete an RR from an RRset (class NONE, TTL 0)
+    push_name(&mut message, name)?;
+    message.extend_from_slice(&TYPE_TXT.to_be_bytes());
+    message.extend_from_slice(&(if add { CLASS_IN } else { CLASS_NONE }).to_be_bytes());
+    message.extend_from_slice(&(if add { ttl } else { 0 }).to_be_bytes());
+    message.extend_from_slice(&(value.len() as u16 + 1).to_be_bytes());
+    message.push(value.len() as u8);
+    message.extend_from_slice(value.as_bytes());
+    Ok(message)
+}
+
+/// Append a TSIG record signing `message` (RFC 8945 section 4.3)
+fn sign_message(message: &mut Vec<u8>, key: &TsigKey, time_signed: u64) -> Result<(), String> {
+    let mut key_name = Vec::new();
+    push_name(&mut key_name, &key.name)?;
+    let mut algorithm = Vec::new();
+    push_name(&mut algorithm, TSIG_ALGORITHM)?;
+    let time = &time_signed.to_be_bytes()[2..];
+
+    let mut signed = message.clone();
+    signed.extend_from_slice(&key_name);
+    signed.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    signed.extend_from_slice(&0u32.to_be_bytes());
+    signed.extend_from_slice(&algorithm);
+    signed.extend_from_slice(time);
+    signed.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+    // Error and other length
+    signed.extend_from_slice(&[0, 0, 0, 0]);
+    let mac = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &key.secret), &signed);
+    let mac = mac.as_ref();
+
+    let mut rdata = algorithm;
+    rdata.extend_from_slice(time);
+    rdata.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+    rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+    rdata.extend_from_slice(mac);
+    rdata.extend_from_slice(&message[..2]);
+    rdata.extend_from_slice(&[0, 0, 0, 0]);
+
+    message.extend_from_slice(&key_name);
+    message.extend_from_slice(&TYPE_TSIG.to_be_bytes());
+    message.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    message.extend_from_slice(&0u32.to_be_bytes());
+    message.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    message.extend_from_slice(&rdata);
+    let additional = u16::from_be_bytes([message[10], message[11]]) + 1;
+    message[10..12].copy_from_slice(&additional.to_be_bytes());
+    Ok(())
+}
+
+fn rcode_name(rcode: u16) -> &'static str {
+    match rcode {
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        _ => "unknown error",
+    }
+}
+
+/// Dynamic updates to the zone's primary nameserver
+#[derive(Debug)]
+pub struct Rfc2136Provider {
+    server: SocketAddr,
+    zone: String,
+    ttl: u32,
+    tsig_key: Option<TsigKey>,
+}
+
+impl Rfc2136Provider {
+    pub fn new(server: SocketAddr, zone: &str, ttl: u32, tsig_key: Option<TsigKey>) -> Result<Self, String> {
+        Ok(Self { server, zone: normalize_hostname(zone)?, ttl, tsig_key })
+    }
+
+    /// Send one update and wait for the server's answer
+    fn update(&self, name: &str, value: &str, add: bool) -> Result<(), String> {
+        if name != self.zone && !name.ends_with(&format!(".{}", self.zone)) {
+            return Err(format!("{} is not in zone {}", name, self.zone));
+        }
+        let id = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos() & 0xffff) as u16;
+        let mut message = update_message(id, &self.zone, name, value, self.ttl, add)?;
+        if let Some(key) = &self.tsig_key {
+            sign_message(&mut message, key, SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())?;
+        }
+
+        let bind_addr = if self.server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind_addr).map_err(|e| format!("Cannot open DNS socket: {}", e))?;
+        socket.connect(self.server).map_err(|e| format!("Cannot reach DNS server {}: {}", self.server, e))?;
+        socket.set_read_timeout(Some(UPDATE_TIMEOUT)).map_err(|e| e.to_string())?;
+        let mut response = [0u8; 512];
+        for _ in 0..UPDATE_ATTEMPTS {
+            socket.send(&message).map_err(|e| format!("Cannot send DNS update to {}: {}", self.server, e))?;
+            // Stray answers to earlier attempts carry the same ID and are just as good
+            let len = match socket.recv(&mut response) {
+                Ok(len) => len,
+                Err(ref e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => continue,
+                Err(e) => return Err(format!("DNS update to {} failed: {}", self.server, e)),
+            };
+            if len < 12 || response[..2] != id.to_be_bytes() || response[2] & 0x80 == 0 {
+                continue;
+            }
+            return match u16::from_be_bytes([response[2], response[3]]) & 0x000f {
+                0 => Ok(()),
+                9 if self.tsig_key.is_some() => Err(format!("DNS server {} answered NOTAUTH: check the TSIG key", self.server)),
+                rcode => Err(format!("DNS server {} answered {} for {}", self.server, rcode_name(rcode), name)),
+            };
+        }
+        Err(format!("No answer from DNS server {}", self.server))
+    }
+}
+
+impl DnsProvider for Rfc2136Provider {
+    fn add_txt(&self, name: &str, value: &str) -> Result<(), String> {
+        self.update(name, value, true)
+    }
+
+    fn remove_txt(&self, name: &str, value: &str) -> Result<(), String> {
+        self.update(name, value, false)
+    }
+}
+
+/// Runs a user script: `SCRIPT add NAME VALUE` and `SCRIPT remove NAME VALUE`
+///
+/// A hook still running after [`HOOK_TIMEOUT`] is killed and the call fails.
+#[derive(Debug)]
+pub struct ExecHookProvider {
+    script: PathBuf,
+    timeout: Duration,
+}
+
+impl ExecHookProvider {
+    pub fn new(script: PathBuf) -> Self {
+        Self { script, timeout: HOOK_TIMEOUT }
+    }
+
+    fn run(&self, action: &str, name: &str, value: &str) -> Result<(), String> {
+        let mut child = Command::new(&self.script)
+            .args([action, name, value])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Cannot run DNS hook {}: {}", self.script.display(), e))?;
+
+        // Drain stderr alongside, so a chatty hook cannot stall on a full pipe
+        let stderr = child.stderr.take().map(|mut stderr| {
+            std::thread::spawn(move || {
+                let mut captured = Vec::new();
+                let _ = (&mut stderr).take(MAX_HOOK_STDERR).read_to_end(&mut captured);
+                let _ = std::io::copy(&mut stderr, &mut std::io::sink());
+                captured
+            })
+        });
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if Instant::now() < deadline => std::thread::sleep(HOOK_POLL_INTERVAL),
+                Ok(None) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(format!(
+                        "DNS hook {} {} {} ran longer than {:?} and was killed",
+                        self.script.display(),
+                        action,
+                        name,
+                        self.timeout
+                    ));
+                }
+                Err(e) => return Err(format!("Cannot wait for DNS hook {}: {}", self.script.display(), e)),
+            }
+        };
+        if status.success() {
+            return Ok(());
+        }
+        let stderr = stderr.and_then(|reader| reader.join().ok()).unwrap_or_default();
+        Err(format!(
+            "DNS hook {} {} {} failed ({}): {}",
+            self.script.display(),
+            action,
+            name,
+            status,
+            String::from_utf8_lossy(&stderr).trim()
+        ))
+    }
+}
+
+impl DnsProvider for ExecHookProvider {
+    fn add_txt(&self, name: &str, value: &str) -> Result<(), String> {
+        self.run("add", name, value)
+    }
+
+    fn remove_txt(&self, name: &str, value: &str) -> Result<(), String> {
+        self.run("remove", name, value)
+    }
+}
+
+/// Keeps the records as `NAME. TTL IN TXT "VALUE"` lines in a file that the zone includes
+#[derive(Debug)]
+pub struct ZoneFileProvider {
+    path: PathBuf,
+    ttl: u32,
+    // Serializes read-modify-write cycles
+    lock: Mutex<()>,
+}
+
+impl ZoneFileProvider {
+    pub fn new(path: PathBuf, ttl: u32) -> Self {
+        Self { path, ttl, lock: Mutex::new(()) }
+    }
+
+    fn record(&self, name: &str, value: &str) -> String {
+        format!("{}. {} IN TXT \"{}\"", name, self.ttl, value)
+    }
+
+    fn rewrite(&self, change: impl FnOnce(&mut Vec<String>)) -> Result<(), String> {
+        let _guard = self.lock.lock().unwrap();
+        let mut lines: Vec<String> = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents.lines().map(str::to_string).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.path.display(), e)),
+        };
+        change(&mut lines);
+        let mut contents = lines.join("\n");
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+        write_atomically(&self.path, contents.as_bytes())
+    }
+}
+
+impl DnsProvider for ZoneFileProvider {
+    fn add_txt(&self, name: &str, value: &str) -> Result<(), String> {
+        let record = self.record(name, value);
+        self.rewrite(|lines| {
+            if !lines.contains(&record) {
+                lines.push(record);
+            }
+        })
+    }
+
+    fn remove_txt(&self, name: &str, value: &str) -> Result<(), String> {
+        let record = self.record(name, value);
+        self.rewrite(|lines| lines.retain(|line| *line != record))
+    }
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), String> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let mut file = fs::File::create(&temp).map_err(|e| format!("Failed to create {}: {}", temp.display(), e))?;
+    file.write_all(data)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("Failed to write {}: {}", temp.display(), e))?;
+    fs::rename(&temp, path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))
+}
+
+/// How often the solver asks for pending challenges
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long a domain is watched after its last handshake
+const WATCH_TIME: Duration = Duration::from_secs(10 * 60);
+
+/// Publishes TXT records for pending DNS-01 challenges
+#[derive(Debug)]
+pub struct Dns01Solver {
+    provider: Arc<dyn DnsProvider>,
+    propagation_wait: Duration,
+    /// Records published, so they survive restarts until withdrawn
+    state_file: Option<PathBuf>,
+    /// Published (name, value) by domain
+    published: Mutex<HashMap<String, (String, String)>>,
+    /// (name, value) of records that could not be withdrawn, kept for the next start
+    stale: Mutex<BTreeSet<(String, String)>>,
+    /// Domains to ask about, with the time they were last seen
+    watched: Mutex<HashMap<String, Instant>>,
+    /// Domains with a publish or withdraw task running
+    busy: Mutex<HashSet<String>>,
+}
+
+impl Dns01Solver {
+    pub fn new(provider: Arc<dyn DnsProvider>, propagation_wait: Duration, state_file: Option<PathBuf>) -> Self {
+        Self {
+            provider,
+            propagation_wait,
+            state_file,
+            published: Mutex::new(HashMap::new()),
+            stale: Mutex::new(BTreeSet::new()),
+            watched: Mutex::new(HashMap::new()),
+            busy: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Ask about `domain` for a while, e.g. because a handshake may have started an order
+    pub fn watch(&self, domain: &str) {
+        self.watched.lock().unwrap().insert(domain.to_string(), Instant::now());
+    }
+
+    /// Poll `lookup` every [`WATCH_INTERVAL`] for the key authorization of watched domains
+    ///
+    /// # Arguments
+    /// * `lookup` - The ACME client's `get_challenge_response`, None once no challenge is pending
+    pub fn spawn_watcher<F, Fut>(self: &Arc<Self>, lookup: F)
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<String>> + Send,
+    {
+        let solver = Arc::downgrade(self);
+        tokio::spawn(async move {
+            while let Some(solver) = solver.upgrade() {
+                solver.poll(&lookup).await;
+                drop(solver);
+                tokio::time::sleep(WATCH_INTERVAL).await;
+            }
+        });
+    }
+
+    /// One round of the watcher: publish or withdraw records whose challenge changed
+    ///
+    /// Every change runs as its own task, so one domain waiting for propagation does not
+    /// hold up the others.
+    async fn poll<F, Fut>(self: &Arc<Self>, lookup: &F)
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Option<String>>,
+    {
+        for domain in self.watched_domains() {
+            if self.busy.lock().unwrap().contains(&domain) {
+                continue;
+            }
+            let key_authorization = lookup(domain.clone()).await;
+            let published = self.published.lock().unwrap().get(&domain).map(|(_, value)| value.clone());
+            let change = match key_authorization {
+                Some(key_authorization) if published.as_deref() != Some(txt_record_value(&key_authorization).as_str()) => {
+                    Some(key_authorization)
+                }
+                None if published.is_some() => None,
+                _ => continue,
+            };
+            self.busy.lock().unwrap().insert(domain.clone());
+            let solver = self.clone();
+            tokio::spawn(async move {
+                let result = match change {
+                    Some(key_authorization) => solver.present(&domain, &key_authorization).await,
+                    None => solver.cleanup(&domain).await,
+                };
+                if let Err(e) = result {
+                    log::warn!(target: "acme", domain:% = domain; "DNS-01 record update failed: {}", e);
+                }
+                solver.busy.lock().unwrap().remove(&domain);
+            });
+        }
+    }
+
+    /// Watched domains, forgetting those idle for [`WATCH_TIME`] without a published record
+    fn watched_domains(&self) -> Vec<String> {
+        let published = self.published.lock().unwrap();
+        let mut watched = self.watched.lock().unwrap();
+        watched.retain(|domain, seen| seen.elapsed() < WATCH_TIME || published.contains_key(domain));
+        watched.keys().cloned().collect()
+    }
+
+    /// Publish the record for a key authorization and wait for it to propagate
+    ///
+    /// # Arguments
+    /// * `domain` - The identifier being validated
+    /// * `key_authorization` - `token.thumbprint` from the ACME challenge
+    pub async fn present(&self, domain: &str, key_authorization: &str) -> Result<(), String> {
+        let name = txt_record_name(domain)?;
+        let value = txt_record_value(key_authorization);
+        // A new challenge for the same domain replaces the old record
+        if let Err(e) = self.cleanup(domain).await {
+            log::warn!(target: "acme", domain:% = domain; "cannot remove previous DNS-01 record: {}", e);
+        }
+        self.call(name.clone(), value.clone(), true).await?;
+        self.published.lock().unwrap().insert(domain.to_string(), (name.clone(), value));
+        self.save_state();
+        log::info!(target: "acme", domain:% = domain; "published DNS-01 record {}, waiting {}s for propagation", name, self.propagation_wait.as_secs());
+        tokio::time::sleep(self.propagation_wait).await;
+        Ok(())
+    }
+
+    /// Withdraw the record published for a domain, if any
+    pub async fn cleanup(&self, domain: &str) -> Result<(), String> {
+        let Some((name, value)) = self.published.lock().unwrap().remove(domain) else {
+            return Ok(());
+        };
+        let result = self.call(name.clone(), value.clone(), false).await;
+        match &result {
+            Ok(()) => log::info!(target: "acme", domain:% = domain; "removed DNS-01 record {}", name),
+            // Keep it in the state file so the next start tries again
+            Err(_) => {
+                self.stale.lock().unwrap().insert((name, value));
+            }
+        }
+        self.save_state();
+        result
+    }
+
+    /// Remove records recorded in the state file by an earlier run
+    ///
+    /// # Returns
+    /// * `usize` - The number of records removed
+    pub async fn remove_stale(&self) -> usize {
+        let Some(path) = &self.state_file else { return 0 };
+        let Ok(contents) = fs::read_to_string(path) else { return 0 };
+        let mut removed = 0;
+        for line in contents.lines() {
+            let Some((name, value)) = line.split_once(' ') else { continue };
+            match self.call(name.to_string(), value.to_string(), false).await {
+                Ok(()) => removed += 1,
+                Err(e) => {
+                    log::warn!(target: "acme", "cannot remove stale DNS-01 record {}: {}", name, e);
+                    self.stale.lock().unwrap().insert((name.to_string(), value.to_string()));
+                }
+            }
+        }
+        self.save_state();
+        removed
+    }
+
+    /// Run a provider call off the async runtime
+    async fn call(&self, name: String, value: String, add: bool) -> Result<(), String> {
+        let provider = self.provider.clone();
+        tokio::task::spawn_blocking(move || if add { provider.add_txt(&name, &value) } else { provider.remove_txt(&name, &value) })
+            .await
+            .map_err(|e| format!("DNS provider task failed: {}", e))?
+    }
+
+    fn save_state(&self) {
+        let Some(path) = &self.state_file else { return };
+        let published: BTreeSet<_> = self.published.lock().unwrap().values().cloned().collect();
+        let stale = self.stale.lock().unwrap();
+        let contents: String = published.union(&stale).map(|(name, value)| format!("{} {}\n", name, value)).collect();
+        if let Err(e) = write_atomically(path, contents.as_bytes()) {
+            log::warn!(target: "acme", "cannot record DNS-01 records: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stand-in nameserver: answers one request with `rcode` and returns the request
+    fn nameserver(rcode: u8) -> (SocketAddr, std::thread::JoinHandle<Vec<u8>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let handle = std::thread::spawn(move || {
+            let mut request = [0u8; 512];
+            let (len, peer) = socket.recv_from(&mut request).unwrap();
+            let response = [request[0], request[1], 0x80 | request[2], rcode, 0, 0, 0, 0, 0, 0, 0, 0];
+            socket.send_to(&response, peer).unwrap();
+            request[..len].to_vec()
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn test_txt_record() {
+        assert_eq!(txt_record_name("*.Example.COM").unwrap(), "_acme-challenge.example.com");
+        assert_eq!(txt_record_name("www.example.com").unwrap(), "_acme-challenge.www.example.com");
+        assert_eq!(txt_record_value(""), "47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU");
+    }
+
+    #[test]
+    fn test_config() {
+        let key = TsigKey::parse("hmac-sha256:acme-key.:c2VjcmV0").unwrap();
+        assert_eq!((key.name.as_str(), key.secret.as_slice()), ("acme-key", &b"secret"[..]));
+        assert!(!format!("{:?}", key).contains("c2VjcmV0"));
+        assert!(TsigKey::parse("hmac-md5:acme-key:c2VjcmV0").is_err());
+
+        assert_eq!(ProviderSpec::parse("rfc2136:127.0.0.1:5353"), Ok(ProviderSpec::Rfc2136("127.0.0.1:5353".to_string())));
+        assert_eq!(ProviderSpec::parse("exec:/usr/local/bin/dns-hook"), Ok(ProviderSpec::Exec(PathBuf::from("/usr/local/bin/dns-hook"))));
+        assert!(ProviderSpec::parse("route53:zone").is_err());
+
+        assert_eq!(resolve_server("::1").unwrap(), "[::1]:53".parse().unwrap());
+        assert_eq!(resolve_server("127.0.0.1").unwrap(), "127.0.0.1:53".parse().unwrap());
+
+        let mut config = Dns01Config { provider: Some(ProviderSpec::Rfc2136("127.0.0.1".to_string())), ..Default::default() };
+        assert!(config.validate().is_err());
+        config.zone = Some("example.com".to_string());
+        assert!(config.validate().is_ok());
+        let config = Dns01Config { provider: Some(ProviderSpec::ZoneFile(PathBuf::from("zone"))), tsig_key: Some(key), ..Default::default() };
+        assert!(config.validate().is_err());
+    }
+
+    /// UPDATE adding TXT "value" at _acme-challenge.www.example.com in zone example.com, signed
+    /// by hickory-proto 0.24 `TSigner` with key acme-key (secret "secret") at time 1700000000,
+    /// and checked with Python's `hmac`. The last 81 bytes are the TSIG record.
+    const TSIG_VECTOR: &str = "123428000001000000010001076578616d706c6503636f6d00000600010f5f61636d652d6368616c6c656e67650377\
+        7777c00c001000010000003c00060576616c75650861636d652d6b65790000fa00ff00000000003d0b686d61632d7368613235360000006553f1\
+        00012c0020be54aec2a0294b8c1c9623220aa605e926334711cb79064e946cfa6aaa1436f5123400000000";
+
+    #[test]
+    fn test_tsig_vector() {
+        let expected: Vec<u8> = (0..TSIG_VECTOR.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&TSIG_VECTOR[i..i + 2], 16).unwrap())
+            .collect();
+        let mut message = expected[..expected.len() - 81].to_vec();
+        message[10..12].copy_from_slice(&[0, 0]);
+        let key = TsigKey { name: "acme-key".to_string(), secret: b"secret".to_vec() };
+        sign_message(&mut message, &key, 1_700_000_000).unwrap();
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn test_rfc2136_update() {
+        let key = TsigKey { name: "acme-key".to_string(), secret: b"secret".to_vec() };
+        let (addr, server) = nameserver(0);
+        let provider = Rfc2136Provider::new(addr, "Example.com", 60, Some(key.clone())).unwrap();
+        provider.add_txt("_acme-challenge.www.example.com", "value").unwrap();
+        let request = server.join().unwrap();
+
+        // UPDATE opcode; one zone, one update and the TSIG record
+        assert_eq!(request[2] >> 3, OPCODE_UPDATE as u8);
+        assert_eq!(&request[4..12], &[0, 1, 0, 0, 0, 1, 0, 1]);
+        let unsigned = update_message(u16::from_be_bytes([request[0], request[1]]), "example.com", "_acme-challenge.www.example.com", "value", 60, true).unwrap();
+        assert_eq!(&request[12..unsigned.len()], &unsigned[12..]);
+        // Time signed sits before fudge, MAC size, MAC, original ID, error and other length
+        let mut time = [0u8; 8];
+        time[2..].copy_from_slice(&request[request.len() - 48..request.len() - 42]);
+        let time_signed = u64::from_be_bytes(time);
+        let mut signed = unsigned;
+        sign_message(&mut signed, &key, time_signed).unwrap();
+        assert_eq!(request, signed);
+
+        // Deletes name the record with class NONE; failures carry the RCODE
+        let (addr, server) = nameserver(5);
+        let provider = Rfc2136Provider::new(addr, "example.com", 60, None).unwrap();
+        let error = provider.remove_txt("_acme-challenge.www.example.com", "value").unwrap_err();
+        assert!(error.contains("REFUSED"), "{}", error);
+        let request = server.join().unwrap();
+        assert_eq!(&request[request.len() - 16..request.len() - 12], &[0, TYPE_TXT as u8, 0, CLASS_NONE as u8]);
+        assert!(provider.add_txt("_acme-challenge.other.org", "value").is_err());
+    }
+
+    #[test]
+    fn test_zone_file_and_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let zone = dir.path().join("acme.zone");
+        let provider = Arc::new(ZoneFileProvider::new(zone.clone(), 60));
+        provider.add_txt("_acme-challenge.example.com", "one").unwrap();
+        provider.add_txt("_acme-challenge.example.com", "one").unwrap();
+        provider.add_txt("_acme-challenge.example.com", "two").unwrap();
+        assert_eq!(
+            fs::read_to_string(&zone).unwrap(),
+            "_acme-challenge.example.com. 60 IN TXT \"one\"\n_acme-challenge.example.com. 60 IN TXT \"two\"\n"
+        );
+        provider.remove_txt("_acme-challenge.example.com", "one").unwrap();
+        assert_eq!(fs::read_to_string(&zone).unwrap(), "_acme-challenge.example.com. 60 IN TXT \"two\"\n");
+
+        // A record left by an earlier run is withdrawn on start
+        let state = dir.path().join("dns01-records");
+        fs::write(&state, "_acme-challenge.example.com two\n").unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let solver = Dns01Solver::new(provider, Duration::ZERO, Some(state.clone()));
+        assert_eq!(runtime.block_on(solver.remove_stale()), 1);
+        assert_eq!(fs::read_to_string(&zone).unwrap(), "");
+
+        runtime.block_on(solver.present("*.example.com", "token.thumbprint")).unwrap();
+        let value = txt_record_value("token.thumbprint");
+        assert_eq!(fs::read_to_string(&state).unwrap(), format!("_acme-challenge.example.com {}\n", value));
+        assert!(fs::read_to_string(&zone).unwrap().contains(&value));
+        runtime.block_on(solver.cleanup("*.example.com")).unwrap();
+        assert_eq!(fs::read_to_string(&zone).unwrap(), "");
+        assert_eq!(fs::read_to_string(&state).unwrap(), "");
+    }
+
+    #[test]
+    fn test_watcher_publishes_in_parallel() {
+        let dir = tempfile::tempdir().unwrap();
+        let zone = dir.path().join("acme.zone");
+        let provider = Arc::new(ZoneFileProvider::new(zone.clone(), 60));
+        let solver = Arc::new(Dns01Solver::new(provider, Duration::from_millis(300), None));
+        let pending = Arc::new(Mutex::new(HashMap::from([
+            ("a.example.com".to_string(), "a.thumbprint".to_string()),
+            ("b.example.com".to_string(), "b.thumbprint".to_string()),
+        ])));
+        let lookup = |domain: String| {
+            let pending = pending.clone();
+            async move { pending.lock().unwrap().get(&domain).cloned() }
+        };
+        solver.watch("a.example.com");
+        solver.watch("b.example.com");
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+        // Both records are out while the first one still waits for propagation
+        runtime.block_on(async {
+            solver.poll(&lookup).await;
+            solver.poll(&lookup).await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        });
+        let records = fs::read_to_string(&zone).unwrap();
+        assert_eq!(records.lines().count(), 2, "{}", records);
+        assert!(records.contains("_acme-challenge.a.example.com.") && records.contains("_acme-challenge.b.example.com."));
+
+        // Withdrawn once the ACME client no longer has a challenge for them
+        runtime.block_on(async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            pending.lock().unwrap().clear();
+            solver.poll(&lookup).await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        });
+        assert_eq!(fs::read_to_string(&zone).unwrap(), "");
+        assert!(solver.busy.lock().unwrap().is_empty());
+    }
+
+    /// Provider whose removals always fail
+    #[derive(Debug)]
+    struct StuckProvider;
+
+    impl DnsProvider for StuckProvider {
+        fn add_txt(&self, _name: &str, _value: &str) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn remove_txt(&self, _name: &str, _value: &str) -> Result<(), String> {
+            Err("nameserver unreachable".to_string())
+        }
+    }
+
+    #[test]
+    fn test_failed_withdrawals_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("dns01-records");
+        let solver = Dns01Solver::new(Arc::new(StuckProvider), Duration::ZERO, Some(state.clone()));
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+        // Two failed withdrawals for the same domain both stay in the state file
+        for key_authorization in ["first.thumbprint", "second.thumbprint"] {
+            runtime.block_on(solver.present("example.com", key_authorization)).unwrap();
+            assert!(runtime.block_on(solver.cleanup("example.com")).is_err());
+        }
+        let contents = fs::read_to_string(&state).unwrap();
+        assert_eq!(contents.lines().count(), 2, "{}", contents);
+        for key_authorization in ["first.thumbprint", "second.thumbprint"] {
+            assert!(contents.contains(&format!("_acme-challenge.example.com {}\n", txt_record_value(key_authorization))));
+        }
+
+        // And survive a restart that still cannot remove them
+        let restarted = Dns01Solver::new(Arc::new(StuckProvider), Duration::ZERO, Some(state.clone()));
+        assert_eq!(runtime.block_on(restarted.remove_stale()), 0);
+        assert_eq!(fs::read_to_string(&state).unwrap(), contents);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_hook() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("calls");
+        let script = dir.path().join("hook.sh");
+        fs::write(&script, format!("#!/bin/sh\necho \"$@\" >> {}\n[ \"$1\" = add ]\n", log.display())).unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let provider = ExecHookProvider::new(script);
+        provider.add_txt("_acme-challenge.example.com", "value").unwrap();
+        assert!(provider.remove_txt("_acme-challenge.example.com", "value").is_err());
+        assert_eq!(fs::read_to_string(&log).unwrap(), "add _acme-challenge.example.com value\nremove _acme-challenge.example.com value\n");
+
+        let slow = dir.path().join("slow.sh");
+        fs::write(&slow, "#!/bin/sh\necho working >&2\nexec sleep 30\n").unwrap();
+        fs::set_permissions(&slow, fs::Permissions::from_mode(0o755)).unwrap();
+        let provider = ExecHookProvider { script: slow, timeout: Duration::from_millis(200) };
+        let started = Instant::now();
+        let error = provider.add_txt("_acme-challenge.example.com", "value").unwrap_err();
+        assert!(error.contains("was killed"), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    /// Against a real nameserver, e.g. BIND with `update-policy { grant KEY zonesub TXT; };`:
+    /// `EASYP_TEST_DNS_SERVER=127.0.0.1:53 EASYP_TEST_DNS_ZONE=example.test
+    /// EASYP_TEST_DNS_TSIG=acme-key:SECRET cargo test test_rfc2136_nameserver -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_rfc2136_nameserver() {
+        let server = resolve_server(&std::env::var("EASYP_TEST_DNS_SERVER").unwrap()).unwrap();
+        let zone = std::env::var("EASYP_TEST_DNS_ZONE").unwrap();
+        let key = std::env::var("EASYP_TEST_DNS_TSIG").ok().map(|key| TsigKey::parse(&key).unwrap());
+        let provider = Rfc2136Provider::new(server, &zone, 60, key).unwrap();
+        let name = txt_record_name(&format!("easyp-test.{}", zone)).unwrap();
+        provider.add_txt(&name, "easyp-test").unwrap();
+        provider.remove_txt(&name, "easyp-test").unwrap();
+    }
+}
@@ -0,0 +1,411 @@
+//! Content-Hash ETags
+//!
+//! This module computes strong ETags from file contents instead of size and mtime.
+//! It provides:
+//! - [`EtagMode`]: `metadata` (the classic `"mtime-size"` tag) or `content` (MD5 of the body)
+//! - [`EtagIndex`]: digests cached by (device, inode, size, mtime), so a file is only
+//!   hashed again after it actually changed
+//! - An optional index file, saved periodically, so large files are not rehashed after
+//!   a restart
+//!
+//! Byte-identical redeploys (rsync, tar) keep their ETags in content mode, and two
+//! different files that happen to share size and mtime no longer collide. Files up to
+//! [`SYNC_HASH_LIMIT`] are hashed on the request that first needs them; larger files are
+//! hashed in the background and keep the metadata ETag until their digest is ready.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Largest file hashed inline while a request waits
+pub const SYNC_HASH_LIMIT: u64 = 8 * 1024 * 1024;
+
+/// Digests kept in memory and in the index file
+pub const MAX_ENTRIES: usize = 200_000;
+
+/// Background hashes of large files running at once
+const MAX_BACKGROUND_HASHES: usize = 2;
+
+/// How often a changed index is written back
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+const INDEX_HEADER: &str = "# easyp etag index v1";
+
+/// How ETags are derived
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EtagMode {
+    /// `"mtime-size"` from the file metadata
+    #[default]
+    Metadata,
+    /// `"<md5 hex>"` of the file contents
+    Content,
+}
+
+impl EtagMode {
+    /// Parse a `--etag` value
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "metadata" | "mtime" => Ok(EtagMode::Metadata),
+            "content" | "hash" => Ok(EtagMode::Content),
+            other => Err(format!("invalid ETag mode '{}': expected metadata or content", other)),
+        }
+    }
+}
+
+/// Identity of one version of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FileKey {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime_ns: u128,
+}
+
+impl FileKey {
+    fn from_metadata(metadata: &fs::Metadata) -> Option<Self> {
+        let mtime_ns = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+        #[cfg(unix)]
+        let (dev, ino) = {
+            use std::os::unix::fs::MetadataExt;
+            (metadata.dev(), metadata.ino())
+        };
+        #[cfg(not(unix))]
+        let (dev, ino) = (0, 0);
+        Some(Self { dev, ino, size: metadata.len(), mtime_ns })
+    }
+}
+
+struct IndexEntry {
+    digest: [u8; 16],
+    /// Looked up or computed since startup; unused entries are dropped first when full
+    used: bool,
+}
+
+#[derive(Default)]
+struct IndexState {
+    entries: HashMap<FileKey, IndexEntry>,
+    pending: HashSet<FileKey>,
+}
+
+struct Shared {
+    path: Option<PathBuf>,
+    state: Mutex<IndexState>,
+    dirty: AtomicBool,
+}
+
+/// Cache of content digests keyed by file identity
+///
+/// Clones share the same index.
+#[derive(Clone)]
+pub struct EtagIndex {
+    shared: Arc<Shared>,
+}
+
+impl std::fmt::Debug for EtagIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EtagIndex").field("path", &self.shared.path).finish()
+    }
+}
+
+impl EtagIndex {
+    /// Create an index, loading `path` if it exists
+    ///
+    /// # Arguments
+    /// * `path` - Index file to load and save periodically; None keeps digests in memory only
+    ///
+    /// # Returns
+    /// * `EtagIndex` - The index (an unreadable index file is logged and ignored)
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let mut state = IndexState::default();
+        if let Some(path) = &path {
+            match load(path) {
+                Ok(entries) => {
+                    log::debug!(target: "files", "loaded {} ETag digests from {}", entries.len(), path.display());
+                    state.entries = entries;
+                }
+                Err(e) => log::warn!(target: "files", "ignoring ETag index {}: {}", path.display(), e),
+            }
+        }
+        let shared = Arc::new(Shared { path, state: Mutex::new(state), dirty: AtomicBool::new(false) });
+        if shared.path.is_some() {
+            spawn_saver(Arc::downgrade(&shared));
+        }
+        Self { shared }
+    }
+
+    /// Index file this index is saved to, if any
+    pub fn path(&self) -> Option<&Path> {
+        self.shared.path.as_deref()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, IndexState> {
+        self.shared.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Content ETag for a file, hashing it if needed
+    ///
+    /// # Arguments
+    /// * `path` - File on disk
+    /// * `metadata` - Metadata of that file, taken by the caller
+    ///
+    /// # Returns
+    /// * `Option<String>` - The quoted ETag, or None while a large file is still being
+    ///   hashed in the background (or if it could not be read)
+    pub fn etag(&self, path: &Path, metadata: &fs::Metadata) -> Option<String> {
+        let key = FileKey::from_metadata(metadata)?;
+        {
+            let mut state = self.lock();
+            if let Some(entry) = state.entries.get_mut(&key) {
+                entry.used = true;
+                return Some(format_etag(&entry.digest));
+            }
+            if key.size > SYNC_HASH_LIMIT {
+                if state.pending.len() < MAX_BACKGROUND_HASHES && state.pending.insert(key) {
+                    drop(state);
+                    self.hash_in_background(path.to_path_buf(), key);
+                }
+                return None;
+            }
+        }
+
+        let digest = hash_file(path, key)?;
+        self.insert(key, digest);
+        Some(format_etag(&digest))
+    }
+
+    fn hash_in_background(&self, path: PathBuf, key: FileKey) {
+        let index = self.clone();
+        let spawned = std::thread::Builder::new().name("etag-hash".to_string()).spawn(move || {
+            let digest = hash_file(&path, key);
+            index.lock().pending.remove(&key);
+            if let Some(digest) = digest {
+                log::debug!(target: "files", "hashed {} for its ETag", path.display());
+                index.insert(key, digest);
+            }
+        });
+        if let Err(e) = spawned {
+            log::warn!(target: "files", "cannot start ETag hashing thread: {}", e);
+            self.lock().pending.remove(&key);
+        }
+    }
+
+    fn insert(&self, key: FileKey, digest: [u8; 16]) {
+        let mut state = self.lock();
+        if state.entries.len() >= MAX_ENTRIES {
+            // Entries from older runs that nobody asked for go first, then everything
+            state.entries.retain(|_, entry| entry.used);
+            if state.entries.len() >= MAX_ENTRIES {
+                state.entries.clear();
+            }
+        }
+        state.entries.insert(key, IndexEntry { digest, used: true });
+        self.shared.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Number of digests held
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Whether no digests are held
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the index file if anything changed since the last save
+    pub fn save(&self) -> Result<(), String> {
+        save(&self.shared)
+    }
+}
+
+fn format_etag(digest: &[u8; 16]) -> String {
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// Hash a file, giving up if it no longer matches `key` afterwards
+fn hash_file(path: &Path, key: FileKey) -> Option<[u8; 16]> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            log::debug!(target: "files", "cannot open {} for hashing: {}", path.display(), e);
+            return None;
+        }
+    };
+    let mut context = md5::Context::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => context.consume(&buffer[..n]),
+            Err(e) => {
+                log::debug!(target: "files", "error hashing {}: {}", path.display(), e);
+                return None;
+            }
+        }
+    }
+    // A write during hashing would otherwise pin a digest of mixed contents to the new mtime
+    let after = file.metadata().ok().and_then(|m| FileKey::from_metadata(&m));
+    if after != Some(key) {
+        log::debug!(target: "files", "{} changed while hashing", path.display());
+        return None;
+    }
+    Some(context.compute().0)
+}
+
+fn spawn_saver(shared: Weak<Shared>) {
+    let spawned = std::thread::Builder::new().name("etag-index".to_string()).spawn(move || loop {
+        std::thread::sleep(SAVE_INTERVAL);
+        let Some(shared) = shared.upgrade() else { break };
+        if let Err(e) = save(&shared) {
+            log::warn!(target: "files", "failed to save ETag index: {}", e);
+        }
+    });
+    if let Err(e) = spawned {
+        log::warn!(target: "files", "cannot start ETag index saver: {}", e);
+    }
+}
+
+fn save(shared: &Shared) -> Result<(), String> {
+    let Some(path) = &shared.path else {
+        return Ok(());
+    };
+    if !shared.dirty.swap(false, Ordering::Relaxed) {
+        return Ok(());
+    }
+    let mut data = String::from(INDEX_HEADER);
+    data.push('\n');
+    {
+        let state = shared.state.lock().unwrap_or_else(|e| e.into_inner());
+        for (key, entry) in &state.entries {
+            let digest = format_etag(&entry.digest);
+            data.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                key.dev,
+                key.ino,
+                key.size,
+                key.mtime_ns,
+                digest.trim_matches('"')
+            ));
+        }
+    }
+    write_atomically(path, data.as_bytes()).inspect_err(|_| shared.dirty.store(true, Ordering::Relaxed))
+}
+
+fn load(path: &Path) -> Result<HashMap<FileKey, IndexEntry>, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut lines = text.lines();
+    if lines.next() != Some(INDEX_HEADER) {
+        return Err("unrecognised header".to_string());
+    }
+    let mut entries = HashMap::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [dev, ino, size, mtime_ns, digest] = fields[..] else { continue };
+        let (Ok(dev), Ok(ino), Ok(size), Ok(mtime_ns), Some(digest)) =
+            (dev.parse(), ino.parse(), size.parse(), mtime_ns.parse(), parse_digest(digest))
+        else {
+            continue;
+        };
+        entries.insert(FileKey { dev, ino, size, mtime_ns }, IndexEntry { digest, used: false });
+        if entries.len() >= MAX_ENTRIES {
+            break;
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_digest(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+    let mut digest = [0u8; 16];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(digest)
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let temp = path.with_extension("tmp");
+    let mut file = fs::File::create(&temp).map_err(|e| format!("Failed to create {}: {}", temp.display(), e))?;
+    file.write_all(data)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("Failed to write {}: {}", temp.display(), e))?;
+    fs::rename(&temp, path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn write_with_mtime(path: &Path, contents: &[u8], mtime: SystemTime) -> fs::Metadata {
+        fs::write(path, contents).unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(mtime).unwrap();
+        drop(file);
+        fs::metadata(path).unwrap()
+    }
+
+    #[test]
+    fn test_content_etags() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = EtagIndex::new(None);
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+
+        // Same size and mtime, different bytes: metadata ETags would collide
+        let meta_a = write_with_mtime(&a, b"hello", mtime);
+        let meta_b = write_with_mtime(&b, b"world", mtime);
+        let etag_a = index.etag(&a, &meta_a).unwrap();
+        let etag_b = index.etag(&b, &meta_b).unwrap();
+        assert_ne!(etag_a, etag_b);
+        assert_eq!(etag_a, "\"5d41402abc4b2a76b9719d911017c592\"");
+
+        // A byte-identical redeploy with a new mtime keeps the ETag
+        let meta_a = write_with_mtime(&a, b"hello", mtime + Duration::from_secs(60));
+        assert_eq!(index.etag(&a, &meta_a).unwrap(), etag_a);
+        assert_eq!(index.len(), 3);
+    }
+
+    #[test]
+    fn test_index_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("page.html");
+        let index_path = dir.path().join("etags.index");
+        let metadata = write_with_mtime(&file, b"<p>hi</p>", UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+
+        let index = EtagIndex::new(Some(index_path.clone()));
+        let etag = index.etag(&file, &metadata).unwrap();
+        index.save().unwrap();
+
+        let reloaded = EtagIndex::new(Some(index_path.clone()));
+        assert_eq!(reloaded.len(), 1);
+        // The digest comes from the index, even though the file is no longer readable
+        fs::remove_file(&file).unwrap();
+        assert_eq!(reloaded.etag(&file, &metadata).unwrap(), etag);
+
+        fs::write(&index_path, "not an index\n").unwrap();
+        assert!(EtagIndex::new(Some(index_path)).is_empty());
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(EtagMode::parse("content").unwrap(), EtagMode::Content);
+        assert_eq!(EtagMode::parse("Metadata").unwrap(), EtagMode::Metadata);
+        assert!(EtagMode::parse("sha1").is_err());
+    }
+}
@@ -0,0 +1,940 @@
+//! FastCGI Client
+//!
+//! This module runs scripts through a FastCGI responder such as PHP-FPM, so legacy
+//! PHP sites can live under a domain's document root. It supports:
+//! - `*.php`-style extension rules (with `PATH_INFO` and directory index files) and
+//!   fixed script paths, optionally per host
+//! - TCP (`127.0.0.1:9000`) and unix socket (`unix:/run/php/php-fpm.sock`) backends
+//! - The RFC 3875 variable set, built with [`CgiVarsBuilder`]
+//! - Streaming request bodies to `FCGI_STDIN` and `FCGI_STDOUT` back to the client
+//! - `Status:` and `Location:` translation into an HTTP status line
+//! - Pooled keep-alive connections per backend
+//!
+//! Script paths go through `sanitize_path_with_root` in the caller, so the usual
+//! traversal and hidden-file checks apply before anything reaches the backend.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::cgi_env::{cgi_response_head, find_cgi_head_end, CgiEnv, CgiVarsBuilder};
+use super::reverse_proxy::{read_head, MessageHead, ProxyOutcome};
+
+/// FastCGI protocol version
+const FCGI_VERSION_1: u8 = 1;
+/// Record types (FastCGI specification section 8)
+const FCGI_BEGIN_REQUEST: u8 = 1;
+const FCGI_END_REQUEST: u8 = 3;
+const FCGI_PARAMS: u8 = 4;
+const FCGI_STDIN: u8 = 5;
+const FCGI_STDOUT: u8 = 6;
+const FCGI_STDERR: u8 = 7;
+/// Responder role and the keep-connection flag
+const FCGI_RESPONDER: u16 = 1;
+const FCGI_KEEP_CONN: u8 = 1;
+/// Protocol status of a completed request
+const FCGI_REQUEST_COMPLETE: u8 = 0;
+/// One request per connection at a time, so the id is always 1
+const REQUEST_ID: u16 = 1;
+/// Largest record body
+const MAX_RECORD_CONTENT: usize = 65535;
+
+/// Default script used for directory requests under an extension rule
+pub const DEFAULT_INDEX_FILE: &str = "index.php";
+
+/// How long a pooled connection may sit idle before it is dropped
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where a FastCGI backend listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FastCgiAddr {
+    /// TCP backend as `host:port`
+    Tcp(String),
+    /// Unix domain socket path
+    Unix(PathBuf),
+}
+
+impl FastCgiAddr {
+    /// Parse `host:port`, `tcp:host:port` or `unix:/path`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if let Some(path) = value.strip_prefix("unix:") {
+            if !path.starts_with('/') {
+                return Err(format!("unix socket path must be absolute in '{}'", value));
+            }
+            return Ok(FastCgiAddr::Unix(PathBuf::from(path)));
+        }
+        let authority = value.strip_prefix("tcp:").unwrap_or(value);
+        match authority.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(FastCgiAddr::Tcp(authority.to_string())),
+            _ => Err(format!("invalid FastCGI backend '{}' (expected HOST:PORT or unix:/PATH)", value)),
+        }
+    }
+}
+
+impl std::fmt::Display for FastCgiAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FastCgiAddr::Tcp(authority) => write!(f, "{}", authority),
+            FastCgiAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Object-safe combination of the async I/O traits for backend connections
+trait FastCgiIo: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> FastCgiIo for T {}
+
+/// An open connection to a backend
+#[derive(Debug)]
+enum FastCgiConn {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+impl FastCgiConn {
+    async fn connect(addr: &FastCgiAddr, timeout: Duration) -> Result<Self, FastCgiError> {
+        let result = match addr {
+            FastCgiAddr::Tcp(authority) => tokio::time::timeout(timeout, TcpStream::connect(authority.as_str()))
+                .await
+                .map(|r| r.map(FastCgiConn::Tcp)),
+            #[cfg(unix)]
+            FastCgiAddr::Unix(path) => tokio::time::timeout(timeout, tokio::net::UnixStream::connect(path))
+                .await
+                .map(|r| r.map(FastCgiConn::Unix)),
+            #[cfg(not(unix))]
+            FastCgiAddr::Unix(path) => {
+                return Err(FastCgiError::BadGateway(format!("unix sockets not supported ({})", path.display())))
+            }
+        };
+        match result {
+            Ok(Ok(conn)) => Ok(conn),
+            Ok(Err(e)) => Err(FastCgiError::BadGateway(format!("connect to {} failed: {}", addr, e))),
+            Err(_) => Err(FastCgiError::Timeout(format!("connect to {} timed out", addr))),
+        }
+    }
+
+    fn io(&mut self) -> &mut dyn FastCgiIo {
+        match self {
+            FastCgiConn::Tcp(stream) => stream,
+            #[cfg(unix)]
+            FastCgiConn::Unix(stream) => stream,
+        }
+    }
+
+    /// Whether the backend closed (or wrote to) the idle connection
+    fn is_stale(&self) -> bool {
+        let mut probe = [0u8; 1];
+        let result = match self {
+            FastCgiConn::Tcp(stream) => stream.try_read(&mut probe),
+            #[cfg(unix)]
+            FastCgiConn::Unix(stream) => stream.try_read(&mut probe),
+        };
+        !matches!(result, Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock)
+    }
+}
+
+/// A backend with its pool of idle keep-alive connections
+#[derive(Debug)]
+pub struct FastCgiBackend {
+    /// Backend address
+    pub addr: FastCgiAddr,
+    /// Idle connections and when they were returned
+    idle: Mutex<Vec<(FastCgiConn, Instant)>>,
+}
+
+impl FastCgiBackend {
+    fn new(addr: FastCgiAddr) -> Self {
+        Self {
+            addr,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Take a live idle connection or open a new one
+    async fn checkout(&self, timeout: Duration) -> Result<FastCgiConn, FastCgiError> {
+        loop {
+            let pooled = self.idle.lock().unwrap().pop();
+            match pooled {
+                Some((conn, since)) if since.elapsed() < POOL_IDLE_TIMEOUT && !conn.is_stale() => return Ok(conn),
+                Some(_) => continue,
+                None => return FastCgiConn::connect(&self.addr, timeout).await,
+            }
+        }
+    }
+
+    /// Return a connection to the pool
+    fn checkin(&self, conn: FastCgiConn, max_idle: usize) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < max_idle {
+            idle.push((conn, Instant::now()));
+        }
+    }
+}
+
+/// Which request paths a rule handles
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FastCgiPattern {
+    /// Any script ending in this extension, e.g. `.php`
+    Extension(String),
+    /// One script path; anything below it becomes `PATH_INFO`
+    Script(String),
+}
+
+/// A FastCGI mapping for a host and pattern
+#[derive(Debug, Clone)]
+pub struct FastCgiRule {
+    /// Host this rule applies to (None = any host)
+    pub host: Option<String>,
+    /// Paths handled by the backend
+    pub pattern: FastCgiPattern,
+    /// Backend with its connection pool
+    pub backend: Arc<FastCgiBackend>,
+}
+
+/// A request path split into script and `PATH_INFO`
+#[derive(Debug, Clone)]
+pub struct FastCgiMatch {
+    /// URI path of the script (SCRIPT_NAME)
+    pub script_name: String,
+    /// Extra path after the script (PATH_INFO)
+    pub path_info: String,
+    /// Backend to run the script on
+    pub backend: Arc<FastCgiBackend>,
+}
+
+impl FastCgiMatch {
+    /// SCRIPT_NAME encoded again for `sanitize_path_with_root`, which decodes its input
+    pub fn script_target(&self) -> String {
+        self.script_name.replace('%', "%25").replace('?', "%3F").replace('#', "%23")
+    }
+}
+
+/// FastCGI configuration
+#[derive(Debug, Clone)]
+pub struct FastCgiConfig {
+    /// Rules, host-specific rules are checked first
+    pub rules: Vec<FastCgiRule>,
+    /// Script tried for directory requests under extension rules
+    pub index_file: String,
+    /// Timeout for connecting to a backend
+    pub connect_timeout: Duration,
+    /// Idle timeout for reads and writes on either side
+    pub io_timeout: Duration,
+    /// Idle connections kept per backend
+    pub max_idle: usize,
+}
+
+impl Default for FastCgiConfig {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            index_file: DEFAULT_INDEX_FILE.to_string(),
+            connect_timeout: Duration::from_secs(5),
+            io_timeout: Duration::from_secs(60),
+            max_idle: 8,
+        }
+    }
+}
+
+impl FastCgiConfig {
+    /// Add a rule of the form `[HOST/]*.EXT=BACKEND` or `[HOST]/SCRIPT=BACKEND`
+    ///
+    /// Examples: `*.php=unix:/run/php/php8.2-fpm.sock`,
+    /// `legacy.example.com/*.php=127.0.0.1:9000`, `/app.fcgi=127.0.0.1:9001`.
+    pub fn add_rule_spec(&mut self, spec: &str) -> Result<(), String> {
+        let (location, backend) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("invalid FastCGI rule '{}' (expected [HOST/]*.EXT=ADDR or [HOST]/SCRIPT=ADDR)", spec))?;
+        let location = location.trim();
+
+        let (host, pattern) = if let Some(pos) = location.find("*.") {
+            let host = &location[..pos];
+            if !host.is_empty() && !host.ends_with('/') {
+                return Err(format!("invalid FastCGI location '{}'", location));
+            }
+            let extension = &location[pos + 1..];
+            if extension.len() < 2 || extension.contains('/') {
+                return Err(format!("invalid FastCGI extension in '{}'", location));
+            }
+            (host.trim_end_matches('/'), FastCgiPattern::Extension(extension.to_lowercase()))
+        } else if let Some(pos) = location.find('/') {
+            let script = location[pos..].trim_end_matches('/');
+            if script.is_empty() {
+                return Err(format!("FastCGI script path missing in '{}'", location));
+            }
+            (&location[..pos], FastCgiPattern::Script(script.to_string()))
+        } else {
+            return Err(format!("invalid FastCGI location '{}'", location));
+        };
+        let host = match host {
+            "" | "*" => None,
+            host => Some(host.to_lowercase()),
+        };
+
+        // Rules for the same backend share one connection pool
+        let addr = FastCgiAddr::parse(backend)?;
+        let backend = match self.rules.iter().find(|r| r.backend.addr == addr) {
+            Some(rule) => rule.backend.clone(),
+            None => Arc::new(FastCgiBackend::new(addr)),
+        };
+
+        self.rules.push(FastCgiRule { host, pattern, backend });
+        Ok(())
+    }
+
+    /// Extensions (without the dot) that extension rules send to a backend
+    pub fn script_extensions(&self) -> Vec<String> {
+        let mut extensions: Vec<String> = self
+            .rules
+            .iter()
+            .filter_map(|rule| match &rule.pattern {
+                FastCgiPattern::Extension(extension) => Some(extension.trim_start_matches('.').to_string()),
+                FastCgiPattern::Script(_) => None,
+            })
+            .collect();
+        extensions.sort();
+        extensions.dedup();
+        extensions
+    }
+
+    /// Find the script for a request
+    ///
+    /// # Arguments
+    /// * `host` - Request host, if known
+    /// * `path` - Request target; it is decoded with [`decode_request_path`] first
+    /// * `document_root` - Root used to look for directory index scripts
+    ///
+    /// # Returns
+    /// * `Option<FastCgiMatch>` - The script and backend, if a rule applies, with
+    ///   SCRIPT_NAME and PATH_INFO taken from the decoded path
+    pub fn find(&self, host: Option<&str>, path: &str, document_root: &Path) -> Option<FastCgiMatch> {
+        let path = decode_request_path(path)?;
+        let path = path.as_str();
+        let mut rules: Vec<&FastCgiRule> = self
+            .rules
+            .iter()
+            .filter(|rule| match (&rule.host, host) {
+                (None, _) => true,
+                (Some(rule_host), Some(host)) => rule_host.eq_ignore_ascii_case(host),
+                (Some(_), None) => false,
+            })
+            .collect();
+        rules.sort_by_key(|rule| rule.host.is_none());
+
+        for rule in rules {
+            let split = match &rule.pattern {
+                FastCgiPattern::Script(script) => match path.strip_prefix(script.as_str()) {
+                    Some(rest) if rest.is_empty() || rest.starts_with('/') => Some((script.clone(), rest.to_string())),
+                    _ => None,
+                },
+                FastCgiPattern::Extension(extension) => split_script_path(path, extension).or_else(|| {
+                    // Directory requests run the index script when it exists
+                    let index = format!("{}{}", path, self.index_file);
+                    let is_script = self.index_file.to_lowercase().ends_with(extension.as_str());
+                    let exists = document_root.join(index.trim_start_matches('/')).is_file();
+                    (path.ends_with('/') && is_script && exists).then(|| (index, String::new()))
+                }),
+            };
+            if let Some((script_name, path_info)) = split {
+                return Some(FastCgiMatch {
+                    script_name,
+                    path_info,
+                    backend: rule.backend.clone(),
+                });
+            }
+        }
+        None
+    }
+}
+
+/// Percent-decode a request target into the path that `sanitize_path_with_root` looks up
+///
+/// Query and fragment are dropped and empty or `.` segments removed, so `/index%2Ephp`
+/// and `//./index.ph%70` match the same rules as `/index.php`. `..` is kept for the
+/// sanitizer to reject.
+pub fn decode_request_path(target: &str) -> Option<String> {
+    let path = target.split('?').next().unwrap_or(target);
+    let path = path.split('#').next().unwrap_or(path);
+    let decoded = urlencoding::decode(path).ok()?;
+    let mut normalized = String::with_capacity(decoded.len());
+    for segment in decoded.split('/').filter(|segment| !segment.is_empty() && *segment != ".") {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if normalized.is_empty() || decoded.ends_with('/') {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+/// Split `/a/b.php/extra` into (`/a/b.php`, `/extra`) at the first segment with the extension
+fn split_script_path(path: &str, extension: &str) -> Option<(String, String)> {
+    let mut end = 0;
+    for segment in path.split('/').skip(1) {
+        end += 1 + segment.len();
+        let lower = segment.to_lowercase();
+        if lower.len() > extension.len() && lower.ends_with(extension) {
+            return Some((path[..end].to_string(), path[end..].to_string()));
+        }
+    }
+    None
+}
+
+/// Connection facts for the CGI variables
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    /// Client address
+    pub remote: SocketAddr,
+    /// Local address the client connected to
+    pub local: SocketAddr,
+    /// Whether the client connection is TLS
+    pub https: bool,
+}
+
+/// Errors that map to error responses before anything was sent to the client
+#[derive(Debug)]
+enum FastCgiError {
+    /// Backend unreachable or misbehaving (502)
+    BadGateway(String),
+    /// Backend did not respond in time (504)
+    Timeout(String),
+    /// Request rejected before reaching the backend
+    Status(u16, &'static str, String),
+}
+
+impl std::fmt::Display for FastCgiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FastCgiError::BadGateway(msg) => write!(f, "bad gateway: {}", msg),
+            FastCgiError::Timeout(msg) => write!(f, "gateway timeout: {}", msg),
+            FastCgiError::Status(code, _, msg) => write!(f, "{}: {}", code, msg),
+        }
+    }
+}
+
+/// Run a request through a FastCGI backend and stream the response back
+///
+/// # Arguments
+/// * `client` - Client connection (plain TCP or TLS)
+/// * `initial` - Bytes already read from the client (at least part of the request head)
+/// * `script` - Matched script and backend
+/// * `script_filename` - Result of `sanitize_path_with_root` for the script
+/// * `document_root` - Document root of the host
+/// * `conn_info` - Client and server addresses
+/// * `config` - Timeouts and pool size
+///
+/// # Returns
+/// * `Result<ProxyOutcome, Box<dyn std::error::Error + Send + Sync>>` - Status and size of the response;
+///   the caller should close the client connection afterwards
+pub async fn fastcgi_request<C>(
+    client: &mut C,
+    initial: &[u8],
+    script: &FastCgiMatch,
+    script_filename: Result<PathBuf, String>,
+    document_root: &Path,
+    conn_info: &ConnectionInfo,
+    config: &FastCgiConfig,
+) -> Result<ProxyOutcome, Box<dyn std::error::Error + Send + Sync>>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    match fastcgi_exchange(client, initial, script, script_filename, document_root, conn_info, config).await {
+        Ok(outcome) => Ok(outcome),
+        Err(Ok(error)) => {
+            let (status, reason) = match &error {
+                FastCgiError::BadGateway(_) => (502, "Bad Gateway"),
+                FastCgiError::Timeout(_) => (504, "Gateway Timeout"),
+                FastCgiError::Status(code, reason, _) => (*code, *reason),
+            };
+            log::error!(target: "fastcgi", "FastCGI error for {}: {}", script.script_name, error);
+            let body = format!("{} {}", status, reason);
+            let response = format!(
+                "HTTP/1.1 {} {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                reason,
+                body.len(),
+                body
+            );
+            client.write_all(response.as_bytes()).await?;
+            client.flush().await?;
+            Ok(ProxyOutcome { status, bytes_sent: response.len() as u64 })
+        }
+        // The response head was already sent; nothing sensible can be written any more
+        Err(Err(io_error)) => Err(io_error.into()),
+    }
+}
+
+/// The actual exchange. Errors before the response head is sent are `Err(Ok(_))`,
+/// I/O errors after that point are `Err(Err(_))`.
+async fn fastcgi_exchange<C>(
+    client: &mut C,
+    initial: &[u8],
+    script: &FastCgiMatch,
+    script_filename: Result<PathBuf, String>,
+    document_root: &Path,
+    conn_info: &ConnectionInfo,
+    config: &FastCgiConfig,
+) -> Result<ProxyOutcome, Result<FastCgiError, std::io::Error>>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let io_timeout = config.io_timeout;
+
+    let script_filename = script_filename.map_err(|e| {
+        Ok(if e.contains("not found") || e.contains("not a file") {
+            FastCgiError::Status(404, "Not Found", e)
+        } else {
+            FastCgiError::Status(403, "Forbidden", e)
+        })
+    })?;
+
+    // Complete the request head if the caller's buffer ended inside it
+    let mut buffered = initial.to_vec();
+    let bad_request = |e: String| Ok(FastCgiError::Status(400, "Bad Request", e));
+    let head_end = read_head(client, &mut buffered, io_timeout).await.map_err(bad_request)?;
+    let request = MessageHead::parse(&buffered[..head_end]).map_err(bad_request)?;
+    let body_start = head_end + 4;
+
+    let mut parts = request.start_line.split_whitespace();
+    let method = parts.next().unwrap_or("GET").to_string();
+    let target = parts.next().unwrap_or("/").to_string();
+    let protocol = parts.next().unwrap_or("HTTP/1.1").to_string();
+    let query = target.split_once('?').map(|(_, q)| q).unwrap_or("");
+
+    if request.get("transfer-encoding").is_some() {
+        return Err(Ok(FastCgiError::Status(411, "Length Required", "chunked request bodies are not supported".to_string())));
+    }
+    let content_length: u64 = match request.get("content-length") {
+        Some(length) => length.parse().map_err(|_| bad_request(format!("invalid Content-Length '{}'", length)))?,
+        None => 0,
+    };
+
+    let headers = request
+        .headers
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), value.clone()))
+        .collect();
+    let host = request.get("host").unwrap_or("localhost");
+    let server_name = host.rsplit_once(':').filter(|(_, port)| port.parse::<u16>().is_ok()).map(|(h, _)| h).unwrap_or(host);
+    let env = CgiEnv::from_request(&method, &target, server_name, query, &headers);
+    let params = CgiVarsBuilder::new(&env)
+        .script(
+            &script.script_name,
+            &script_filename.to_string_lossy(),
+            &script.path_info,
+            &document_root.to_string_lossy(),
+        )
+        .remote(conn_info.remote)
+        .server(conn_info.local, conn_info.https)
+        .protocol(&protocol)
+        .build();
+
+    // Send the request head
+    let mut conn = script.backend.checkout(config.connect_timeout).await.map_err(Ok)?;
+    let mut head = Vec::new();
+    let mut begin = Vec::with_capacity(8);
+    begin.extend_from_slice(&FCGI_RESPONDER.to_be_bytes());
+    begin.push(FCGI_KEEP_CONN);
+    begin.extend_from_slice(&[0; 5]);
+    head.extend(encode_record(FCGI_BEGIN_REQUEST, &begin));
+    for chunk in encode_params(&params).chunks(MAX_RECORD_CONTENT) {
+        head.extend(encode_record(FCGI_PARAMS, chunk));
+    }
+    head.extend(encode_record(FCGI_PARAMS, &[]));
+    write_backend(&mut conn, &head, io_timeout).await.map_err(Ok)?;
+
+    // Stream the request body as FCGI_STDIN
+    let already = &buffered[body_start..];
+    let first = (already.len() as u64).min(content_length) as usize;
+    if first > 0 {
+        write_backend(&mut conn, &encode_record(FCGI_STDIN, &already[..first]), io_timeout).await.map_err(Ok)?;
+    }
+    let mut remaining = content_length - first as u64;
+    let mut chunk = vec![0u8; 16 * 1024];
+    while remaining > 0 {
+        let want = remaining.min(chunk.len() as u64) as usize;
+        let n = match tokio::time::timeout(io_timeout, client.read(&mut chunk[..want])).await {
+            Ok(Ok(n)) if n > 0 => n,
+            _ => return Err(bad_request("client closed during request body".to_string())),
+        };
+        write_backend(&mut conn, &encode_record(FCGI_STDIN, &chunk[..n]), io_timeout).await.map_err(Ok)?;
+        remaining -= n as u64;
+    }
+    write_backend(&mut conn, &encode_record(FCGI_STDIN, &[]), io_timeout).await.map_err(Ok)?;
+
+    // Collect FCGI_STDOUT until the CGI header block is complete
+    let mut stdout = Vec::new();
+    let mut ended = false;
+    let (header_len, separator_len) = loop {
+        if let Some(found) = find_cgi_head_end(&stdout) {
+            break found;
+        }
+        if stdout.len() > super::reverse_proxy::MAX_HEAD_BYTES {
+            return Err(Ok(FastCgiError::BadGateway("response headers too large".to_string())));
+        }
+        match read_record(&mut conn, io_timeout).await.map_err(Ok)? {
+            (FCGI_STDOUT, content) => stdout.extend_from_slice(&content),
+            (FCGI_STDERR, content) => log_stderr(&script.script_name, &content),
+            (FCGI_END_REQUEST, _) => {
+                // Script ended without a header block: treat everything as body
+                if stdout.is_empty() {
+                    return Err(Ok(FastCgiError::BadGateway("script produced no output".to_string())));
+                }
+                ended = true;
+                break (0, 0);
+            }
+            (other, _) => return Err(Ok(FastCgiError::BadGateway(format!("unexpected record type {}", other)))),
+        }
+    };
+
+    let cgi_head = String::from_utf8_lossy(&stdout[..header_len]).to_string();
+    let (status, client_head) = cgi_response_head(&cgi_head);
+
+    // From here on the client has seen a response, so errors just close the connection
+    let mut bytes_sent = client_head.len() as u64;
+    client.write_all(client_head.as_bytes()).await.map_err(Err)?;
+    let leftover = &stdout[header_len + separator_len..];
+    client.write_all(leftover).await.map_err(Err)?;
+    bytes_sent += leftover.len() as u64;
+
+    if !ended {
+        loop {
+            let record = read_record(&mut conn, io_timeout).await;
+            match record {
+                Ok((FCGI_STDOUT, content)) => {
+                    client.write_all(&content).await.map_err(Err)?;
+                    bytes_sent += content.len() as u64;
+                }
+                Ok((FCGI_STDERR, content)) => log_stderr(&script.script_name, &content),
+                Ok((FCGI_END_REQUEST, content)) => {
+                    // Only a cleanly completed request leaves the connection reusable
+                    if content.get(4) == Some(&FCGI_REQUEST_COMPLETE) {
+                        script.backend.checkin(conn, config.max_idle);
+                    }
+                    break;
+                }
+                Ok((other, _)) => {
+                    log::warn!(target: "fastcgi", "FastCGI backend sent unexpected record type {}, closing", other);
+                    break;
+                }
+                Err(e) => {
+                    log::warn!(target: "fastcgi", "FastCGI response stream ended early: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+    client.flush().await.map_err(Err)?;
+
+    Ok(ProxyOutcome { status, bytes_sent })
+}
+
+/// Encode one record with padding to a multiple of 8 bytes
+fn encode_record(record_type: u8, content: &[u8]) -> Vec<u8> {
+    let padding = (8 - content.len() % 8) % 8;
+    let mut record = Vec::with_capacity(8 + content.len() + padding);
+    record.push(FCGI_VERSION_1);
+    record.push(record_type);
+    record.extend_from_slice(&REQUEST_ID.to_be_bytes());
+    record.extend_from_slice(&(content.len() as u16).to_be_bytes());
+    record.push(padding as u8);
+    record.push(0);
+    record.extend_from_slice(content);
+    record.resize(record.len() + padding, 0);
+    record
+}
+
+/// Encode name-value pairs for FCGI_PARAMS
+fn encode_params(params: &[(String, String)]) -> Vec<u8> {
+    fn push_length(out: &mut Vec<u8>, len: usize) {
+        if len < 128 {
+            out.push(len as u8);
+        } else {
+            out.extend_from_slice(&((len as u32) | 0x8000_0000).to_be_bytes());
+        }
+    }
+
+    let mut out = Vec::new();
+    for (name, value) in params {
+        push_length(&mut out, name.len());
+        push_length(&mut out, value.len());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(value.as_bytes());
+    }
+    out
+}
+
+/// Write to the backend with a timeout
+async fn write_backend(conn: &mut FastCgiConn, bytes: &[u8], io_timeout: Duration) -> Result<(), FastCgiError> {
+    match tokio::time::timeout(io_timeout, conn.io().write_all(bytes)).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(FastCgiError::BadGateway(format!("write to backend failed: {}", e))),
+        Err(_) => Err(FastCgiError::Timeout("write to backend timed out".to_string())),
+    }
+}
+
+/// Read one record, returning its type and content
+async fn read_record(conn: &mut FastCgiConn, io_timeout: Duration) -> Result<(u8, Vec<u8>), FastCgiError> {
+    let read = async {
+        let mut header = [0u8; 8];
+        conn.io().read_exact(&mut header).await?;
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        let mut content = vec![0u8; length + header[6] as usize];
+        conn.io().read_exact(&mut content).await?;
+        content.truncate(length);
+        Ok::<_, std::io::Error>((header, content))
+    };
+    match tokio::time::timeout(io_timeout, read).await {
+        Ok(Ok((header, content))) => {
+            if header[0] != FCGI_VERSION_1 {
+                return Err(FastCgiError::BadGateway(format!("unsupported FastCGI version {}", header[0])));
+            }
+            Ok((header[1], content))
+        }
+        Ok(Err(e)) => Err(FastCgiError::BadGateway(format!("read from backend failed: {}", e))),
+        Err(_) => Err(FastCgiError::Timeout("backend did not respond in time".to_string())),
+    }
+}
+
+/// Log what a script wrote to FCGI_STDERR
+fn log_stderr(script: &str, content: &[u8]) {
+    for line in String::from_utf8_lossy(content).lines().filter(|l| !l.trim().is_empty()) {
+        log::warn!(target: "fastcgi", "FastCGI stderr ({}): {}", script, line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tokio::net::TcpListener;
+
+    fn site(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("easyp_fastcgi_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("blog")).unwrap();
+        fs::write(root.join("index.php"), "<?php").unwrap();
+        root
+    }
+
+    #[test]
+    fn test_rule_parsing_and_matching() {
+        let root = site("match");
+        let mut config = FastCgiConfig::default();
+        config.add_rule_spec("*.php=unix:/run/php/php-fpm.sock").unwrap();
+        config.add_rule_spec("legacy.example.com/*.php=127.0.0.1:9000").unwrap();
+        config.add_rule_spec("/app.fcgi=127.0.0.1:9000").unwrap();
+
+        let m = config.find(Some("example.com"), "/wiki/index.php/Main_Page?x=1", &root).unwrap();
+        assert_eq!((m.script_name.as_str(), m.path_info.as_str()), ("/wiki/index.php", "/Main_Page"));
+        assert_eq!(m.backend.addr, FastCgiAddr::Unix(PathBuf::from("/run/php/php-fpm.sock")));
+
+        let legacy = config.find(Some("legacy.example.com"), "/x.PHP", &root).unwrap();
+        assert_eq!(legacy.backend.addr, FastCgiAddr::Tcp("127.0.0.1:9000".to_string()));
+        // Both TCP rules share one pool
+        assert!(Arc::ptr_eq(&legacy.backend, &config.rules[2].backend));
+
+        let app = config.find(None, "/app.fcgi/users/1", &root).unwrap();
+        assert_eq!((app.script_name.as_str(), app.path_info.as_str()), ("/app.fcgi", "/users/1"));
+        assert!(config.find(None, "/app.fcgix", &root).is_none());
+
+        let index = config.find(None, "/", &root).unwrap();
+        assert_eq!(index.script_name, "/index.php");
+        assert!(config.find(None, "/blog/", &root).is_none());
+        assert!(config.find(None, "/style.css", &root).is_none());
+        assert!(config.find(None, "/.php", &root).is_none());
+
+        assert_eq!(config.script_extensions(), vec!["php"]);
+
+        assert!(config.add_rule_spec("php=127.0.0.1:9000").is_err());
+        assert!(config.add_rule_spec("*.php=127.0.0.1").is_err());
+        assert!(config.add_rule_spec("*.php=unix:relative.sock").is_err());
+    }
+
+    #[test]
+    fn test_encoded_script_paths() {
+        let root = site("encoded");
+        let mut config = FastCgiConfig::default();
+        config.add_rule_spec("*.php=127.0.0.1:9000").unwrap();
+
+        for target in ["/index%2Ephp", "/index%2ephp", "/index.ph%70", "/INDEX.PH%50", "//./index.php", "/index%2Ephp?x=%2F"] {
+            let m = config.find(None, target, &root).unwrap_or_else(|| panic!("{} not matched", target));
+            assert_eq!(m.script_name.to_lowercase(), "/index.php", "{}", target);
+        }
+        let m = config.find(None, "/wiki/index%2Ephp/Main%20Page", &root).unwrap();
+        assert_eq!((m.script_name.as_str(), m.path_info.as_str()), ("/wiki/index.php", "/Main Page"));
+
+        // A literal percent sign survives the second decode in sanitize_path_with_root
+        let m = config.find(None, "/100%25.php", &root).unwrap();
+        assert_eq!((m.script_name.as_str(), m.script_target().as_str()), ("/100%.php", "/100%25.php"));
+        assert_eq!(decode_request_path("/blog/./").as_deref(), Some("/blog/"));
+        assert_eq!(decode_request_path("/%2E%2E/x.php").as_deref(), Some("/../x.php"));
+        assert!(decode_request_path("/%FF.php").is_none());
+    }
+
+    #[test]
+    fn test_record_encoding() {
+        let record = encode_record(FCGI_STDIN, b"hello");
+        assert_eq!(&record[..8], &[1, FCGI_STDIN, 0, 1, 0, 5, 3, 0]);
+        assert_eq!(record.len(), 16);
+
+        let long = "x".repeat(200);
+        let params = encode_params(&[("A".to_string(), "b".to_string()), ("LONG".to_string(), long.clone())]);
+        assert_eq!(&params[..4], &[1, 1, b'A', b'b']);
+        assert_eq!(&params[4..9], &[4, 0x80, 0, 0, 200]);
+        assert_eq!(params.len(), 4 + 5 + 4 + 200);
+    }
+
+    #[test]
+    fn test_cgi_head_translation() {
+        let (status, head) = cgi_response_head("Status: 404 Not Found\r\nContent-Type: text/plain\r\nConnection: keep-alive");
+        assert_eq!(status, 404);
+        assert!(head.starts_with("HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\n"));
+        assert!(head.ends_with("Connection: close\r\n\r\n"));
+        assert!(!head.contains("keep-alive"));
+
+        let (status, head) = cgi_response_head("Location: /login");
+        assert_eq!(status, 302);
+        assert!(head.contains("Location: /login\r\n"));
+
+        let (status, head) = cgi_response_head("Status: 201\nX-Powered-By: PHP");
+        assert_eq!(status, 201);
+        assert!(head.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(head.contains("Content-Type: text/html; charset=utf-8\r\n"));
+
+        assert_eq!(find_cgi_head_end(b"A: b\n\nbody"), Some((4, 2)));
+        assert_eq!(find_cgi_head_end(b"A: b\r\n\r\nbody"), Some((4, 4)));
+    }
+
+    #[test]
+    fn test_cgi_variables() {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("content-type".to_string(), "application/x-www-form-urlencoded".to_string());
+        headers.insert("user-agent".to_string(), "test".to_string());
+        headers.insert("proxy".to_string(), "http://evil".to_string());
+        headers.insert("x-client-cert-cn".to_string(), "admin".to_string());
+        headers.insert("x_client_cert_verify".to_string(), "SUCCESS".to_string());
+        let env = CgiEnv::from_request("POST", "/a.php/x?y=1", "example.com", "y=1", &headers);
+        let vars: std::collections::HashMap<String, String> = CgiVarsBuilder::new(&env)
+            .script("/a.php", "/srv/a.php", "/x", "/srv")
+            .remote("203.0.113.9:5555".parse().unwrap())
+            .server("192.0.2.1:443".parse().unwrap(), true)
+            .build()
+            .into_iter()
+            .collect();
+
+        assert_eq!(vars["SCRIPT_FILENAME"], "/srv/a.php");
+        assert_eq!(vars["PATH_INFO"], "/x");
+        assert_eq!(vars["PATH_TRANSLATED"], "/srv/x");
+        assert_eq!(vars["REMOTE_ADDR"], "203.0.113.9");
+        assert_eq!(vars["SERVER_PORT"], "443");
+        assert_eq!(vars["HTTPS"], "on");
+        assert_eq!(vars["CONTENT_TYPE"], "application/x-www-form-urlencoded");
+        assert_eq!(vars["HTTP_USER_AGENT"], "test");
+        assert_eq!(vars["QUERY_STRING"], "y=1");
+        assert!(!vars.contains_key("HTTP_PROXY"));
+        assert!(!vars.contains_key("HTTP_CONTENT_TYPE"));
+        assert!(!vars.keys().any(|name| name.starts_with("HTTP_X_CLIENT_CERT")));
+    }
+
+    /// Minimal responder: echoes SCRIPT_FILENAME and stdin, then keeps the connection open
+    async fn fake_backend(listener: TcpListener) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        loop {
+            let mut params = Vec::new();
+            let mut stdin = Vec::new();
+            loop {
+                let mut header = [0u8; 8];
+                if socket.read_exact(&mut header).await.is_err() {
+                    return;
+                }
+                let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+                let mut content = vec![0u8; length + header[6] as usize];
+                socket.read_exact(&mut content).await.unwrap();
+                content.truncate(length);
+                match header[1] {
+                    FCGI_PARAMS => params.extend(content),
+                    FCGI_STDIN if length == 0 => break,
+                    FCGI_STDIN => stdin.extend(content),
+                    _ => {}
+                }
+            }
+            let params = String::from_utf8_lossy(&params).to_string();
+            let script = if params.contains("/srv/form.php") { "form.php" } else { "other" };
+            let body = format!("{}:{}", script, String::from_utf8_lossy(&stdin));
+            let stdout = format!("Status: 201 Created\r\nContent-Type: text/plain\r\n\r\n{}", body);
+            socket.write_all(&encode_record(FCGI_STDERR, b"notice: hi\n")).await.unwrap();
+            socket.write_all(&encode_record(FCGI_STDOUT, stdout.as_bytes())).await.unwrap();
+            socket.write_all(&encode_record(FCGI_STDOUT, &[])).await.unwrap();
+            socket.write_all(&encode_record(FCGI_END_REQUEST, &[0, 0, 0, 0, 0, 0, 0, 0])).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fastcgi_roundtrip_reuses_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // The backend accepts exactly one connection, so the second request must reuse it
+        tokio::spawn(fake_backend(listener));
+
+        let mut config = FastCgiConfig::default();
+        config.add_rule_spec(&format!("*.php={}", addr)).unwrap();
+        let script = config.find(None, "/form.php", Path::new("/srv")).unwrap();
+        let conn_info = ConnectionInfo {
+            remote: "203.0.113.9:5555".parse().unwrap(),
+            local: "127.0.0.1:443".parse().unwrap(),
+            https: true,
+        };
+
+        for _ in 0..2 {
+            let (mut client, mut server_side) = tokio::io::duplex(64 * 1024);
+            client.write_all(b"lo").await.unwrap();
+            let initial = b"POST /form.php HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\n\r\nhel";
+            let outcome = fastcgi_request(
+                &mut server_side,
+                initial,
+                &script,
+                Ok(PathBuf::from("/srv/form.php")),
+                Path::new("/srv"),
+                &conn_info,
+                &config,
+            )
+            .await
+            .unwrap();
+            assert_eq!(outcome.status, 201);
+            drop(server_side);
+
+            let mut response = String::new();
+            client.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+            assert!(response.ends_with("\r\n\r\nform.php:hello"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_script_paths() {
+        let mut config = FastCgiConfig::default();
+        config.add_rule_spec("*.php=127.0.0.1:1").unwrap();
+        let script = config.find(None, "/../etc/x.php", Path::new("/srv")).unwrap();
+        let conn_info = ConnectionInfo {
+            remote: "203.0.113.9:5555".parse().unwrap(),
+            local: "127.0.0.1:80".parse().unwrap(),
+            https: false,
+        };
+        let request = b"GET /../etc/x.php HTTP/1.1\r\nHost: example.com\r\n\r\n";
+
+        for (error, status) in [("Directory traversal attack detected", 403), ("File not found", 404)] {
+            let (_client, mut server_side) = tokio::io::duplex(4096);
+            let outcome = fastcgi_request(&mut server_side, request, &script, Err(error.to_string()), Path::new("/srv"), &conn_info, &config)
+                .await
+                .unwrap();
+            assert_eq!(outcome.status, status);
+        }
+    }
+}
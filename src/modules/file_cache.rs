@@ -76,6 +76,10 @@ impl FileCacheInfo {
             t if t.starts_with("application/x-tar") => 86400, // 1 day
             t if t.starts_with("application/octet-stream") => 86400, // 1 day
 
+            // Audio and video - cache for 1 day
+            t if t.starts_with("audio/") => 86400, // 1 day
+            t if t.starts_with("video/") => 86400, // 1 day
+
             // HTML files - cache for 1 hour
             t if t.starts_with("text/html") => 3600, // 1 hour
 
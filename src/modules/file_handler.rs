@@ -3,18 +3,44 @@
 //! This module provides centralized file serving logic for both HTTP and HTTPS connections.
 //! It handles domain-based document root selection, file serving, and response generation.
 
+use super::hostname::parse_host;
 
 /// Extract domain from HTTP Host header
+/// Returns the normalised host name without port (IPv6 literals stay bracketed),
+/// or None if the header is missing or not a valid host
 pub fn extract_domain_from_host_header(request: &str) -> Option<String> {
     for line in request.lines() {
         if line.to_lowercase().starts_with("host:") {
-            let host = line.split(':').nth(1)?.trim();
-            // Remove port if present
-            let domain = host.split(':').next()?.to_string();
-            return Some(domain);
+            let (_, value) = line.split_once(':')?;
+            return match parse_host(value) {
+                Ok((host, _port)) => Some(host.to_string()),
+                Err(e) => {
+                    log::debug!(target: "http", "ignoring invalid Host header '{}': {}", value.trim(), e);
+                    None
+                }
+            };
         }
     }
     None
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_domain_from_host_header() {
+        let request = "GET / HTTP/1.1\r\nHost: My-Site.Example.com:8443\r\n\r\n";
+        assert_eq!(extract_domain_from_host_header(request).as_deref(), Some("my-site.example.com"));
+
+        let request = "GET / HTTP/1.1\r\nhost: [::1]:443\r\n\r\n";
+        assert_eq!(extract_domain_from_host_header(request).as_deref(), Some("[::1]"));
+
+        let request = "GET / HTTP/1.1\r\nHost: ../etc\r\n\r\n";
+        assert_eq!(extract_domain_from_host_header(request), None);
+
+        assert_eq!(extract_domain_from_host_header("GET / HTTP/1.0\r\n\r\n"), None);
+    }
+}
+
 
@@ -1,33 +1,33 @@
 // file_logger.rs - Persistent file logging system
 // Writes logs to files with rotation and proper formatting
 
-use std::fs::{File, OpenOptions};
-use std::io::{self, Write};
+use std::cell::Cell;
+use std::io::Write;
 use std::path::Path;
 use std::sync::{Arc, Mutex, OnceLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::log_rotation::{RotatingFile, RotationPolicy};
+
+thread_local! {
+    // Set while this thread holds the log file, so messages logged underneath it
+    // (a failed rotation) go to stdout only instead of re-locking it
+    static HOLDING_FILE: Cell<bool> = const { Cell::new(false) };
+}
+
 /// File logger that writes logs to a file
 pub struct FileLogger {
-    file: Arc<Mutex<File>>,
+    file: Mutex<RotatingFile>,
     log_path: String,
 }
 
 impl FileLogger {
-    /// Create a new file logger
-    pub fn new(log_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        // Ensure the directory exists
-        if let Some(parent) = Path::new(log_path).parent() {
-            std::fs::create_dir_all(parent)?;
-        }
-
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(log_path)?;
+    /// Create a new file logger, rotating the file according to `policy`
+    pub fn new(log_path: &str, policy: RotationPolicy) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = RotatingFile::open(Path::new(log_path), policy)?;
 
         Ok(FileLogger {
-            file: Arc::new(Mutex::new(file)),
+            file: Mutex::new(file),
             log_path: log_path.to_string(),
         })
     }
@@ -40,41 +40,31 @@ impl FileLogger {
 
         let log_entry = format!("{} {}: {}\n", timestamp, level, message);
 
-        let mut file = self.file.lock().unwrap();
-        file.write_all(log_entry.as_bytes())?;
-        file.flush()?;
+        self.with_file(|file| {
+            file.write_all(log_entry.as_bytes())?;
+            file.flush()
+        })?;
 
         Ok(())
     }
 
+    /// Run `f` on the locked file, marking this thread as holding it
+    fn with_file<T>(&self, f: impl FnOnce(&mut RotatingFile) -> std::io::Result<T>) -> std::io::Result<T> {
+        let mut file = self.file.lock().unwrap();
+        HOLDING_FILE.with(|holding| holding.set(true));
+        let result = f(&mut file);
+        HOLDING_FILE.with(|holding| holding.set(false));
+        result
+    }
+
     /// Get the log file path
     pub fn log_path(&self) -> &str {
         &self.log_path
     }
 
-    /// Rotate the log file (rename current and create new)
+    /// Rotate the log file now (current file becomes generation 1)
     pub fn rotate(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)?
-            .as_secs();
-
-        let rotated_path = format!("{}.{}", self.log_path, timestamp);
-
-        // Close the current file
-        drop(self.file.lock().unwrap());
-
-        // Rename current file
-        std::fs::rename(&self.log_path, &rotated_path)?;
-
-        // Create new file
-        let new_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.log_path)?;
-
-        // Update the file reference
-        *self.file.lock().unwrap() = new_file;
-
+        self.with_file(RotatingFile::rotate)?;
         Ok(())
     }
 }
@@ -83,14 +73,17 @@ impl FileLogger {
 static FILE_LOGGER: OnceLock<Arc<FileLogger>> = OnceLock::new();
 
 /// Initialize the global file logger
-pub fn init_file_logger(log_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    FILE_LOGGER.set(Arc::new(FileLogger::new(log_path)?))
+pub fn init_file_logger(log_path: &str, policy: RotationPolicy) -> Result<(), Box<dyn std::error::Error>> {
+    FILE_LOGGER.set(Arc::new(FileLogger::new(log_path, policy)?))
         .map_err(|_| "File logger already initialized")?;
     Ok(())
 }
 
 /// Write a log entry to the file logger
 pub fn write_file_log(level: &str, message: &str) {
+    if HOLDING_FILE.with(Cell::get) {
+        return;
+    }
     if let Some(logger) = FILE_LOGGER.get() {
         if let Err(e) = logger.write_log(level, message) {
             eprintln!("Failed to write to file logger: {}", e);
@@ -0,0 +1,338 @@
+//! Health and Readiness Probes
+//!
+//! This module answers liveness and readiness checks for monitors and load balancers.
+//! It provides:
+//! - `/healthz`: the process is alive and the async runtime is responsive (a heartbeat
+//!   task must have ticked recently)
+//! - `/readyz`: listeners are bound, the certificate resolver is in place, document roots
+//!   are readable and statistics files are writable
+//! - JSON bodies with a status per check; 200 when everything passes, 503 otherwise
+//! - A separate plain-HTTP listener (`--health-listen`), and opt-in paths on the site
+//!   listeners (`--health-path`)
+//!
+//! The site listeners are public, so probes there are off unless asked for, run after the
+//! client certificate rules, and leave out each check's detail (paths, pid, addresses).
+//!
+//! Checks only read state recorded at startup plus a few `stat`-sized filesystem calls,
+//! and never reach the ACME client. Probing over HTTPS still needs a TLS handshake, which
+//! may resolve a certificate for the SNI name; point probes at HTTP or the separate
+//! listener to keep them entirely away from certificate management.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// How often the heartbeat task ticks
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Heartbeat age beyond which the runtime is reported as stalled
+const HEARTBEAT_STALE: Duration = Duration::from_secs(5);
+
+/// Which probe a request asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    Health,
+    Ready,
+}
+
+#[derive(Default)]
+struct HealthState {
+    listeners: Mutex<BTreeMap<&'static str, Option<SocketAddr>>>,
+    cert_resolver: AtomicBool,
+    document_roots: Mutex<Vec<PathBuf>>,
+    stats_files: Mutex<Vec<PathBuf>>,
+    heartbeat_millis: AtomicU64,
+}
+
+static STATE: OnceLock<HealthState> = OnceLock::new();
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static PATH_PREFIX: OnceLock<Option<String>> = OnceLock::new();
+
+fn state() -> &'static HealthState {
+    STARTED.get_or_init(Instant::now);
+    STATE.get_or_init(HealthState::default)
+}
+
+fn uptime_millis() -> u64 {
+    STARTED.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+/// Set where the probes are served on the site listeners
+///
+/// # Arguments
+/// * `prefix` - `""` for `/healthz` and `/readyz`, `/_easyp` for `/_easyp/healthz`, or
+///   None (the default) to answer probes only on the separate health listener
+pub fn set_path_prefix(prefix: Option<String>) {
+    let _ = PATH_PREFIX.set(prefix.map(|p| p.trim_end_matches('/').to_string()));
+}
+
+/// Probe requested by a site-listener path, if any
+pub fn probe_for_path(path: &str) -> Option<Probe> {
+    let prefix = PATH_PREFIX.get_or_init(|| None).as_deref()?;
+    match path.split('?').next()?.strip_prefix(prefix)? {
+        "/healthz" => Some(Probe::Health),
+        "/readyz" => Some(Probe::Ready),
+        _ => None,
+    }
+}
+
+/// Record a listener as bound (`addr` Some) or failed (None)
+pub fn set_listener(name: &'static str, addr: Option<SocketAddr>) {
+    state().listeners.lock().unwrap_or_else(|e| e.into_inner()).insert(name, addr);
+}
+
+/// Record that the TLS certificate resolver is installed
+pub fn set_cert_resolver_ready(ready: bool) {
+    state().cert_resolver.store(ready, Ordering::Relaxed);
+}
+
+/// Document roots that must be readable for readiness
+pub fn set_document_roots(roots: Vec<PathBuf>) {
+    *state().document_roots.lock().unwrap_or_else(|e| e.into_inner()) = roots;
+}
+
+/// Statistics files that must be writable for readiness
+pub fn add_stats_file(path: PathBuf) {
+    state().stats_files.lock().unwrap_or_else(|e| e.into_inner()).push(path);
+}
+
+/// Tick a heartbeat on the async runtime so `/healthz` can tell it is responsive
+pub fn spawn_heartbeat() {
+    let state = state();
+    state.heartbeat_millis.store(uptime_millis(), Ordering::Relaxed);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            interval.tick().await;
+            state.heartbeat_millis.store(uptime_millis(), Ordering::Relaxed);
+        }
+    });
+}
+
+struct Check {
+    name: String,
+    ok: bool,
+    detail: String,
+}
+
+fn check(name: impl Into<String>, ok: bool, detail: impl Into<String>) -> Check {
+    Check {
+        name: name.into(),
+        ok,
+        detail: detail.into(),
+    }
+}
+
+fn json_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Whether the current user may write `path` (or create it in its directory)
+fn is_writable(path: &Path) -> bool {
+    let target = if path.exists() {
+        path
+    } else {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        }
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let Ok(c_path) = std::ffi::CString::new(target.as_os_str().as_bytes()) else {
+            return false;
+        };
+        // SAFETY: c_path is a valid NUL-terminated string for the duration of the call
+        unsafe { libc::access(c_path.as_ptr(), libc::W_OK) == 0 }
+    }
+    #[cfg(not(unix))]
+    {
+        std::fs::metadata(target).map(|m| !m.permissions().readonly()).unwrap_or(false)
+    }
+}
+
+fn liveness_checks() -> Vec<Check> {
+    let age = uptime_millis().saturating_sub(state().heartbeat_millis.load(Ordering::Relaxed));
+    vec![
+        check("process", true, format!("pid {}", std::process::id())),
+        check(
+            "event_loop",
+            age <= HEARTBEAT_STALE.as_millis() as u64,
+            format!("heartbeat {} ms ago", age),
+        ),
+    ]
+}
+
+fn readiness_checks() -> Vec<Check> {
+    let state = state();
+    let mut checks = Vec::new();
+
+    let listeners = state.listeners.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    if listeners.is_empty() {
+        checks.push(check("listeners", false, "not bound yet"));
+    }
+    for (name, addr) in listeners {
+        checks.push(match addr {
+            Some(addr) => check(format!("listener_{}", name), true, addr.to_string()),
+            None => check(format!("listener_{}", name), false, "not bound"),
+        });
+    }
+
+    let resolver = state.cert_resolver.load(Ordering::Relaxed);
+    checks.push(check("cert_resolver", resolver, if resolver { "installed" } else { "not installed" }));
+
+    for root in state.document_roots.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        checks.push(match std::fs::read_dir(root) {
+            Ok(_) => check("document_root", true, root.display().to_string()),
+            Err(e) => check("document_root", false, format!("{}: {}", root.display(), e)),
+        });
+    }
+
+    for file in state.stats_files.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let writable = is_writable(file);
+        let detail = format!("{}{}", file.display(), if writable { "" } else { ": not writable" });
+        checks.push(check("stats_file", writable, detail));
+    }
+    checks
+}
+
+/// Run a probe and return (HTTP status code, JSON body)
+///
+/// # Arguments
+/// * `probe` - Liveness or readiness
+/// * `detail` - Include each check's detail; only for the private health listener
+pub fn evaluate(probe: Probe, detail: bool) -> (u16, String) {
+    let checks = match probe {
+        Probe::Health => liveness_checks(),
+        Probe::Ready => readiness_checks(),
+    };
+    let ok = checks.iter().all(|c| c.ok);
+    let items: Vec<String> = checks
+        .iter()
+        .map(|c| {
+            format!(
+                "{{\"name\":\"{}\",\"status\":\"{}\"{}}}",
+                json_escape(&c.name),
+                if c.ok { "ok" } else { "fail" },
+                if detail { format!(",\"detail\":\"{}\"", json_escape(&c.detail)) } else { String::new() }
+            )
+        })
+        .collect();
+    let body = format!(
+        "{{\"status\":\"{}\",\"uptime_seconds\":{},\"checks\":[{}]}}\n",
+        if ok { "ok" } else { "fail" },
+        uptime_millis() / 1000,
+        items.join(",")
+    );
+    (if ok { 200 } else { 503 }, body)
+}
+
+/// Complete HTTP/1.1 response for a probe (`head_only` omits the body)
+pub fn http_response(probe: Probe, head_only: bool, detail: bool) -> Vec<u8> {
+    let (status, body) = evaluate(probe, detail);
+    let reason = if status == 200 { "OK" } else { "Service Unavailable" };
+    let mut response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        body.len()
+    );
+    if !head_only {
+        response.push_str(&body);
+    }
+    response.into_bytes()
+}
+
+/// Serve `/healthz` and `/readyz` on a separate plain-HTTP listener
+///
+/// # Arguments
+/// * `addr` - Address to bind, e.g. 127.0.0.1:8081
+///
+/// # Returns
+/// * `std::io::Result<()>` - Error if the address cannot be bound; otherwise runs forever
+pub async fn run_health_listener(addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log::info!(target: "server", "health listener on http://{}/healthz and /readyz", addr);
+    loop {
+        let (mut stream, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log::warn!(target: "server", "health accept error: {}", e);
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 2048];
+            let Ok(Ok(n)) = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer)).await else {
+                return;
+            };
+            let request = String::from_utf8_lossy(&buffer[..n]);
+            let mut parts = request.split_whitespace();
+            let method = parts.next().unwrap_or("");
+            let path = parts.next().unwrap_or("").split('?').next().unwrap_or("");
+            let probe = match path {
+                "/healthz" => Some(Probe::Health),
+                "/readyz" => Some(Probe::Ready),
+                _ => None,
+            };
+            let response = match probe {
+                Some(probe) if method == "GET" || method == "HEAD" => http_response(probe, method == "HEAD", true),
+                _ => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+            };
+            let _ = stream.write_all(&response).await;
+            let _ = stream.shutdown().await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_for_path() {
+        set_path_prefix(Some(String::new()));
+        assert_eq!(probe_for_path("/healthz"), Some(Probe::Health));
+        assert_eq!(probe_for_path("/readyz?verbose"), Some(Probe::Ready));
+        assert_eq!(probe_for_path("/healthz/x"), None);
+        assert_eq!(probe_for_path("/index.html"), None);
+    }
+
+    #[tokio::test]
+    async fn test_readiness_reports_each_check() {
+        let dir = tempfile::tempdir().unwrap();
+        spawn_heartbeat();
+        set_listener("http", Some("127.0.0.1:80".parse().unwrap()));
+        set_cert_resolver_ready(true);
+        set_document_roots(vec![dir.path().to_path_buf()]);
+        add_stats_file(dir.path().join("traffic.stats"));
+
+        let (status, body) = evaluate(Probe::Ready, true);
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.contains("{\"name\":\"listener_http\",\"status\":\"ok\",\"detail\":\"127.0.0.1:80\"}"));
+        assert!(body.contains("\"name\":\"stats_file\",\"status\":\"ok\""));
+
+        // Public listeners only learn whether each check passed
+        let (_, body) = evaluate(Probe::Ready, false);
+        assert!(body.contains("{\"name\":\"listener_http\",\"status\":\"ok\"}"));
+        assert!(!body.contains("detail") && !body.contains(&dir.path().display().to_string()));
+
+        set_document_roots(vec![dir.path().join("missing")]);
+        let (status, body) = evaluate(Probe::Ready, true);
+        assert_eq!(status, 503);
+        assert!(body.starts_with("{\"status\":\"fail\""));
+        assert!(body.contains("\"name\":\"document_root\",\"status\":\"fail\""));
+        set_document_roots(vec![dir.path().to_path_buf()]);
+
+        let response = String::from_utf8(http_response(Probe::Health, true, false)).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+}
@@ -0,0 +1,341 @@
+//! Hostname Normalisation and Validation
+//!
+//! This module is the single place where host names from Host headers, SNI and
+//! the certificate cache are turned into canonical form. It:
+//! - Validates labels per RFC 1123 (letters, digits and inner hyphens, 1-63 octets)
+//! - Converts Unicode IDN labels to punycode (RFC 3492) with the `xn--` prefix
+//! - Lowercases names and strips a trailing root dot
+//! - Parses Host header values including bracketed IPv6 literals and ports
+//!
+//! IDN mapping is a lightweight subset of UTS #46: labels are lowercased and the
+//! ideographic full stops are treated as dots, but no Unicode normalisation is applied.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+
+/// Maximum length of a full host name in its ASCII form (without trailing dot)
+pub const MAX_HOSTNAME_LEN: usize = 253;
+
+/// Maximum length of a single label
+pub const MAX_LABEL_LEN: usize = 63;
+
+/// A parsed host from a Host header, URL authority or SNI value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Host {
+    /// A normalised DNS name (lowercase ASCII, punycode for IDNs)
+    Domain(String),
+    /// An IPv4 or IPv6 literal
+    Ip(IpAddr),
+}
+
+impl fmt::Display for Host {
+    /// Formats the host as it would appear in a URL authority (IPv6 in brackets)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Host::Domain(name) => write!(f, "{}", name),
+            Host::Ip(IpAddr::V4(ip)) => write!(f, "{}", ip),
+            Host::Ip(IpAddr::V6(ip)) => write!(f, "[{}]", ip),
+        }
+    }
+}
+
+/// Check whether an ASCII host name is valid per RFC 1123
+///
+/// The name must already be normalised (lowercase, punycode, no trailing dot).
+///
+/// # Arguments
+/// * `name` - Host name to check
+///
+/// # Returns
+/// * `bool` - True if every label is 1-63 characters of `[a-z0-9-]` without leading or trailing hyphen
+pub fn is_valid_hostname(name: &str) -> bool {
+    if name.is_empty() || name.len() > MAX_HOSTNAME_LEN {
+        return false;
+    }
+
+    name.split('.').all(is_valid_label)
+}
+
+/// Check a single normalised label
+fn is_valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= MAX_LABEL_LEN
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+/// Normalise a host name to its canonical ASCII form
+///
+/// # Arguments
+/// * `name` - Host name in any case, optionally with a trailing dot or Unicode labels
+///
+/// # Returns
+/// * `Result<String, String>` - Lowercase ASCII host name, or a description of why it is invalid
+pub fn normalize_hostname(name: &str) -> Result<String, String> {
+    let trimmed = name.trim();
+    let trimmed = trimmed.strip_suffix('.').unwrap_or(trimmed);
+    if trimmed.is_empty() {
+        return Err("empty host name".to_string());
+    }
+
+    let mut labels = Vec::new();
+    for label in trimmed.split(['.', '\u{3002}', '\u{FF0E}', '\u{FF61}']) {
+        if label.is_ascii() {
+            labels.push(label.to_ascii_lowercase());
+        } else {
+            let lower: String = label.chars().flat_map(char::to_lowercase).collect();
+            let encoded = punycode_encode(&lower)
+                .ok_or_else(|| format!("cannot encode label '{}' as punycode", label))?;
+            labels.push(format!("xn--{}", encoded));
+        }
+    }
+
+    let normalized = labels.join(".");
+    if !is_valid_hostname(&normalized) {
+        return Err(format!("invalid host name '{}'", name));
+    }
+
+    Ok(normalized)
+}
+
+/// Parse a Host header value (or URL authority) into a host and optional port
+///
+/// Accepts `example.com`, `example.com:8443`, `1.2.3.4:80`, `[::1]` and `[::1]:443`.
+///
+/// # Arguments
+/// * `value` - Raw Host header value
+///
+/// # Returns
+/// * `Result<(Host, Option<u16>), String>` - The normalised host and the port if present
+pub fn parse_host(value: &str) -> Result<(Host, Option<u16>), String> {
+    let value = value.trim();
+
+    if let Some(rest) = value.strip_prefix('[') {
+        let (literal, after) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("unterminated IPv6 literal in '{}'", value))?;
+        let ip: Ipv6Addr = literal
+            .parse()
+            .map_err(|_| format!("invalid IPv6 literal '{}'", literal))?;
+        let port = match after {
+            "" => None,
+            _ => Some(parse_port(after.strip_prefix(':').ok_or_else(|| format!("invalid host '{}'", value))?)?),
+        };
+        return Ok((Host::Ip(IpAddr::V6(ip)), port));
+    }
+
+    let (host, port) = match value.rsplit_once(':') {
+        Some((host, port)) => {
+            // An unbracketed IPv6 address is not a valid Host header
+            if host.contains(':') {
+                return Err(format!("IPv6 literal must be bracketed in '{}'", value));
+            }
+            (host, Some(parse_port(port)?))
+        }
+        None => (value, None),
+    };
+
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok((Host::Ip(ip), port));
+    }
+
+    Ok((Host::Domain(normalize_hostname(host)?), port))
+}
+
+/// Parse a decimal port number
+fn parse_port(port: &str) -> Result<u16, String> {
+    if port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("invalid port '{}'", port));
+    }
+    port.parse().map_err(|_| format!("invalid port '{}'", port))
+}
+
+/// Encode a Unicode label with the punycode bootstring algorithm (RFC 3492)
+///
+/// # Arguments
+/// * `input` - Label without the `xn--` prefix
+///
+/// # Returns
+/// * `Option<String>` - Encoded label, or None on overflow
+pub fn punycode_encode(input: &str) -> Option<String> {
+    const BASE: u32 = 36;
+    const T_MIN: u32 = 1;
+    const T_MAX: u32 = 26;
+    const INITIAL_BIAS: u32 = 72;
+    const INITIAL_N: u32 = 128;
+
+    let code_points: Vec<u32> = input.chars().map(|c| c as u32).collect();
+    let mut output: String = input.chars().filter(char::is_ascii).collect();
+    let basic_len = output.len() as u32;
+    let mut handled = basic_len;
+    if basic_len > 0 {
+        output.push('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let total = code_points.len() as u32;
+
+    while handled < total {
+        let m = code_points.iter().copied().filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+
+        for &c in &code_points {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias {
+                        T_MIN
+                    } else if k >= bias + T_MAX {
+                        T_MAX
+                    } else {
+                        k - bias
+                    };
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(q));
+                bias = adapt_bias(delta, handled + 1, handled == basic_len);
+                delta = 0;
+                handled += 1;
+            }
+        }
+
+        delta = delta.checked_add(1)?;
+        n = n.checked_add(1)?;
+    }
+
+    Some(output)
+}
+
+/// Map a punycode digit value (0-35) to its character
+fn encode_digit(d: u32) -> char {
+    match d {
+        0..=25 => (b'a' + d as u8) as char,
+        _ => (b'0' + (d - 26) as u8) as char,
+    }
+}
+
+/// Bias adaptation function from RFC 3492 section 6.1
+fn adapt_bias(delta: u32, num_points: u32, first_time: bool) -> u32 {
+    const BASE: u32 = 36;
+    const T_MIN: u32 = 1;
+    const T_MAX: u32 = 26;
+    const SKEW: u32 = 38;
+    const DAMP: u32 = 700;
+
+    let mut delta = if first_time { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (((BASE - T_MIN + 1) * delta) / (delta + SKEW))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_valid_hostnames() {
+        assert!(is_valid_hostname("example.com"));
+        assert!(is_valid_hostname("my-site.example.com"));
+        assert!(is_valid_hostname("a.b.c.d"));
+        assert!(is_valid_hostname("localhost"));
+        assert!(is_valid_hostname("123.example"));
+        assert!(is_valid_hostname(&format!("{}.com", "a".repeat(63))));
+    }
+
+    #[test]
+    fn test_invalid_hostnames() {
+        assert!(!is_valid_hostname(""));
+        assert!(!is_valid_hostname("-site.example.com"));
+        assert!(!is_valid_hostname("site-.example.com"));
+        assert!(!is_valid_hostname("exa_mple.com"));
+        assert!(!is_valid_hostname("example..com"));
+        assert!(!is_valid_hostname(".example.com"));
+        assert!(!is_valid_hostname("example.com."));
+        assert!(!is_valid_hostname("Example.com"));
+        assert!(!is_valid_hostname("../etc/passwd"));
+        assert!(!is_valid_hostname("exa mple.com"));
+        assert!(!is_valid_hostname(&format!("{}.com", "a".repeat(64))));
+        let long = vec!["abcdefghi"; 26].join(".");
+        assert!(long.len() > MAX_HOSTNAME_LEN);
+        assert!(!is_valid_hostname(&long));
+    }
+
+    #[test]
+    fn test_normalize_case_and_trailing_dot() {
+        assert_eq!(normalize_hostname("Example.COM").unwrap(), "example.com");
+        assert_eq!(normalize_hostname("example.com.").unwrap(), "example.com");
+        assert_eq!(normalize_hostname(" My-Site.Example.com ").unwrap(), "my-site.example.com");
+        assert!(normalize_hostname(".").is_err());
+        assert!(normalize_hostname("example.com..").is_err());
+        assert!(normalize_hostname("exa/mple.com").is_err());
+    }
+
+    #[test]
+    fn test_punycode_rfc3492_samples() {
+        // Samples from RFC 3492 section 7.1 (lowercased)
+        assert_eq!(punycode_encode("bücher").unwrap(), "bcher-kva");
+        assert_eq!(punycode_encode("münchen").unwrap(), "mnchen-3ya");
+        assert_eq!(punycode_encode("他们为什么不说中文").unwrap(), "ihqwcrb4cv8a8dqg056pqjye");
+        assert_eq!(punycode_encode("ليهمابتكلموشعربي؟").unwrap(), "egbpdaj6bu4bxfgehfvwxn");
+        assert_eq!(punycode_encode("abc").unwrap(), "abc-");
+    }
+
+    #[test]
+    fn test_normalize_idn() {
+        assert_eq!(normalize_hostname("Bücher.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(normalize_hostname("MÜNCHEN.de").unwrap(), "xn--mnchen-3ya.de");
+        assert_eq!(normalize_hostname("例え。テスト").unwrap(), "xn--r8jz45g.xn--zckzah");
+    }
+
+    #[test]
+    fn test_parse_host_names_and_ports() {
+        assert_eq!(parse_host("example.com").unwrap(), (Host::Domain("example.com".to_string()), None));
+        assert_eq!(parse_host("Example.com:8443").unwrap(), (Host::Domain("example.com".to_string()), Some(8443)));
+        assert_eq!(parse_host("example.com.:80").unwrap(), (Host::Domain("example.com".to_string()), Some(80)));
+        assert!(parse_host("example.com:").is_err());
+        assert!(parse_host("example.com:99999").is_err());
+        assert!(parse_host("example.com:8o").is_err());
+        assert!(parse_host("bad_host:80").is_err());
+    }
+
+    #[test]
+    fn test_parse_host_ip_literals() {
+        let v4 = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        assert_eq!(parse_host("192.0.2.1").unwrap(), (Host::Ip(v4), None));
+        assert_eq!(parse_host("192.0.2.1:80").unwrap(), (Host::Ip(v4), Some(80)));
+        assert_eq!(parse_host("[::1]").unwrap(), (Host::Ip(v6), None));
+        assert_eq!(parse_host("[::1]:443").unwrap(), (Host::Ip(v6), Some(443)));
+        assert!(parse_host("[::1").is_err());
+        assert!(parse_host("[::1]443").is_err());
+        assert!(parse_host("[example.com]").is_err());
+        assert!(parse_host("::1").is_err());
+    }
+
+    #[test]
+    fn test_host_display() {
+        assert_eq!(Host::Domain("example.com".to_string()).to_string(), "example.com");
+        assert_eq!(Host::Ip(IpAddr::V6(Ipv6Addr::LOCALHOST)).to_string(), "[::1]");
+        assert_eq!(Host::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST)).to_string(), "127.0.0.1");
+    }
+}
@@ -62,7 +62,7 @@ impl HourlyStatsCollector {
 
         // Load existing data
         if let Err(e) = collector.load_stats() {
-            eprintln!("Warning: Failed to load existing stats: {}", e);
+            log::warn!(target: "stats", "failed to load existing stats: {}", e);
         }
 
         collector
@@ -358,7 +358,7 @@ impl HourlyStatsCollector {
             if let Some(stat) = HourlyStats::from_tsv_line(line) {
                 stats.push_back(stat);
             } else {
-                eprintln!("Warning: Failed to parse stats line: {}", line);
+                log::warn!(target: "stats", "failed to parse stats line: {}", line);
             }
         }
 
@@ -374,9 +374,9 @@ pub async fn start_stats_collection_task(collector: Arc<HourlyStatsCollector>) {
         interval.tick().await;
 
         if let Err(e) = collector.collect_current_stats() {
-            eprintln!("⚠️  Failed to collect hourly stats: {}", e);
-            eprintln!("ℹ️  Stats file: {}", collector.data_file);
-            eprintln!("ℹ️  This is not critical - the server will continue running normally");
+            log::warn!(target: "stats", "failed to collect hourly stats: {}", e);
+            log::info!(target: "stats", "stats file: {}", collector.data_file);
+            log::info!(target: "stats", "this is not critical - the server will continue running normally");
         }
     }
 }
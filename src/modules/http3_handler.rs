@@ -65,7 +65,7 @@ impl Http3Handler {
 
     /// Start accepting HTTP/3 connections
     pub async fn run(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        println!("🔍 Starting HTTP/3 server on UDP port {}", self.endpoint.local_addr()?.port());
+        log::debug!(target: "http", "starting HTTP/3 server on UDP port {}", self.endpoint.local_addr()?.port());
 
         let incoming = self.endpoint.accept();
         self.handle_incoming_connections(incoming).await
@@ -77,7 +77,7 @@ impl Http3Handler {
             let connection = connection.await?;
             let client_addr = connection.remote_address();
 
-            println!("🔍 New HTTP/3 connection from {}", client_addr);
+            log::debug!(target: "http", "new HTTP/3 connection from {}", client_addr);
 
             // Clone shared resources for this connection
             let file_server = Arc::clone(&self.file_server);
@@ -87,7 +87,7 @@ impl Http3Handler {
             // Spawn task to handle this connection
             tokio::spawn(async move {
                 if let Err(e) = Self::handle_connection(connection, file_server, stats_collector, security_config, client_addr).await {
-                    eprintln!("🔍 Error handling HTTP/3 connection from {}: {}", client_addr, e);
+                    log::warn!(target: "http", "error handling HTTP/3 connection from {}: {}", client_addr, e);
                 }
             });
         }
@@ -127,7 +127,7 @@ impl Http3Handler {
         let uri = request.uri().to_string();
         let headers = request.headers().clone();
 
-        println!("🔍 HTTP/3 {} {} from {}", method, uri, client_addr);
+        log::debug!(target: "http", "HTTP/3 {} {} from {}", method, uri, client_addr);
 
         // Extract domain from Host header
         let domain = extract_domain_from_host_header(&headers);
@@ -255,7 +255,7 @@ impl Http3Monitor {
 
                 // Log current stats
                 let stats = self.get_stats();
-                println!("🔍 HTTP/3 Monitor: Alt-Svc sent: {}, HTTP/3 connections: {}, Failures: {}, Timeouts: {}",
+                log::debug!(target: "http", "HTTP/3 Monitor: Alt-Svc sent: {}, HTTP/3 connections: {}, Failures: {}, Timeouts: {}",
                     stats.alt_svc_sent, stats.http3_connections, stats.http3_failures, stats.connection_timeouts);
             }
         });
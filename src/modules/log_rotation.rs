@@ -0,0 +1,445 @@
+//! Log Rotation
+//!
+//! This module keeps the server log and access logs from growing without bound.
+//! It provides:
+//! - Rotation when a file reaches a size limit, at the first write of each UTC day, or both
+//! - Numbered generations (`server.log.1` is the newest) with N-file retention
+//! - Optional gzip compression of rotated generations (`server.log.2.gz`)
+//! - Reopening on SIGUSR1, so an external logrotate can move files away
+//! - Reading rotated and compressed generations back for the log viewer
+//!
+//! Files are written through [`RotatingFile`], which checks the policy before each write.
+//! Rotation itself only renames; compression runs the system `gzip` on a background thread
+//! afterwards, and if gzip is missing the generation is kept uncompressed.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Bumped on every reopen request; each open file remembers the value it last saw
+static REOPEN_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Generations still being gzipped; rotation waits for them so it never moves a file gzip is reading
+static COMPRESSING: (Mutex<usize>, Condvar) = (Mutex::new(0), Condvar::new());
+
+/// When and how log files are rotated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// Rotate once the file would grow past this many bytes (None = no size limit)
+    pub max_size: Option<u64>,
+    /// Rotate at the first write of each new UTC day
+    pub daily: bool,
+    /// Rotated generations to keep (0 = discard on rotation)
+    pub keep: usize,
+    /// gzip rotated generations
+    pub compress: bool,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self {
+            max_size: Some(100 * 1024 * 1024),
+            daily: false,
+            keep: 7,
+            compress: true,
+        }
+    }
+}
+
+impl RotationPolicy {
+    /// Policy that never rotates, for setups where an external logrotate owns the files
+    pub fn never() -> Self {
+        Self { max_size: None, daily: false, ..Self::default() }
+    }
+
+    /// Whether this policy ever rotates
+    pub fn is_enabled(&self) -> bool {
+        self.max_size.is_some() || self.daily
+    }
+
+    /// Parse a size such as `1048576`, `512K`, `100M` or `1G`
+    ///
+    /// # Arguments
+    /// * `value` - Size with an optional K, M or G suffix (powers of 1024)
+    ///
+    /// # Returns
+    /// * `Result<u64, String>` - Size in bytes
+    pub fn parse_size(value: &str) -> Result<u64, String> {
+        let value = value.trim();
+        let (digits, multiplier) = match value.char_indices().last() {
+            Some((i, 'k' | 'K')) => (&value[..i], 1024),
+            Some((i, 'm' | 'M')) => (&value[..i], 1024 * 1024),
+            Some((i, 'g' | 'G')) => (&value[..i], 1024 * 1024 * 1024),
+            _ => (value, 1),
+        };
+        digits
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(multiplier))
+            .filter(|&n| n > 0)
+            .ok_or_else(|| format!("invalid log size '{}'", value))
+    }
+}
+
+/// Ask every open [`RotatingFile`] to reopen its path before its next write
+pub fn request_reopen() {
+    REOPEN_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Reopen log files whenever the process receives SIGUSR1
+///
+/// Must be called from within the tokio runtime.
+#[cfg(unix)]
+pub fn spawn_reopen_on_sigusr1() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut usr1 = match signal(SignalKind::user_defined1()) {
+        Ok(stream) => stream,
+        Err(e) => {
+            log::warn!(target: "server", "cannot listen for SIGUSR1, log reopen disabled: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while usr1.recv().await.is_some() {
+            log::info!(target: "server", "SIGUSR1 received, reopening log files");
+            request_reopen();
+        }
+    });
+}
+
+/// An append-only log file that rotates itself according to a [`RotationPolicy`]
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    day: u64,
+    reopen_seen: u64,
+    policy: RotationPolicy,
+}
+
+impl RotatingFile {
+    /// Open (or create) a log file for appending
+    ///
+    /// # Arguments
+    /// * `path` - Log file; missing parent directories are created
+    /// * `policy` - When to rotate
+    ///
+    /// # Returns
+    /// * `io::Result<Self>` - The open file
+    pub fn open(path: &Path, policy: RotationPolicy) -> io::Result<Self> {
+        let (file, size, day) = open_append(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            day,
+            reopen_seen: REOPEN_GENERATION.load(Ordering::SeqCst),
+            policy,
+        })
+    }
+
+    /// The path being written
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reopen the path, picking up a file created by an external rotation
+    pub fn reopen(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let (file, size, day) = open_append(&self.path)?;
+        self.file = file;
+        self.size = size;
+        self.day = day;
+        Ok(())
+    }
+
+    /// Move the current file to generation 1 and start a new one
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        rotate_generations(&self.path, &self.policy)?;
+        self.reopen()
+    }
+
+    fn needs_rotation(&self, incoming: usize) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let too_big = self
+            .policy
+            .max_size
+            .is_some_and(|max| self.size + incoming as u64 > max);
+        let new_day = self.policy.daily && current_day() != self.day;
+        too_big || new_day
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let requested = REOPEN_GENERATION.load(Ordering::SeqCst);
+        if requested != self.reopen_seen {
+            self.reopen_seen = requested;
+            self.reopen()?;
+        }
+        if self.needs_rotation(data.len()) {
+            // A failed rotation must not lose the line; keep appending to the current file
+            if let Err(e) = self.rotate() {
+                // For the server log itself this reaches stdout only; the file logger skips
+                // messages logged while it holds the file
+                log::warn!(target: "server", "log rotation of {} failed: {}", self.path.display(), e);
+                self.day = current_day();
+            }
+        }
+        let written = self.file.write(data)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn current_day() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 86400).unwrap_or(0)
+}
+
+/// Open for append and report the current size and the UTC day it was last written
+fn open_append(path: &Path) -> io::Result<(File, u64, u64)> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let metadata = file.metadata()?;
+    // A file left over from an earlier day is rotated on the first write under a daily policy
+    let day = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or_else(current_day);
+    Ok((file, metadata.len(), day))
+}
+
+/// Path of generation `n`, compressed or not
+fn generation_path(path: &Path, n: usize, compressed: bool) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", n));
+    if compressed {
+        name.push(".gz");
+    }
+    PathBuf::from(name)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Shift `path.1 .. path.N` up by one, move `path` to `path.1` and drop what exceeds `keep`
+///
+/// # Arguments
+/// * `path` - Live log file
+/// * `policy` - Retention and compression settings
+///
+/// # Returns
+/// * `io::Result<()>` - Error if a rename fails
+pub fn rotate_generations(path: &Path, policy: &RotationPolicy) -> io::Result<()> {
+    wait_for_compression();
+    if !path.exists() {
+        return Ok(());
+    }
+    if policy.keep == 0 {
+        return remove_if_exists(path);
+    }
+
+    // Drop the oldest kept generation and anything left from a larger earlier keep
+    let mut n = policy.keep;
+    loop {
+        let plain = generation_path(path, n, false);
+        let gz = generation_path(path, n, true);
+        if n > policy.keep && !plain.exists() && !gz.exists() {
+            break;
+        }
+        remove_if_exists(&plain)?;
+        remove_if_exists(&gz)?;
+        n += 1;
+    }
+
+    for n in (1..policy.keep).rev() {
+        for compressed in [false, true] {
+            let from = generation_path(path, n, compressed);
+            if from.exists() {
+                std::fs::rename(&from, generation_path(path, n + 1, compressed))?;
+            }
+        }
+    }
+
+    let first = generation_path(path, 1, false);
+    std::fs::rename(path, &first)?;
+    if policy.compress {
+        compress_in_background(first);
+    }
+    Ok(())
+}
+
+/// Block until every rotated generation handed to gzip has been compressed
+///
+/// Rotation calls this before moving generations; it only waits when rotations come
+/// faster than gzip finishes.
+pub fn wait_for_compression() {
+    let (pending, done) = &COMPRESSING;
+    let pending = pending.lock().unwrap_or_else(|e| e.into_inner());
+    drop(done.wait_while(pending, |pending| *pending > 0));
+}
+
+/// gzip a rotated generation on its own thread, so the writer that rotated is not held up
+fn compress_in_background(path: PathBuf) {
+    let finished = || {
+        let (pending, done) = &COMPRESSING;
+        *pending.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        done.notify_all();
+    };
+    *COMPRESSING.0.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+    let spawned = std::thread::Builder::new().name("log-gzip".to_string()).spawn(move || {
+        compress(&path);
+        finished();
+    });
+    if let Err(e) = spawned {
+        log::warn!(target: "server", "cannot start gzip thread, rotated log left uncompressed: {}", e);
+        finished();
+    }
+}
+
+/// gzip a rotated generation in place, leaving it uncompressed if gzip fails
+fn compress(path: &Path) {
+    match Command::new("gzip").arg("-f").arg("-q").arg(path).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => log::warn!(target: "server", "gzip of {} exited with {}", path.display(), status),
+        Err(e) => log::warn!(target: "server", "cannot run gzip for {}: {}", path.display(), e),
+    }
+}
+
+/// Existing rotated generations of a log file, newest first
+///
+/// # Arguments
+/// * `path` - Live log file
+///
+/// # Returns
+/// * `Vec<PathBuf>` - `path.1`, `path.2.gz`, ... up to the first missing generation
+pub fn rotated_generations(path: &Path) -> Vec<PathBuf> {
+    let mut generations = Vec::new();
+    for n in 1.. {
+        let plain = generation_path(path, n, false);
+        let gz = generation_path(path, n, true);
+        if plain.exists() {
+            generations.push(plain);
+        } else if gz.exists() {
+            generations.push(gz);
+        } else {
+            break;
+        }
+    }
+    generations
+}
+
+/// Read a log file, decompressing `.gz` generations with `gzip -dc`
+///
+/// # Arguments
+/// * `path` - Live or rotated log file
+///
+/// # Returns
+/// * `io::Result<String>` - File contents, invalid UTF-8 replaced
+pub fn read_log_file(path: &Path) -> io::Result<String> {
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        let output = Command::new("gzip").arg("-dc").arg(path).output()?;
+        if !output.status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("gzip -dc {} exited with {}", path.display(), output.status),
+            ));
+        }
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+    let bytes = std::fs::read(path)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_size: u64, keep: usize) -> RotationPolicy {
+        RotationPolicy { max_size: Some(max_size), daily: false, keep, compress: false }
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(RotationPolicy::parse_size("4096").unwrap(), 4096);
+        assert_eq!(RotationPolicy::parse_size("512K").unwrap(), 512 * 1024);
+        assert_eq!(RotationPolicy::parse_size("100m").unwrap(), 100 * 1024 * 1024);
+        assert_eq!(RotationPolicy::parse_size("1G").unwrap(), 1024 * 1024 * 1024);
+        assert!(RotationPolicy::parse_size("0").is_err());
+        assert!(RotationPolicy::parse_size("big").is_err());
+    }
+
+    #[test]
+    fn test_size_rotation_and_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.log");
+        let mut file = RotatingFile::open(&path, policy(10, 2)).unwrap();
+        for line in ["aaaaaaa\n", "bbbbbbb\n", "ccccccc\n", "ddddddd\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ddddddd\n");
+        assert_eq!(
+            rotated_generations(&path),
+            vec![generation_path(&path, 1, false), generation_path(&path, 2, false)]
+        );
+        assert_eq!(read_log_file(&generation_path(&path, 1, false)).unwrap(), "ccccccc\n");
+        assert_eq!(read_log_file(&generation_path(&path, 2, false)).unwrap(), "bbbbbbb\n");
+        assert!(!generation_path(&path, 3, false).exists());
+    }
+
+    #[test]
+    fn test_reopen_after_external_move() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let mut file = RotatingFile::open(&path, RotationPolicy::never()).unwrap();
+        file.write_all(b"before\n").unwrap();
+
+        std::fs::rename(&path, dir.path().join("access.log.old")).unwrap();
+        request_reopen();
+        file.write_all(b"after\n").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "after\n");
+        assert_eq!(std::fs::read_to_string(dir.path().join("access.log.old")).unwrap(), "before\n");
+    }
+
+    #[test]
+    fn test_compressed_generations() {
+        if Command::new("gzip").arg("--version").output().is_err() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.log");
+        let mut file = RotatingFile::open(&path, RotationPolicy { compress: true, ..policy(4, 3) }).unwrap();
+        file.write_all(b"one\n").unwrap();
+        file.write_all(b"two\n").unwrap();
+        file.write_all(b"three\n").unwrap();
+        wait_for_compression();
+
+        let generations = rotated_generations(&path);
+        assert_eq!(generations, vec![generation_path(&path, 1, true), generation_path(&path, 2, true)]);
+        assert_eq!(read_log_file(&generations[0]).unwrap(), "two\n");
+        assert_eq!(read_log_file(&generations[1]).unwrap(), "one\n");
+    }
+}
@@ -0,0 +1,459 @@
+//! Structured Logging
+//!
+//! This module is the `log` backend for the whole server. It provides:
+//! - Per-subsystem targets (`tls`, `acme`, `http`, `files`, `extensions`, `admin`, ...)
+//!   each with its own level, set from a spec like `info,tls=debug,acme=trace`
+//! - `key=value` fields from the `log` macros (`info!(target: "tls", domain = name; "...")`)
+//! - Plain text or JSON lines on stdout; the server log file always gets the text form
+//! - Level changes at runtime from the logs admin panel or SIGUSR2 (toggle debug)
+//! - Redaction of secrets: fields named like keys, tokens or passwords, and admin
+//!   keys embedded in admin panel paths, are never written out
+//! - [`subscribe`]: a live feed of the lines written, for the logs admin WebSocket tail
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use tokio::sync::broadcast;
+
+use crate::file_logger::write_file_log;
+
+/// Subsystem targets used by the server, in the order the admin panel lists them
+pub const TARGETS: &[&str] = &[
+    "server", "tls", "acme", "http", "files", "proxy", "fastcgi", "cgi", "websocket", "extensions", "admin", "stats",
+];
+
+const REDACTED: &str = "[redacted]";
+
+/// Lines buffered per live subscriber before it starts missing some
+const TAIL_CAPACITY: usize = 1024;
+
+/// One line as written, for live subscribers
+#[derive(Debug, Clone)]
+pub struct TailLine {
+    pub level: Level,
+    pub target: String,
+    /// The line in JSON form, secrets already redacted
+    pub json: String,
+}
+
+static TAIL: OnceLock<broadcast::Sender<TailLine>> = OnceLock::new();
+
+fn tail() -> &'static broadcast::Sender<TailLine> {
+    TAIL.get_or_init(|| broadcast::channel(TAIL_CAPACITY).0)
+}
+
+/// Receive every line logged from now on
+///
+/// A subscriber that falls more than [`TAIL_CAPACITY`] lines behind gets
+/// `RecvError::Lagged` with the number of lines it missed.
+pub fn subscribe() -> broadcast::Receiver<TailLine> {
+    tail().subscribe()
+}
+
+/// Hand a line to live subscribers, if there are any
+fn publish(level: Level, target: &str, json: impl FnOnce() -> String) {
+    let sender = tail();
+    if sender.receiver_count() > 0 {
+        let _ = sender.send(TailLine { level, target: target.to_string(), json: json() });
+    }
+}
+
+/// How lines are written to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogOutput {
+    /// `INFO [tls] message key=value`
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl LogOutput {
+    /// Parse `text` or `json`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "text" => Ok(LogOutput::Text),
+            "json" => Ok(LogOutput::Json),
+            other => Err(format!("unknown log format '{}' (expected text or json)", other)),
+        }
+    }
+}
+
+/// A default level plus per-target overrides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLevels {
+    default: LevelFilter,
+    targets: BTreeMap<String, LevelFilter>,
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        Self { default: LevelFilter::Info, targets: BTreeMap::new() }
+    }
+}
+
+impl LogLevels {
+    /// Parse a level spec such as `info`, `tls=debug` or `warn,acme=trace,files=off`
+    ///
+    /// # Arguments
+    /// * `spec` - Comma-separated bare default level and `target=level` pairs
+    ///
+    /// # Returns
+    /// * `Result<Self, String>` - Parsed levels, error on an unknown level name
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut levels = LogLevels::default();
+        for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            match part.split_once('=') {
+                Some((target, level)) => {
+                    levels.targets.insert(target.trim().to_string(), parse_level(level)?);
+                }
+                None => levels.default = parse_level(part)?,
+            }
+        }
+        Ok(levels)
+    }
+
+    /// Same levels, with the default replaced
+    pub fn with_default(mut self, level: LevelFilter) -> Self {
+        self.default = level;
+        self
+    }
+
+    /// Level for targets without an override
+    pub fn default_level(&self) -> LevelFilter {
+        self.default
+    }
+
+    /// Level in force for a target; `easyp::tls::acceptor` falls back to `tls`
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let mut name = target;
+        loop {
+            if let Some(level) = self.targets.get(name) {
+                return *level;
+            }
+            match name.rfind("::") {
+                Some(pos) => name = &name[..pos],
+                None => break,
+            }
+        }
+        // Module paths like `easyp::upstream` map onto their last segment
+        match target.rsplit("::").next().and_then(|last| self.targets.get(last)) {
+            Some(level) => *level,
+            None => self.default,
+        }
+    }
+
+    /// Most verbose level of any target, for `log::set_max_level`
+    pub fn max_level(&self) -> LevelFilter {
+        self.targets.values().copied().fold(self.default, std::cmp::max)
+    }
+
+    /// Back to spec form (`info,tls=debug`)
+    pub fn to_spec(&self) -> String {
+        let mut spec = self.default.to_string().to_lowercase();
+        for (target, level) in &self.targets {
+            let _ = write!(spec, ",{}={}", target, level.to_string().to_lowercase());
+        }
+        spec
+    }
+}
+
+fn parse_level(value: &str) -> Result<LevelFilter, String> {
+    value.trim().parse().map_err(|_| format!("unknown log level '{}'", value.trim()))
+}
+
+/// The `log` implementation installed by [`init_logging`]
+struct StructuredLogger {
+    levels: RwLock<LogLevels>,
+    output: LogOutput,
+    // Levels to restore when SIGUSR2 toggles debug off again
+    saved: Mutex<Option<LogLevels>>,
+}
+
+static LOGGER: OnceLock<StructuredLogger> = OnceLock::new();
+
+impl Log for StructuredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let levels = self.levels.read().unwrap_or_else(|e| e.into_inner());
+        metadata.level() <= levels.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let target = short_target(record.target());
+        let message = redact_admin_keys(&record.args().to_string());
+        let mut fields = Fields::default();
+        let _ = record.key_values().visit(&mut fields);
+
+        let text = format!("[{}] {}{}", target, message, fields.text());
+        match self.output {
+            LogOutput::Text => println!("{} {}", record.level(), text),
+            LogOutput::Json => println!("{}", json_line(record.level(), target, &message, &fields)),
+        }
+        write_file_log(&record.level().to_string(), &text);
+        publish(record.level(), target, || json_line(record.level(), target, &message, &fields));
+    }
+
+    fn flush(&self) {}
+}
+
+/// `easyp::upstream` -> `upstream`; explicit targets are kept as they are
+fn short_target(target: &str) -> &str {
+    target.rsplit("::").next().unwrap_or(target)
+}
+
+/// Collected `key=value` fields of one record, secrets already redacted
+#[derive(Default)]
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let key = key.as_str().to_string();
+        let value = if is_secret_key(&key) { REDACTED.to_string() } else { redact_admin_keys(&value.to_string()) };
+        self.0.push((key, value));
+        Ok(())
+    }
+}
+
+impl Fields {
+    fn text(&self) -> String {
+        let mut out = String::new();
+        for (key, value) in &self.0 {
+            if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+                let _ = write!(out, " {}=\"{}\"", key, value.replace('\\', "\\\\").replace('"', "\\\""));
+            } else {
+                let _ = write!(out, " {}={}", key, value);
+            }
+        }
+        out
+    }
+}
+
+fn json_line(level: Level, target: &str, message: &str, fields: &Fields) -> String {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    let mut line = format!(
+        "{{\"ts\":{},\"level\":\"{}\",\"target\":\"{}\",\"msg\":\"{}\"",
+        timestamp,
+        level,
+        json_escape(target),
+        json_escape(message)
+    );
+    for (key, value) in &fields.0 {
+        let _ = write!(line, ",\"{}\":\"{}\"", json_escape(key), json_escape(value));
+    }
+    line.push('}');
+    line
+}
+
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether a field name suggests its value is a credential
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_lowercase();
+    ["key", "token", "secret", "password", "passwd", "authorization", "cookie", "signature"]
+        .iter()
+        .any(|word| key.contains(word))
+}
+
+/// Hide admin keys in admin panel paths (`/logs_0123456789abcdef` -> `/logs_[redacted]`)
+///
+/// # Arguments
+/// * `text` - Message or field value that may contain an admin path
+///
+/// # Returns
+/// * `String` - The text with every `_` + 16 hex digit key replaced
+pub fn redact_admin_keys(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let is_key = bytes[i] == b'_'
+            && i + 17 <= bytes.len()
+            && bytes[i + 1..i + 17].iter().all(u8::is_ascii_hexdigit)
+            && bytes.get(i + 17).is_none_or(|b| !b.is_ascii_alphanumeric());
+        if is_key {
+            out.push_str(&text[last..=i]);
+            out.push_str(REDACTED);
+            i += 17;
+            last = i;
+        } else {
+            i += 1;
+        }
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// Install the structured logger as the `log` backend
+///
+/// # Arguments
+/// * `levels` - Initial default and per-target levels
+/// * `output` - Text or JSON lines on stdout
+///
+/// # Returns
+/// * `Result<(), String>` - Error if a logger is already installed
+pub fn init_logging(levels: LogLevels, output: LogOutput) -> Result<(), String> {
+    log::set_max_level(levels.max_level());
+    let logger = LOGGER.get_or_init(|| StructuredLogger {
+        levels: RwLock::new(levels),
+        output,
+        saved: Mutex::new(None),
+    });
+    log::set_logger(logger).map_err(|e| format!("cannot install logger: {}", e))
+}
+
+/// Current levels in spec form, e.g. `info,tls=debug`
+pub fn current_levels() -> LogLevels {
+    LOGGER
+        .get()
+        .map(|logger| logger.levels.read().unwrap_or_else(|e| e.into_inner()).clone())
+        .unwrap_or_default()
+}
+
+/// Replace all levels at runtime
+pub fn set_levels(levels: LogLevels) {
+    if let Some(logger) = LOGGER.get() {
+        log::set_max_level(levels.max_level());
+        *logger.levels.write().unwrap_or_else(|e| e.into_inner()) = levels;
+    }
+}
+
+/// Change the level of one target at runtime (`*` changes the default)
+///
+/// # Arguments
+/// * `target` - Subsystem target, or `*` for the default level
+/// * `level` - Level name: off, error, warn, info, debug or trace
+///
+/// # Returns
+/// * `Result<(), String>` - Error on an unknown level name
+pub fn set_target_level(target: &str, level: &str) -> Result<(), String> {
+    let level = parse_level(level)?;
+    let mut levels = current_levels();
+    if target == "*" {
+        levels.default = level;
+    } else {
+        levels.targets.insert(target.to_string(), level);
+    }
+    set_levels(levels);
+    log::info!(target: "server", subsystem = target, level:% = level; "log level changed");
+    Ok(())
+}
+
+/// Switch every target to debug, or back to the levels in force before
+pub fn toggle_debug() {
+    let Some(logger) = LOGGER.get() else {
+        return;
+    };
+    let restored = logger.saved.lock().unwrap_or_else(|e| e.into_inner()).take();
+    match restored {
+        Some(levels) => {
+            set_levels(levels);
+            log::info!(target: "server", spec:% = current_levels().to_spec(); "debug logging off");
+        }
+        None => {
+            let levels = current_levels();
+            *logger.saved.lock().unwrap_or_else(|e| e.into_inner()) = Some(levels);
+            set_levels(LogLevels::default().with_default(LevelFilter::Debug));
+            log::info!(target: "server", "debug logging on for all targets");
+        }
+    }
+}
+
+/// Toggle debug logging whenever the process receives SIGUSR2
+///
+/// Must be called from within the tokio runtime.
+#[cfg(unix)]
+pub fn spawn_debug_toggle_on_sigusr2() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut usr2 = match signal(SignalKind::user_defined2()) {
+        Ok(stream) => stream,
+        Err(e) => {
+            log::warn!(target: "server", "cannot listen for SIGUSR2, runtime debug toggle disabled: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while usr2.recv().await.is_some() {
+            toggle_debug();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_spec() {
+        let levels = LogLevels::parse("warn, tls=debug,acme=trace").unwrap();
+        assert_eq!(levels.level_for("tls"), LevelFilter::Debug);
+        assert_eq!(levels.level_for("tls::handshake"), LevelFilter::Debug);
+        assert_eq!(levels.level_for("easyp::acme"), LevelFilter::Trace);
+        assert_eq!(levels.level_for("files"), LevelFilter::Warn);
+        assert_eq!(levels.max_level(), LevelFilter::Trace);
+        assert_eq!(levels.to_spec(), "warn,acme=trace,tls=debug");
+        assert!(LogLevels::parse("tls=loud").is_err());
+    }
+
+    #[test]
+    fn test_redaction() {
+        assert_eq!(
+            redact_admin_keys("admin request for /logs_0123456789abcdef?limit=5"),
+            "admin request for /logs_[redacted]?limit=5"
+        );
+        assert_eq!(redact_admin_keys("/upload_0123456789abcdef0"), "/upload_0123456789abcdef0");
+        assert_eq!(redact_admin_keys("plain_text"), "plain_text");
+        assert!(is_secret_key("admin_key"));
+        assert!(is_secret_key("Authorization"));
+        assert!(!is_secret_key("domain"));
+    }
+
+    #[test]
+    fn test_tail() {
+        publish(Level::Info, "tls", || unreachable!("formatted without subscribers"));
+        let mut lines = subscribe();
+        publish(Level::Warn, "acme", || "{\"msg\":\"renewal failed\"}".to_string());
+        let line = lines.try_recv().unwrap();
+        assert_eq!((line.level, line.target.as_str(), line.json.as_str()), (Level::Warn, "acme", "{\"msg\":\"renewal failed\"}"));
+        assert!(lines.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_fields_and_json() {
+        let mut fields = Fields::default();
+        fields.visit_pair(Key::from_str("domain"), Value::from("example.com")).unwrap();
+        fields.visit_pair(Key::from_str("token"), Value::from("abc")).unwrap();
+        fields.visit_pair(Key::from_str("path"), Value::from("/a b")).unwrap();
+        assert_eq!(fields.text(), " domain=example.com token=[redacted] path=\"/a b\"");
+        assert_eq!(
+            json_line(Level::Info, "tls", "hi \"x\"", &fields)
+                .split_once(",\"level\"")
+                .unwrap()
+                .1,
+            ":\"INFO\",\"target\":\"tls\",\"msg\":\"hi \\\"x\\\"\",\"domain\":\"example.com\",\"token\":\"[redacted]\",\"path\":\"/a b\"}"
+        );
+    }
+}
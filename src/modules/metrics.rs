@@ -0,0 +1,445 @@
+//! Prometheus Metrics
+//!
+//! This module keeps process-wide counters and renders them in the Prometheus text
+//! exposition format (version 0.0.4). It provides:
+//! - Requests by domain, method and status class, bytes sent per domain and a request
+//!   latency histogram (fed by the access log's `AccessRecorder`)
+//! - Active connections per transport, TLS handshakes (by version, full or resumed) and
+//!   handshake failures by reason
+//! - ACME HTTP-01 validations answered (orders are placed inside the rustls-acme
+//!   resolver, so each answered validation stands for one order attempt)
+//! - Certificate expiry per domain, extension invocations and errors, cache hits/misses
+//! - A small plain-HTTP listener serving `GET /metrics` (`--metrics-listen`)
+//!
+//! Domains are capped at [`MAX_DOMAINS`] label values; later ones are counted as `other`
+//! so a flood of random Host headers cannot grow memory without bound.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Upper bounds (seconds) of the request latency histogram buckets
+pub const LATENCY_BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+/// Distinct domain label values kept before new domains are counted as `other`
+pub const MAX_DOMAINS: usize = 1000;
+
+const METHODS: [&str; 9] = ["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH", "CONNECT", "TRACE"];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct Metrics {
+    requests: Mutex<BTreeMap<(String, &'static str, &'static str), u64>>,
+    bytes_sent: Mutex<BTreeMap<String, u64>>,
+    latency: Histogram,
+    connections: Mutex<BTreeMap<&'static str, (u64, i64)>>,
+    tls_handshakes: AtomicU64,
+    tls_sessions: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+    tls_failures: Mutex<BTreeMap<&'static str, u64>>,
+    acme_challenges: Mutex<BTreeMap<&'static str, u64>>,
+    certificate_expiry: Mutex<BTreeMap<String, i64>>,
+    extensions: Mutex<BTreeMap<String, (u64, u64)>>,
+    cache: Mutex<BTreeMap<&'static str, (u64, u64)>>,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+static STARTED: OnceLock<SystemTime> = OnceLock::new();
+
+fn metrics() -> &'static Metrics {
+    STARTED.get_or_init(SystemTime::now);
+    METRICS.get_or_init(Metrics::default)
+}
+
+/// Label value for a request host, folding unknown and excess domains
+fn domain_label(host: Option<&str>, known: &BTreeMap<String, u64>) -> String {
+    let Some(host) = host.filter(|h| !h.is_empty()) else {
+        return "-".to_string();
+    };
+    let host = host.to_lowercase();
+    if known.contains_key(&host) || known.len() < MAX_DOMAINS {
+        host
+    } else {
+        "other".to_string()
+    }
+}
+
+fn method_label(method: &str) -> &'static str {
+    METHODS.iter().find(|m| **m == method).copied().unwrap_or("OTHER")
+}
+
+fn status_class(status: u16) -> &'static str {
+    match status {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+/// Count one finished request
+///
+/// # Arguments
+/// * `host` - Host the request was for (None if it had no Host header)
+/// * `method` - Request method
+/// * `status` - Response status code
+/// * `bytes` - Response body bytes sent
+/// * `duration` - Time from the first request byte to the last response byte
+pub fn record_request(host: Option<&str>, method: &str, status: u16, bytes: u64, duration: Duration) {
+    let metrics = metrics();
+    {
+        let mut bytes_sent = metrics.bytes_sent.lock().unwrap_or_else(|e| e.into_inner());
+        let domain = domain_label(host, &bytes_sent);
+        *bytes_sent.entry(domain.clone()).or_insert(0) += bytes;
+        let mut requests = metrics.requests.lock().unwrap_or_else(|e| e.into_inner());
+        *requests.entry((domain, method_label(method), status_class(status))).or_insert(0) += 1;
+    }
+    metrics.latency.observe(duration);
+}
+
+/// Marks a connection as active until dropped
+pub struct ConnectionGuard {
+    transport: &'static str,
+}
+
+/// Count a new client connection on a transport (`http`, `https`, ...)
+pub fn track_connection(transport: &'static str) -> ConnectionGuard {
+    let mut connections = metrics().connections.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = connections.entry(transport).or_insert((0, 0));
+    entry.0 += 1;
+    entry.1 += 1;
+    ConnectionGuard { transport }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = metrics().connections.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = connections.get_mut(self.transport) {
+            entry.1 -= 1;
+        }
+    }
+}
+
+/// Count a completed TLS handshake by protocol version and kind
+///
+/// # Arguments
+/// * `version` - `"1.2"` or `"1.3"`
+/// * `resumed` - Whether an earlier session was resumed
+pub fn record_tls_session(version: &'static str, resumed: bool) {
+    let kind = if resumed { "resumed" } else { "full" };
+    *metrics().tls_sessions.lock().unwrap_or_else(|e| e.into_inner()).entry((version, kind)).or_insert(0) += 1;
+}
+
+/// Count a completed TLS handshake
+pub fn record_tls_handshake() {
+    metrics().tls_handshakes.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count a failed TLS handshake
+pub fn record_tls_failure(error: &std::io::Error) {
+    let reason = tls_failure_reason(error);
+    *metrics().tls_failures.lock().unwrap_or_else(|e| e.into_inner()).entry(reason).or_insert(0) += 1;
+}
+
+/// Short, bounded reason label for a handshake error
+pub fn tls_failure_reason(error: &std::io::Error) -> &'static str {
+    use std::io::ErrorKind;
+
+    match error.kind() {
+        ErrorKind::UnexpectedEof => return "eof",
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => return "reset",
+        ErrorKind::TimedOut => return "timeout",
+        _ => {}
+    }
+    let message = error.to_string().to_lowercase();
+    if message.contains("no server certificate") || message.contains("no certificate") {
+        "no_certificate"
+    } else if message.contains("protocol version") || message.contains("protocolversion") {
+        "protocol_version"
+    } else if message.contains("no cipher") || message.contains("handshakefailure") || message.contains("handshake failure") {
+        "no_shared_cipher"
+    } else if message.contains("alert") {
+        "client_alert"
+    } else if message.contains("corrupt") || message.contains("invalid") || message.contains("decode") {
+        "bad_message"
+    } else {
+        "other"
+    }
+}
+
+/// Count an ACME HTTP-01 validation request (`found` = a token matched)
+pub fn record_acme_challenge(found: bool) {
+    let result = if found { "served" } else { "not_found" };
+    *metrics().acme_challenges.lock().unwrap_or_else(|e| e.into_inner()).entry(result).or_insert(0) += 1;
+}
+
+/// Record when a domain's certificate expires (Unix seconds)
+pub fn set_certificate_expiry(domain: &str, not_after: i64) {
+    metrics()
+        .certificate_expiry
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(domain.to_lowercase(), not_after);
+}
+
+/// Count an extension call (`error` = it returned an error)
+pub fn record_extension(name: &str, error: bool) {
+    let mut extensions = metrics().extensions.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = extensions.entry(name.to_string()).or_insert((0, 0));
+    entry.0 += 1;
+    if error {
+        entry.1 += 1;
+    }
+}
+
+/// Count a lookup in one of the server's caches
+pub fn record_cache(cache: &'static str, hit: bool) {
+    let mut caches = metrics().cache.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = caches.entry(cache).or_insert((0, 0));
+    if hit {
+        entry.0 += 1;
+    } else {
+        entry.1 += 1;
+    }
+}
+
+/// Escape a label value for the text format
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Render every metric in the Prometheus text format
+pub fn render() -> String {
+    let metrics = metrics();
+    let mut out = String::new();
+
+    header(&mut out, "easyp_requests_total", "counter", "HTTP requests by domain, method and status class.");
+    for ((domain, method, class), count) in metrics.requests.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let _ = writeln!(
+            out,
+            "easyp_requests_total{{domain=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+            label(domain),
+            method,
+            class,
+            count
+        );
+    }
+
+    header(&mut out, "easyp_response_bytes_total", "counter", "Response body bytes sent by domain.");
+    for (domain, bytes) in metrics.bytes_sent.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let _ = writeln!(out, "easyp_response_bytes_total{{domain=\"{}\"}} {}", label(domain), bytes);
+    }
+
+    header(&mut out, "easyp_request_duration_seconds", "histogram", "Time from first request byte to last response byte.");
+    for (bucket, bound) in metrics.latency.buckets.iter().zip(LATENCY_BUCKETS) {
+        let _ = writeln!(
+            out,
+            "easyp_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+            bound,
+            bucket.load(Ordering::Relaxed)
+        );
+    }
+    let count = metrics.latency.count.load(Ordering::Relaxed);
+    let _ = writeln!(out, "easyp_request_duration_seconds_bucket{{le=\"+Inf\"}} {}", count);
+    let _ = writeln!(
+        out,
+        "easyp_request_duration_seconds_sum {}",
+        metrics.latency.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    );
+    let _ = writeln!(out, "easyp_request_duration_seconds_count {}", count);
+
+    let connections = metrics.connections.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    header(&mut out, "easyp_connections_total", "counter", "Client connections accepted by transport.");
+    for (transport, (total, _)) in &connections {
+        let _ = writeln!(out, "easyp_connections_total{{transport=\"{}\"}} {}", transport, total);
+    }
+    header(&mut out, "easyp_active_connections", "gauge", "Client connections currently open by transport.");
+    for (transport, (_, active)) in &connections {
+        let _ = writeln!(out, "easyp_active_connections{{transport=\"{}\"}} {}", transport, active);
+    }
+
+    header(&mut out, "easyp_tls_handshakes_total", "counter", "Completed TLS handshakes.");
+    let _ = writeln!(out, "easyp_tls_handshakes_total {}", metrics.tls_handshakes.load(Ordering::Relaxed));
+    header(&mut out, "easyp_tls_sessions_total", "counter", "Completed TLS handshakes by protocol version and kind.");
+    for ((version, kind), count) in metrics.tls_sessions.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let _ = writeln!(out, "easyp_tls_sessions_total{{version=\"{}\",kind=\"{}\"}} {}", version, kind, count);
+    }
+    header(&mut out, "easyp_tls_handshake_failures_total", "counter", "Failed TLS handshakes by reason.");
+    for (reason, count) in metrics.tls_failures.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let _ = writeln!(out, "easyp_tls_handshake_failures_total{{reason=\"{}\"}} {}", reason, count);
+    }
+
+    header(&mut out, "easyp_acme_challenges_total", "counter", "ACME HTTP-01 validation requests by result.");
+    for (result, count) in metrics.acme_challenges.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let _ = writeln!(out, "easyp_acme_challenges_total{{result=\"{}\"}} {}", result, count);
+    }
+
+    header(&mut out, "easyp_certificate_expiry_timestamp_seconds", "gauge", "Certificate notAfter time by domain.");
+    for (domain, not_after) in metrics.certificate_expiry.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let _ = writeln!(out, "easyp_certificate_expiry_timestamp_seconds{{domain=\"{}\"}} {}", label(domain), not_after);
+    }
+
+    let extensions = metrics.extensions.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    header(&mut out, "easyp_extension_calls_total", "counter", "Extension invocations by extension.");
+    for (name, (calls, _)) in &extensions {
+        let _ = writeln!(out, "easyp_extension_calls_total{{extension=\"{}\"}} {}", label(name), calls);
+    }
+    header(&mut out, "easyp_extension_errors_total", "counter", "Extension invocations that returned an error.");
+    for (name, (_, errors)) in &extensions {
+        let _ = writeln!(out, "easyp_extension_errors_total{{extension=\"{}\"}} {}", label(name), errors);
+    }
+
+    let caches = metrics.cache.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    header(&mut out, "easyp_cache_hits_total", "counter", "Cache lookups answered from the cache.");
+    for (cache, (hits, _)) in &caches {
+        let _ = writeln!(out, "easyp_cache_hits_total{{cache=\"{}\"}} {}", cache, hits);
+    }
+    header(&mut out, "easyp_cache_misses_total", "counter", "Cache lookups that missed.");
+    for (cache, (_, misses)) in &caches {
+        let _ = writeln!(out, "easyp_cache_misses_total{{cache=\"{}\"}} {}", cache, misses);
+    }
+
+    let started = STARTED.get().copied().unwrap_or(UNIX_EPOCH);
+    header(&mut out, "easyp_start_time_seconds", "gauge", "Unix time the server started.");
+    let _ = writeln!(
+        out,
+        "easyp_start_time_seconds {}",
+        started.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    );
+    out
+}
+
+/// Serve `GET /metrics` on a separate plain-HTTP listener
+///
+/// # Arguments
+/// * `addr` - Address to bind, e.g. 127.0.0.1:9100
+///
+/// # Returns
+/// * `std::io::Result<()>` - Error if the address cannot be bound; otherwise runs forever
+pub async fn run_metrics_listener(addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log::info!(target: "server", "metrics listener on http://{}/metrics", addr);
+    metrics();
+    loop {
+        let (mut stream, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log::warn!(target: "server", "metrics accept error: {}", e);
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 2048];
+            let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer)).await;
+            let Ok(Ok(n)) = read else {
+                return;
+            };
+            let request = String::from_utf8_lossy(&buffer[..n]);
+            let mut parts = request.split_whitespace();
+            let response = match (parts.next(), parts.next().map(|t| t.split('?').next().unwrap_or(t))) {
+                (Some("GET"), Some("/metrics")) => {
+                    let body = render();
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                }
+                _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(status_class(204), "2xx");
+        assert_eq!(status_class(499), "4xx");
+        assert_eq!(method_label("GET"), "GET");
+        assert_eq!(method_label("BREW"), "OTHER");
+        assert_eq!(label("a\"b\\c"), "a\\\"b\\\\c");
+
+        let mut known = BTreeMap::new();
+        assert_eq!(domain_label(Some("Example.COM"), &known), "example.com");
+        assert_eq!(domain_label(None, &known), "-");
+        for i in 0..MAX_DOMAINS {
+            known.insert(format!("d{}.test", i), 0);
+        }
+        assert_eq!(domain_label(Some("d1.test"), &known), "d1.test");
+        assert_eq!(domain_label(Some("new.test"), &known), "other");
+    }
+
+    #[test]
+    fn test_tls_failure_reason() {
+        use std::io::{Error, ErrorKind};
+        assert_eq!(tls_failure_reason(&Error::new(ErrorKind::UnexpectedEof, "eof")), "eof");
+        assert_eq!(
+            tls_failure_reason(&Error::new(ErrorKind::InvalidData, "peer is incompatible: ProtocolVersion")),
+            "protocol_version"
+        );
+        assert_eq!(
+            tls_failure_reason(&Error::new(ErrorKind::InvalidData, "received fatal alert: CertificateUnknown")),
+            "client_alert"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        record_request(Some("metrics-test.example"), "GET", 200, 1234, Duration::from_millis(3));
+        record_request(Some("metrics-test.example"), "GET", 404, 10, Duration::from_millis(30));
+        {
+            let _connection = track_connection("metrics-test");
+            record_extension("metrics_test", true);
+            record_cache("metrics-test", true);
+            set_certificate_expiry("Metrics-Test.example", 1_900_000_000);
+            record_tls_session("1.3", true);
+            let text = render();
+            assert!(text.contains("easyp_requests_total{domain=\"metrics-test.example\",method=\"GET\",status=\"2xx\"} 1"));
+            assert!(text.contains("easyp_requests_total{domain=\"metrics-test.example\",method=\"GET\",status=\"4xx\"} 1"));
+            assert!(text.contains("easyp_response_bytes_total{domain=\"metrics-test.example\"} 1244"));
+            assert!(text.contains("easyp_active_connections{transport=\"metrics-test\"} 1"));
+            assert!(text.contains("easyp_extension_errors_total{extension=\"metrics_test\"} 1"));
+            assert!(text.contains("easyp_cache_hits_total{cache=\"metrics-test\"} 1"));
+            assert!(text.contains("easyp_certificate_expiry_timestamp_seconds{domain=\"metrics-test.example\"} 1900000000"));
+            assert!(text.contains("# TYPE easyp_request_duration_seconds histogram"));
+            assert!(text.contains("easyp_tls_sessions_total{version=\"1.3\",kind=\"resumed\"}"));
+        }
+        assert!(render().contains("easyp_active_connections{transport=\"metrics-test\"} 0"));
+    }
+}
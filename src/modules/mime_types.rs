@@ -0,0 +1,440 @@
+//! MIME Type Resolution
+//!
+//! This module maps files to the Content-Type they are served with. It provides:
+//! - A built-in table covering common web, media, font, document and archive formats
+//! - Loading of `mime.types` files (the system `/etc/mime.types` plus extra files from
+//!   the command line), either globally or for a single domain
+//! - A charset parameter for textual types (`text/*`, JavaScript, JSON and XML)
+//! - Optional content sniffing for files without an extension
+//! - A configurable default type for everything else
+//!
+//! Later sources win: the system file, then the built-in table (so web-specific choices
+//! such as `application/javascript` and `font/woff2` are kept), then extra files, then
+//! per-domain files for requests to that domain.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Bytes read from an extension-less file to sniff its type
+pub const SNIFF_LEN: usize = 512;
+
+/// Where most Unix systems keep the shared extension table
+pub const SYSTEM_MIME_TYPES: &str = "/etc/mime.types";
+
+/// Sources and options used to build a [`MimeTypes`] table
+#[derive(Debug, Clone, PartialEq)]
+pub struct MimeConfig {
+    /// System `mime.types` file; silently skipped when missing
+    pub system_file: Option<PathBuf>,
+    /// Extra `mime.types` files applied to every domain
+    pub files: Vec<PathBuf>,
+    /// `mime.types` files applied to a single domain
+    pub domain_files: Vec<(String, PathBuf)>,
+    /// Type for files nothing else matched
+    pub default_type: String,
+    /// Charset added to textual types, None to leave them bare
+    pub charset: Option<String>,
+    /// Sniff the contents of extension-less files
+    pub sniff: bool,
+}
+
+impl Default for MimeConfig {
+    fn default() -> Self {
+        Self {
+            system_file: Some(PathBuf::from(SYSTEM_MIME_TYPES)),
+            files: Vec::new(),
+            domain_files: Vec::new(),
+            default_type: "application/octet-stream".to_string(),
+            charset: Some("utf-8".to_string()),
+            sniff: false,
+        }
+    }
+}
+
+/// Built-in extension table (types without parameters; charsets are added on lookup)
+const BUILTIN_TYPES: &[(&str, &str)] = &[
+    // HTML and text files
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("txt", "text/plain"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("vtt", "text/vtt"),
+    // JavaScript and data
+    ("js", "application/javascript"),
+    ("mjs", "application/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml", "application/xml"),
+    ("rss", "application/rss+xml"),
+    ("atom", "application/atom+xml"),
+    // WebAssembly
+    ("wasm", "application/wasm"),
+    // Images
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("svg", "image/svg+xml"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    // Audio
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("m4a", "audio/mp4"),
+    ("aac", "audio/aac"),
+    // Video
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("mov", "video/quicktime"),
+    // Fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    // Documents
+    ("pdf", "application/pdf"),
+    ("epub", "application/epub+zip"),
+    // Archives
+    ("zip", "application/zip"),
+    ("tar", "application/x-tar"),
+    ("gz", "application/gzip"),
+    ("tgz", "application/gzip"),
+    ("bz2", "application/x-bzip2"),
+    ("xz", "application/x-xz"),
+    ("7z", "application/x-7z-compressed"),
+    // Default binary type
+    ("bin", "application/octet-stream"),
+];
+
+/// MIME type mappings for file extensions, with per-domain overrides
+#[derive(Debug, Clone)]
+pub struct MimeTypes {
+    types: HashMap<String, String>,
+    domains: HashMap<String, HashMap<String, String>>,
+    default_type: String,
+    charset: Option<String>,
+    sniff: bool,
+}
+
+impl Default for MimeTypes {
+    fn default() -> Self {
+        let defaults = MimeConfig::default();
+        let mut mime_types = Self {
+            types: HashMap::new(),
+            domains: HashMap::new(),
+            default_type: defaults.default_type,
+            charset: defaults.charset,
+            sniff: defaults.sniff,
+        };
+        for (extension, mime_type) in BUILTIN_TYPES {
+            mime_types.insert(extension, mime_type);
+        }
+        mime_types
+    }
+}
+
+impl MimeTypes {
+    /// Build a table from the system file, the built-ins and configured files
+    ///
+    /// Unreadable configured files are logged and skipped so a bad mapping file cannot
+    /// stop the server; the command line checks they exist up front.
+    ///
+    /// # Arguments
+    /// * `config` - Sources and options
+    ///
+    /// # Returns
+    /// * `MimeTypes` - The combined table
+    pub fn from_config(config: &MimeConfig) -> Self {
+        let mut mime_types = Self {
+            types: HashMap::new(),
+            domains: HashMap::new(),
+            default_type: config.default_type.clone(),
+            charset: config.charset.clone(),
+            sniff: config.sniff,
+        };
+
+        if let Some(system_file) = &config.system_file {
+            match fs::read_to_string(system_file) {
+                Ok(text) => {
+                    for (extension, mime_type) in parse_mime_types(&text) {
+                        mime_types.types.insert(extension, mime_type);
+                    }
+                }
+                Err(e) => log::debug!(target: "files", "no system MIME types from {}: {}", system_file.display(), e),
+            }
+        }
+        for (extension, mime_type) in BUILTIN_TYPES {
+            mime_types.insert(extension, mime_type);
+        }
+        for file in &config.files {
+            if let Err(e) = mime_types.load_file(file) {
+                log::error!(target: "files", "{}", e);
+            }
+        }
+        for (domain, file) in &config.domain_files {
+            if let Err(e) = mime_types.load_domain_file(domain, file) {
+                log::error!(target: "files", "{}", e);
+            }
+        }
+        mime_types
+    }
+
+    /// Map an extension to a type for every domain
+    pub fn insert(&mut self, extension: &str, mime_type: &str) {
+        self.types.insert(extension.to_ascii_lowercase(), mime_type.to_string());
+    }
+
+    /// Merge a `mime.types` file into the global table
+    ///
+    /// # Returns
+    /// * `Result<usize, String>` - Number of extensions mapped, or a read error
+    pub fn load_file(&mut self, path: &Path) -> Result<usize, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read MIME types {}: {}", path.display(), e))?;
+        let entries = parse_mime_types(&text);
+        let count = entries.len();
+        self.types.extend(entries);
+        Ok(count)
+    }
+
+    /// Merge a `mime.types` file into the overrides for one domain
+    ///
+    /// # Returns
+    /// * `Result<usize, String>` - Number of extensions mapped, or a read error
+    pub fn load_domain_file(&mut self, domain: &str, path: &Path) -> Result<usize, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read MIME types {}: {}", path.display(), e))?;
+        let entries = parse_mime_types(&text);
+        let count = entries.len();
+        self.domains.entry(domain.to_ascii_lowercase()).or_default().extend(entries);
+        Ok(count)
+    }
+
+    /// Get MIME type for a file extension
+    pub fn get_mime_type(&self, path: &Path) -> String {
+        self.lookup(path, None).unwrap_or_else(|| self.default_type.clone())
+    }
+
+    /// Get MIME type for a file served to `domain`, sniffing extension-less files if enabled
+    ///
+    /// # Arguments
+    /// * `path` - File on disk
+    /// * `domain` - Requested host, for per-domain overrides
+    /// * `head` - Start of the file if the caller already has it in memory
+    ///
+    /// # Returns
+    /// * `String` - Content-Type value, including a charset for textual types
+    pub fn get_mime_type_for(&self, path: &Path, domain: Option<&str>, head: Option<&[u8]>) -> String {
+        if let Some(mime_type) = self.lookup(path, domain) {
+            return mime_type;
+        }
+        if self.sniff && path.extension().is_none() {
+            let sniffed = match head {
+                Some(head) => sniff(&head[..head.len().min(SNIFF_LEN)]),
+                None => read_head(path).and_then(|head| sniff(&head)),
+            };
+            if let Some(mime_type) = sniffed {
+                return self.with_charset(mime_type);
+            }
+        }
+        self.default_type.clone()
+    }
+
+    /// Extension lookup only: domain overrides first, then the global table
+    fn lookup(&self, path: &Path, domain: Option<&str>) -> Option<String> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        let overrides = domain.and_then(|domain| self.domains.get(&domain.to_ascii_lowercase()));
+        overrides
+            .and_then(|types| types.get(&extension))
+            .or_else(|| self.types.get(&extension))
+            .map(|mime_type| self.with_charset(mime_type))
+    }
+
+    fn with_charset(&self, mime_type: &str) -> String {
+        match &self.charset {
+            Some(charset) if is_textual(mime_type) && !mime_type.contains(';') => format!("{}; charset={}", mime_type, charset),
+            _ => mime_type.to_string(),
+        }
+    }
+}
+
+/// Parse `mime.types` text: `type ext1 ext2 ...` per line, `#` starts a comment
+///
+/// # Returns
+/// * `Vec<(String, String)>` - (lowercase extension, type) pairs in file order
+pub fn parse_mime_types(text: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        let Some(mime_type) = fields.next() else { continue };
+        if !mime_type.contains('/') {
+            continue;
+        }
+        for extension in fields {
+            entries.push((extension.trim_start_matches('.').to_ascii_lowercase(), mime_type.to_string()));
+        }
+    }
+    entries
+}
+
+/// Whether a type carries text that needs a charset
+fn is_textual(mime_type: &str) -> bool {
+    mime_type.starts_with("text/")
+        || matches!(mime_type, "application/javascript" | "application/json" | "application/xml")
+        || (mime_type.starts_with("application/") && (mime_type.ends_with("+json") || mime_type.ends_with("+xml")))
+}
+
+fn read_head(path: &Path) -> Option<Vec<u8>> {
+    let file = fs::File::open(path).ok()?;
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    file.take(SNIFF_LEN as u64).read_to_end(&mut head).ok()?;
+    Some(head)
+}
+
+/// Guess a type from the first bytes of a file
+///
+/// Recognises common binary signatures, HTML, XML/SVG and plain UTF-8 text.
+///
+/// # Returns
+/// * `Option<&'static str>` - The type (without charset), or None if unrecognised
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\0asm", "application/wasm"),
+        (b"OggS", "audio/ogg"),
+        (b"ID3", "audio/mpeg"),
+        (b"fLaC", "audio/flac"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+    ];
+    for (signature, mime_type) in SIGNATURES {
+        if head.starts_with(signature) {
+            return Some(mime_type);
+        }
+    }
+    if head.len() >= 12 && head.starts_with(b"RIFF") {
+        match &head[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            _ => {}
+        }
+    }
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return Some(match &head[8..12] {
+            b"avif" | b"avis" => "image/avif",
+            b"M4A " => "audio/mp4",
+            b"qt  " => "video/quicktime",
+            _ => "video/mp4",
+        });
+    }
+
+    // Text: must be UTF-8 (a multi-byte sequence may be cut at the end) without control bytes
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    if text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b')) {
+        return None;
+    }
+    let start = text.trim_start_matches('\u{feff}').trim_start().to_ascii_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        Some("text/html")
+    } else if start.starts_with("<svg") || (start.starts_with("<?xml") && start.contains("<svg")) {
+        Some("image/svg+xml")
+    } else if start.starts_with("<?xml") {
+        Some("application/xml")
+    } else {
+        Some("text/plain")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mime_types() {
+        let text = "# comment\n\
+                    video/mp4\t\tmp4 mp4v mpg4\n\
+                    text/x-rust rs # trailing comment\n\
+                    application/x-empty\n\
+                    not-a-type foo\n";
+        let entries = parse_mime_types(text);
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0], ("mp4".to_string(), "video/mp4".to_string()));
+        assert_eq!(entries[3], ("rs".to_string(), "text/x-rust".to_string()));
+    }
+
+    #[test]
+    fn test_charset_and_overrides() {
+        let dir = std::env::temp_dir().join(format!("easyp-mime-types-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let global = dir.join("global.types");
+        let domain = dir.join("domain.types");
+        fs::write(&global, "text/x-rust rs\napplication/x-custom cst\n").unwrap();
+        fs::write(&domain, "text/plain rs\n").unwrap();
+
+        let config = MimeConfig {
+            system_file: None,
+            files: vec![global],
+            domain_files: vec![("Docs.Example.com".to_string(), domain)],
+            default_type: "application/x-unknown".to_string(),
+            ..MimeConfig::default()
+        };
+        let mime_types = MimeTypes::from_config(&config);
+        assert_eq!(mime_types.get_mime_type(Path::new("main.rs")), "text/x-rust; charset=utf-8");
+        assert_eq!(mime_types.get_mime_type(Path::new("data.cst")), "application/x-custom");
+        assert_eq!(mime_types.get_mime_type_for(Path::new("main.rs"), Some("docs.example.com"), None), "text/plain; charset=utf-8");
+        assert_eq!(mime_types.get_mime_type_for(Path::new("main.rs"), Some("other.example.com"), None), "text/x-rust; charset=utf-8");
+        assert_eq!(mime_types.get_mime_type(Path::new("movie.mp4")), "video/mp4");
+        assert_eq!(mime_types.get_mime_type(Path::new("site.webmanifest")), "application/manifest+json; charset=utf-8");
+        assert_eq!(mime_types.get_mime_type(Path::new("README")), "application/x-unknown");
+
+        let bare = MimeTypes::from_config(&MimeConfig { system_file: None, charset: None, ..MimeConfig::default() });
+        assert_eq!(bare.get_mime_type(Path::new("index.html")), "text/html");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("image/png"));
+        assert_eq!(sniff(b"\0\0\0\x1cftypavif\0\0\0\0"), Some("image/avif"));
+        assert_eq!(sniff(b"\0\0\0\x18ftypisom\0\0\x02\0"), Some("video/mp4"));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"\xef\xbb\xbf  <!DOCTYPE html><title>x</title>"), Some("text/html"));
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"\"/>"), Some("image/svg+xml"));
+        assert_eq!(sniff("plain caf\u{e9}".as_bytes()), Some("text/plain"));
+        // A multi-byte character cut off by the sniff window is still text
+        assert_eq!(sniff(&"caf\u{e9}".as_bytes()[..4]), Some("text/plain"));
+        assert_eq!(sniff(b"\x00\x01\x02\x03binary"), None);
+
+        let mime_types = MimeTypes::from_config(&MimeConfig { system_file: None, sniff: true, ..MimeConfig::default() });
+        assert_eq!(mime_types.get_mime_type_for(Path::new("LICENSE"), None, Some(b"MIT License")), "text/plain; charset=utf-8");
+        // Unknown extensions are not sniffed
+        assert_eq!(mime_types.get_mime_type_for(Path::new("x.unknown"), None, Some(b"text")), "application/octet-stream");
+    }
+}
@@ -3,10 +3,36 @@
 //! This module contains various components for handling HTTP requests,
 //! file serving, security, and protocol support.
 
+pub mod access_log;
+pub mod acme_challenge;
+pub mod cgi_exec;
+pub mod client_auth;
 pub mod connection_policy;
+pub mod content_cache;
+pub mod digest;
+pub mod dns01;
+pub mod etag_index;
 pub mod extension_traits;
+pub mod fastcgi;
 pub mod file_cache;
 pub mod file_handler;
+pub mod health;
+pub mod hostname;
 pub mod http_response;
 pub mod http_version;
+pub mod log_rotation;
+pub mod logging;
+pub mod metrics;
+pub mod mime_types;
+pub mod ocsp;
+pub mod reverse_proxy;
 pub mod secure_file_server_module;
+pub mod static_certs;
+pub mod tls_alpn;
+pub mod tls_policy;
+pub mod traffic_stats;
+pub mod try_files;
+pub mod upstream;
+pub mod vhost;
+pub mod websocket;
+pub mod x509;
@@ -0,0 +1,789 @@
+//! OCSP Stapling
+//!
+//! This module fetches OCSP responses for the certificates in use so they can be
+//! stapled to the TLS handshake, sparing clients a round trip to the CA. It provides:
+//! - [`build_request`] / [`parse_response`]: the DER encodings of RFC 6960, for a single
+//!   certificate identified by a SHA-1 [`CertId`]
+//! - [`OcspStapler`]: registered certificate files, their current responses and a
+//!   background refresher
+//!
+//! Responses are cached next to each certificate as `<cert>.ocsp`, so a restart staples
+//! immediately, and are refreshed halfway between `thisUpdate` and `nextUpdate`. A failed
+//! fetch keeps the previous response until it expires. Certificates without an OCSP URL
+//! (self-signed, or from CAs that dropped OCSP) are left alone.
+//!
+//! A response is only cached or stapled once its signature verifies against the issuer's
+//! key, or against a delegated responder certificate that the issuer signed for OCSP.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::signature;
+use x509_parser::asn1_rs::{Any, Class, FromDer, Tag};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::time::ASN1Time;
+
+use super::digest::sha1;
+use super::x509::{parse_certificate, CertInfo};
+
+/// How often the refresher looks for responses that are due
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Validity assumed for a response without nextUpdate
+const DEFAULT_VALIDITY: i64 = 24 * 3600;
+
+/// Longest wait between retries of a failing responder
+const MAX_RETRY_DELAY: i64 = 3600;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_RESPONSE_SIZE: u64 = 64 * 1024;
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+const OID_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+const OID_ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_ECDSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+const OID_RSA_SHA1: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x05];
+const OID_RSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const OID_RSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+const OID_RSA_SHA512: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+
+/// Stapling settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct OcspConfig {
+    /// Fetch and staple responses (`--no-ocsp` turns this off)
+    pub enabled: bool,
+    /// Refuse to serve a certificate that has an OCSP URL but no fresh response
+    pub must_staple: bool,
+    /// Send every request here instead of the URL in the certificate
+    pub responder: Option<String>,
+}
+
+impl Default for OcspConfig {
+    fn default() -> Self {
+        Self { enabled: true, must_staple: false, responder: None }
+    }
+}
+
+/// Revocation status reported by the responder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+/// The parts of a response that decide when to staple and refresh it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OcspResponse {
+    pub status: CertStatus,
+    /// Unix seconds
+    pub this_update: i64,
+    /// Unix seconds
+    pub next_update: Option<i64>,
+}
+
+impl OcspResponse {
+    /// When the response stops being valid
+    pub fn expires(&self) -> i64 {
+        self.next_update.unwrap_or(self.this_update + DEFAULT_VALIDITY)
+    }
+
+    /// Whether the response may still be stapled at `now`
+    pub fn is_fresh(&self, now: i64) -> bool {
+        self.this_update <= now + 300 && now < self.expires()
+    }
+
+    /// When a replacement should be fetched: halfway through the validity period
+    pub fn refresh_at(&self) -> i64 {
+        self.this_update + (self.expires() - self.this_update) / 2
+    }
+}
+
+/// Identifies a certificate to the responder (hashes are SHA-1, as all responders accept)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertId {
+    issuer_name_hash: [u8; 20],
+    issuer_key_hash: [u8; 20],
+    serial: Vec<u8>,
+}
+
+impl CertId {
+    /// Build the CertID of `leaf`, issued by `issuer`
+    pub fn new(leaf: &CertInfo, issuer: &CertInfo) -> Self {
+        Self {
+            issuer_name_hash: sha1(&leaf.issuer),
+            issuer_key_hash: sha1(&issuer.public_key_bits),
+            serial: leaf.serial.clone(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let algorithm = der(TAG_SEQUENCE, &[der(TAG_OID, OID_SHA1), vec![0x05, 0x00]].concat());
+        der(
+            TAG_SEQUENCE,
+            &[
+                algorithm,
+                der(TAG_OCTET_STRING, &self.issuer_name_hash),
+                der(TAG_OCTET_STRING, &self.issuer_key_hash),
+                der(TAG_INTEGER, &self.serial),
+            ]
+            .concat(),
+        )
+    }
+
+    /// Whether the contents of a CertID from a response name this certificate
+    fn matches(&self, encoded: &[u8]) -> bool {
+        let parse = || -> Option<bool> {
+            let mut rest = encoded;
+            let oid = take(&mut take(&mut rest, Class::Universal, Tag::Sequence)?, Class::Universal, Tag::Oid)?;
+            let name_hash = take(&mut rest, Class::Universal, Tag::OctetString)?;
+            let key_hash = take(&mut rest, Class::Universal, Tag::OctetString)?;
+            let serial = take(&mut rest, Class::Universal, Tag::Integer)?;
+            // Responders may answer with another hash algorithm; the serial still has to match
+            let hashes_match = oid != OID_SHA1 || (name_hash == self.issuer_name_hash && key_hash == self.issuer_key_hash);
+            Some(hashes_match && serial == self.serial.as_slice())
+        };
+        parse().unwrap_or(false)
+    }
+}
+
+/// Split the next DER element off `input` if it has the given class and tag
+///
+/// # Returns
+/// * `Option<&[u8]>` - The element's contents; `input` is left alone if it does not match
+fn take<'a>(input: &mut &'a [u8], class: Class, tag: Tag) -> Option<&'a [u8]> {
+    let (rest, element) = Any::from_der(input).ok()?;
+    if element.header.class() != class || element.header.tag() != tag {
+        return None;
+    }
+    *input = rest;
+    Some(element.data)
+}
+
+/// Split a UTCTime or GeneralizedTime off `input`, as Unix seconds
+fn take_time(input: &mut &[u8]) -> Option<i64> {
+    let (rest, time) = ASN1Time::from_der(input).ok()?;
+    *input = rest;
+    Some(time.timestamp())
+}
+
+/// Encode one DER element
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len.to_be_bytes().iter().copied().skip_while(|&b| b == 0).collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend_from_slice(&bytes);
+    }
+    out.extend_from_slice(contents);
+    out
+}
+
+/// Encode an OCSPRequest for one certificate, without a nonce so responses can be cached
+pub fn build_request(id: &CertId) -> Vec<u8> {
+    let request = der(TAG_SEQUENCE, &id.encode());
+    let request_list = der(TAG_SEQUENCE, &request);
+    let tbs_request = der(TAG_SEQUENCE, &request_list);
+    der(TAG_SEQUENCE, &tbs_request)
+}
+
+/// Check a signature made with the key whose subjectPublicKey bits are `public_key`
+///
+/// # Arguments
+/// * `algorithm` - Signature algorithm OID; the curve of an ECDSA key follows from its size
+fn verify_signature(public_key: &[u8], algorithm: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let algorithm: &dyn signature::VerificationAlgorithm = match (algorithm, public_key.len()) {
+        (OID_ECDSA_SHA256, 65) => &signature::ECDSA_P256_SHA256_ASN1,
+        (OID_ECDSA_SHA256, 97) => &signature::ECDSA_P384_SHA256_ASN1,
+        (OID_ECDSA_SHA384, 65) => &signature::ECDSA_P256_SHA384_ASN1,
+        (OID_ECDSA_SHA384, 97) => &signature::ECDSA_P384_SHA384_ASN1,
+        (OID_RSA_SHA1, _) => &signature::RSA_PKCS1_2048_8192_SHA1_FOR_LEGACY_USE_ONLY,
+        (OID_RSA_SHA256, _) => &signature::RSA_PKCS1_2048_8192_SHA256,
+        (OID_RSA_SHA384, _) => &signature::RSA_PKCS1_2048_8192_SHA384,
+        (OID_RSA_SHA512, _) => &signature::RSA_PKCS1_2048_8192_SHA512,
+        (OID_ED25519, 32) => &signature::ED25519,
+        _ => return false,
+    };
+    signature::UnparsedPublicKey::new(algorithm, public_key).verify(message, signature).is_ok()
+}
+
+/// Whether one of the `certs` of a response is a responder certificate that `issuer`
+/// signed for OCSP (RFC 6960 section 4.2.2.2), and made `signature`
+fn signed_by_delegate(mut certs: &[u8], issuer: &CertInfo, algorithm: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    while let Ok((rest, cert)) = X509Certificate::from_der(certs) {
+        certs = rest;
+        let delegated = cert.issuer().as_raw() == issuer.subject.as_slice()
+            && cert.validity().is_valid()
+            && matches!(cert.extended_key_usage(), Ok(Some(usage)) if usage.value.ocsp_signing)
+            && verify_signature(
+                &issuer.public_key_bits,
+                cert.signature_algorithm.algorithm.as_bytes(),
+                cert.tbs_certificate.as_ref(),
+                &cert.signature_value.data,
+            );
+        if delegated && verify_signature(&cert.public_key().subject_public_key.data, algorithm, message, signature) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Decode an OCSPResponse, check its signature and find the status of one certificate
+///
+/// # Arguments
+/// * `response` - DER from the responder
+/// * `id` - The certificate the response must be about
+/// * `issuer` - The certificate's issuer, which must have signed the response (or certified its signer)
+///
+/// # Returns
+/// * `Result<OcspResponse, String>` - Status and validity, or why the response is unusable
+pub fn parse_response(response: &[u8], id: &CertId, issuer: &CertInfo) -> Result<OcspResponse, String> {
+    let mut response = take(&mut &response[..], Class::Universal, Tag::Sequence).ok_or("not an OCSP response")?;
+    let status = take(&mut response, Class::Universal, Tag::Enumerated).ok_or("missing responseStatus")?;
+    match status {
+        [0] => {}
+        [1] => return Err("responder says the request is malformed".to_string()),
+        [2] => return Err("responder internal error".to_string()),
+        [3] => return Err("responder asks to try later".to_string()),
+        [5] => return Err("responder requires signed requests".to_string()),
+        [6] => return Err("responder does not serve this certificate".to_string()),
+        _ => return Err(format!("unexpected responseStatus {:?}", status)),
+    }
+
+    let mut bytes = take(&mut response, Class::ContextSpecific, Tag(0)).ok_or("missing responseBytes")?;
+    let mut bytes = take(&mut bytes, Class::Universal, Tag::Sequence).ok_or("bad responseBytes")?;
+    let kind = take(&mut bytes, Class::Universal, Tag::Oid).ok_or("bad responseType")?;
+    if kind != OID_OCSP_BASIC {
+        return Err("not a basic OCSP response".to_string());
+    }
+    let mut basic = take(&mut bytes, Class::Universal, Tag::OctetString).ok_or("bad response")?;
+    let mut basic = take(&mut basic, Class::Universal, Tag::Sequence).ok_or("bad BasicOCSPResponse")?;
+    let signed = basic;
+    let mut data = take(&mut basic, Class::Universal, Tag::Sequence).ok_or("bad ResponseData")?;
+    let signed = &signed[..signed.len() - basic.len()];
+
+    // signatureAlgorithm, signature and the optional [0] certs of a delegated responder
+    let mut algorithm = take(&mut basic, Class::Universal, Tag::Sequence).ok_or("bad signatureAlgorithm")?;
+    let algorithm = take(&mut algorithm, Class::Universal, Tag::Oid).ok_or("bad signatureAlgorithm")?;
+    let signature = take(&mut basic, Class::Universal, Tag::BitString)
+        .and_then(|bits| bits.strip_prefix(&[0]))
+        .ok_or("bad signature")?;
+    let certs = take(&mut basic, Class::ContextSpecific, Tag(0))
+        .and_then(|mut certs| take(&mut certs, Class::Universal, Tag::Sequence))
+        .unwrap_or_default();
+    if !verify_signature(&issuer.public_key_bits, algorithm, signed, signature)
+        && !signed_by_delegate(certs, issuer, algorithm, signed, signature)
+    {
+        return Err("response is not signed by the issuer or a responder it certified".to_string());
+    }
+
+    // Optional [0] version, then responderID ([1] name or [2] key hash) and producedAt
+    take(&mut data, Class::ContextSpecific, Tag(0));
+    data = Any::from_der(data).map_err(|_| "bad responderID")?.0;
+    take(&mut data, Class::Universal, Tag::GeneralizedTime).ok_or("bad producedAt")?;
+    let mut responses = take(&mut data, Class::Universal, Tag::Sequence).ok_or("bad responses")?;
+
+    while let Some(mut single) = take(&mut responses, Class::Universal, Tag::Sequence) {
+        let cert_id = take(&mut single, Class::Universal, Tag::Sequence).ok_or("bad CertID")?;
+        if !id.matches(cert_id) {
+            continue;
+        }
+        let (rest, cert_status) = Any::from_der(single).map_err(|_| "bad certStatus")?;
+        let status = match (cert_status.header.class(), cert_status.header.tag()) {
+            (Class::ContextSpecific, Tag(0)) => CertStatus::Good,
+            (Class::ContextSpecific, Tag(1)) => CertStatus::Revoked,
+            (Class::ContextSpecific, Tag(2)) => CertStatus::Unknown,
+            _ => return Err("bad certStatus".to_string()),
+        };
+        single = rest;
+        let this_update = take_time(&mut single).ok_or("bad thisUpdate")?;
+        let next_update = match take(&mut single, Class::ContextSpecific, Tag(0)) {
+            Some(mut next_update) => Some(take_time(&mut next_update).ok_or("bad nextUpdate")?),
+            None => None,
+        };
+        return Ok(OcspResponse { status, this_update, next_update });
+    }
+    Err("response does not cover this certificate".to_string())
+}
+
+/// POST a request to an `http://` responder (OCSP is served over plain HTTP)
+///
+/// # Returns
+/// * `Result<Vec<u8>, String>` - The response body
+fn post(url: &str, body: &[u8]) -> Result<Vec<u8>, String> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| format!("unsupported OCSP URL {}", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/"),
+    };
+    let address = if authority.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) && !authority.ends_with(']') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    let address = address
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", authority, e))?
+        .next()
+        .ok_or_else(|| format!("cannot resolve {}", authority))?;
+
+    let mut stream = TcpStream::connect_timeout(&address, FETCH_TIMEOUT).map_err(|e| format!("{}: {}", url, e))?;
+    stream.set_read_timeout(Some(FETCH_TIMEOUT)).ok();
+    stream.set_write_timeout(Some(FETCH_TIMEOUT)).ok();
+    let header = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/ocsp-request\r\nAccept: application/ocsp-response\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        authority,
+        body.len()
+    );
+    stream
+        .write_all(header.as_bytes())
+        .and_then(|_| stream.write_all(body))
+        .map_err(|e| format!("{}: {}", url, e))?;
+
+    let mut response = Vec::new();
+    stream
+        .take(MAX_RESPONSE_SIZE + 4096)
+        .read_to_end(&mut response)
+        .map_err(|e| format!("{}: {}", url, e))?;
+    let split = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| format!("{}: malformed HTTP response", url))?;
+    let head = String::from_utf8_lossy(&response[..split]);
+    let status = head.split_whitespace().nth(1).unwrap_or("");
+    if status != "200" {
+        return Err(format!("{}: HTTP status {}", url, status));
+    }
+    Ok(response[split + 4..].to_vec())
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// What to staple for a certificate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Staple {
+    /// A current response
+    Fresh(Arc<Vec<u8>>),
+    /// The certificate has an OCSP URL but no current response (yet)
+    Missing,
+    /// The certificate is not handled by the stapler
+    Unmanaged,
+}
+
+/// One certificate and its latest response
+#[derive(Debug)]
+struct Entry {
+    cert_path: PathBuf,
+    id: CertId,
+    /// Signs responses, or certifies the responder that does
+    issuer: CertInfo,
+    url: String,
+    response: Option<(Arc<Vec<u8>>, OcspResponse)>,
+    /// Earliest time for the next attempt after a failure
+    retry_at: i64,
+    failures: u32,
+}
+
+impl Entry {
+    fn due(&self, now: i64) -> bool {
+        now >= self.retry_at && self.response.as_ref().is_none_or(|(_, response)| now >= response.refresh_at())
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    config: OcspConfig,
+    files: Mutex<Vec<PathBuf>>,
+    /// Keyed by the leaf certificate DER
+    entries: Mutex<HashMap<Vec<u8>, Entry>>,
+    /// Set when a file is registered, so the refresher fetches without waiting out its interval
+    wake: Arc<(Mutex<bool>, Condvar)>,
+}
+
+/// Fetches, caches and hands out OCSP responses for registered certificate files
+#[derive(Debug, Clone)]
+pub struct OcspStapler {
+    shared: Arc<Shared>,
+}
+
+impl OcspStapler {
+    /// Create a stapler; call [`OcspStapler::spawn_refresher`] to start fetching
+    pub fn new(config: OcspConfig) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                config,
+                files: Mutex::new(Vec::new()),
+                entries: Mutex::new(HashMap::new()),
+                wake: Arc::new((Mutex::new(false), Condvar::new())),
+            }),
+        }
+    }
+
+    /// Whether certificates without a fresh response must not be served
+    pub fn must_staple(&self) -> bool {
+        self.shared.config.must_staple
+    }
+
+    /// Start managing the certificate chain in `cert_path` (leaf first, then its issuer)
+    ///
+    /// The file is read again on every refresh, so a replaced certificate gets its own
+    /// response. A cached `<cert>.ocsp` is used right away if it is still fresh.
+    pub fn register(&self, cert_path: &Path) {
+        {
+            let mut files = self.shared.files.lock().unwrap_or_else(|e| e.into_inner());
+            if files.iter().any(|file| file == cert_path) {
+                return;
+            }
+            files.push(cert_path.to_path_buf());
+        }
+        self.track(cert_path, unix_now());
+        let (pending, wake) = &*self.shared.wake;
+        *pending.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        wake.notify_one();
+    }
+
+    /// Add an entry for the current contents of a certificate file
+    ///
+    /// # Returns
+    /// * `Option<Vec<u8>>` - The leaf DER, if the file holds an OCSP-enabled chain
+    fn track(&self, cert_path: &Path, now: i64) -> Option<Vec<u8>> {
+        let text = fs::read(cert_path).ok()?;
+        let chain: Vec<_> = rustls_pemfile::certs(&mut text.as_slice()).collect::<Result<_, _>>().ok()?;
+        let leaf_der = chain.first()?.to_vec();
+        let mut entries = self.shared.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.contains_key(&leaf_der) {
+            return Some(leaf_der);
+        }
+
+        let leaf = parse_certificate(&leaf_der).ok()?;
+        let url = self.shared.config.responder.clone().or_else(|| leaf.ocsp_urls.first().cloned())?;
+        let Some(issuer) = chain.get(1).and_then(|der| parse_certificate(der).ok()) else {
+            log::debug!(target: "tls", "not stapling {}: the file has no issuer certificate", cert_path.display());
+            return None;
+        };
+        let id = CertId::new(&leaf, &issuer);
+
+        // A response cached by a previous run
+        let response = fs::read(cache_path(cert_path))
+            .ok()
+            .and_then(|der| Some((parse_response(&der, &id, &issuer).ok()?, der)))
+            .filter(|(response, _)| response.is_fresh(now))
+            .map(|(response, der)| (Arc::new(der), response));
+        if response.is_some() {
+            log::debug!(target: "tls", "using cached OCSP response for {}", cert_path.display());
+        }
+
+        entries.insert(leaf_der.clone(), Entry { cert_path: cert_path.to_path_buf(), id, issuer, url, response, retry_at: 0, failures: 0 });
+        Some(leaf_der)
+    }
+
+    /// Response to staple for a leaf certificate
+    pub fn staple(&self, leaf_der: &[u8]) -> Staple {
+        let entries = self.shared.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match entries.get(leaf_der) {
+            Some(Entry { response: Some((der, response)), .. }) if response.is_fresh(unix_now()) => Staple::Fresh(der.clone()),
+            Some(_) => Staple::Missing,
+            None => Staple::Unmanaged,
+        }
+    }
+
+    /// Re-read registered files and fetch every response that is due
+    ///
+    /// # Returns
+    /// * `usize` - Number of responses fetched
+    pub fn refresh(&self) -> usize {
+        let now = unix_now();
+        let files = self.shared.files.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let current: Vec<Vec<u8>> = files.iter().filter_map(|file| self.track(file, now)).collect();
+
+        // Forget replaced certificates, and collect the work without holding the lock
+        let due: Vec<(Vec<u8>, CertId, CertInfo, String, PathBuf)> = {
+            let mut entries = self.shared.entries.lock().unwrap_or_else(|e| e.into_inner());
+            entries.retain(|leaf, _| current.contains(leaf));
+            entries
+                .iter()
+                .filter(|(_, entry)| entry.due(now))
+                .map(|(leaf, entry)| {
+                    (leaf.clone(), entry.id.clone(), entry.issuer.clone(), entry.url.clone(), entry.cert_path.clone())
+                })
+                .collect()
+        };
+
+        let mut fetched = 0;
+        for (leaf, id, issuer, url, cert_path) in due {
+            let result = post(&url, &build_request(&id)).and_then(|der| Ok((parse_response(&der, &id, &issuer)?, der)));
+            let mut entries = self.shared.entries.lock().unwrap_or_else(|e| e.into_inner());
+            let Some(entry) = entries.get_mut(&leaf) else { continue };
+            match result {
+                Ok((response, der)) => {
+                    if response.status == CertStatus::Revoked {
+                        log::error!(target: "tls", "OCSP responder reports {} as revoked", cert_path.display());
+                    } else {
+                        log::debug!(target: "tls", "fetched OCSP response for {} ({:?})", cert_path.display(), response.status);
+                    }
+                    if let Err(e) = write_atomically(&cache_path(&cert_path), &der) {
+                        log::debug!(target: "tls", "cannot cache OCSP response: {}", e);
+                    }
+                    entry.response = Some((Arc::new(der), response));
+                    entry.failures = 0;
+                    entry.retry_at = 0;
+                    fetched += 1;
+                }
+                Err(e) => {
+                    entry.failures += 1;
+                    let delay = (REFRESH_INTERVAL.as_secs() as i64) << entry.failures.min(6);
+                    entry.retry_at = now + delay.min(MAX_RETRY_DELAY);
+                    let still_valid = entry.response.as_ref().is_some_and(|(_, response)| response.is_fresh(now));
+                    log::warn!(target: "tls", "OCSP fetch for {} failed: {}{}", cert_path.display(), e,
+                        if still_valid { "; keeping the previous response" } else { "" });
+                }
+            }
+        }
+        fetched
+    }
+
+    /// Refresh responses on a background thread until the stapler is dropped
+    ///
+    /// Runs every [`REFRESH_INTERVAL`], and right away when a certificate is registered.
+    pub fn spawn_refresher(&self) {
+        let shared: Weak<Shared> = Arc::downgrade(&self.shared);
+        let wake = self.shared.wake.clone();
+        let spawned = std::thread::Builder::new().name("ocsp".to_string()).spawn(move || {
+            // The strong reference is dropped before sleeping
+            while let Some(shared) = shared.upgrade() {
+                OcspStapler { shared }.refresh();
+                let (pending, wake) = &*wake;
+                let pending = pending.lock().unwrap_or_else(|e| e.into_inner());
+                let (mut pending, _) = wake
+                    .wait_timeout_while(pending, REFRESH_INTERVAL, |pending| !*pending)
+                    .unwrap_or_else(|e| e.into_inner());
+                *pending = false;
+            }
+        });
+        if let Err(e) = spawned {
+            log::warn!(target: "tls", "cannot start OCSP refresher: {}", e);
+        }
+    }
+}
+
+/// Where the response for a certificate file is cached
+fn cache_path(cert_path: &Path) -> PathBuf {
+    let mut path = cert_path.as_os_str().to_owned();
+    path.push(".ocsp");
+    PathBuf::from(path)
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), String> {
+    let temp = path.with_extension("ocsp.tmp");
+    let mut file = fs::File::create(&temp).map_err(|e| format!("Failed to create {}: {}", temp.display(), e))?;
+    file.write_all(data)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("Failed to write {}: {}", temp.display(), e))?;
+    fs::rename(&temp, path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    const CHAIN: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIIBbzCCARWgAwIBAgIIEjRWeJCrze8wCgYIKoZIzj0EAwIwEjEQMA4GA1UEAwwH\n\
+VGVzdCBDQTAeFw0yNjAxMDEwMDAwMDBaFw0zNjAxMDEwMDAwMDBaMBcxFTATBgNV\n\
+BAMMDG9jc3AuZXhhbXBsZTBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABPExVEZF\n\
+sDz3E6+QPKIx4m+reIJRtKMfOariwYPh2eLZc1T5RHKiktfnt+07vO6A+L6YKWwk\n\
+gbMKzl++D375a5OjUDBOMBcGA1UdEQQQMA6CDG9jc3AuZXhhbXBsZTAzBggrBgEF\n\
+BQcBAQQnMCUwIwYIKwYBBQUHMAGGF2h0dHA6Ly8xMjcuMC4wLjE6MS9vY3NwMAoG\n\
+CCqGSM49BAMCA0gAMEUCIQDXPnFRu4w4FYXmVzno6ynvka8BZQO0tptcstBJBgPG\n\
+2gIgBIB74VVRSkbX2Beu/pt/48jXI3JDYkF0L6e7ZJ2D9O4=\n\
+-----END CERTIFICATE-----\n\
+-----BEGIN CERTIFICATE-----\n\
+MIIBJDCBzKADAgECAgEBMAoGCCqGSM49BAMCMBIxEDAOBgNVBAMMB1Rlc3QgQ0Ew\n\
+HhcNMjYwMTAxMDAwMDAwWhcNMzYwMTAxMDAwMDAwWjASMRAwDgYDVQQDDAdUZXN0\n\
+IENBMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEnxXExJ318xte8e9nQWDL3DFs\n\
+mVV05NxhwhSc6uow7n7Zm8mfx8J2p8pDMcSZQkChdKQrvoqhMUK8MGPUOqKiKKMT\n\
+MBEwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNHADBEAiBr3X5svvv2iNly\n\
+ivCJ2qr3peWAGS7Pmlyc7MT4WYBUDwIgXhsNyE9qqmRaHdUEiLu5qGdJP7Qgtv61\n\
+PHmY+YxOxhw=\n\
+-----END CERTIFICATE-----\n\
+";
+
+    const REQUEST: &[u8] = &[
+        0x30, 0x49, 0x30, 0x47, 0x30, 0x45, 0x30, 0x43, 0x30, 0x41, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e,
+        0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14, 0xbf, 0x70, 0x52, 0xc8, 0xb9, 0xc0, 0xf7, 0x60, 0xc8,
+        0x91, 0x23, 0xe0, 0x99, 0x81, 0x5e, 0xb2, 0xc0, 0x39, 0x42, 0x26, 0x04, 0x14, 0x4d, 0xfd, 0xfd,
+        0xbe, 0xe9, 0xf3, 0x40, 0xa4, 0xd0, 0x51, 0xe6, 0xe0, 0x49, 0xc7, 0x96, 0x77, 0xa2, 0x8a, 0x6d,
+        0xda, 0x02, 0x08, 0x12, 0x34, 0x56, 0x78, 0x90, 0xab, 0xcd, 0xef,
+    ];
+
+    const GOOD_RESPONSE: &[u8] = &[
+        0x30, 0x82, 0x01, 0x0b, 0x0a, 0x01, 0x00, 0xa0, 0x82, 0x01, 0x04, 0x30, 0x82, 0x01, 0x00, 0x06,
+        0x09, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01, 0x04, 0x81, 0xf2, 0x30, 0x81, 0xef,
+        0x30, 0x81, 0x96, 0xa2, 0x16, 0x04, 0x14, 0x4d, 0xfd, 0xfd, 0xbe, 0xe9, 0xf3, 0x40, 0xa4, 0xd0,
+        0x51, 0xe6, 0xe0, 0x49, 0xc7, 0x96, 0x77, 0xa2, 0x8a, 0x6d, 0xda, 0x18, 0x0f, 0x32, 0x30, 0x32,
+        0x36, 0x31, 0x30, 0x31, 0x38, 0x31, 0x34, 0x30, 0x31, 0x32, 0x31, 0x5a, 0x30, 0x6b, 0x30, 0x69,
+        0x30, 0x41, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14, 0xbf,
+        0x70, 0x52, 0xc8, 0xb9, 0xc0, 0xf7, 0x60, 0xc8, 0x91, 0x23, 0xe0, 0x99, 0x81, 0x5e, 0xb2, 0xc0,
+        0x39, 0x42, 0x26, 0x04, 0x14, 0x4d, 0xfd, 0xfd, 0xbe, 0xe9, 0xf3, 0x40, 0xa4, 0xd0, 0x51, 0xe6,
+        0xe0, 0x49, 0xc7, 0x96, 0x77, 0xa2, 0x8a, 0x6d, 0xda, 0x02, 0x08, 0x12, 0x34, 0x56, 0x78, 0x90,
+        0xab, 0xcd, 0xef, 0x80, 0x00, 0x18, 0x0f, 0x32, 0x30, 0x32, 0x36, 0x30, 0x31, 0x30, 0x31, 0x30,
+        0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0xa0, 0x11, 0x18, 0x0f, 0x32, 0x30, 0x39, 0x39, 0x30, 0x31,
+        0x30, 0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48,
+        0xce, 0x3d, 0x04, 0x03, 0x02, 0x03, 0x48, 0x00, 0x30, 0x45, 0x02, 0x21, 0x00, 0xcd, 0xaf, 0xb1,
+        0xa6, 0x44, 0x23, 0x04, 0x86, 0x84, 0x15, 0xa1, 0x20, 0xa0, 0x22, 0x07, 0xa5, 0x14, 0x91, 0x25,
+        0xe9, 0xea, 0xc9, 0x42, 0xfd, 0xa7, 0xef, 0x51, 0x9d, 0xcf, 0xe1, 0x01, 0xf4, 0x02, 0x20, 0x1d,
+        0xa2, 0x27, 0x7d, 0x07, 0x69, 0x8a, 0x0c, 0x96, 0xb3, 0x3e, 0x0a, 0xf1, 0xa7, 0x63, 0x9b, 0xc9,
+        0x99, 0xab, 0xf0, 0x61, 0x86, 0x70, 0x0f, 0xa1, 0xf8, 0x8c, 0x1e, 0xdf, 0x42, 0xc8, 0xcf,
+    ];
+
+    const REVOKED_RESPONSE: &[u8] = &[
+        0x30, 0x82, 0x01, 0x22, 0x0a, 0x01, 0x00, 0xa0, 0x82, 0x01, 0x1b, 0x30, 0x82, 0x01, 0x17, 0x06,
+        0x09, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01, 0x04, 0x82, 0x01, 0x08, 0x30, 0x82,
+        0x01, 0x04, 0x30, 0x81, 0xab, 0xa1, 0x14, 0x30, 0x12, 0x31, 0x10, 0x30, 0x0e, 0x06, 0x03, 0x55,
+        0x04, 0x03, 0x0c, 0x07, 0x54, 0x65, 0x73, 0x74, 0x20, 0x43, 0x41, 0x18, 0x0f, 0x32, 0x30, 0x32,
+        0x36, 0x31, 0x30, 0x31, 0x38, 0x31, 0x34, 0x30, 0x31, 0x32, 0x31, 0x5a, 0x30, 0x81, 0x81, 0x30,
+        0x7f, 0x30, 0x41, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14,
+        0xbf, 0x70, 0x52, 0xc8, 0xb9, 0xc0, 0xf7, 0x60, 0xc8, 0x91, 0x23, 0xe0, 0x99, 0x81, 0x5e, 0xb2,
+        0xc0, 0x39, 0x42, 0x26, 0x04, 0x14, 0x4d, 0xfd, 0xfd, 0xbe, 0xe9, 0xf3, 0x40, 0xa4, 0xd0, 0x51,
+        0xe6, 0xe0, 0x49, 0xc7, 0x96, 0x77, 0xa2, 0x8a, 0x6d, 0xda, 0x02, 0x08, 0x12, 0x34, 0x56, 0x78,
+        0x90, 0xab, 0xcd, 0xef, 0xa1, 0x16, 0x18, 0x0f, 0x32, 0x30, 0x32, 0x36, 0x30, 0x31, 0x30, 0x31,
+        0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0xa0, 0x03, 0x0a, 0x01, 0x01, 0x18, 0x0f, 0x32, 0x30,
+        0x32, 0x36, 0x30, 0x31, 0x30, 0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0xa0, 0x11, 0x18,
+        0x0f, 0x32, 0x30, 0x39, 0x39, 0x30, 0x31, 0x30, 0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a,
+        0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02, 0x03, 0x48, 0x00, 0x30,
+        0x45, 0x02, 0x20, 0x25, 0xf3, 0x70, 0x6b, 0xcc, 0xf0, 0xf7, 0x91, 0x46, 0x15, 0x2e, 0xc9, 0x2f,
+        0x25, 0x95, 0x25, 0xbd, 0x2f, 0xbc, 0x96, 0x80, 0xdb, 0x6e, 0x79, 0xab, 0x65, 0x15, 0x45, 0x19,
+        0x88, 0x78, 0xc6, 0x02, 0x21, 0x00, 0xd5, 0x69, 0xb7, 0x43, 0x34, 0x4c, 0xdb, 0x88, 0x85, 0xc9,
+        0xc7, 0x34, 0x0a, 0xde, 0x4c, 0xcd, 0xdd, 0xb9, 0x51, 0xd0, 0xaa, 0x79, 0x3b, 0xc4, 0xd4, 0x69,
+        0xff, 0x5f, 0xad, 0x85, 0x79, 0x04,
+    ];
+
+    fn chain() -> (CertInfo, CertInfo) {
+        let chain: Vec<_> = rustls_pemfile::certs(&mut CHAIN.as_bytes()).map(|der| parse_certificate(&der.unwrap()).unwrap()).collect();
+        (chain[0].clone(), chain[1].clone())
+    }
+
+    fn leaf_der() -> Vec<u8> {
+        rustls_pemfile::certs(&mut CHAIN.as_bytes()).next().unwrap().unwrap().to_vec()
+    }
+
+    /// Stand-in responder: answers one POST with `response` and returns the request body
+    fn responder(response: &'static [u8]) -> (String, std::thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ocsp", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let Some(split) = request.windows(4).position(|w| w == b"\r\n\r\n") else { continue };
+                let head = String::from_utf8_lossy(&request[..split]).to_ascii_lowercase();
+                let length: usize = head.lines().find_map(|l| l.strip_prefix("content-length:")).unwrap().trim().parse().unwrap();
+                if request.len() >= split + 4 + length {
+                    let header = format!("HTTP/1.0 200 OK\r\nContent-Type: application/ocsp-response\r\nContent-Length: {}\r\n\r\n", response.len());
+                    stream.write_all(header.as_bytes()).unwrap();
+                    stream.write_all(response).unwrap();
+                    return request[split + 4..].to_vec();
+                }
+            }
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_request_and_response() {
+        let (leaf, issuer) = chain();
+        assert_eq!(leaf.ocsp_urls, vec!["http://127.0.0.1:1/ocsp"]);
+        let id = CertId::new(&leaf, &issuer);
+        assert_eq!(build_request(&id), REQUEST);
+
+        let response = parse_response(GOOD_RESPONSE, &id, &issuer).unwrap();
+        assert_eq!(response, OcspResponse { status: CertStatus::Good, this_update: 1767225600, next_update: Some(4070908800) });
+        assert!(response.is_fresh(1767225600));
+        assert!(!response.is_fresh(4070908800));
+        assert_eq!(response.refresh_at(), (1767225600 + 4070908800) / 2);
+        assert_eq!(parse_response(REVOKED_RESPONSE, &id, &issuer).unwrap().status, CertStatus::Revoked);
+
+        // A response about another certificate is rejected
+        let other = CertId { serial: vec![0x01], ..id.clone() };
+        assert!(parse_response(GOOD_RESPONSE, &other, &issuer).is_err());
+        assert!(parse_response(&[0x30, 0x03, 0x0a, 0x01, 0x03], &id, &issuer).unwrap_err().contains("try later"));
+
+        // So is one that was altered, or that the issuer did not sign
+        let mut tampered = GOOD_RESPONSE.to_vec();
+        let produced_at = tampered.windows(2).position(|w| w == [0x18, 0x0f]).unwrap();
+        tampered[produced_at + 5] ^= 0x01;
+        assert!(parse_response(&tampered, &id, &issuer).unwrap_err().contains("not signed"));
+        assert!(parse_response(GOOD_RESPONSE, &id, &leaf).unwrap_err().contains("not signed"));
+    }
+
+    #[test]
+    fn test_stapler_with_local_responder() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert_path = dir.path().join("fullchain.pem");
+        fs::write(&cert_path, CHAIN).unwrap();
+        let leaf_der = leaf_der();
+
+        let (url, handle) = responder(GOOD_RESPONSE);
+        let config = OcspConfig { responder: Some(url), ..OcspConfig::default() };
+        let stapler = OcspStapler::new(config.clone());
+        stapler.register(&cert_path);
+        assert_eq!(stapler.staple(&leaf_der), Staple::Missing);
+        assert_eq!(stapler.staple(b"unknown"), Staple::Unmanaged);
+
+        assert_eq!(stapler.refresh(), 1);
+        assert_eq!(handle.join().unwrap(), REQUEST);
+        assert_eq!(stapler.staple(&leaf_der), Staple::Fresh(Arc::new(GOOD_RESPONSE.to_vec())));
+        // Not due again until halfway to nextUpdate
+        assert_eq!(stapler.refresh(), 0);
+
+        // A new instance staples from the on-disk cache without asking the responder
+        assert_eq!(fs::read(dir.path().join("fullchain.pem.ocsp")).unwrap(), GOOD_RESPONSE);
+        let restarted = OcspStapler::new(config);
+        restarted.register(&cert_path);
+        assert!(matches!(restarted.staple(&leaf_der), Staple::Fresh(_)));
+    }
+
+    #[test]
+    fn test_refresher_fetches_newly_registered() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert_path = dir.path().join("fullchain.pem");
+        fs::write(&cert_path, CHAIN).unwrap();
+        let leaf_der = leaf_der();
+
+        // Started before anything is registered, as for certificates issued later
+        let (url, handle) = responder(GOOD_RESPONSE);
+        let stapler = OcspStapler::new(OcspConfig { responder: Some(url), ..OcspConfig::default() });
+        stapler.spawn_refresher();
+        std::thread::sleep(Duration::from_millis(50));
+        stapler.register(&cert_path);
+        handle.join().unwrap();
+        for _ in 0..100 {
+            if stapler.staple(&leaf_der) != Staple::Missing {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(stapler.staple(&leaf_der), Staple::Fresh(Arc::new(GOOD_RESPONSE.to_vec())));
+    }
+}
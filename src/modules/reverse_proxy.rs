@@ -0,0 +1,1231 @@
+//! Reverse Proxy Module
+//!
+//! This module forwards requests for configured hosts and path prefixes to upstream
+//! HTTP backends, so easyp can front local app servers while still terminating TLS
+//! with its on-demand ACME certificates. It supports:
+//! - `http://host:port[/path]` and unix socket (`unix:/run/app.sock`) upstreams
+//! - Streaming request bodies (Content-Length and chunked) and response bodies
+//! - `X-Forwarded-For`, `X-Forwarded-Proto`, `X-Forwarded-Host` and `Forwarded` headers
+//! - Rewriting upstream `Location` headers back to the public host
+//! - Connect and idle timeouts (502/504 on failure)
+//! - `Upgrade` (e.g. WebSocket) pass-through after a `101 Switching Protocols`
+//! - Load-balanced upstream groups (`upstream:NAME`) with retries on connect failure
+//!
+//! Each proxied request uses its own upstream connection and the client connection is
+//! closed afterwards, so response bodies can be streamed without re-framing them.
+
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::client_auth::is_client_cert_header;
+use super::upstream::{UpstreamLease, UpstreamRegistry};
+
+/// Maximum size of a request or response header block
+pub const MAX_HEAD_BYTES: usize = 64 * 1024;
+
+/// Hop-by-hop headers that must not be forwarded (RFC 7230 section 6.1)
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "upgrade",
+];
+
+/// Where an upstream lives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpstreamAddr {
+    /// TCP upstream as `host:port`
+    Tcp(String),
+    /// Unix domain socket path
+    Unix(PathBuf),
+    /// Named upstream group, resolved per request
+    Group(String),
+}
+
+/// A parsed `proxy_pass` target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamTarget {
+    /// Address to connect to
+    pub addr: UpstreamAddr,
+    /// Path on the upstream that replaces the matched prefix (empty = pass through unchanged)
+    pub path: String,
+}
+
+impl UpstreamTarget {
+    /// Parse an upstream URL
+    ///
+    /// Accepted forms: `http://127.0.0.1:3000`, `http://app.internal/v1`,
+    /// `unix:/run/app.sock`, `http://unix:/run/app.sock:/v1` (nginx style)
+    /// and `upstream:NAME[/path]` for a load-balanced group.
+    ///
+    /// # Arguments
+    /// * `url` - Upstream URL
+    ///
+    /// # Returns
+    /// * `Result<UpstreamTarget, String>` - Parsed target
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let url = url.trim();
+
+        if let Some(rest) = url.strip_prefix("upstream:") {
+            let (name, path) = match rest.find('/') {
+                Some(pos) => (&rest[..pos], &rest[pos..]),
+                None => (rest, ""),
+            };
+            if name.is_empty() {
+                return Err(format!("missing upstream group name in '{}'", url));
+            }
+            return Ok(Self {
+                addr: UpstreamAddr::Group(name.to_lowercase()),
+                path: path.trim_end_matches('/').to_string(),
+            });
+        }
+
+        if let Some(rest) = url.strip_prefix("unix:").or_else(|| url.strip_prefix("http://unix:")) {
+            let (socket, path) = match rest.split_once(":/") {
+                Some((socket, path)) => (socket, format!("/{}", path)),
+                None => (rest, String::new()),
+            };
+            if !socket.starts_with('/') {
+                return Err(format!("unix socket path must be absolute in '{}'", url));
+            }
+            return Ok(Self {
+                addr: UpstreamAddr::Unix(PathBuf::from(socket)),
+                path: path.trim_end_matches('/').to_string(),
+            });
+        }
+
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("unsupported upstream '{}' (expected http:// or unix:)", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err(format!("missing upstream host in '{}'", url));
+        }
+        let has_port = match authority.rfind(':') {
+            Some(pos) => !authority[pos..].contains(']'),
+            None => false,
+        };
+        let authority = if has_port { authority.to_string() } else { format!("{}:80", authority) };
+
+        Ok(Self {
+            addr: UpstreamAddr::Tcp(authority),
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Origins the upstream may use in absolute `Location` headers
+    fn origins(&self) -> Vec<String> {
+        match &self.addr {
+            UpstreamAddr::Tcp(authority) => {
+                let mut origins = vec![format!("http://{}", authority)];
+                if let Some(host) = authority.strip_suffix(":80") {
+                    origins.push(format!("http://{}", host));
+                }
+                origins
+            }
+            UpstreamAddr::Unix(_) => vec!["http://localhost".to_string()],
+            // Origins depend on the group member that served the request
+            UpstreamAddr::Group(_) => Vec::new(),
+        }
+    }
+}
+
+/// A `proxy_pass` rule for a host and path prefix
+#[derive(Debug, Clone)]
+pub struct ProxyRoute {
+    /// Host this route applies to (None = any host)
+    pub host: Option<String>,
+    /// Path prefix, always starting with `/`
+    pub prefix: String,
+    /// Upstream to forward to
+    pub upstream: UpstreamTarget,
+}
+
+impl ProxyRoute {
+    /// Parse a route of the form `[HOST][/PREFIX]=URL`
+    ///
+    /// Examples: `app.example.com=http://127.0.0.1:3000`,
+    /// `example.com/api=http://127.0.0.1:8080/v1`, `/socket=unix:/run/app.sock`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (location, url) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("invalid proxy route '{}' (expected [HOST][/PREFIX]=URL)", spec))?;
+        let location = location.trim();
+        let (host, prefix) = match location.find('/') {
+            Some(pos) => (&location[..pos], &location[pos..]),
+            None => (location, "/"),
+        };
+        let host = match host {
+            "" | "*" => None,
+            host => Some(host.to_lowercase()),
+        };
+        let prefix = match prefix.trim_end_matches('/') {
+            "" => "/".to_string(),
+            trimmed => trimmed.to_string(),
+        };
+
+        Ok(Self {
+            host,
+            prefix,
+            upstream: UpstreamTarget::parse(url)?,
+        })
+    }
+
+    /// Whether this route matches a host and path (path may include a query)
+    pub fn matches(&self, host: Option<&str>, path: &str) -> bool {
+        if let Some(route_host) = &self.host {
+            if host.map(|h| !h.eq_ignore_ascii_case(route_host)).unwrap_or(true) {
+                return false;
+            }
+        }
+        if self.prefix == "/" {
+            return true;
+        }
+        match path.strip_prefix(self.prefix.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || rest.starts_with('?'),
+            None => false,
+        }
+    }
+
+    /// Map a client request target to the upstream request target
+    pub fn upstream_target(&self, target: &str) -> String {
+        if self.upstream.path.is_empty() {
+            return target.to_string();
+        }
+        let rest = if self.prefix == "/" {
+            target
+        } else {
+            target.strip_prefix(self.prefix.as_str()).unwrap_or(target)
+        };
+        match rest {
+            "" => format!("{}/", self.upstream.path),
+            rest if rest.starts_with('?') => format!("{}/{}", self.upstream.path, rest),
+            rest => format!("{}{}", self.upstream.path, rest),
+        }
+    }
+
+    /// Rewrite an upstream `Location` value to the public origin
+    ///
+    /// # Arguments
+    /// * `location` - Location header from the upstream
+    /// * `public_origin` - e.g. `https://example.com`
+    pub fn rewrite_location(&self, location: &str, public_origin: &str) -> String {
+        self.rewrite_location_from(location, public_origin, &self.upstream.origins())
+    }
+
+    /// Rewrite a `Location` value given the origins of the server that answered
+    fn rewrite_location_from(&self, location: &str, public_origin: &str, origins: &[String]) -> String {
+        for origin in origins {
+            if let Some(rest) = location.strip_prefix(origin.as_str()) {
+                if rest.is_empty() || rest.starts_with('/') {
+                    return format!("{}{}", public_origin, self.public_path(if rest.is_empty() { "/" } else { rest }));
+                }
+            }
+        }
+        if location.starts_with('/') && !location.starts_with("//") {
+            return self.public_path(location);
+        }
+        location.to_string()
+    }
+
+    /// Map an upstream path back to the public path space
+    fn public_path(&self, path: &str) -> String {
+        if self.upstream.path.is_empty() {
+            return path.to_string();
+        }
+        match path.strip_prefix(self.upstream.path.as_str()) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') || rest.starts_with('?') => {
+                let prefix = self.prefix.trim_end_matches('/');
+                match rest {
+                    "" => format!("{}/", prefix),
+                    rest => format!("{}{}", prefix, rest),
+                }
+            }
+            _ => path.to_string(),
+        }
+    }
+}
+
+/// Reverse proxy configuration
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    /// Routes, the most specific match wins
+    pub routes: Vec<ProxyRoute>,
+    /// Timeout for connecting to an upstream
+    pub connect_timeout: Duration,
+    /// Idle timeout for reads and writes on either side
+    pub io_timeout: Duration,
+    /// Upstream groups referenced by `upstream:NAME` routes
+    pub upstreams: UpstreamRegistry,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            routes: Vec::new(),
+            connect_timeout: Duration::from_secs(5),
+            io_timeout: Duration::from_secs(60),
+            upstreams: UpstreamRegistry::default(),
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// Add a route from its command line form (see [`ProxyRoute::parse`])
+    pub fn add_route_spec(&mut self, spec: &str) -> Result<(), String> {
+        self.routes.push(ProxyRoute::parse(spec)?);
+        Ok(())
+    }
+
+    /// Attach the upstream groups, checking that every `upstream:NAME` route has one
+    pub fn set_upstreams(&mut self, upstreams: UpstreamRegistry) -> Result<(), String> {
+        for route in &self.routes {
+            if let UpstreamAddr::Group(name) = &route.upstream.addr {
+                if upstreams.get(name).is_none() {
+                    return Err(format!("proxy route {} uses unknown upstream group '{}'", route.prefix, name));
+                }
+            }
+        }
+        self.upstreams = upstreams;
+        Ok(())
+    }
+
+    /// Find the route for a request
+    ///
+    /// Host-specific routes beat any-host routes, then the longest prefix wins.
+    pub fn find_route(&self, host: Option<&str>, path: &str) -> Option<&ProxyRoute> {
+        self.routes
+            .iter()
+            .filter(|route| route.matches(host, path))
+            .max_by_key(|route| (route.host.is_some(), route.prefix.len()))
+    }
+}
+
+/// Client-side facts the proxy adds to forwarded requests
+#[derive(Debug, Clone)]
+pub struct ForwardInfo {
+    /// Address of the connected client
+    pub client_ip: IpAddr,
+    /// Scheme the client used (`http` or `https`)
+    pub proto: &'static str,
+}
+
+/// Summary of a proxied exchange
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyOutcome {
+    /// Status code sent to the client
+    pub status: u16,
+    /// Bytes sent to the client (head and body)
+    pub bytes_sent: u64,
+}
+
+/// Parsed request or response head
+#[derive(Debug, Clone)]
+pub(crate) struct MessageHead {
+    /// Request line or status line
+    pub(crate) start_line: String,
+    /// Header fields in order
+    pub(crate) headers: Vec<(String, String)>,
+}
+
+impl MessageHead {
+    pub(crate) fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let text = std::str::from_utf8(bytes).map_err(|_| "message head is not valid UTF-8".to_string())?;
+        let mut lines = text.split("\r\n");
+        let start_line = lines.next().unwrap_or("").to_string();
+        if start_line.is_empty() {
+            return Err("empty start line".to_string());
+        }
+        let mut headers = Vec::new();
+        for line in lines {
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("malformed header line '{}'", line))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        Ok(Self { start_line, headers })
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Header names listed in the Connection header are hop-by-hop as well
+    fn connection_tokens(&self) -> Vec<String> {
+        self.get("connection")
+            .map(|v| v.split(',').map(|t| t.trim().to_ascii_lowercase()).collect())
+            .unwrap_or_default()
+    }
+
+    fn is_hop_by_hop(&self, name: &str) -> bool {
+        let lower = name.to_ascii_lowercase();
+        HOP_BY_HOP.contains(&lower.as_str()) || self.connection_tokens().contains(&lower)
+    }
+
+    /// Every comma-separated value of a header, across repeated fields
+    fn values(&self, name: &str) -> Vec<String> {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .flat_map(|(_, v)| v.split(','))
+            .map(|v| v.trim().to_ascii_lowercase())
+            .filter(|v| !v.is_empty())
+            .collect()
+    }
+
+    /// How the request body is framed (RFC 9112 section 6.3)
+    ///
+    /// Anything an upstream could read differently from us is refused: Transfer-Encoding
+    /// together with Content-Length, more than one Content-Length, or a transfer coding
+    /// list that does not end in `chunked`.
+    fn body_framing(&self) -> Result<BodyFraming, String> {
+        let codings = self.values("transfer-encoding");
+        let lengths = self.values("content-length");
+        if !codings.is_empty() {
+            if !lengths.is_empty() {
+                return Err("both Transfer-Encoding and Content-Length".to_string());
+            }
+            if codings.last().map(String::as_str) != Some("chunked") {
+                return Err(format!("final transfer coding is not chunked ('{}')", codings.join(", ")));
+            }
+            return Ok(BodyFraming::Chunked);
+        }
+        match lengths.as_slice() {
+            [] => Ok(BodyFraming::None),
+            [length] if !length.is_empty() && length.bytes().all(|b| b.is_ascii_digit()) => length
+                .parse()
+                .map(BodyFraming::Length)
+                .map_err(|_| format!("invalid Content-Length '{}'", length)),
+            [length] => Err(format!("invalid Content-Length '{}'", length)),
+            _ => Err(format!("multiple Content-Length values ({})", lengths.join(", "))),
+        }
+    }
+
+    /// Whether the client asked for a protocol upgrade
+    fn upgrade(&self) -> Option<&str> {
+        if self.connection_tokens().iter().any(|t| t == "upgrade") {
+            self.get("upgrade")
+        } else {
+            None
+        }
+    }
+}
+
+/// How the request body is framed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyFraming {
+    None,
+    Length(u64),
+    Chunked,
+}
+
+/// Incremental tracker that finds the end of a chunked body without decoding it
+#[derive(Debug, Default)]
+struct ChunkedTracker {
+    state: ChunkState,
+    line: Vec<u8>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum ChunkState {
+    #[default]
+    SizeLine,
+    Data(u64),
+    Trailer,
+    Done,
+}
+
+impl ChunkedTracker {
+    /// Feed bytes, returning how many of them belong to the body
+    fn feed(&mut self, buf: &[u8]) -> Result<usize, String> {
+        let mut pos = 0;
+        while pos < buf.len() && self.state != ChunkState::Done {
+            match self.state {
+                ChunkState::Data(remaining) => {
+                    let take = remaining.min((buf.len() - pos) as u64);
+                    pos += take as usize;
+                    self.state = match remaining - take {
+                        0 => ChunkState::SizeLine,
+                        left => ChunkState::Data(left),
+                    };
+                }
+                ChunkState::SizeLine | ChunkState::Trailer => {
+                    let byte = buf[pos];
+                    pos += 1;
+                    if byte != b'\n' {
+                        if self.line.len() >= 4096 {
+                            return Err("chunk line too long".to_string());
+                        }
+                        self.line.push(byte);
+                        continue;
+                    }
+                    let line = String::from_utf8_lossy(&self.line).trim_end_matches('\r').to_string();
+                    self.line.clear();
+                    if self.state == ChunkState::Trailer {
+                        if line.is_empty() {
+                            self.state = ChunkState::Done;
+                        }
+                        continue;
+                    }
+                    let size_str = line.split(';').next().unwrap_or("").trim();
+                    let size = u64::from_str_radix(size_str, 16)
+                        .map_err(|_| format!("invalid chunk size '{}'", size_str))?;
+                    // Chunk data is followed by CRLF
+                    self.state = if size == 0 { ChunkState::Trailer } else { ChunkState::Data(size + 2) };
+                }
+                ChunkState::Done => {}
+            }
+        }
+        Ok(pos)
+    }
+
+    fn is_done(&self) -> bool {
+        self.state == ChunkState::Done
+    }
+}
+
+/// Object-safe combination of the async I/O traits for upstream connections
+trait UpstreamStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> UpstreamStream for T {}
+
+/// Errors that map to gateway responses
+#[derive(Debug)]
+enum ProxyError {
+    /// Upstream unreachable or sent an invalid response (502)
+    BadGateway(String),
+    /// Upstream did not respond in time (504)
+    Timeout(String),
+    /// Client sent something we cannot forward (400)
+    BadRequest(String),
+}
+
+impl std::fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyError::BadGateway(msg) => write!(f, "bad gateway: {}", msg),
+            ProxyError::Timeout(msg) => write!(f, "gateway timeout: {}", msg),
+            ProxyError::BadRequest(msg) => write!(f, "bad request: {}", msg),
+        }
+    }
+}
+
+/// Proxy one request to its upstream and stream the response back
+///
+/// # Arguments
+/// * `client` - Client connection (plain TCP or TLS)
+/// * `initial` - Bytes already read from the client (at least part of the request head)
+/// * `route` - Matched route
+/// * `forward` - Client address and scheme for the forwarding headers
+/// * `config` - Timeouts
+///
+/// # Returns
+/// * `Result<ProxyOutcome, Box<dyn std::error::Error + Send + Sync>>` - Status and size of the response;
+///   the caller should close the client connection afterwards
+pub async fn proxy_request<C>(
+    client: &mut C,
+    initial: &[u8],
+    route: &ProxyRoute,
+    forward: &ForwardInfo,
+    config: &ProxyConfig,
+) -> Result<ProxyOutcome, Box<dyn std::error::Error + Send + Sync>>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    match proxy_exchange(client, initial, route, forward, config).await {
+        Ok(outcome) => Ok(outcome),
+        Err(Ok(error)) => {
+            let (status, reason) = match &error {
+                ProxyError::BadGateway(_) => (502, "Bad Gateway"),
+                ProxyError::Timeout(_) => (504, "Gateway Timeout"),
+                ProxyError::BadRequest(_) => (400, "Bad Request"),
+            };
+            log::error!(target: "proxy", "proxy error for prefix {}: {}", route.prefix, error);
+            let body = format!("{} {}", status, reason);
+            let response = format!(
+                "HTTP/1.1 {} {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status, reason, body.len(), body
+            );
+            client.write_all(response.as_bytes()).await?;
+            client.flush().await?;
+            Ok(ProxyOutcome { status, bytes_sent: response.len() as u64 })
+        }
+        // The response head was already sent; nothing sensible can be written any more
+        Err(Err(io_error)) => Err(io_error.into()),
+    }
+}
+
+/// The actual exchange. Errors before the response head is sent are `Err(Ok(_))`,
+/// I/O errors after that point are `Err(Err(_))`.
+async fn proxy_exchange<C>(
+    client: &mut C,
+    initial: &[u8],
+    route: &ProxyRoute,
+    forward: &ForwardInfo,
+    config: &ProxyConfig,
+) -> Result<ProxyOutcome, Result<ProxyError, std::io::Error>>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let io_timeout = config.io_timeout;
+
+    // Complete the request head if the caller's buffer ended inside it
+    let mut buffered = initial.to_vec();
+    let head_end = read_head(client, &mut buffered, io_timeout)
+        .await
+        .map_err(|e| Ok(ProxyError::BadRequest(e)))?;
+    let request = MessageHead::parse(&buffered[..head_end]).map_err(|e| Ok(ProxyError::BadRequest(e)))?;
+    let body_start = head_end + 4;
+
+    let mut parts = request.start_line.split_whitespace();
+    let method = parts.next().unwrap_or("GET").to_string();
+    let target = parts.next().unwrap_or("/").to_string();
+    let public_host = request.get("host").unwrap_or("localhost").to_string();
+    let upgrade = request.upgrade().map(str::to_string);
+
+    let framing = request.body_framing().map_err(|e| Ok(ProxyError::BadRequest(e)))?;
+
+    let upstream_head = build_upstream_request(&request, &method, &route.upstream_target(&target), forward, framing, upgrade.as_deref());
+
+    // Connect to the upstream (or a group member) and send the head
+    let (mut upstream, origins, lease) = open_upstream(route, forward, config, upstream_head.as_bytes()).await.map_err(Ok)?;
+    let fail = |error: ProxyError| {
+        if let Some(lease) = &lease {
+            lease.record_failure(&error.to_string());
+        }
+        Ok(error)
+    };
+
+    // Stream the request body
+    forward_request_body(client, &mut upstream, &buffered[body_start..], framing, io_timeout)
+        .await
+        .map_err(|e| match e {
+            ProxyError::BadRequest(_) => Ok(e),
+            e => fail(e),
+        })?;
+    with_timeout(io_timeout, upstream.flush()).await.map_err(|e| fail(ProxyError::BadGateway(e)))?;
+
+    // Read the response head
+    let mut response_buf = Vec::new();
+    let response_head_end = read_head(&mut upstream, &mut response_buf, io_timeout).await.map_err(|e| {
+        if e.contains("timed out") {
+            fail(ProxyError::Timeout(e))
+        } else {
+            fail(ProxyError::BadGateway(e))
+        }
+    })?;
+    let response = MessageHead::parse(&response_buf[..response_head_end]).map_err(|e| fail(ProxyError::BadGateway(e)))?;
+    let status: u16 = response
+        .start_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| fail(ProxyError::BadGateway(format!("invalid status line '{}'", response.start_line))))?;
+    if let Some(lease) = &lease {
+        lease.record_success();
+    }
+
+    let public_origin = format!("{}://{}", forward.proto, public_host);
+    let switching = status == 101 && upgrade.is_some();
+    let client_head = build_client_response(&response, route, &public_origin, &origins, switching);
+
+    // From here on the client has seen a response, so errors just close the connection
+    let mut bytes_sent = client_head.len() as u64;
+    client.write_all(client_head.as_bytes()).await.map_err(Err)?;
+    let leftover = &response_buf[response_head_end + 4..];
+    client.write_all(leftover).await.map_err(Err)?;
+    bytes_sent += leftover.len() as u64;
+    client.flush().await.map_err(Err)?;
+
+    if switching {
+        log::debug!(target: "proxy", "upgraded proxied connection to {}", upgrade.as_deref().unwrap_or(""));
+        let (_, to_client) = tokio::io::copy_bidirectional(client, &mut upstream).await.map_err(Err)?;
+        return Ok(ProxyOutcome { status, bytes_sent: bytes_sent + to_client });
+    }
+
+    // Stream the rest of the response until the upstream closes
+    let mut chunk = vec![0u8; 16 * 1024];
+    loop {
+        let n = match tokio::time::timeout(io_timeout, upstream.read(&mut chunk)).await {
+            Ok(result) => result.map_err(Err)?,
+            Err(_) => {
+                log::warn!(target: "proxy", "proxy response stream idle for {:?}, closing", io_timeout);
+                break;
+            }
+        };
+        if n == 0 {
+            break;
+        }
+        client.write_all(&chunk[..n]).await.map_err(Err)?;
+        bytes_sent += n as u64;
+    }
+    client.flush().await.map_err(Err)?;
+
+    Ok(ProxyOutcome { status, bytes_sent })
+}
+
+/// Read until the end of a message head, returning the offset of `\r\n\r\n`
+pub(crate) async fn read_head<S>(stream: &mut S, buf: &mut Vec<u8>, io_timeout: Duration) -> Result<usize, String>
+where
+    S: AsyncRead + Unpin + ?Sized,
+{
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(pos) = find_head_end(buf) {
+            return Ok(pos);
+        }
+        if buf.len() > MAX_HEAD_BYTES {
+            return Err("message head too large".to_string());
+        }
+        let n = match tokio::time::timeout(io_timeout, stream.read(&mut chunk)).await {
+            Ok(Ok(n)) => n,
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => return Err("timed out reading message head".to_string()),
+        };
+        if n == 0 {
+            return Err("connection closed before end of message head".to_string());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// Find the `\r\n\r\n` that ends a message head
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+/// Run an I/O future with a timeout, flattening errors to strings
+async fn with_timeout<F, T>(timeout: Duration, future: F) -> Result<T, String>
+where
+    F: std::future::Future<Output = std::io::Result<T>>,
+{
+    match tokio::time::timeout(timeout, future).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timed out".to_string()),
+    }
+}
+
+/// Connect to the route's upstream and send the request head
+///
+/// For `upstream:NAME` routes a group member is chosen per attempt. Nothing has been
+/// read from the client body yet, so a member that fails to connect or to accept the
+/// head is marked failed and the next member is tried.
+///
+/// # Returns
+/// * The connection, the origins the upstream may use in `Location`, and the lease
+///   that keeps the member's in-flight count while the exchange runs
+async fn open_upstream(
+    route: &ProxyRoute,
+    forward: &ForwardInfo,
+    config: &ProxyConfig,
+    head: &[u8],
+) -> Result<(Box<dyn UpstreamStream>, Vec<String>, Option<UpstreamLease>), ProxyError> {
+    let name = match &route.upstream.addr {
+        UpstreamAddr::Group(name) => name,
+        _ => {
+            let mut upstream = connect_upstream(&route.upstream, config.connect_timeout).await?;
+            with_timeout(config.io_timeout, upstream.write_all(head))
+                .await
+                .map_err(ProxyError::BadGateway)?;
+            return Ok((upstream, route.upstream.origins(), None));
+        }
+    };
+
+    let group = config
+        .upstreams
+        .get(name)
+        .ok_or_else(|| ProxyError::BadGateway(format!("unknown upstream group '{}'", name)))?;
+    let mut tried = Vec::new();
+    let mut last_error = None;
+    while let Some(lease) = group.select(forward.client_ip, &tried) {
+        tried.push(lease.index);
+        let attempt = async {
+            let mut upstream = connect_upstream(&lease.server.target, config.connect_timeout).await?;
+            with_timeout(config.io_timeout, upstream.write_all(head))
+                .await
+                .map_err(ProxyError::BadGateway)?;
+            Ok::<_, ProxyError>(upstream)
+        };
+        match attempt.await {
+            Ok(upstream) => return Ok((upstream, lease.server.target.origins(), Some(lease))),
+            Err(error) => {
+                log::info!(target: "proxy", "upstream {} in group '{}' failed, trying another: {}", lease.server.address(), name, error);
+                lease.record_failure(&error.to_string());
+                last_error = Some(error);
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| ProxyError::BadGateway(format!("no live upstreams in group '{}'", name))))
+}
+
+/// Connect to a TCP or unix socket upstream
+async fn connect_upstream(target: &UpstreamTarget, timeout: Duration) -> Result<Box<dyn UpstreamStream>, ProxyError> {
+    match &target.addr {
+        UpstreamAddr::Tcp(authority) => match tokio::time::timeout(timeout, TcpStream::connect(authority.as_str())).await {
+            Ok(Ok(stream)) => {
+                let _ = stream.set_nodelay(true);
+                Ok(Box::new(stream))
+            }
+            Ok(Err(e)) => Err(ProxyError::BadGateway(format!("connect to {} failed: {}", authority, e))),
+            Err(_) => Err(ProxyError::Timeout(format!("connect to {} timed out", authority))),
+        },
+        #[cfg(unix)]
+        UpstreamAddr::Unix(path) => match tokio::time::timeout(timeout, tokio::net::UnixStream::connect(path)).await {
+            Ok(Ok(stream)) => Ok(Box::new(stream)),
+            Ok(Err(e)) => Err(ProxyError::BadGateway(format!("connect to {} failed: {}", path.display(), e))),
+            Err(_) => Err(ProxyError::Timeout(format!("connect to {} timed out", path.display()))),
+        },
+        #[cfg(not(unix))]
+        UpstreamAddr::Unix(path) => Err(ProxyError::BadGateway(format!("unix sockets not supported ({})", path.display()))),
+        UpstreamAddr::Group(name) => Err(ProxyError::BadGateway(format!("upstream group '{}' was not resolved", name))),
+    }
+}
+
+/// Build the request head sent to the upstream
+fn build_upstream_request(
+    request: &MessageHead,
+    method: &str,
+    upstream_target: &str,
+    forward: &ForwardInfo,
+    framing: BodyFraming,
+    upgrade: Option<&str>,
+) -> String {
+    let mut head = format!("{} {} HTTP/1.1\r\n", method, upstream_target);
+
+    let mut forwarded_for = None;
+    let mut forwarded = None;
+    for (name, value) in &request.headers {
+        let lower = name.to_ascii_lowercase();
+        // Only the TLS layer may vouch for a client certificate
+        if request.is_hop_by_hop(name) || is_client_cert_header(name) {
+            continue;
+        }
+        match lower.as_str() {
+            "x-forwarded-for" => forwarded_for = Some(value.clone()),
+            "forwarded" => forwarded = Some(value.clone()),
+            // Set from our own view of the connection below
+            "x-forwarded-proto" | "x-forwarded-host" => {}
+            // A chunked body carries its own length
+            "content-length" if framing == BodyFraming::Chunked => {}
+            _ => head.push_str(&format!("{}: {}\r\n", name, value)),
+        }
+    }
+
+    let client_ip = forward.client_ip.to_string();
+    let host = request.get("host").unwrap_or("localhost");
+    head.push_str(&format!(
+        "X-Forwarded-For: {}\r\n",
+        match forwarded_for {
+            Some(existing) => format!("{}, {}", existing, client_ip),
+            None => client_ip,
+        }
+    ));
+    head.push_str(&format!("X-Forwarded-Proto: {}\r\n", forward.proto));
+    head.push_str(&format!("X-Forwarded-Host: {}\r\n", host));
+
+    let for_value = match forward.client_ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    };
+    let element = format!("for={};proto={};host=\"{}\"", for_value, forward.proto, host.replace('"', ""));
+    head.push_str(&format!(
+        "Forwarded: {}\r\n",
+        match forwarded {
+            Some(existing) => format!("{}, {}", existing, element),
+            None => element,
+        }
+    ));
+
+    match upgrade {
+        Some(protocol) => {
+            head.push_str(&format!("Upgrade: {}\r\n", protocol));
+            head.push_str("Connection: upgrade\r\n");
+        }
+        None => head.push_str("Connection: close\r\n"),
+    }
+
+    head.push_str("\r\n");
+    head
+}
+
+/// Build the response head sent to the client
+fn build_client_response(
+    response: &MessageHead,
+    route: &ProxyRoute,
+    public_origin: &str,
+    origins: &[String],
+    switching: bool,
+) -> String {
+    let mut head = format!("{}\r\n", response.start_line);
+    for (name, value) in &response.headers {
+        let lower = name.to_ascii_lowercase();
+        if switching && (lower == "connection" || lower == "upgrade") {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+            continue;
+        }
+        if response.is_hop_by_hop(name) {
+            continue;
+        }
+        if lower == "location" || lower == "content-location" {
+            head.push_str(&format!("{}: {}\r\n", name, route.rewrite_location_from(value, public_origin, origins)));
+        } else {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    if !switching {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+    head
+}
+
+/// Forward the request body according to its framing
+async fn forward_request_body<C>(
+    client: &mut C,
+    upstream: &mut Box<dyn UpstreamStream>,
+    already_read: &[u8],
+    framing: BodyFraming,
+    io_timeout: Duration,
+) -> Result<(), ProxyError>
+where
+    C: AsyncRead + Unpin,
+{
+    let mut chunk = vec![0u8; 16 * 1024];
+    match framing {
+        BodyFraming::None => Ok(()),
+        BodyFraming::Length(total) => {
+            let first = (already_read.len() as u64).min(total) as usize;
+            with_timeout(io_timeout, upstream.write_all(&already_read[..first]))
+                .await
+                .map_err(ProxyError::BadGateway)?;
+            let mut remaining = total - first as u64;
+            while remaining > 0 {
+                let want = remaining.min(chunk.len() as u64) as usize;
+                let n = with_timeout(io_timeout, client.read(&mut chunk[..want]))
+                    .await
+                    .map_err(ProxyError::BadRequest)?;
+                if n == 0 {
+                    return Err(ProxyError::BadRequest("client closed during request body".to_string()));
+                }
+                with_timeout(io_timeout, upstream.write_all(&chunk[..n]))
+                    .await
+                    .map_err(ProxyError::BadGateway)?;
+                remaining -= n as u64;
+            }
+            Ok(())
+        }
+        BodyFraming::Chunked => {
+            let mut tracker = ChunkedTracker::default();
+            let used = tracker.feed(already_read).map_err(ProxyError::BadRequest)?;
+            with_timeout(io_timeout, upstream.write_all(&already_read[..used]))
+                .await
+                .map_err(ProxyError::BadGateway)?;
+            while !tracker.is_done() {
+                let n = with_timeout(io_timeout, client.read(&mut chunk))
+                    .await
+                    .map_err(ProxyError::BadRequest)?;
+                if n == 0 {
+                    return Err(ProxyError::BadRequest("client closed during chunked body".to_string()));
+                }
+                let used = tracker.feed(&chunk[..n]).map_err(ProxyError::BadRequest)?;
+                with_timeout(io_timeout, upstream.write_all(&chunk[..used]))
+                    .await
+                    .map_err(ProxyError::BadGateway)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::upstream::UpstreamConfig;
+    use std::net::Ipv4Addr;
+    use tokio::net::TcpListener;
+
+    fn forward() -> ForwardInfo {
+        ForwardInfo {
+            client_ip: IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)),
+            proto: "https",
+        }
+    }
+
+    #[test]
+    fn test_parse_upstreams() {
+        let tcp = UpstreamTarget::parse("http://127.0.0.1:3000").unwrap();
+        assert_eq!(tcp.addr, UpstreamAddr::Tcp("127.0.0.1:3000".to_string()));
+        assert_eq!(tcp.path, "");
+
+        let with_path = UpstreamTarget::parse("http://app.internal/v1/").unwrap();
+        assert_eq!(with_path.addr, UpstreamAddr::Tcp("app.internal:80".to_string()));
+        assert_eq!(with_path.path, "/v1");
+
+        let unix = UpstreamTarget::parse("unix:/run/app.sock").unwrap();
+        assert_eq!(unix.addr, UpstreamAddr::Unix(PathBuf::from("/run/app.sock")));
+
+        let nginx = UpstreamTarget::parse("http://unix:/run/app.sock:/api").unwrap();
+        assert_eq!(nginx.addr, UpstreamAddr::Unix(PathBuf::from("/run/app.sock")));
+        assert_eq!(nginx.path, "/api");
+
+        let group = UpstreamTarget::parse("upstream:App/v2").unwrap();
+        assert_eq!(group.addr, UpstreamAddr::Group("app".to_string()));
+        assert_eq!(group.path, "/v2");
+
+        assert!(UpstreamTarget::parse("upstream:").is_err());
+        assert!(UpstreamTarget::parse("https://example.com").is_err());
+        assert!(UpstreamTarget::parse("unix:relative.sock").is_err());
+    }
+
+    #[test]
+    fn test_route_matching() {
+        let mut config = ProxyConfig::default();
+        config.add_route_spec("app.example.com=http://127.0.0.1:3000").unwrap();
+        config.add_route_spec("example.com/api=http://127.0.0.1:8080/v1").unwrap();
+        config.add_route_spec("/socket=unix:/run/app.sock").unwrap();
+
+        assert_eq!(config.find_route(Some("app.example.com"), "/anything").unwrap().prefix, "/");
+        assert_eq!(config.find_route(Some("example.com"), "/api/users").unwrap().prefix, "/api");
+        assert_eq!(config.find_route(Some("example.com"), "/api?x=1").unwrap().prefix, "/api");
+        assert!(config.find_route(Some("example.com"), "/apix").is_none());
+        assert!(config.find_route(Some("example.com"), "/index.html").is_none());
+        assert_eq!(config.find_route(Some("other.org"), "/socket/x").unwrap().prefix, "/socket");
+        assert!(config.find_route(None, "/api").is_none());
+        assert!(ProxyRoute::parse("example.com").is_err());
+    }
+
+    #[test]
+    fn test_target_and_location_rewrite() {
+        let route = ProxyRoute::parse("example.com/api=http://127.0.0.1:8080/v1").unwrap();
+        assert_eq!(route.upstream_target("/api/users?x=1"), "/v1/users?x=1");
+        assert_eq!(route.upstream_target("/api"), "/v1/");
+
+        let origin = "https://example.com";
+        assert_eq!(route.rewrite_location("http://127.0.0.1:8080/v1/login", origin), "https://example.com/api/login");
+        assert_eq!(route.rewrite_location("/v1/login", origin), "/api/login");
+        assert_eq!(route.rewrite_location("https://elsewhere.org/x", origin), "https://elsewhere.org/x");
+
+        let root = ProxyRoute::parse("app.example.com=http://localhost").unwrap();
+        assert_eq!(root.rewrite_location("http://localhost/next", "https://app.example.com"), "https://app.example.com/next");
+        assert_eq!(root.upstream_target("/x"), "/x");
+    }
+
+    #[test]
+    fn test_forwarding_headers() {
+        let request = MessageHead::parse(
+            b"POST /api/x HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-For: 198.51.100.1\r\nX-Forwarded-Proto: http\r\nConnection: keep-alive, X-Secret\r\nX-Secret: 1\r\nX-Client-Cert-CN: admin\r\nX_Client_Cert_Verify: SUCCESS\r\nContent-Length: 3",
+        )
+        .unwrap();
+        let head = build_upstream_request(&request, "POST", "/v1/x", &forward(), BodyFraming::Length(3), None);
+        assert!(head.starts_with("POST /v1/x HTTP/1.1\r\n"));
+        assert!(head.contains("X-Forwarded-For: 198.51.100.1, 203.0.113.7\r\n"));
+        assert!(head.contains("X-Forwarded-Proto: https\r\n"));
+        assert!(!head.contains("X-Forwarded-Proto: http\r\n"));
+        assert!(head.contains("X-Forwarded-Host: example.com\r\n"));
+        assert!(head.contains("Forwarded: for=203.0.113.7;proto=https;host=\"example.com\"\r\n"));
+        assert!(head.contains("Connection: close\r\n"));
+        assert!(!head.contains("X-Secret"));
+        assert!(!head.contains("Client") && !head.contains("admin"));
+        assert!(head.contains("Content-Length: 3\r\n"));
+    }
+
+    #[test]
+    fn test_body_framing() {
+        let framing = |headers: &str| {
+            let head = format!("POST / HTTP/1.1\r\nHost: example.com\r\n{}", headers);
+            MessageHead::parse(head.as_bytes()).unwrap().body_framing()
+        };
+        assert_eq!(framing(""), Ok(BodyFraming::None));
+        assert_eq!(framing("Content-Length: 11"), Ok(BodyFraming::Length(11)));
+        assert_eq!(framing("Transfer-Encoding: gzip, Chunked"), Ok(BodyFraming::Chunked));
+        assert_eq!(framing("Transfer-Encoding: gzip\r\nTransfer-Encoding: chunked"), Ok(BodyFraming::Chunked));
+        assert!(framing("Transfer-Encoding: chunked\r\nContent-Length: 5").is_err());
+        assert!(framing("Transfer-Encoding: chunked, gzip").is_err());
+        assert!(framing("Transfer-Encoding: xchunked").is_err());
+        assert!(framing("Content-Length: 5\r\nContent-Length: 5").is_err());
+        assert!(framing("Content-Length: 5, 6").is_err());
+        assert!(framing("Content-Length: +5").is_err());
+
+        // The upstream never sees a length next to a chunked body
+        let request = MessageHead::parse(b"POST / HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: chunked\r\nContent-Length: 5").unwrap();
+        let head = build_upstream_request(&request, "POST", "/", &forward(), BodyFraming::Chunked, None);
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!head.contains("Content-Length"));
+    }
+
+    #[test]
+    fn test_chunked_tracker() {
+        let body = b"4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\nTrailer: x\r\n\r\nNEXT";
+        let mut tracker = ChunkedTracker::default();
+        let used = tracker.feed(&body[..10]).unwrap() + tracker.feed(&body[10..]).unwrap();
+        assert!(tracker.is_done());
+        assert_eq!(&body[used..], b"NEXT");
+
+        let mut bad = ChunkedTracker::default();
+        assert!(bad.feed(b"zz\r\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_roundtrip_with_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let upstream = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            let head_end = read_head(&mut socket, &mut buf, Duration::from_secs(5)).await.unwrap();
+            while buf.len() < head_end + 4 + 11 {
+                let mut chunk = [0u8; 64];
+                let n = socket.read(&mut chunk).await.unwrap();
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            let request = String::from_utf8(buf).unwrap();
+            socket
+                .write_all(b"HTTP/1.1 302 Found\r\nLocation: http://127.0.0.1/v1/done\r\nKeep-Alive: timeout=5\r\nContent-Length: 2\r\n\r\nok")
+                .await
+                .unwrap();
+            request
+        });
+
+        let route = ProxyRoute::parse(&format!("example.com/api=http://{}/v1", addr)).unwrap();
+        let (mut client, mut server_side) = tokio::io::duplex(64 * 1024);
+        let initial = b"POST /api/submit HTTP/1.1\r\nHost: example.com\r\nContent-Length: 11\r\n\r\nhello";
+        client.write_all(b" world").await.unwrap();
+
+        let outcome = proxy_request(&mut server_side, initial, &route, &forward(), &ProxyConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(outcome.status, 302);
+        drop(server_side);
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 302 Found\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(!response.contains("Keep-Alive"));
+        assert!(response.ends_with("\r\n\r\nok"));
+
+        let request = upstream.await.unwrap();
+        assert!(request.starts_with("POST /v1/submit HTTP/1.1\r\n"));
+        assert!(request.ends_with("hello world"));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_upgrade_passthrough() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            read_head(&mut socket, &mut buf, Duration::from_secs(5)).await.unwrap();
+            assert!(String::from_utf8_lossy(&buf).contains("Upgrade: websocket\r\n"));
+            socket
+                .write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n")
+                .await
+                .unwrap();
+            let mut frame = [0u8; 4];
+            socket.read_exact(&mut frame).await.unwrap();
+            socket.write_all(&frame).await.unwrap();
+        });
+
+        let route = ProxyRoute::parse(&format!("=http://{}", addr)).unwrap();
+        let (mut client, mut server_side) = tokio::io::duplex(64 * 1024);
+        let initial = b"GET /ws HTTP/1.1\r\nHost: example.com\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n";
+
+        let proxy = tokio::spawn(async move {
+            proxy_request(&mut server_side, initial, &route, &forward(), &ProxyConfig::default()).await.unwrap()
+        });
+
+        let mut buf = Vec::new();
+        read_head(&mut client, &mut buf, Duration::from_secs(5)).await.unwrap();
+        assert!(String::from_utf8_lossy(&buf).contains("Connection: Upgrade\r\n"));
+        client.write_all(b"ping").await.unwrap();
+        let mut echo = [0u8; 4];
+        client.read_exact(&mut echo).await.unwrap();
+        assert_eq!(&echo, b"ping");
+        drop(client);
+        assert_eq!(proxy.await.unwrap().status, 101);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_bad_gateway() {
+        // Bind then drop to get a port with nothing listening
+        let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let route = ProxyRoute::parse(&format!("=http://{}", addr)).unwrap();
+        let (mut client, mut server_side) = tokio::io::duplex(4096);
+
+        let outcome = proxy_request(&mut server_side, b"GET / HTTP/1.1\r\nHost: x\r\n\r\n", &route, &forward(), &ProxyConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(outcome.status, 502);
+        drop(server_side);
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 502 Bad Gateway"));
+    }
+
+    #[tokio::test]
+    async fn test_group_retries_dead_member() {
+        let dead = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                read_head(&mut socket, &mut buf, Duration::from_secs(5)).await.unwrap();
+                socket
+                    .write_all(format!("HTTP/1.1 302 Found\r\nLocation: http://{}/v1/next\r\nContent-Length: 0\r\n\r\n", live).as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let mut upstreams = UpstreamConfig::default();
+        upstreams.add_group_spec(&format!("app=http://{},http://{}", dead, live)).unwrap();
+        upstreams.max_fails = 1;
+        let mut config = ProxyConfig::default();
+        config.add_route_spec("example.com/api=upstream:app/v1").unwrap();
+        config.set_upstreams(UpstreamRegistry::new(&upstreams).unwrap()).unwrap();
+        let route = config.find_route(Some("example.com"), "/api").unwrap().clone();
+
+        // Round-robin starts with the dead member, which is retried away and then ejected
+        for _ in 0..2 {
+            let (mut client, mut server_side) = tokio::io::duplex(4096);
+            let outcome = proxy_request(&mut server_side, b"GET /api/x HTTP/1.1\r\nHost: example.com\r\n\r\n", &route, &forward(), &config)
+                .await
+                .unwrap();
+            assert_eq!(outcome.status, 302);
+            drop(server_side);
+            let mut response = String::new();
+            client.read_to_string(&mut response).await.unwrap();
+            assert!(response.contains("Location: https://example.com/api/next\r\n"));
+        }
+
+        let status = config.upstreams.status();
+        assert_eq!(status[0].servers[0].failures, 1);
+        assert_eq!(status[0].servers[0].state, "ejected");
+        assert_eq!(status[0].servers[1].requests, 2);
+
+        let mut unknown = ProxyConfig::default();
+        unknown.add_route_spec("/=upstream:missing").unwrap();
+        assert!(unknown.set_upstreams(UpstreamRegistry::default()).is_err());
+    }
+}
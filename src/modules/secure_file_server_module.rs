@@ -6,7 +6,6 @@
 //! - File Type Support: MIME type support for various file formats
 //! - Privilege Dropping: Can drop to unprivileged user after binding to privileged ports
 
-use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::Read;
 use std::path::{Path, PathBuf, Component};
@@ -15,6 +14,12 @@ use std::time::{SystemTime, UNIX_EPOCH, Duration};
 use super::http_version::HttpVersion;
 use super::http_response::HttpResponse;
 use super::file_cache::{FileCacheInfo, should_return_not_modified, parse_conditional_headers};
+use super::content_cache::{CachedFile, ContentCache, DEFAULT_CACHE_SIZE};
+use super::etag_index::{EtagIndex, EtagMode};
+pub use super::mime_types::{MimeConfig, MimeTypes};
+use super::vhost::{UnknownHostPolicy, VhostConfig, VhostResolution};
+use super::hostname::is_valid_hostname;
+use super::try_files::{Candidate, TryFilesConfig};
 
 // Unix-specific imports for privilege dropping
 //#[cfg(unix)]
@@ -78,6 +83,80 @@ fn format_http_date(time: &SystemTime) -> String {
             day_of_week, day_of_month, month_name, year, hours, minutes, seconds)
 }
 
+/// Build the Location for a canonical-host redirect
+/// Keeps the original request target (including query) and any port from the Host header.
+/// The scheme is left relative so HTTP and HTTPS listeners both redirect correctly.
+fn canonical_location(request: &str, canonical_host: &str, request_path: &str) -> String {
+    let target = request
+        .lines()
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .filter(|t| t.starts_with('/'))
+        .unwrap_or(request_path);
+
+    let port = request
+        .lines()
+        .find(|l| l.to_ascii_lowercase().starts_with("host:"))
+        .and_then(|l| l.split_once(':').map(|(_, value)| value))
+        .and_then(|h| h.trim().rsplit_once(':').map(|(_, port)| port.to_string()))
+        .filter(|port| !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()))
+        .map(|port| format!(":{}", port))
+        .unwrap_or_default();
+
+    format!("//{}{}{}", canonical_host, port, target)
+}
+
+/// Result of walking the try_files chain for a request path
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PathResolution {
+    /// Serve this sanitized file
+    File(PathBuf),
+    /// Redirect permanently to this path
+    Redirect(String),
+    /// Answer with this status code (from a `=CODE` entry)
+    Status(u16),
+    /// Nothing matched
+    NotFound,
+}
+
+/// Split a request target into path and optional query string
+fn split_query(request_path: &str) -> (&str, Option<&str>) {
+    match request_path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (request_path, None),
+    }
+}
+
+/// Check that a URI path names a directory inside the document root
+/// Rejects traversal and hidden components, mirroring `sanitize_path_with_root`
+fn is_directory_within(uri: &str, document_root: &Path) -> bool {
+    let decoded = match urlencoding::decode(uri) {
+        Ok(decoded) => decoded.into_owned(),
+        Err(_) => return false,
+    };
+    if decoded.split('/').any(|segment| segment.starts_with('.')) {
+        return false;
+    }
+    document_root.join(decoded.trim_start_matches('/')).is_dir()
+}
+
+/// Reason phrase for status codes used by `=CODE` try_files entries
+fn status_reason(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        410 => "Gone",
+        451 => "Unavailable For Legal Reasons",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Status",
+    }
+}
+
 // Simple HTTP method classification for file serving
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum HttpMethod {
@@ -134,77 +213,6 @@ fn parse_method_and_range(request: &str) -> (HttpMethod, Option<(u64, Option<u64
     (method, range)
 }
 
-/// MIME type mappings for common file extensions
-#[derive(Debug, Clone)]
-pub struct MimeTypes {
-    //TODO: Should this be &str, &str?
-    types: HashMap<String, String>,
-}
-
-impl Default for MimeTypes {
-    fn default() -> Self {
-        let mut types = HashMap::new();
-
-        // HTML and text files
-        types.insert("html".to_string(), "text/html; charset=utf-8".to_string());
-        types.insert("htm".to_string(),  "text/html; charset=utf-8".to_string());
-        types.insert("txt".to_string(),  "text/plain; charset=utf-8".to_string());
-        types.insert("css".to_string(),  "text/css; charset=utf-8".to_string());
-
-        // JavaScript
-        types.insert("js".to_string(),   "application/javascript; charset=utf-8".to_string());
-        types.insert("mjs".to_string(),  "application/javascript; charset=utf-8".to_string());
-
-        // WebAssembly
-        types.insert("wasm".to_string(), "application/wasm".to_string());
-
-        // Images
-        types.insert("jpg".to_string(),  "image/jpeg".to_string());
-        types.insert("jpeg".to_string(), "image/jpeg".to_string());
-        types.insert("png".to_string(),  "image/png".to_string());
-        types.insert("gif".to_string(),  "image/gif".to_string());
-        types.insert("svg".to_string(),  "image/svg+xml".to_string());
-        types.insert("webp".to_string(), "image/webp".to_string());
-        types.insert("ico".to_string(),  "image/x-icon".to_string());
-        types.insert("bmp".to_string(),  "image/bmp".to_string());
-
-        // Fonts
-        types.insert("woff".to_string(),  "font/woff".to_string());
-        types.insert("woff2".to_string(), "font/woff2".to_string());
-        types.insert("ttf".to_string(),   "font/ttf".to_string());
-        types.insert("otf".to_string(),   "font/otf".to_string());
-
-        // Documents
-        types.insert("pdf".to_string(),  "application/pdf".to_string());
-        types.insert("json".to_string(), "application/json; charset=utf-8".to_string());
-        types.insert("xml".to_string(),  "application/xml; charset=utf-8".to_string());
-
-        // Archives
-        types.insert("zip".to_string(), "application/zip".to_string());
-        types.insert("tar".to_string(), "application/x-tar".to_string());
-        types.insert("gz".to_string(),  "application/gzip".to_string());
-        types.insert("tgz".to_string(), "application/gzip".to_string());
-
-        // Default binary type
-        types.insert("bin".to_string(), "application/octet-stream".to_string());
-
-        Self { types }
-    }
-}
-
-impl MimeTypes {
-    /// Get MIME type for a file extension
-    pub fn get_mime_type(&self, path: &Path) -> String {
-        if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
-            self.types.get(extension.to_lowercase().as_str())
-                .cloned()
-                .unwrap_or_else(|| "application/octet-stream".to_string())
-        } else {
-            "application/octet-stream".to_string()
-        }
-    }
-}
-
 /// Security configuration for file serving
 #[derive(Debug, Clone)]
 pub struct SecurityConfig {
@@ -228,6 +236,18 @@ pub struct SecurityConfig {
     pub keep_alive_max_requests: usize,
     /// Minimum HTTP version to support
     pub minimum_http_version: HttpVersion,
+    /// Virtual host mapping (root template, aliases, wildcards, canonical redirects)
+    pub vhosts: VhostConfig,
+    /// Index files, try_files chains, clean URLs and SPA mode
+    pub try_files: TryFilesConfig,
+    /// In-memory cache budget for small hot files, in bytes (0 disables it)
+    pub file_cache_size: u64,
+    /// Whether ETags come from size and mtime or from a hash of the contents
+    pub etag_mode: EtagMode,
+    /// File that keeps content digests across restarts (content mode only)
+    pub etag_index: Option<PathBuf>,
+    /// MIME type sources, charset, sniffing and default type
+    pub mime: MimeConfig,
 }
 
 impl Default for SecurityConfig {
@@ -255,6 +275,12 @@ impl Default for SecurityConfig {
             keep_alive_timeout: Duration::from_secs(5),
             keep_alive_max_requests: 100,
             minimum_http_version: HttpVersion::Http09,
+            vhosts: VhostConfig::default(),
+            try_files: TryFilesConfig::default(),
+            file_cache_size: DEFAULT_CACHE_SIZE,
+            etag_mode: EtagMode::Metadata,
+            etag_index: None,
+            mime: MimeConfig::default(),
         }
     }
 }
@@ -263,6 +289,8 @@ impl Default for SecurityConfig {
 pub struct SecureFileServer {
     config: SecurityConfig,
     mime_types: MimeTypes,
+    cache: ContentCache,
+    etags: Option<EtagIndex>,
 }
 
 impl Clone for SecureFileServer {
@@ -270,6 +298,9 @@ impl Clone for SecureFileServer {
         Self {
             config: self.config.clone(),
             mime_types: self.mime_types.clone(),
+            // Clones share one cache and one ETag index
+            cache: self.cache.clone(),
+            etags: self.etags.clone(),
         }
     }
 }
@@ -278,59 +309,48 @@ impl SecureFileServer {
     /// Create a new secure file server
     pub fn new(config: SecurityConfig) -> Self {
         Self {
+            cache: ContentCache::new(config.file_cache_size),
+            etags: Self::etag_index_for(&config),
+            mime_types: MimeTypes::from_config(&config.mime),
             config,
-            mime_types: MimeTypes::default(),
         }
     }
 
     /// Validate if a domain is safe for file serving
     /// A safe domain:
-    /// - Contains only alphanumeric characters and dots
-    /// - Does not contain ".." (directory traversal)
+    /// - Is a valid RFC 1123 host name (ASCII letters, digits and inner hyphens per label)
+    /// - Has no empty labels, so it cannot contain ".." (directory traversal)
     /// - Contains at least one dot
     pub fn is_domain_safe(domain: &str) -> bool {
-        // Check if domain contains only alphanumeric characters and dots
-        if !domain.chars().all(|c| c.is_alphanumeric() || c == '.') {
-            return false;
-        }
-
-        // Check if domain contains ".." (directory traversal)
-        if domain.contains("..") {
-            return false;
-        }
-
-        // Check if domain contains at least one dot
-        if !domain.contains('.') {
+        // Labels are validated in lowercase; vhost lookup lowercases the name itself
+        if !is_valid_hostname(&domain.to_ascii_lowercase()) {
             return false;
         }
 
-        // Additional safety checks
-        // Domain should not start or end with a dot
-        if domain.starts_with('.') || domain.ends_with('.') {
-            return false;
-        }
+        // Check if domain contains at least one dot (keeps names like "html" out of the root template)
+        domain.contains('.')
+    }
 
-        // Domain should not have consecutive dots
-        if domain.contains("...") {
-            return false;
+    /// Resolve a domain against the vhost configuration
+    /// Unsafe domains are always treated as unknown
+    pub fn resolve_vhost(&self, domain: &str) -> VhostResolution {
+        if !Self::is_domain_safe(domain) {
+            return VhostResolution::Unknown;
         }
-
-        true
+        self.config.vhosts.resolve(domain)
     }
 
     /// Get the document root for a specific domain
-    /// Returns /var/www/DOMAIN if it exists and domain is safe, otherwise falls back to default
+    /// Uses the vhost root template (default /var/www/DOMAIN), aliases and wildcards,
+    /// falling back to the default document root when no vhost matches
     pub fn get_domain_document_root(&self, domain: &str) -> PathBuf {
-        if Self::is_domain_safe(domain) {
-            let domain_path = PathBuf::from("/var/www").join(domain);
-            if domain_path.exists() && domain_path.is_dir() {
-                println!("Using domain-specific document root: {}", domain_path.display());
-                return domain_path;
-            }
+        if let VhostResolution::Root(domain_path) = self.resolve_vhost(domain) {
+            log::debug!(target: "files", "using domain-specific document root: {}", domain_path.display());
+            return domain_path;
         }
 
         // Fall back to default document root
-        println!("Using default document root: {}", self.config.document_root.display());
+        log::debug!(target: "files", "using default document root: {}", self.config.document_root.display());
         self.config.document_root.clone()
     }
 
@@ -339,7 +359,7 @@ impl SecureFileServer {
         #[cfg(unix)]
         {
             if let (Some(uid), Some(gid)) = (self.config.drop_to_uid, self.config.drop_to_gid) {
-                println!("Dropping privileges to UID {} and GID {}", uid, gid);
+                log::info!(target: "server", "dropping privileges to UID {} and GID {}", uid, gid);
 
                 // Set GID first, then UID
                 unsafe {
@@ -360,14 +380,14 @@ impl SecureFileServer {
                                      uid, gid, new_uid, new_gid).into());
                 }
 
-                println!("Successfully dropped privileges to UID {} and GID {}", uid, gid);
+                log::info!(target: "server", "successfully dropped privileges to UID {} and GID {}", uid, gid);
             }
         }
 
         #[cfg(not(unix))]
         {
             if self.config.drop_to_uid.is_some() || self.config.drop_to_gid.is_some() {
-                println!("Warning: Privilege dropping not supported on non-Unix systems");
+                log::warn!(target: "server", "privilege dropping not supported on non-Unix systems");
             }
         }
 
@@ -464,7 +484,7 @@ impl SecureFileServer {
             if permissions.readonly() {
                 // File is read-only, that's good for security
             } else {
-                println!("Warning: File {} has write permissions", canonical_path.display());
+                log::warn!(target: "files", "file {} has write permissions", canonical_path.display());
             }
         }
 
@@ -494,13 +514,11 @@ impl SecureFileServer {
     }
 
     /// Check if a path should redirect with domain-specific document root
+    /// Follows the try_files chain, so a directory without trailing slash only redirects
+    /// when no earlier candidate (e.g. `$uri.html`) matched, and `.html` URLs redirect
+    /// to their clean form when clean URLs are enabled.
     /// Returns Some(redirect_url) if redirect is needed, None otherwise
     pub fn check_redirect_with_domain(&self, request_path: &str, domain: Option<&str>) -> Option<String> {
-        // Only check for redirects if path doesn't end with slash
-        if request_path.ends_with('/') {
-            return None;
-        }
-
         // Get the appropriate document root for this domain
         let document_root = if let Some(domain) = domain {
             self.get_domain_document_root(domain)
@@ -508,21 +526,59 @@ impl SecureFileServer {
             self.config.document_root.clone()
         };
 
-        // Build the full path to check if it's a directory
-        let clean_path = request_path.trim_start_matches('/');
-        let file_path = document_root.join(clean_path);
+        let (path, query) = split_query(request_path);
+        match self.resolve_try_files(path, &document_root) {
+            PathResolution::Redirect(location) => {
+                let redirect_url = match query {
+                    Some(query) => format!("{}?{}", location, query),
+                    None => location,
+                };
+                log::debug!(target: "files", "redirecting '{}' to '{}'", request_path, redirect_url);
+                Some(redirect_url)
+            }
+            _ => None,
+        }
+    }
+
+    /// Walk the clean URL redirect and try_files chain for a path (without query string)
+    fn resolve_try_files(&self, path: &str, document_root: &Path) -> PathResolution {
+        let try_files = &self.config.try_files;
 
-        // Check if the path is a directory
-        if file_path.is_dir() {
-            // Redirect to the same path with trailing slash
-            let redirect_url = format!("{}/", request_path);
-            println!("Redirecting '{}' to '{}'", request_path, redirect_url);
-            return Some(redirect_url);
+        // Redirect /page.html -> /page only if the .html file really exists
+        if let Some(clean) = try_files.clean_url_for(path) {
+            if self.sanitize_path_with_root(path, document_root).is_ok() {
+                return PathResolution::Redirect(clean);
+            }
         }
 
-        None
-    }
+        for candidate in try_files.candidates(path) {
+            match candidate {
+                Candidate::File(uri) => {
+                    if let Ok(file_path) = self.sanitize_path_with_root(&uri, document_root) {
+                        return PathResolution::File(file_path);
+                    }
+                }
+                Candidate::Directory(uri) => {
+                    if !is_directory_within(&uri, document_root) {
+                        continue;
+                    }
+                    if !path.ends_with('/') {
+                        // Directory without trailing slash: redirect so relative links work
+                        return PathResolution::Redirect(format!("{}/", uri));
+                    }
+                    let dir = uri.trim_end_matches('/');
+                    for index in &try_files.index_files {
+                        if let Ok(file_path) = self.sanitize_path_with_root(&format!("{}/{}", dir, index), document_root) {
+                            return PathResolution::File(file_path);
+                        }
+                    }
+                }
+                Candidate::Status(code) => return PathResolution::Status(code),
+            }
+        }
 
+        PathResolution::NotFound
+    }
 
     /// Serve a file with domain-specific document root and caching support
     ///
@@ -543,10 +599,34 @@ impl SecureFileServer {
         version: &HttpVersion,
         keep_alive: bool
     ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
-        // Check for redirects first using domain-specific document root
-        if let Some(redirect_url) = self.check_redirect_with_domain(request_path, domain) {
-            let response = self.generate_redirect_response(&redirect_url, version, keep_alive);
-            return Ok(Some(response.as_bytes().to_vec()));
+        // Apply canonical-host redirects and the unknown host policy before touching the filesystem
+        if let Some(domain) = domain {
+            match self.resolve_vhost(domain) {
+                VhostResolution::Redirect(canonical) => {
+                    let location = canonical_location(request, &canonical, request_path);
+                    log::debug!(target: "files", "canonical host redirect '{}' -> '{}'", domain, location);
+                    let response = self.generate_redirect_response(&location, version, keep_alive);
+                    return Ok(Some(response.as_bytes().to_vec()));
+                }
+                VhostResolution::Unknown => match self.config.vhosts.unknown_host {
+                    UnknownHostPolicy::ServeDefault => {}
+                    UnknownHostPolicy::Misdirected => {
+                        log::debug!(target: "files", "rejecting request for unknown host '{}' with 421", domain);
+                        let mut response = HttpResponse::new(421, "Misdirected Request", b"421 Misdirected Request".to_vec());
+                        response.set_content_type("text/plain; charset=utf-8");
+                        response.set_content_length();
+                        return Ok(Some(response.encode(version, keep_alive)));
+                    }
+                    UnknownHostPolicy::NotFound => {
+                        log::debug!(target: "files", "rejecting request for unknown host '{}' with 404", domain);
+                        let mut response = HttpResponse::not_found(b"404 Not Found".to_vec());
+                        response.set_content_type("text/plain; charset=utf-8");
+                        response.set_content_length();
+                        return Ok(Some(response.encode(version, keep_alive)));
+                    }
+                },
+                VhostResolution::Root(_) => {}
+            }
         }
 
         // Get the appropriate document root for this domain
@@ -556,39 +636,31 @@ impl SecureFileServer {
             self.config.document_root.clone()
         };
 
-        // Handle directory requests (paths ending with /)
-        if request_path.ends_with('/') {
-            let clean_path = request_path.trim_start_matches('/');
-            let dir_path = document_root.join(clean_path);
-
-            if dir_path.is_dir() {
-                // Try to serve index.html or index.htm from the directory
-                let index_html = dir_path.join("index.html");
-                let index_htm = dir_path.join("index.htm");
-
-                let file_to_serve = if index_html.exists() {
-                    index_html
-                } else if index_htm.exists() {
-                    index_htm
-                } else {
-                    // No index file found, return 404
-                    return Ok(None);
+        // Resolve redirects, try_files candidates and index files in one pass
+        let (path, query) = split_query(request_path);
+        match self.resolve_try_files(path, &document_root) {
+            PathResolution::File(file_path) => self.serve_file_with_caching(&file_path, domain, request, version, keep_alive),
+            PathResolution::Redirect(location) => {
+                let redirect_url = match query {
+                    Some(query) => format!("{}?{}", location, query),
+                    None => location,
                 };
-
-                return self.serve_file_with_caching(&file_to_serve, request, version, keep_alive);
+                log::debug!(target: "files", "redirecting '{}' to '{}'", request_path, redirect_url);
+                let response = self.generate_redirect_response(&redirect_url, version, keep_alive);
+                Ok(Some(response.as_bytes().to_vec()))
             }
-        }
-
-        // For regular file requests, use the existing sanitize_path logic with domain-specific root
-        let file_path = match self.sanitize_path_with_root(request_path, &document_root) {
-            Ok(path) => path,
-            Err(e) => {
-                println!("Security error serving {}: {}", request_path, e);
-                return Ok(None); // Return None to indicate file not found (security through obscurity)
+            PathResolution::Status(code) => {
+                let reason = status_reason(code);
+                let mut response = HttpResponse::new(code, reason, format!("{} {}", code, reason).into_bytes());
+                response.set_content_type("text/plain; charset=utf-8");
+                response.set_content_length();
+                Ok(Some(response.encode(version, keep_alive)))
             }
-        };
-
-        self.serve_file_with_caching(&file_path, request, version, keep_alive)
+            PathResolution::NotFound => {
+                log::debug!(target: "files", "no try_files candidate matched {}", request_path);
+                Ok(None) // Return None to indicate file not found (security through obscurity)
+            }
+        }
     }
 
     /// Serve a file with caching support
@@ -604,22 +676,39 @@ impl SecureFileServer {
     fn serve_file_with_caching(
         &self,
         file_path: &Path,
+        domain: Option<&str>,
         request: &str,
         version: &HttpVersion,
         keep_alive: bool,
     ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
-        // Get file metadata and basic info
-        let metadata = match std::fs::metadata(file_path) {
-            Ok(metadata) => metadata,
-            Err(e) => {
-                println!("Error getting metadata for file {}: {}", file_path.display(), e);
-                return Ok(None);
-            }
+        // Hot files come from memory; everything else starts with the file metadata
+        let (cached, metadata) = match self.cache.get_file(file_path) {
+            Some(cached) => (Some(cached), None),
+            None => match std::fs::metadata(file_path) {
+                Ok(metadata) => (None, Some(metadata)),
+                Err(e) => {
+                    log::debug!(target: "files", "error getting metadata for file {}: {}", file_path.display(), e);
+                    return Ok(None);
+                }
+            },
         };
 
-        let total_size = metadata.len();
-        let cache_info = FileCacheInfo::from_metadata(&metadata);
-        let mime_type = self.get_mime_type(file_path);
+        let cache_info = match (&cached, &metadata) {
+            (Some(cached), _) => cached.info.clone(),
+            (None, Some(metadata)) => {
+                let mut info = FileCacheInfo::from_metadata(metadata);
+                // Large files keep the metadata ETag until their background hash is ready
+                if let Some(etag) = self.etags.as_ref().and_then(|etags| etags.etag(file_path, metadata)) {
+                    info.etag = etag;
+                }
+                info
+            }
+            (None, None) => unreachable!("either a cached entry or metadata is present"),
+        };
+        // Per-domain overrides mean the type is looked up per request, not cached with the file
+        let head = cached.as_ref().map(|cached| cached.contents.as_slice());
+        let mime_type = self.mime_types.get_mime_type_for(file_path, domain, head);
+        let total_size = cache_info.size;
 
         // Method and Range parsing
         let (method, mut range_spec) = parse_method_and_range(request);
@@ -627,12 +716,17 @@ impl SecureFileServer {
 
         // Conditional request handling (applies to both GET/HEAD)
         let (if_modified_since, if_none_match) = parse_conditional_headers(request);
+        let conditional = if_modified_since.is_some() || if_none_match.is_some();
         if should_return_not_modified(&cache_info, if_modified_since.as_deref(), if_none_match.as_deref()) {
+            super::metrics::record_cache("http_conditional", true);
             let mut response = HttpResponse::not_modified(&cache_info.last_modified_http(), &cache_info.etag);
             response.set_header("Accept-Ranges", "bytes");
             let response_bytes = response.encode(version, keep_alive);
             return Ok(Some(response_bytes));
         }
+        if conditional {
+            super::metrics::record_cache("http_conditional", false);
+        }
 
         // Resolve suffix range (bytes=-SUFFIX) encoded as (u64::MAX, Some(suffix))
         if let Some((start, end_opt)) = range_spec {
@@ -671,6 +765,14 @@ impl SecureFileServer {
             return Ok(Some(response_bytes));
         }
 
+        // Small files are read whole (and cached) so later ranges and full requests hit memory
+        let mut cached = cached;
+        if cached.is_none() && !head_only && total_size <= self.cache.max_entry_size() {
+            if let Some(metadata) = &metadata {
+                cached = self.read_into_cache(file_path, metadata, &cache_info);
+            }
+        }
+
         // Partial content path
         if let Some((start, end)) = valid_range {
             let content_len = end - start + 1;
@@ -682,23 +784,25 @@ impl SecureFileServer {
             response.set_header("Content-Range", &format!("bytes {}-{}/{}", start, end, total_size));
             response.set_header("Content-Length", &content_len.to_string());
 
-            if !head_only {
+            if let (false, Some(cached)) = (head_only, &cached) {
+                response.body = cached.contents[start as usize..=end as usize].to_vec();
+            } else if !head_only {
                 // Read only requested slice
                 let mut file = match File::open(file_path) {
                     Ok(file) => file,
                     Err(e) => {
-                        println!("Error opening file {}: {}", file_path.display(), e);
+                        log::error!(target: "files", "error opening file {}: {}", file_path.display(), e);
                         return Ok(None);
                     }
                 };
                 use std::io::{Seek, SeekFrom, Read};
                 if let Err(e) = file.seek(SeekFrom::Start(start)) {
-                    println!("Error seeking file {}: {}", file_path.display(), e);
+                    log::error!(target: "files", "error seeking file {}: {}", file_path.display(), e);
                     return Ok(None);
                 }
                 let mut contents = vec![0u8; content_len as usize];
                 if let Err(e) = file.read_exact(&mut contents) {
-                    println!("Error reading range from file {}: {}", file_path.display(), e);
+                    log::error!(target: "files", "error reading range from file {}: {}", file_path.display(), e);
                     return Ok(None);
                 }
                 response.body = contents;
@@ -721,25 +825,31 @@ impl SecureFileServer {
         }
 
         // GET full body
-        let mut file = match File::open(file_path) {
-            Ok(file) => file,
-            Err(e) => {
-                println!("Error opening file {}: {}", file_path.display(), e);
-                return Ok(None);
+        let contents = match &cached {
+            Some(cached) => cached.contents.to_vec(),
+            None => {
+                let mut file = match File::open(file_path) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        log::error!(target: "files", "error opening file {}: {}", file_path.display(), e);
+                        return Ok(None);
+                    }
+                };
+                let mut contents = Vec::new();
+                if let Err(e) = file.read_to_end(&mut contents) {
+                    log::error!(target: "files", "error reading file {}: {}", file_path.display(), e);
+                    return Ok(None);
+                }
+                contents
             }
         };
-        let mut contents = Vec::new();
-        if let Err(e) = file.read_to_end(&mut contents) {
-            println!("Error reading file {}: {}", file_path.display(), e);
-            return Ok(None);
-        }
 
         // Process HTML content note (leave processing to caller if enabled)
         if mime_type.starts_with("text/html") {
             if let Ok(_content_string) = String::from_utf8(contents.clone()) {
                 #[cfg(feature = "extensions")]
                 {
-                    println!("DEBUG: HTML file detected, but extension processing needs to be done in calling code");
+                    log::debug!(target: "files", "HTML file detected, but extension processing needs to be done in calling code");
                 }
             }
         }
@@ -747,13 +857,33 @@ impl SecureFileServer {
         response.body = contents;
         response.set_content_length();
 
-        println!("Successfully served file: {} ({} bytes)", file_path.display(), cache_info.size);
+        log::debug!(target: "files", "successfully served file: {} ({} bytes)", file_path.display(), cache_info.size);
 
         let response_bytes = response.encode(version, keep_alive);
         Ok(Some(response_bytes))
     }
 
 
+    /// Read a small file whole and offer it to the content cache
+    ///
+    /// # Returns
+    /// * `Option<CachedFile>` - The file contents, or None if it could not be read
+    ///   completely (the caller then falls back to reading from disk)
+    fn read_into_cache(&self, file_path: &Path, metadata: &std::fs::Metadata, cache_info: &FileCacheInfo) -> Option<CachedFile> {
+        let contents = match fs::read(file_path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                log::debug!(target: "files", "error reading file {} for the cache: {}", file_path.display(), e);
+                return None;
+            }
+        };
+        // A file that changed size while being read would not match its validators
+        if contents.len() as u64 != cache_info.size {
+            return None;
+        }
+        Some(self.cache.insert_file(file_path, metadata, contents, cache_info.clone()))
+    }
+
     /// Get MIME type for a path
     pub fn get_mime_type(&self, path: &Path) -> String {
         self.mime_types.get_mime_type(path)
@@ -794,9 +924,30 @@ impl SecureFileServer {
 
     /// Update security configuration
     pub fn update_config(&mut self, config: SecurityConfig) {
+        if config.file_cache_size != self.cache.max_bytes() {
+            self.cache = ContentCache::new(config.file_cache_size);
+        }
+        if config.etag_mode != self.config.etag_mode || config.etag_index != self.config.etag_index {
+            self.etags = Self::etag_index_for(&config);
+        }
+        if config.mime != self.config.mime {
+            self.mime_types = MimeTypes::from_config(&config.mime);
+        }
         self.config = config;
     }
 
+    fn etag_index_for(config: &SecurityConfig) -> Option<EtagIndex> {
+        match config.etag_mode {
+            EtagMode::Metadata => None,
+            EtagMode::Content => Some(EtagIndex::new(config.etag_index.clone())),
+        }
+    }
+
+    /// In-memory cache shared by all clones of this server
+    pub fn content_cache(&self) -> &ContentCache {
+        &self.cache
+    }
+
     /// Generate a default informational page when index.html is missing
     pub fn generate_default_page(&self, domain: &str) -> String {
         format!(
@@ -1007,4 +1158,109 @@ mod tests {
         assert_eq!(mime_types.get_mime_type(Path::new("test.wasm")), "application/wasm");
         assert_eq!(mime_types.get_mime_type(Path::new("test.unknown")), "application/octet-stream");
     }
+
+    #[test]
+    fn test_is_domain_safe() {
+        assert!(SecureFileServer::is_domain_safe("example.com"));
+        assert!(SecureFileServer::is_domain_safe("my-site.example.com"));
+        assert!(SecureFileServer::is_domain_safe("xn--bcher-kva.example"));
+        assert!(!SecureFileServer::is_domain_safe("localhost"));
+        assert!(!SecureFileServer::is_domain_safe("../example.com"));
+        assert!(!SecureFileServer::is_domain_safe("example..com"));
+        assert!(!SecureFileServer::is_domain_safe("-bad.example.com"));
+        assert!(!SecureFileServer::is_domain_safe("[::1]"));
+    }
+
+    fn make_site(name: &str, files: &[&str]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("easyp_sfs_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(&path).unwrap().write_all(file.as_bytes()).unwrap();
+        }
+        root
+    }
+
+    fn get(server: &SecureFileServer, path: &str) -> Option<String> {
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        server
+            .serve_file_with_domain_and_caching(path, None, &request, &HttpVersion::Http11, false)
+            .unwrap()
+            .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    #[test]
+    fn test_try_files_clean_urls() {
+        let root = make_site("clean", &["about.html", "docs.html", "docs/index.html", "guide/index.htm"]);
+        let mut config = SecurityConfig { document_root: root, ..SecurityConfig::default() };
+        config.try_files.enable_clean_urls();
+        let server = SecureFileServer::new(config);
+
+        assert!(get(&server, "/about").unwrap().ends_with("about.html"));
+        // docs.html wins over the docs/ directory, so no trailing-slash redirect
+        assert!(get(&server, "/docs").unwrap().ends_with("docs.html"));
+        assert_eq!(server.check_redirect("/docs"), None);
+        assert!(get(&server, "/docs/").unwrap().ends_with("docs/index.html"));
+        assert!(get(&server, "/guide/").unwrap().ends_with("guide/index.htm"));
+        assert_eq!(server.check_redirect("/guide?x=1").as_deref(), Some("/guide/?x=1"));
+        assert_eq!(server.check_redirect("/about.html").as_deref(), Some("/about"));
+        assert_eq!(server.check_redirect("/docs/index.html").as_deref(), Some("/docs/"));
+        assert!(get(&server, "/missing").is_none());
+    }
+
+    #[test]
+    fn test_try_files_spa_and_status() {
+        let root = make_site("spa", &["index.html", "app.js"]);
+        let mut config = SecurityConfig { document_root: root, ..SecurityConfig::default() };
+        config.try_files.spa_shell = Some("/index.html".to_string());
+        config.try_files.add_location_spec("/api=$uri,=404").unwrap();
+        config.try_files.set_index_files("default.html").unwrap();
+        let server = SecureFileServer::new(config);
+
+        let shell = get(&server, "/dashboard/settings").unwrap();
+        assert!(shell.starts_with("HTTP/1.1 200 OK"));
+        assert!(shell.ends_with("index.html"));
+        assert!(get(&server, "/missing.js").is_none());
+        assert!(get(&server, "/api/users").unwrap().starts_with("HTTP/1.1 404 Not Found"));
+        // index.html is not in the index list, but the SPA shell still covers "/"
+        assert!(get(&server, "/").unwrap().ends_with("index.html"));
+    }
+
+    #[test]
+    fn test_fastcgi_scripts_not_served_as_source() {
+        let root = make_site("php_source", &["index.php", "page.html"]);
+        let mut config = SecurityConfig { document_root: root, ..SecurityConfig::default() };
+        config.blocked_extensions.push("php".to_string());
+        let server = SecureFileServer::new(config);
+
+        assert!(get(&server, "/page.html").unwrap().ends_with("page.html"));
+        for path in ["/index.php", "/index%2Ephp", "/index%2ephp", "/index.ph%70", "/INDEX.PH%50", "/./index.php"] {
+            assert!(get(&server, path).is_none_or(|response| !response.ends_with("index.php")), "{} served as source", path);
+        }
+    }
+
+    #[test]
+    fn test_canonical_location() {
+        let request = "GET /docs/page.html?x=1 HTTP/1.1\r\nHost: www.example.com:9443\r\n\r\n";
+        assert_eq!(canonical_location(request, "example.com", "/docs/page.html"), "//example.com:9443/docs/page.html?x=1");
+
+        let request = "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        assert_eq!(canonical_location(request, "www.example.com", "/"), "//www.example.com/");
+    }
+
+    #[test]
+    fn test_unknown_host_policy() {
+        let mut config = SecurityConfig::default();
+        config.vhosts.root_template = "/nonexistent/easyp/{domain}".to_string();
+        config.vhosts.unknown_host = UnknownHostPolicy::Misdirected;
+        let server = SecureFileServer::new(config);
+
+        let request = "GET / HTTP/1.1\r\nHost: unknown.example\r\n\r\n";
+        let response = server
+            .serve_file_with_domain_and_caching("/", Some("unknown.example"), request, &HttpVersion::Http11, false)
+            .unwrap()
+            .unwrap();
+        assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 421 Misdirected Request"));
+    }
 }
@@ -0,0 +1,662 @@
+//! Static Certificates
+//!
+//! This module serves certificates from PEM files named in the configuration, for
+//! domains whose certificates come from elsewhere (a corporate CA, a manual purchase).
+//! It provides:
+//! - [`CertSpec`]: one `--cert PATTERN=CERT,KEY` entry, where PATTERN is a host name or a
+//!   single-label wildcard such as `*.example.com`
+//! - [`SniMap`]: exact and wildcard host lookup
+//! - [`WatchedCert`]: a certificate that is reloaded when its files are replaced
+//! - [`StaticCertResolver`]: a rustls resolver that answers from the map, uses a default
+//!   certificate for clients without SNI and hands every other name to a fallback
+//!   resolver (normally the on-demand ACME resolver)
+//! - [`spawn_cert_reloader`]: a background thread that polls resolvers for changed files
+//!
+//! With an [`OcspStapler`] attached, every certificate served from here carries its OCSP
+//! response, and in must-staple mode a certificate whose response is missing or expired
+//! is not served at all.
+//!
+//! Static entries always win over the fallback, so ACME is never asked about a name
+//! that has a configured certificate.
+//!
+//! Reloads are atomic: the new pair is loaded and its key checked against the
+//! certificate before it replaces the old one, and a failed reload keeps serving the
+//! previous certificate. Each newly loaded certificate's expiry is logged and published
+//! to the metrics module.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+
+use super::access_log::utc_parts;
+use super::hostname::normalize_hostname;
+use super::metrics;
+use super::ocsp::{OcspStapler, Staple};
+use super::x509::{parse_certificate, CertInfo};
+
+/// How often [`spawn_cert_reloader`] looks at certificate files by default
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Reads a certificate chain and key from PEM files (cert path, key path)
+pub type CertLoader = dyn Fn(&str, &str) -> Result<CertifiedKey, String> + Send + Sync;
+
+/// A stapled copy of a certificate and the response it carries
+type StapledEntry = (Arc<Vec<u8>>, Arc<CertifiedKey>);
+
+/// Stapled copies kept before the map is rebuilt from scratch
+const MAX_STAPLED: usize = 1024;
+
+/// One configured certificate and the names it is served for
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertSpec {
+    /// Host name or `*.` wildcard, lowercase
+    pub pattern: String,
+    /// PEM certificate chain (leaf first)
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+}
+
+impl CertSpec {
+    /// Parse `PATTERN=CERT,KEY`
+    ///
+    /// # Arguments
+    /// * `value` - Command-line value, e.g. `*.corp.example=/etc/ssl/corp.pem,/etc/ssl/corp.key`
+    ///
+    /// # Returns
+    /// * `Result<CertSpec, String>` - The entry, or a description of what is wrong
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (pattern, files) = value
+            .split_once('=')
+            .ok_or_else(|| format!("invalid certificate '{}': expected PATTERN=CERT,KEY", value))?;
+        let (cert, key) = parse_pair(files).ok_or_else(|| format!("invalid certificate '{}': expected PATTERN=CERT,KEY", value))?;
+        Ok(Self { pattern: normalize_pattern(pattern)?, cert, key })
+    }
+}
+
+/// Parse `CERT,KEY` (used on its own by `--default-cert`)
+pub fn parse_pair(value: &str) -> Option<(PathBuf, PathBuf)> {
+    let (cert, key) = value.split_once(',')?;
+    if cert.trim().is_empty() || key.trim().is_empty() {
+        return None;
+    }
+    Some((PathBuf::from(cert.trim()), PathBuf::from(key.trim())))
+}
+
+/// Validate and lowercase a host name or `*.` wildcard
+fn normalize_pattern(pattern: &str) -> Result<String, String> {
+    match pattern.trim().strip_prefix("*.") {
+        Some(base) => {
+            let base = normalize_hostname(base).map_err(|e| format!("invalid certificate pattern '{}': {}", pattern, e))?;
+            Ok(format!("*.{}", base))
+        }
+        None => normalize_hostname(pattern).map_err(|e| format!("invalid certificate pattern '{}': {}", pattern, e)),
+    }
+}
+
+/// Host name lookup with exact entries and single-label wildcards
+///
+/// `*.example.com` matches `www.example.com` but neither `example.com` nor
+/// `a.b.example.com`, as for certificate wildcards. Exact entries win over wildcards.
+#[derive(Debug, Clone)]
+pub struct SniMap<T> {
+    exact: HashMap<String, T>,
+    /// Keyed by the part after `*.`
+    wildcard: HashMap<String, T>,
+}
+
+impl<T> Default for SniMap<T> {
+    fn default() -> Self {
+        Self { exact: HashMap::new(), wildcard: HashMap::new() }
+    }
+}
+
+impl<T> SniMap<T> {
+    /// Add an entry; `pattern` must already be normalized (see [`CertSpec::parse`])
+    pub fn insert(&mut self, pattern: &str, value: T) {
+        match pattern.strip_prefix("*.") {
+            Some(base) => self.wildcard.insert(base.to_string(), value),
+            None => self.exact.insert(pattern.to_string(), value),
+        };
+    }
+
+    /// Find the entry for a host name
+    pub fn get(&self, host: &str) -> Option<&T> {
+        let host = normalize_hostname(host).ok()?;
+        if let Some(value) = self.exact.get(&host) {
+            return Some(value);
+        }
+        let (_, parent) = host.split_once('.')?;
+        self.wildcard.get(parent)
+    }
+
+    /// Number of patterns
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.wildcard.len()
+    }
+
+    /// Whether no patterns are configured
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Identity of a file's contents as far as the file system tells us
+///
+/// Replacing a file by rename changes the inode; rewriting it in place changes the
+/// modification time or length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    inode: u64,
+}
+
+impl FileStamp {
+    /// Stamp of the file at `path`, None if it does not exist (e.g. mid-replacement)
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+        Some(Self { modified: metadata.modified().ok(), len: metadata.len(), inode })
+    }
+}
+
+/// Load a pair with `loader` and check that the key belongs to the leaf certificate
+///
+/// A key whose match cannot be determined is refused as well.
+///
+/// # Returns
+/// * `Result<(CertInfo, CertifiedKey), String>` - The parsed leaf and the loaded pair, or why the pair is unusable
+fn load_pair(cert: &Path, key: &Path, loader: &CertLoader) -> Result<(CertInfo, CertifiedKey), String> {
+    let certified_key = loader(&cert.to_string_lossy(), &key.to_string_lossy())?;
+    certified_key
+        .keys_match()
+        .map_err(|e| format!("{} does not match the certificate in {}: {}", key.display(), cert.display(), e))?;
+    let leaf = certified_key.end_entity_cert().map_err(|e| format!("{}: {}", cert.display(), e))?;
+    let info = parse_certificate(leaf.as_ref()).map_err(|e| format!("{}: {}", cert.display(), e))?;
+    Ok((info, certified_key))
+}
+
+/// Log the expiry of a newly loaded certificate and publish it for each of its names
+///
+/// # Arguments
+/// * `info` - The leaf certificate
+/// * `source` - File it came from, for the log line and as the label when it has no DNS names
+fn publish_expiry(info: &CertInfo, source: &Path) {
+    let (year, month, day, ..) = utc_parts(UNIX_EPOCH + Duration::from_secs(info.not_after.max(0) as u64));
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let days_left = (info.not_after - now) / 86400;
+    if info.not_after <= now {
+        log::warn!(target: "tls", "loaded certificate {} expired on {:04}-{:02}-{:02}", source.display(), year, month, day);
+    } else {
+        log::info!(target: "tls", "loaded certificate {} for [{}], valid until {:04}-{:02}-{:02} ({} days)",
+            source.display(), info.dns_names.join(", "), year, month, day, days_left);
+    }
+
+    if info.dns_names.is_empty() {
+        if let Some(dir) = source.parent().and_then(|dir| dir.file_name()) {
+            metrics::set_certificate_expiry(&dir.to_string_lossy(), info.not_after);
+        }
+    }
+    for name in &info.dns_names {
+        metrics::set_certificate_expiry(name, info.not_after);
+    }
+}
+
+/// A certificate loaded from a pair of PEM files and replaced when they change
+#[derive(Debug)]
+pub struct WatchedCert {
+    cert: PathBuf,
+    key: PathBuf,
+    /// Stamps of the files last tried, successfully or not, so a bad pair is reported once
+    stamps: Mutex<(Option<FileStamp>, Option<FileStamp>)>,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl WatchedCert {
+    /// Wrap a certificate that was just loaded from (or written to) `cert` and `key`
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>, certified_key: CertifiedKey) -> Self {
+        let (cert, key) = (cert.into(), key.into());
+        let stamps = (FileStamp::of(&cert), FileStamp::of(&key));
+        Self { cert, key, stamps: Mutex::new(stamps), current: RwLock::new(Arc::new(certified_key)) }
+    }
+
+    /// Load a certificate, checking that the key matches and publishing its expiry
+    ///
+    /// # Returns
+    /// * `Result<WatchedCert, String>` - The certificate, or why it could not be used
+    pub fn load(cert: &Path, key: &Path, loader: &CertLoader) -> Result<Self, String> {
+        let (info, certified_key) = load_pair(cert, key, loader)?;
+        publish_expiry(&info, cert);
+        Ok(Self::new(cert, key, certified_key))
+    }
+
+    /// PEM certificate chain file
+    pub fn cert_path(&self) -> &Path {
+        &self.cert
+    }
+
+    /// Certificate to serve now
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Reload the pair if either file changed since the last attempt
+    ///
+    /// # Returns
+    /// * `bool` - true if a new certificate is now being served
+    pub fn reload_if_changed(&self, loader: &CertLoader) -> bool {
+        let (Some(cert_stamp), Some(key_stamp)) = (FileStamp::of(&self.cert), FileStamp::of(&self.key)) else {
+            return false;
+        };
+        {
+            let mut stamps = self.stamps.lock().unwrap_or_else(|e| e.into_inner());
+            if *stamps == (Some(cert_stamp), Some(key_stamp)) {
+                return false;
+            }
+            *stamps = (Some(cert_stamp), Some(key_stamp));
+        }
+
+        match load_pair(&self.cert, &self.key, loader) {
+            Ok((info, certified_key)) => {
+                *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(certified_key);
+                publish_expiry(&info, &self.cert);
+                true
+            }
+            Err(e) => {
+                log::warn!(target: "tls", "not reloading {}: {}; keeping the previous certificate", self.cert.display(), e);
+                false
+            }
+        }
+    }
+}
+
+/// Something holding certificates that can be refreshed from disk
+pub trait ReloadCerts: Send + Sync {
+    /// Reload whatever changed
+    ///
+    /// # Returns
+    /// * `usize` - Number of certificates replaced
+    fn reload_certs(&self) -> usize;
+}
+
+/// Poll resolvers for changed certificate files on a background thread
+///
+/// The thread holds weak references and exits once every resolver has been dropped.
+///
+/// # Arguments
+/// * `targets` - Resolvers to poll
+/// * `interval` - Time between polls (see [`RELOAD_INTERVAL`])
+pub fn spawn_cert_reloader(targets: Vec<Weak<dyn ReloadCerts>>, interval: Duration) {
+    if targets.is_empty() {
+        return;
+    }
+    let spawned = std::thread::Builder::new().name("cert-reloader".to_string()).spawn(move || loop {
+        std::thread::sleep(interval);
+        let mut alive = false;
+        for target in targets.iter().filter_map(Weak::upgrade) {
+            alive = true;
+            let reloaded = target.reload_certs();
+            if reloaded > 0 {
+                log::info!(target: "tls", "hot-swapped {} certificate(s)", reloaded);
+            }
+        }
+        if !alive {
+            break;
+        }
+    });
+    if let Err(e) = spawned {
+        log::warn!(target: "tls", "failed to start certificate reloader: {}", e);
+    }
+}
+
+/// Names covered by a certificate loaded from the ACME cache, and the certificate
+type CacheEntry = (Vec<String>, Arc<CertifiedKey>);
+
+/// Certificates written to the ACME cache directory after startup
+///
+/// rustls-acme loads its cache once, so a certificate restored from a backup or placed
+/// there by other tooling would otherwise only be picked up on restart. Files present at
+/// startup are left to the ACME resolver, unless OCSP stapling needs them loaded here;
+/// files that appear or change later are always loaded here and served ahead of it.
+/// Expired certificates are never taken over.
+#[derive(Debug)]
+struct CacheOverlay {
+    dir: PathBuf,
+    stamps: Mutex<HashMap<PathBuf, FileStamp>>,
+    /// Loaded certificates by cert file
+    loaded: Mutex<HashMap<PathBuf, CacheEntry>>,
+    certs: RwLock<SniMap<Arc<CertifiedKey>>>,
+}
+
+impl CacheOverlay {
+    fn new(dir: PathBuf, load_existing: bool) -> Self {
+        let stamps = if load_existing {
+            HashMap::new()
+        } else {
+            Self::scan(&dir).into_iter().filter_map(|(cert, key)| Some((cert.clone(), Self::stamp(&cert, &key)?))).collect()
+        };
+        Self { dir, stamps: Mutex::new(stamps), loaded: Mutex::new(HashMap::new()), certs: RwLock::new(SniMap::default()) }
+    }
+
+    /// (cert, key) pairs: combined `cached_cert*` files and `fullchain.pem` + `privkey.pem`
+    fn scan(dir: &Path) -> Vec<(PathBuf, PathBuf)> {
+        fn visit(dir: &Path, depth: usize, pairs: &mut Vec<(PathBuf, PathBuf)>) {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                return;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let name = entry.file_name().to_string_lossy().to_string();
+                if path.is_dir() {
+                    // acme_lib holds account data, not certificates
+                    if depth > 0 && name != "acme_lib" {
+                        visit(&path, depth - 1, pairs);
+                    }
+                } else if name.starts_with("cached_cert") && !name.ends_with(".ocsp") {
+                    pairs.push((path.clone(), path));
+                } else if name == "fullchain.pem" && dir.join("privkey.pem").exists() {
+                    pairs.push((path, dir.join("privkey.pem")));
+                }
+            }
+        }
+        let mut pairs = Vec::new();
+        visit(dir, 1, &mut pairs);
+        pairs
+    }
+
+    /// Combined stamp of a pair, so that a change to either file is noticed
+    fn stamp(cert: &Path, key: &Path) -> Option<FileStamp> {
+        let cert_stamp = FileStamp::of(cert)?;
+        let key_stamp = FileStamp::of(key)?;
+        Some(FileStamp {
+            modified: cert_stamp.modified.max(key_stamp.modified),
+            len: cert_stamp.len.wrapping_add(key_stamp.len),
+            inode: cert_stamp.inode ^ key_stamp.inode.rotate_left(32),
+        })
+    }
+
+    fn reload(&self, loader: &CertLoader, stapler: Option<&OcspStapler>) -> usize {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        let mut reloaded = 0;
+        for (cert, key) in Self::scan(&self.dir) {
+            let Some(stamp) = Self::stamp(&cert, &key) else {
+                continue;
+            };
+            if self.stamps.lock().unwrap_or_else(|e| e.into_inner()).insert(cert.clone(), stamp) == Some(stamp) {
+                continue;
+            }
+            match load_pair(&cert, &key, loader) {
+                Ok((info, _)) if info.not_after <= now => {
+                    log::debug!(target: "tls", "ignoring expired ACME cache entry {}", cert.display());
+                }
+                Ok((info, certified_key)) => {
+                    publish_expiry(&info, &cert);
+                    if let Some(stapler) = stapler {
+                        stapler.register(&cert);
+                    }
+                    self.loaded.lock().unwrap_or_else(|e| e.into_inner()).insert(cert, (info.dns_names, Arc::new(certified_key)));
+                    reloaded += 1;
+                }
+                Err(e) => log::warn!(target: "tls", "ignoring changed ACME cache entry: {}", e),
+            }
+        }
+
+        if reloaded > 0 {
+            let mut certs = SniMap::default();
+            for (names, certified_key) in self.loaded.lock().unwrap_or_else(|e| e.into_inner()).values() {
+                for name in names {
+                    certs.insert(name, certified_key.clone());
+                }
+            }
+            *self.certs.write().unwrap_or_else(|e| e.into_inner()) = certs;
+        }
+        reloaded
+    }
+
+    fn get(&self, host: &str) -> Option<Arc<CertifiedKey>> {
+        self.certs.read().unwrap_or_else(|e| e.into_inner()).get(host).cloned()
+    }
+}
+
+/// Certificate resolver for statically configured PEM files
+pub struct StaticCertResolver {
+    certs: SniMap<Arc<WatchedCert>>,
+    /// Every distinct watched pair, for reloading
+    watched: Vec<Arc<WatchedCert>>,
+    default_cert: Option<Arc<WatchedCert>>,
+    cache: Option<CacheOverlay>,
+    fallback: Option<Arc<dyn ResolvesServerCert + Send + Sync>>,
+    loader: Box<CertLoader>,
+    stapler: Option<OcspStapler>,
+    /// Stapled copies by leaf certificate DER
+    stapled: Mutex<HashMap<Vec<u8>, StapledEntry>>,
+}
+
+impl std::fmt::Debug for StaticCertResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StaticCertResolver")
+            .field("certs", &self.certs.len())
+            .field("default_cert", &self.default_cert.is_some())
+            .field("cache", &self.cache.as_ref().map(|cache| &cache.dir))
+            .field("fallback", &self.fallback)
+            .field("stapling", &self.stapler.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl StaticCertResolver {
+    /// Load every configured certificate
+    ///
+    /// A file that appears in several entries is read once. Any unreadable file, or a key
+    /// that does not match its certificate, fails the whole load, so a typo is caught at
+    /// startup rather than on the first handshake.
+    ///
+    /// # Arguments
+    /// * `specs` - `--cert` entries
+    /// * `default_cert` - Certificate for clients that send no SNI
+    /// * `fallback` - Resolver for names without an entry
+    /// * `loader` - Reads a certificate chain and key from PEM files; kept for reloads
+    ///
+    /// # Returns
+    /// * `Result<StaticCertResolver, String>` - The resolver, or the first load error
+    pub fn load<F, E>(
+        specs: &[CertSpec],
+        default_cert: Option<&(PathBuf, PathBuf)>,
+        fallback: Option<Arc<dyn ResolvesServerCert + Send + Sync>>,
+        loader: F,
+    ) -> Result<Self, String>
+    where
+        F: Fn(&str, &str) -> Result<CertifiedKey, E> + Send + Sync + 'static,
+        E: std::fmt::Display,
+    {
+        let loader: Box<CertLoader> = Box::new(move |cert: &str, key: &str| loader(cert, key).map_err(|e| e.to_string()));
+        let mut loaded: HashMap<(PathBuf, PathBuf), Arc<WatchedCert>> = HashMap::new();
+        let mut load_pair = |cert: &PathBuf, key: &PathBuf| -> Result<Arc<WatchedCert>, String> {
+            if let Some(watched) = loaded.get(&(cert.clone(), key.clone())) {
+                return Ok(watched.clone());
+            }
+            let watched = WatchedCert::load(cert, key, &*loader)
+                .map(Arc::new)
+                .map_err(|e| format!("Failed to load certificate {} / {}: {}", cert.display(), key.display(), e))?;
+            loaded.insert((cert.clone(), key.clone()), watched.clone());
+            Ok(watched)
+        };
+
+        let mut certs = SniMap::default();
+        for spec in specs {
+            certs.insert(&spec.pattern, load_pair(&spec.cert, &spec.key)?);
+            log::info!(target: "tls", "serving {} with the certificate from {}", spec.pattern, spec.cert.display());
+        }
+        let default_cert = match default_cert {
+            Some((cert, key)) => Some(load_pair(cert, key)?),
+            None => None,
+        };
+        let watched = loaded.into_values().collect();
+        Ok(Self { certs, watched, default_cert, cache: None, fallback, loader, stapler: None, stapled: Mutex::new(HashMap::new()) })
+    }
+
+    /// Staple OCSP responses to the certificates served from here
+    ///
+    /// Call before [`StaticCertResolver::watch_cache_dir`], so that ACME certificates
+    /// already in the cache are taken over and stapled too.
+    pub fn with_stapler(mut self, stapler: OcspStapler) -> Self {
+        for watched in &self.watched {
+            stapler.register(watched.cert_path());
+        }
+        self.stapler = Some(stapler);
+        self
+    }
+
+    /// Also serve certificates that change in an ACME cache directory after startup
+    ///
+    /// # Arguments
+    /// * `dir` - The ACME cache directory in use (e.g. `<cache-dir>/production`)
+    pub fn watch_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        let cache = CacheOverlay::new(dir.into(), self.stapler.is_some());
+        if self.stapler.is_some() {
+            cache.reload(&*self.loader, self.stapler.as_ref());
+        }
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Attach the current OCSP response to a certificate
+    ///
+    /// # Returns
+    /// * `Option<Arc<CertifiedKey>>` - The certificate to serve, None in must-staple mode
+    ///   when it has no fresh response
+    fn with_staple(&self, certified_key: Arc<CertifiedKey>) -> Option<Arc<CertifiedKey>> {
+        let Some(stapler) = &self.stapler else {
+            return Some(certified_key);
+        };
+        let Ok(leaf) = certified_key.end_entity_cert() else {
+            return Some(certified_key);
+        };
+        match stapler.staple(leaf.as_ref()) {
+            Staple::Fresh(response) => {
+                let mut stapled = self.stapled.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some((cached_response, cached_key)) = stapled.get(leaf.as_ref()) {
+                    if Arc::ptr_eq(cached_response, &response) {
+                        return Some(cached_key.clone());
+                    }
+                }
+                let mut with_response = (*certified_key).clone();
+                with_response.ocsp = Some(response.to_vec());
+                let with_response = Arc::new(with_response);
+                if stapled.len() >= MAX_STAPLED {
+                    stapled.clear();
+                }
+                stapled.insert(leaf.to_vec(), (response, with_response.clone()));
+                Some(with_response)
+            }
+            Staple::Missing if stapler.must_staple() => {
+                log::debug!(target: "tls", "must-staple: no fresh OCSP response, refusing the certificate");
+                None
+            }
+            _ => Some(certified_key),
+        }
+    }
+}
+
+impl ReloadCerts for StaticCertResolver {
+    fn reload_certs(&self) -> usize {
+        let mut reloaded = self.watched.iter().filter(|watched| watched.reload_if_changed(&*self.loader)).count();
+        if let Some(cache) = &self.cache {
+            reloaded += cache.reload(&*self.loader, self.stapler.as_ref());
+        }
+        reloaded
+    }
+}
+
+impl ResolvesServerCert for StaticCertResolver {
+    fn resolve(&self, client_hello: &ClientHello<'_>) -> Result<rustls::sign::CertifiedSigner, rustls::Error> {
+        let certified_key = match client_hello.server_name() {
+            Some(server_name) => self
+                .certs
+                .get(server_name.as_ref())
+                .map(|watched| watched.current())
+                .or_else(|| self.cache.as_ref()?.get(server_name.as_ref())),
+            None => self.default_cert.as_ref().map(|watched| watched.current()),
+        };
+        if let Some(certified_key) = certified_key {
+            let certified_key = self.with_staple(certified_key).ok_or(rustls::Error::NoSuitableCertificate)?;
+            return certified_key.signer(client_hello.signature_schemes()).ok_or(rustls::Error::NoSuitableCertificate);
+        }
+        match &self.fallback {
+            Some(fallback) => fallback.resolve(client_hello),
+            None => Err(rustls::Error::NoSuitableCertificate),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cert_spec() {
+        let spec = CertSpec::parse("*.Corp.Example.=/etc/ssl/corp.pem, /etc/ssl/corp.key").unwrap();
+        assert_eq!(spec.pattern, "*.corp.example");
+        assert_eq!(spec.cert, PathBuf::from("/etc/ssl/corp.pem"));
+        assert_eq!(spec.key, PathBuf::from("/etc/ssl/corp.key"));
+
+        assert!(CertSpec::parse("example.com=/only/cert.pem").is_err());
+        assert!(CertSpec::parse("/a.pem,/b.key").is_err());
+        assert!(CertSpec::parse("bad_host!=/a.pem,/b.key").is_err());
+    }
+
+    #[test]
+    fn test_sni_map_matching() {
+        let mut map = SniMap::default();
+        map.insert("*.example.com", "wildcard");
+        map.insert("api.example.com", "exact");
+        map.insert("example.org", "org");
+
+        assert_eq!(map.get("API.example.com"), Some(&"exact"));
+        assert_eq!(map.get("www.example.com"), Some(&"wildcard"));
+        assert_eq!(map.get("www.example.com."), Some(&"wildcard"));
+        assert_eq!(map.get("example.com"), None);
+        assert_eq!(map.get("a.b.example.com"), None);
+        assert_eq!(map.get("example.org"), Some(&"org"));
+        assert_eq!(map.get("other.net"), None);
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn test_cache_overlay_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let domain = dir.path().join("example.com");
+        std::fs::create_dir(&domain).unwrap();
+        std::fs::create_dir(dir.path().join("acme_lib")).unwrap();
+        std::fs::write(dir.path().join("cached_cert_abc"), "combined").unwrap();
+        std::fs::write(domain.join("fullchain.pem"), "chain").unwrap();
+        std::fs::write(domain.join("privkey.pem"), "key").unwrap();
+        std::fs::write(dir.path().join("acme_lib").join("cached_cert_account"), "account").unwrap();
+        std::fs::write(dir.path().join("fullchain.pem"), "chain without key").unwrap();
+
+        let mut pairs = CacheOverlay::scan(dir.path());
+        pairs.sort();
+        assert_eq!(
+            pairs,
+            vec![
+                (dir.path().join("cached_cert_abc"), dir.path().join("cached_cert_abc")),
+                (domain.join("fullchain.pem"), domain.join("privkey.pem")),
+            ]
+        );
+
+        // Rewriting either file of a pair changes its stamp
+        let before = CacheOverlay::stamp(&domain.join("fullchain.pem"), &domain.join("privkey.pem")).unwrap();
+        std::fs::write(domain.join("privkey.pem"), "a longer key").unwrap();
+        let after = CacheOverlay::stamp(&domain.join("fullchain.pem"), &domain.join("privkey.pem")).unwrap();
+        assert_ne!(before, after);
+        assert!(FileStamp::of(&dir.path().join("missing.pem")).is_none());
+    }
+}
@@ -0,0 +1,225 @@
+//! TLS-ALPN-01 Challenges
+//!
+//! This module answers ACME TLS-ALPN-01 validation (RFC 8737) on the HTTPS port, for
+//! hosts where port 80 is closed. It provides:
+//! - [`challenge_certificate`]: a self-signed certificate for one domain carrying the
+//!   critical acmeIdentifier extension, generated with rcgen
+//! - [`TlsAlpnChallenges`]: the challenge certificates currently offered, keyed by domain
+//! - [`TlsAlpnResolver`]: a rustls resolver that serves those certificates to clients
+//!   offering only the `acme-tls/1` protocol and hands every other handshake to the
+//!   normal resolver
+//!
+//! Key authorizations come either from [`TlsAlpnChallenges::insert`] or from a lookup
+//! function asked at handshake time (normally the ACME client's challenge storage); a
+//! certificate is generated once per key authorization and reused for the repeated
+//! connections ACME servers make from several vantage points. Validation connections
+//! carry no application data, and are closed as soon as the handshake completes.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use rcgen::{CertificateParams, CustomExtension, DistinguishedName, KeyPair};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+
+use super::acme_challenge::{acme_identifier, ACME_TLS_ALPN_PROTOCOL};
+use super::hostname::normalize_hostname;
+use super::metrics;
+
+/// Finds the current key authorization for a domain, if a challenge is pending
+pub type KeyAuthorizationLookup = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+/// Whether a ClientHello is a TLS-ALPN-01 validation attempt, which offers only `acme-tls/1`
+pub fn is_challenge_hello(client_hello: &ClientHello<'_>) -> bool {
+    client_hello
+        .alpn()
+        .is_some_and(|protocols| protocols.collect::<Vec<&[u8]>>() == [ACME_TLS_ALPN_PROTOCOL])
+}
+
+/// Build the validation certificate for a domain
+///
+/// # Arguments
+/// * `domain` - The identifier being validated
+/// * `key_authorization` - `token.thumbprint` from the ACME challenge
+///
+/// # Returns
+/// * `Result<CertifiedKey, String>` - A fresh key and self-signed certificate
+pub fn challenge_certificate(domain: &str, key_authorization: &str) -> Result<CertifiedKey, String> {
+    let key_pair = KeyPair::generate().map_err(|e| format!("Failed to generate challenge key: {}", e))?;
+    let mut params = CertificateParams::new(vec![domain.to_string()]).map_err(|e| format!("Invalid challenge domain {}: {}", domain, e))?;
+    params.distinguished_name = DistinguishedName::new();
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(&acme_identifier(key_authorization))];
+    let cert = params.self_signed(&key_pair).map_err(|e| format!("Failed to create challenge certificate: {}", e))?;
+
+    let cert_chain = vec![CertificateDer::from(cert.der().to_vec())];
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+    CertifiedKey::from_der(cert_chain.into(), key, &rustls::crypto::ring::default_provider())
+        .map_err(|e| format!("Failed to create CertifiedKey: {}", e))
+}
+
+/// A generated certificate and the key authorization it proves
+struct ChallengeCert {
+    key_authorization: String,
+    certified_key: Arc<CertifiedKey>,
+}
+
+/// Challenge certificates by (normalized) domain
+#[derive(Default)]
+pub struct TlsAlpnChallenges {
+    certs: RwLock<HashMap<String, ChallengeCert>>,
+    lookup: Option<Box<KeyAuthorizationLookup>>,
+}
+
+impl std::fmt::Debug for TlsAlpnChallenges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsAlpnChallenges")
+            .field("domains", &self.certs.read().map(|certs| certs.keys().cloned().collect::<Vec<_>>()).unwrap_or_default())
+            .field("lookup", &self.lookup.is_some())
+            .finish()
+    }
+}
+
+impl TlsAlpnChallenges {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask `lookup` for the key authorization of domains seen in validation handshakes
+    pub fn with_lookup(mut self, lookup: impl Fn(&str) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.lookup = Some(Box::new(lookup));
+        self
+    }
+
+    /// Offer a challenge certificate for a domain, replacing any earlier one
+    pub fn insert(&self, domain: &str, key_authorization: &str) -> Result<Arc<CertifiedKey>, String> {
+        let domain = normalize_hostname(domain)?;
+        let certified_key = Arc::new(challenge_certificate(&domain, key_authorization)?);
+        log::debug!(target: "acme", domain:% = domain; "TLS-ALPN-01 challenge certificate ready");
+        self.certs.write().unwrap().insert(domain, ChallengeCert { key_authorization: key_authorization.to_string(), certified_key: certified_key.clone() });
+        Ok(certified_key)
+    }
+
+    /// Stop offering the challenge certificate for a domain
+    pub fn remove(&self, domain: &str) {
+        if let Ok(domain) = normalize_hostname(domain) {
+            self.certs.write().unwrap().remove(&domain);
+        }
+    }
+
+    /// The certificate to present to a validation connection for `domain`
+    ///
+    /// With a lookup function, a certificate is dropped as soon as the lookup no longer
+    /// reports a pending challenge for its domain.
+    ///
+    /// # Returns
+    /// * `Option<Arc<CertifiedKey>>` - None if no challenge is pending for the domain
+    pub fn certificate(&self, domain: &str) -> Option<Arc<CertifiedKey>> {
+        let domain = normalize_hostname(domain).ok()?;
+        let stored = self.certs.read().unwrap().get(&domain).map(|cert| (cert.key_authorization.clone(), cert.certified_key.clone()));
+        let Some(lookup) = self.lookup.as_ref() else {
+            return stored.map(|(_, certified_key)| certified_key);
+        };
+        match lookup(&domain) {
+            Some(key_authorization) => match stored {
+                Some((stored_authorization, certified_key)) if stored_authorization == key_authorization => Some(certified_key),
+                _ => match self.insert(&domain, &key_authorization) {
+                    Ok(certified_key) => Some(certified_key),
+                    Err(e) => {
+                        log::warn!(target: "acme", domain:% = domain; "TLS-ALPN-01 challenge certificate failed: {}", e);
+                        None
+                    }
+                },
+            },
+            None => {
+                if stored.is_some() {
+                    self.certs.write().unwrap().remove(&domain);
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Serves challenge certificates to `acme-tls/1` handshakes, and asks `inner` otherwise
+pub struct TlsAlpnResolver {
+    challenges: Arc<TlsAlpnChallenges>,
+    inner: Arc<dyn ResolvesServerCert + Send + Sync>,
+}
+
+impl std::fmt::Debug for TlsAlpnResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsAlpnResolver").field("challenges", &self.challenges).finish()
+    }
+}
+
+impl TlsAlpnResolver {
+    pub fn new(challenges: Arc<TlsAlpnChallenges>, inner: Arc<dyn ResolvesServerCert + Send + Sync>) -> Self {
+        Self { challenges, inner }
+    }
+}
+
+impl ResolvesServerCert for TlsAlpnResolver {
+    fn resolve(&self, client_hello: &ClientHello<'_>) -> Result<rustls::sign::CertifiedSigner, rustls::Error> {
+        if !is_challenge_hello(client_hello) {
+            return self.inner.resolve(client_hello);
+        }
+        // A validation handshake never gets a normal certificate, even if no challenge is pending
+        let server_name: Option<&str> = client_hello.server_name().map(|name| name.as_ref());
+        let certified_key = server_name.and_then(|server_name| self.challenges.certificate(server_name));
+        metrics::record_acme_challenge(certified_key.is_some());
+        log::debug!(target: "acme", domain:? = server_name, found = certified_key.is_some(); "TLS-ALPN-01 validation handshake");
+        certified_key
+            .ok_or(rustls::Error::NoSuitableCertificate)?
+            .signer(client_hello.signature_schemes())
+            .ok_or(rustls::Error::NoSuitableCertificate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x509::parse_certificate;
+
+    /// id-pe-acmeIdentifier (1.3.6.1.5.5.7.1.31)
+    const OID_ACME_IDENTIFIER: &[u8] = &[0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x1f];
+
+    #[test]
+    fn test_challenge_certificate() {
+        let certified_key = challenge_certificate("www.example.com", "token.thumbprint").unwrap();
+        let der = certified_key.end_entity_cert().unwrap().as_ref().to_vec();
+        assert_eq!(parse_certificate(&der).unwrap().dns_names, vec!["www.example.com"]);
+        assert!(der.windows(OID_ACME_IDENTIFIER.len()).any(|window| window == OID_ACME_IDENTIFIER));
+        let digest = acme_identifier("token.thumbprint");
+        assert!(der.windows(digest.len()).any(|window| window == digest));
+    }
+
+    #[test]
+    fn test_challenges_follow_lookup() {
+        let current = Arc::new(RwLock::new(Some("first.thumbprint".to_string())));
+        let lookup_current = current.clone();
+        let challenges = TlsAlpnChallenges::new().with_lookup(move |domain| {
+            assert_eq!(domain, "www.example.com");
+            lookup_current.read().unwrap().clone()
+        });
+
+        let first = challenges.certificate("WWW.example.com").unwrap();
+        assert!(Arc::ptr_eq(&first, &challenges.certificate("www.example.com").unwrap()));
+
+        *current.write().unwrap() = Some("second.thumbprint".to_string());
+        let second = challenges.certificate("www.example.com").unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+
+        // Once the challenge is no longer pending its certificate is dropped
+        *current.write().unwrap() = None;
+        assert!(challenges.certificate("www.example.com").is_none());
+        assert!(challenges.certs.read().unwrap().is_empty());
+
+        // Certificates inserted by hand, without a lookup, stay until removed
+        let manual = TlsAlpnChallenges::new();
+        manual.insert("www.example.com", "token.thumbprint").unwrap();
+        assert!(manual.certificate("www.example.com").is_some());
+        manual.remove("www.example.com");
+        assert!(manual.certificate("www.example.com").is_none());
+    }
+}
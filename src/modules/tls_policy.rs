@@ -0,0 +1,351 @@
+//! TLS Policy
+//!
+//! This module builds the single, long-lived rustls `ServerConfig` shared by every HTTPS
+//! connection, so sessions can be resumed across connections. It provides:
+//! - [`TlsPolicy`]: minimum protocol version, cipher suites, key exchange groups, ALPN
+//!   protocols, session cache size, session tickets and 0-RTT
+//! - [`build_server_configs`]: the `ServerConfig` for a policy and certificate resolver,
+//!   plus variants that ask for client certificates when [`ClientAuthConfig`] has a CA
+//! - Process-wide handshake counters by protocol version, full or resumed, for the
+//!   stats panel and metrics
+//!
+//! Cipher suites and groups are named as rustls prints them (`TLS13_AES_128_GCM_SHA256`,
+//! `TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256`, `X25519`, `secp256r1`) and matched
+//! case-insensitively; an empty list keeps the provider's defaults. Session tickets are
+//! encrypted with keys that rustls rotates every six hours, accepting the previous key
+//! for one more period. Early data (0-RTT) can be replayed by an attacker and is off
+//! unless `--tls-early-data` allows it.
+//!
+//! The client certificate variants are chosen per connection from the SNI name (see
+//! [`ClientAuthConfig::handshake_mode`]); each keeps its own session cache and ticket
+//! keys, so a session is only resumed under the same client certificate policy.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use rustls::crypto::CryptoProvider;
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::{HandshakeKind, ResolvesServerCert, ServerConnection, WebPkiClientVerifier};
+use rustls::{ProtocolVersion, RootCertStore, ServerConfig};
+
+use super::acme_challenge::ACME_TLS_ALPN_PROTOCOL;
+use super::client_auth::{ClientAuthConfig, ClientAuthMode};
+use super::metrics;
+
+/// Sessions kept by the stateful resumption cache by default
+pub const DEFAULT_SESSION_CACHE: usize = 4096;
+
+static HANDSHAKES_TLS12: AtomicU64 = AtomicU64::new(0);
+static HANDSHAKES_TLS13: AtomicU64 = AtomicU64::new(0);
+static RESUMED: AtomicU64 = AtomicU64::new(0);
+
+/// Lowest protocol version accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TlsVersion {
+    #[default]
+    Tls12,
+    Tls13,
+}
+
+impl TlsVersion {
+    /// Parse a `--tls-min-version` value (`1.2` or `1.3`)
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().trim_start_matches("tls").trim_start_matches('v') {
+            "1.2" | "12" => Ok(TlsVersion::Tls12),
+            "1.3" | "13" => Ok(TlsVersion::Tls13),
+            _ => Err(format!("invalid TLS version '{}': expected 1.2 or 1.3", value)),
+        }
+    }
+}
+
+/// Settings for the shared `ServerConfig`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsPolicy {
+    pub min_version: TlsVersion,
+    /// Allowed cipher suites; empty keeps the provider's list
+    pub cipher_suites: Vec<String>,
+    /// Allowed key exchange groups, in preference order; empty keeps the provider's list
+    pub kx_groups: Vec<String>,
+    /// ALPN protocols offered, in preference order
+    pub alpn: Vec<String>,
+    /// Stateful session cache entries; 0 disables the cache
+    pub session_cache: usize,
+    /// Issue stateless session tickets
+    pub tickets: bool,
+    /// Largest early data (0-RTT) accepted in bytes; 0 disables it
+    pub early_data: u32,
+    /// Also accept `acme-tls/1`, for TLS-ALPN-01 validation connections
+    pub acme_tls_alpn: bool,
+}
+
+impl Default for TlsPolicy {
+    fn default() -> Self {
+        Self {
+            min_version: TlsVersion::Tls12,
+            cipher_suites: Vec::new(),
+            kx_groups: Vec::new(),
+            alpn: vec!["http/1.1".to_string()],
+            session_cache: DEFAULT_SESSION_CACHE,
+            tickets: true,
+            early_data: 0,
+            acme_tls_alpn: false,
+        }
+    }
+}
+
+/// Split a comma-separated command-line list, dropping empty items
+pub fn parse_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect()
+}
+
+/// Parse an `--alpn` list; only the HTTP/1.x protocols the server speaks may be offered
+///
+/// # Returns
+/// * `Result<Vec<String>, String>` - The protocols in preference order, or the first one refused
+pub fn parse_alpn(value: &str) -> Result<Vec<String>, String> {
+    const SUPPORTED: [&str; 2] = ["http/1.1", "http/1.0"];
+    let protocols = parse_list(value);
+    if protocols.is_empty() {
+        return Err("--alpn needs at least one protocol (http/1.1 or http/1.0)".to_string());
+    }
+    protocols
+        .into_iter()
+        .map(|protocol| match SUPPORTED.iter().find(|supported| supported.eq_ignore_ascii_case(&protocol)) {
+            Some(supported) => Ok(supported.to_string()),
+            None => Err(format!("unsupported ALPN protocol '{}': expected {}", protocol, SUPPORTED.join(" or "))),
+        })
+        .collect()
+}
+
+/// Keep the items whose name is in `wanted`, in the order of `wanted`
+///
+/// # Returns
+/// * `Result<Vec<T>, String>` - The selection, or the first unknown name with the known ones
+fn select<T: Copy>(available: &[T], wanted: &[String], name: impl Fn(&T) -> String, what: &str) -> Result<Vec<T>, String> {
+    wanted
+        .iter()
+        .map(|wanted| {
+            available.iter().find(|item| name(item).eq_ignore_ascii_case(wanted)).copied().ok_or_else(|| {
+                let known: Vec<String> = available.iter().map(&name).collect();
+                format!("unknown {} '{}' (available: {})", what, wanted, known.join(", "))
+            })
+        })
+        .collect()
+}
+
+/// The shared configurations, one per way of asking for a client certificate
+pub struct ServerConfigs {
+    /// No client certificate requested
+    pub default: Arc<ServerConfig>,
+    /// Client certificate requested but not needed to complete the handshake
+    pub optional: Option<Arc<ServerConfig>>,
+    /// Client certificate needed to complete the handshake
+    pub required: Option<Arc<ServerConfig>>,
+}
+
+impl ServerConfigs {
+    /// The configuration for a [`ClientAuthConfig::handshake_mode`] result
+    pub fn for_mode(&self, mode: Option<ClientAuthMode>) -> Arc<ServerConfig> {
+        let config = match mode {
+            Some(ClientAuthMode::Required) => self.required.as_ref(),
+            Some(ClientAuthMode::Optional) => self.optional.as_ref(),
+            None => None,
+        };
+        config.unwrap_or(&self.default).clone()
+    }
+}
+
+/// Build the configurations shared by all HTTPS connections
+///
+/// # Arguments
+/// * `policy` - Versions, algorithms and session settings
+/// * `resolver` - Certificate resolver for every handshake
+/// * `client_auth` - Client certificate CA and rules; without a CA only `default` is built
+///
+/// # Returns
+/// * `Result<ServerConfigs, String>` - The configurations, or why the policy cannot be met
+pub fn build_server_configs(
+    policy: &TlsPolicy,
+    resolver: Arc<dyn ResolvesServerCert + Send + Sync>,
+    client_auth: &ClientAuthConfig,
+) -> Result<ServerConfigs, String> {
+    let provider = Arc::new(build_provider(policy)?);
+    let default = build_server_config(policy, &provider, resolver.clone(), None)?;
+
+    let (optional, required) = match &client_auth.ca_file {
+        Some(ca_file) => {
+            let roots = load_client_roots(ca_file)?;
+            let verifier = |mandatory: bool| {
+                let builder = WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone());
+                let builder = if mandatory { builder } else { builder.allow_unauthenticated() };
+                builder.build().map_err(|e| format!("Failed to create client certificate verifier: {}", e))
+            };
+            let optional = build_server_config(policy, &provider, resolver.clone(), Some(verifier(false)?))?;
+            let required = build_server_config(policy, &provider, resolver, Some(verifier(true)?))?;
+            log::info!(target: "tls", "client certificates verified against {} ({} CA certificates, {} rules)",
+                ca_file.display(), roots.len(), client_auth.rules.len());
+            (Some(optional), Some(required))
+        }
+        None => (None, None),
+    };
+
+    log::info!(target: "tls", "TLS policy: minimum {}, ALPN [{}], session cache {}, tickets {}, 0-RTT {}",
+        if policy.min_version == TlsVersion::Tls13 { "1.3" } else { "1.2" },
+        policy.alpn.join(", "),
+        policy.session_cache,
+        if policy.tickets { "on" } else { "off" },
+        if policy.early_data > 0 { "on" } else { "off" });
+    Ok(ServerConfigs { default, optional, required })
+}
+
+/// Read the CA certificates that client certificates must chain to
+fn load_client_roots(ca_file: &Path) -> Result<Arc<RootCertStore>, String> {
+    let file = std::fs::File::open(ca_file).map_err(|e| format!("Failed to open client CA file {}: {}", ca_file.display(), e))?;
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut std::io::BufReader::new(file)) {
+        let cert = cert.map_err(|e| format!("Failed to read client CA file {}: {}", ca_file.display(), e))?;
+        roots.add(cert).map_err(|e| format!("Invalid CA certificate in {}: {}", ca_file.display(), e))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("No CA certificates found in {}", ca_file.display()));
+    }
+    Ok(Arc::new(roots))
+}
+
+/// The crypto provider restricted to the policy's cipher suites and groups
+fn build_provider(policy: &TlsPolicy) -> Result<CryptoProvider, String> {
+    let mut provider = rustls::crypto::ring::default_provider();
+
+    if !policy.cipher_suites.is_empty() {
+        // A name may be either kind; only unknown names in both lists are an error
+        let tls13: Vec<String> = policy.cipher_suites.iter().filter(|name| name.to_ascii_uppercase().starts_with("TLS13_")).cloned().collect();
+        let tls12: Vec<String> = policy.cipher_suites.iter().filter(|name| !name.to_ascii_uppercase().starts_with("TLS13_")).cloned().collect();
+        provider.tls13_cipher_suites = select(&provider.tls13_cipher_suites, &tls13, |suite| format!("{:?}", suite.common.suite), "cipher suite")?.into();
+        provider.tls12_cipher_suites = select(&provider.tls12_cipher_suites, &tls12, |suite| format!("{:?}", suite.common.suite), "cipher suite")?.into();
+    }
+    if policy.min_version == TlsVersion::Tls13 {
+        provider.tls12_cipher_suites = Vec::new().into();
+    }
+    if provider.tls13_cipher_suites.is_empty() && provider.tls12_cipher_suites.is_empty() {
+        return Err("the TLS policy leaves no cipher suites".to_string());
+    }
+    if !policy.kx_groups.is_empty() {
+        provider.kx_groups = select(&provider.kx_groups, &policy.kx_groups, |group| format!("{:?}", group.name()), "key exchange group")?.into();
+    }
+    Ok(provider)
+}
+
+/// Build one `ServerConfig` with the policy's session settings
+fn build_server_config(
+    policy: &TlsPolicy,
+    provider: &Arc<CryptoProvider>,
+    resolver: Arc<dyn ResolvesServerCert + Send + Sync>,
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+) -> Result<Arc<ServerConfig>, String> {
+    let builder = ServerConfig::builder_with_provider(provider.clone());
+    let builder = match client_verifier {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_cert_resolver(resolver)
+        .map_err(|e| format!("Failed to create server config: {}", e))?;
+
+    config.alpn_protocols = policy.alpn.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+    if policy.acme_tls_alpn {
+        config.alpn_protocols.push(ACME_TLS_ALPN_PROTOCOL.to_vec());
+    }
+    config.session_storage = if policy.session_cache == 0 {
+        Arc::new(rustls::server::NoServerSessionStorage {})
+    } else {
+        rustls::server::ServerSessionMemoryCache::new(policy.session_cache)
+    };
+    if policy.tickets {
+        config.ticketer = rustls::crypto::ring::Ticketer::new().map_err(|e| format!("Failed to create session ticketer: {}", e))?;
+    }
+    config.max_early_data_size = policy.early_data;
+    config.send_half_rtt_data = false;
+    Ok(Arc::new(config))
+}
+
+/// Snapshot of the process-wide handshake counters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TlsStats {
+    pub tls12: u64,
+    pub tls13: u64,
+    /// Handshakes that resumed an earlier session (cache or ticket)
+    pub resumed: u64,
+}
+
+impl TlsStats {
+    /// All completed handshakes
+    pub fn handshakes(&self) -> u64 {
+        self.tls12 + self.tls13
+    }
+
+    /// Fraction of handshakes that were resumptions, if there were any
+    pub fn resumption_ratio(&self) -> Option<f64> {
+        match self.handshakes() {
+            0 => None,
+            total => Some(self.resumed as f64 / total as f64),
+        }
+    }
+}
+
+/// Read the process-wide handshake counters
+pub fn stats() -> TlsStats {
+    TlsStats {
+        tls12: HANDSHAKES_TLS12.load(Ordering::Relaxed),
+        tls13: HANDSHAKES_TLS13.load(Ordering::Relaxed),
+        resumed: RESUMED.load(Ordering::Relaxed),
+    }
+}
+
+/// Count a completed handshake
+///
+/// # Arguments
+/// * `version` - `"1.2"` or `"1.3"`
+/// * `resumed` - Whether an earlier session was resumed
+pub fn record_handshake(version: &'static str, resumed: bool) {
+    match version {
+        "1.3" => HANDSHAKES_TLS13.fetch_add(1, Ordering::Relaxed),
+        _ => HANDSHAKES_TLS12.fetch_add(1, Ordering::Relaxed),
+    };
+    if resumed {
+        RESUMED.fetch_add(1, Ordering::Relaxed);
+    }
+    metrics::record_tls_session(version, resumed);
+}
+
+/// Count the handshake of an established connection
+pub fn record_connection(connection: &ServerConnection) {
+    let version = match connection.protocol_version() {
+        Some(ProtocolVersion::TLSv1_3) => "1.3",
+        _ => "1.2",
+    };
+    record_handshake(version, connection.handshake_kind() == Some(HandshakeKind::Resumed));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy_values() {
+        assert_eq!(TlsVersion::parse("1.3"), Ok(TlsVersion::Tls13));
+        assert_eq!(TlsVersion::parse("TLSv1.2"), Ok(TlsVersion::Tls12));
+        assert!(TlsVersion::parse("1.1").is_err());
+        assert_eq!(parse_list(" h2 , http/1.1,,"), vec!["h2", "http/1.1"]);
+        assert_eq!(parse_alpn("HTTP/1.1, http/1.0"), Ok(vec!["http/1.1".to_string(), "http/1.0".to_string()]));
+        assert!(parse_alpn("h2,http/1.1").unwrap_err().contains("'h2'"));
+        assert!(parse_alpn("acme-tls/1").is_err());
+        assert!(parse_alpn(",").is_err());
+
+        let available = ["X25519", "secp256r1", "secp384r1"];
+        let wanted = parse_list("SECP384R1,x25519");
+        assert_eq!(select(&available, &wanted, |g| g.to_string(), "group"), Ok(vec!["secp384r1", "X25519"]));
+        let error = select(&available, &parse_list("ffdhe2048"), |g| g.to_string(), "group").unwrap_err();
+        assert!(error.contains("ffdhe2048") && error.contains("secp256r1"));
+    }
+}
@@ -0,0 +1,824 @@
+//! Traffic Statistics
+//!
+//! This module keeps long-term request statistics, complementing the 48-hour system
+//! samples in `hourly_stats`. It provides:
+//! - Minute buckets rolled up into hour, day and (calendar, UTC) month buckets, each
+//!   with its own retention (`--stats-retention minute=1440,hour=744,day=400,month=120`)
+//! - Per-bucket request and byte totals, per-status-code and per-domain counters
+//! - A latency histogram per bucket for p50/p90/p99 estimates
+//! - Unique visitors estimated with a HyperLogLog sketch, so no client address is stored
+//! - A compact binary snapshot (varints, sparse sketches) with a checksum, written to a
+//!   temporary file and renamed into place, so a crash loses at most one save interval
+//!
+//! Requests arrive from the access log's `AccessRecorder` through [`record_request`].
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::access_log::utc_parts;
+
+/// Upper bounds (milliseconds) of the latency histogram; one more bucket holds the rest
+pub const LATENCY_BOUNDS_MS: [u64; 13] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+const LATENCY_BUCKETS: usize = LATENCY_BOUNDS_MS.len() + 1;
+
+/// Domains tracked per bucket before the rest are counted as `other`
+pub const MAX_DOMAINS_PER_BUCKET: usize = 500;
+
+/// HyperLogLog precision: 2^10 registers, about 3% standard error
+const HLL_PRECISION: u32 = 10;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+
+const MAGIC: &[u8; 4] = b"EZTS";
+const FORMAT_VERSION: u8 = 1;
+
+/// How often the in-memory series are written to disk
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Granularity of a statistics series
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Resolution {
+    Minute,
+    Hour,
+    Day,
+    Month,
+}
+
+impl Resolution {
+    pub const ALL: [Resolution; 4] = [Resolution::Minute, Resolution::Hour, Resolution::Day, Resolution::Month];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Resolution::Minute => "minute",
+            Resolution::Hour => "hour",
+            Resolution::Day => "day",
+            Resolution::Month => "month",
+        }
+    }
+
+    /// Parse `minute`, `hour`, `day` or `month` (plural forms accepted)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().trim_end_matches('s') {
+            "minute" => Some(Resolution::Minute),
+            "hour" => Some(Resolution::Hour),
+            "day" => Some(Resolution::Day),
+            "month" => Some(Resolution::Month),
+            _ => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Start (Unix seconds, UTC) of the bucket containing `time`
+    pub fn bucket_start(self, time: u64) -> u64 {
+        match self {
+            Resolution::Minute => time - time % 60,
+            Resolution::Hour => time - time % 3600,
+            Resolution::Day => time - time % 86400,
+            Resolution::Month => {
+                let (year, month, ..) = utc_parts(UNIX_EPOCH + Duration::from_secs(time));
+                days_from_civil(year, month, 1).max(0) as u64 * 86400
+            }
+        }
+    }
+
+    /// Human-readable label for a bucket start
+    pub fn label(self, start: u64) -> String {
+        let (year, month, day, hour, minute, _) = utc_parts(UNIX_EPOCH + Duration::from_secs(start));
+        match self {
+            Resolution::Minute => format!("{}-{:02}-{:02} {:02}:{:02}", year, month, day, hour, minute),
+            Resolution::Hour => format!("{}-{:02}-{:02} {:02}:00", year, month, day, hour),
+            Resolution::Day => format!("{}-{:02}-{:02}", year, month, day),
+            Resolution::Month => format!("{}-{:02}", year, month),
+        }
+    }
+}
+
+/// Days since the epoch of a civil date (inverse of `utc_parts`)
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Parse a UTC time: Unix seconds, `YYYY-MM-DD`, or `YYYY-MM-DDTHH:MM[:SS][Z]`
+pub fn parse_time(value: &str) -> Option<u64> {
+    let value = value.trim().trim_end_matches('Z');
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(secs);
+    }
+    let (date, time) = match value.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut seconds = 0u64;
+    if let Some(time) = time {
+        let mut time_parts = time.splitn(3, ':');
+        let hour: u64 = time_parts.next()?.parse().ok()?;
+        let minute: u64 = time_parts.next()?.parse().ok()?;
+        let second: u64 = time_parts.next().map(str::parse).transpose().ok()?.unwrap_or(0);
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        seconds = hour * 3600 + minute * 60 + second;
+    }
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(days * 86400 + seconds)
+}
+
+/// `2024-02-29T13:45:00Z`
+pub fn iso_time(secs: u64) -> String {
+    let (year, month, day, hour, minute, second) = utc_parts(UNIX_EPOCH + Duration::from_secs(secs));
+    format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+}
+
+/// Number of buckets kept per resolution
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Retention {
+    pub minutes: usize,
+    pub hours: usize,
+    pub days: usize,
+    pub months: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            minutes: 24 * 60,
+            hours: 31 * 24,
+            days: 400,
+            months: 120,
+        }
+    }
+}
+
+impl Retention {
+    /// Parse `minute=1440,hour=744,day=400,month=120`; unnamed resolutions keep defaults
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut retention = Self::default();
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, count) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid stats retention '{}': expected <resolution>=<buckets>", part))?;
+            let resolution = Resolution::parse(name)
+                .ok_or_else(|| format!("Unknown stats resolution '{}': expected minute, hour, day or month", name))?;
+            let count: usize = count
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid bucket count '{}' for {}", count, resolution.name()))?;
+            match resolution {
+                Resolution::Minute => retention.minutes = count,
+                Resolution::Hour => retention.hours = count,
+                Resolution::Day => retention.days = count,
+                Resolution::Month => retention.months = count,
+            }
+        }
+        Ok(retention)
+    }
+
+    pub fn keep(&self, resolution: Resolution) -> usize {
+        match resolution {
+            Resolution::Minute => self.minutes,
+            Resolution::Hour => self.hours,
+            Resolution::Day => self.days,
+            Resolution::Month => self.months,
+        }
+    }
+}
+
+/// HyperLogLog sketch for counting distinct visitors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; HLL_REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    /// Add a client address
+    pub fn insert_ip(&mut self, ip: IpAddr) {
+        let hash = match ip {
+            IpAddr::V4(v4) => hash64(&v4.octets()),
+            IpAddr::V6(v6) => hash64(&v6.octets()),
+        };
+        self.insert_hash(hash);
+    }
+
+    fn insert_hash(&mut self, hash: u64) {
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        let rest = hash << HLL_PRECISION;
+        let rank = (rest.leading_zeros() + 1).min(64 - HLL_PRECISION + 1) as u8;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// Combine with another sketch (union of the two visitor sets)
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (mine, theirs) in self.registers.iter_mut().zip(&other.registers) {
+            *mine = (*mine).max(*theirs);
+        }
+    }
+
+    /// Estimated number of distinct entries
+    pub fn estimate(&self) -> u64 {
+        let m = HLL_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            // Linear counting is more accurate for small sets
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+}
+
+/// FNV-1a followed by a SplitMix64 finaliser; stable across builds, unlike `DefaultHasher`
+fn hash64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+/// Requests and bytes for one domain in a bucket
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DomainCounters {
+    pub requests: u64,
+    pub bytes: u64,
+}
+
+/// Everything counted during one minute, hour, day or month
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsBucket {
+    /// Bucket start (Unix seconds, UTC)
+    pub start: u64,
+    pub requests: u64,
+    /// Response body bytes sent
+    pub bytes: u64,
+    /// Requests per status code
+    pub statuses: BTreeMap<u16, u64>,
+    /// Requests and bytes per domain (`-` without a Host header)
+    pub domains: BTreeMap<String, DomainCounters>,
+    /// Requests per latency bucket (see [`LATENCY_BOUNDS_MS`])
+    pub latency: [u64; LATENCY_BUCKETS],
+    pub visitors: HyperLogLog,
+}
+
+impl StatsBucket {
+    pub fn new(start: u64) -> Self {
+        Self {
+            start,
+            requests: 0,
+            bytes: 0,
+            statuses: BTreeMap::new(),
+            domains: BTreeMap::new(),
+            latency: [0; LATENCY_BUCKETS],
+            visitors: HyperLogLog::default(),
+        }
+    }
+
+    fn record(&mut self, domain: &str, status: u16, bytes: u64, duration: Duration, remote: IpAddr) {
+        self.requests += 1;
+        self.bytes += bytes;
+        *self.statuses.entry(status).or_insert(0) += 1;
+        let domain = if self.domains.contains_key(domain) || self.domains.len() < MAX_DOMAINS_PER_BUCKET {
+            domain
+        } else {
+            "other"
+        };
+        let counters = self.domains.entry(domain.to_string()).or_default();
+        counters.requests += 1;
+        counters.bytes += bytes;
+        let micros = duration.as_micros();
+        let slot = LATENCY_BOUNDS_MS
+            .iter()
+            .position(|&bound| micros <= bound as u128 * 1000)
+            .unwrap_or(LATENCY_BOUNDS_MS.len());
+        self.latency[slot] += 1;
+        self.visitors.insert_ip(remote);
+    }
+
+    /// Add another bucket's counts into this one
+    pub fn merge(&mut self, other: &StatsBucket) {
+        self.requests += other.requests;
+        self.bytes += other.bytes;
+        for (status, count) in &other.statuses {
+            *self.statuses.entry(*status).or_insert(0) += count;
+        }
+        for (domain, counters) in &other.domains {
+            let mine = self.domains.entry(domain.clone()).or_default();
+            mine.requests += counters.requests;
+            mine.bytes += counters.bytes;
+        }
+        for (mine, theirs) in self.latency.iter_mut().zip(other.latency) {
+            *mine += theirs;
+        }
+        self.visitors.merge(&other.visitors);
+    }
+
+    /// Requests per status class (1xx to 5xx)
+    pub fn status_classes(&self) -> [u64; 5] {
+        let mut classes = [0; 5];
+        for (status, count) in &self.statuses {
+            let class = (*status as usize / 100).clamp(1, 5) - 1;
+            classes[class] += count;
+        }
+        classes
+    }
+
+    /// Estimated latency percentile in milliseconds (`quantile` in 0..=1)
+    ///
+    /// Interpolates within the histogram bucket; requests slower than the last bound
+    /// are reported at that bound.
+    pub fn latency_percentile(&self, quantile: f64) -> Option<f64> {
+        let total: u64 = self.latency.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let target = (quantile.clamp(0.0, 1.0) * total as f64).max(1.0);
+        let mut seen = 0u64;
+        for (slot, &count) in self.latency.iter().enumerate() {
+            if count > 0 && (seen + count) as f64 >= target {
+                let lower = if slot == 0 { 0 } else { LATENCY_BOUNDS_MS[slot - 1] } as f64;
+                let Some(&upper) = LATENCY_BOUNDS_MS.get(slot) else {
+                    return Some(lower);
+                };
+                let fraction = (target - seen as f64) / count as f64;
+                return Some(lower + (upper as f64 - lower) * fraction);
+            }
+            seen += count;
+        }
+        None
+    }
+
+    /// Estimated distinct client addresses
+    pub fn unique_visitors(&self) -> u64 {
+        self.visitors.estimate()
+    }
+}
+
+/// Minute, hour, day and month series with retention and on-disk snapshots
+pub struct TrafficStats {
+    series: Mutex<[VecDeque<StatsBucket>; 4]>,
+    retention: Retention,
+    path: Option<PathBuf>,
+    dirty: AtomicBool,
+}
+
+impl TrafficStats {
+    /// Create the series, loading a previous snapshot from `path` if there is one
+    ///
+    /// # Arguments
+    /// * `path` - Snapshot file (None keeps statistics in memory only)
+    /// * `retention` - Buckets kept per resolution
+    pub fn new(path: Option<PathBuf>, retention: Retention) -> Self {
+        let stats = Self {
+            series: Mutex::new(Default::default()),
+            retention,
+            path,
+            dirty: AtomicBool::new(false),
+        };
+        if let Some(ref path) = stats.path {
+            if let Err(e) = stats.load(path) {
+                log::warn!(target: "stats", "failed to load traffic statistics from {}: {}", path.display(), e);
+                // Keep the unreadable snapshot for inspection instead of overwriting it
+                let _ = fs::rename(path, path.with_extension("corrupt"));
+            }
+        }
+        stats
+    }
+
+    /// Count one request in every resolution
+    ///
+    /// # Arguments
+    /// * `time` - When the request started
+    /// * `host` - Host header without port
+    /// * `status` - Response status code
+    /// * `bytes` - Response body bytes sent
+    /// * `duration` - Request duration
+    /// * `remote` - Client address (only hashed into the visitor sketch)
+    pub fn record(&self, time: SystemTime, host: Option<&str>, status: u16, bytes: u64, duration: Duration, remote: IpAddr) {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let domain = host.filter(|h| !h.is_empty()).map(str::to_lowercase).unwrap_or_else(|| "-".to_string());
+        let mut series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        for resolution in Resolution::ALL {
+            let keep = self.retention.keep(resolution);
+            if keep == 0 {
+                continue;
+            }
+            let buckets = &mut series[resolution.index()];
+            let bucket = bucket_for(buckets, resolution.bucket_start(secs));
+            bucket.record(&domain, status, bytes, duration, remote);
+            while buckets.len() > keep {
+                buckets.pop_front();
+            }
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Buckets of one resolution starting in `from..to` (Unix seconds), oldest first
+    pub fn series(&self, resolution: Resolution, from: u64, to: u64) -> Vec<StatsBucket> {
+        let series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        series[resolution.index()]
+            .iter()
+            .filter(|bucket| bucket.start >= from && bucket.start < to)
+            .cloned()
+            .collect()
+    }
+
+    /// Write a snapshot if anything changed since the last one
+    pub fn save(&self) -> Result<(), String> {
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let data = {
+            let series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+            encode(&series)
+        };
+        write_atomically(path, &data).inspect_err(|_| self.dirty.store(true, Ordering::Relaxed))
+    }
+
+    fn load(&self, path: &Path) -> Result<(), String> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.to_string()),
+        };
+        let mut loaded = decode(&data)?;
+        for resolution in Resolution::ALL {
+            let buckets = &mut loaded[resolution.index()];
+            while buckets.len() > self.retention.keep(resolution) {
+                buckets.pop_front();
+            }
+        }
+        *self.series.lock().unwrap_or_else(|e| e.into_inner()) = loaded;
+        Ok(())
+    }
+}
+
+/// Find or insert the bucket starting at `start`, keeping the series ordered
+fn bucket_for(buckets: &mut VecDeque<StatsBucket>, start: u64) -> &mut StatsBucket {
+    let position = match buckets.back() {
+        Some(last) if last.start == start => buckets.len() - 1,
+        Some(last) if last.start < start => {
+            buckets.push_back(StatsBucket::new(start));
+            buckets.len() - 1
+        }
+        None => {
+            buckets.push_back(StatsBucket::new(start));
+            0
+        }
+        // The clock went backwards: find or insert the older bucket in place
+        Some(_) => match buckets.binary_search_by_key(&start, |bucket| bucket.start) {
+            Ok(index) => index,
+            Err(index) => {
+                buckets.insert(index, StatsBucket::new(start));
+                index
+            }
+        },
+    };
+    &mut buckets[position]
+}
+
+/// Write to a temporary file, sync, then rename over `path`
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let temp = path.with_extension("tmp");
+    let mut file = fs::File::create(&temp).map_err(|e| format!("Failed to create {}: {}", temp.display(), e))?;
+    file.write_all(data)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("Failed to write {}: {}", temp.display(), e))?;
+    fs::rename(&temp, path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))?;
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        // Make the rename itself durable
+        let _ = fs::File::open(parent).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.data.get(self.pos).ok_or("Truncated statistics file")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Invalid varint in statistics file".to_string())
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len()).ok_or("Truncated statistics file")?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+}
+
+/// Serialise all series: header, buckets, then a checksum of everything before it
+fn encode(series: &[VecDeque<StatsBucket>; 4]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(FORMAT_VERSION);
+    put_varint(&mut out, series.iter().map(|s| s.len() as u64).sum());
+    for resolution in Resolution::ALL {
+        for bucket in &series[resolution.index()] {
+            out.push(resolution.index() as u8);
+            put_varint(&mut out, bucket.start);
+            put_varint(&mut out, bucket.requests);
+            put_varint(&mut out, bucket.bytes);
+            put_varint(&mut out, bucket.statuses.len() as u64);
+            for (status, count) in &bucket.statuses {
+                put_varint(&mut out, *status as u64);
+                put_varint(&mut out, *count);
+            }
+            put_varint(&mut out, bucket.domains.len() as u64);
+            for (domain, counters) in &bucket.domains {
+                put_varint(&mut out, domain.len() as u64);
+                out.extend_from_slice(domain.as_bytes());
+                put_varint(&mut out, counters.requests);
+                put_varint(&mut out, counters.bytes);
+            }
+            for count in bucket.latency {
+                put_varint(&mut out, count);
+            }
+            // Sketches are sparse for quiet buckets: store only non-zero registers
+            let used: Vec<(usize, u8)> =
+                bucket.visitors.registers.iter().enumerate().filter(|(_, &r)| r > 0).map(|(i, &r)| (i, r)).collect();
+            put_varint(&mut out, used.len() as u64);
+            for (index, rank) in used {
+                put_varint(&mut out, index as u64);
+                out.push(rank);
+            }
+        }
+    }
+    let checksum = hash64(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+fn decode(data: &[u8]) -> Result<[VecDeque<StatsBucket>; 4], String> {
+    if data.len() < MAGIC.len() + 1 + 8 || &data[..MAGIC.len()] != MAGIC {
+        return Err("Not a traffic statistics file".to_string());
+    }
+    let (body, checksum) = data.split_at(data.len() - 8);
+    if hash64(body).to_le_bytes() != checksum {
+        return Err("Checksum mismatch (incomplete write?)".to_string());
+    }
+    let mut reader = Reader { data: body, pos: MAGIC.len() };
+    let version = reader.byte()?;
+    if version != FORMAT_VERSION {
+        return Err(format!("Unsupported statistics format version {}", version));
+    }
+
+    let mut series: [VecDeque<StatsBucket>; 4] = Default::default();
+    let count = reader.varint()?;
+    for _ in 0..count {
+        let resolution = *Resolution::ALL.get(reader.byte()? as usize).ok_or("Invalid resolution in statistics file")?;
+        let mut bucket = StatsBucket::new(reader.varint()?);
+        bucket.requests = reader.varint()?;
+        bucket.bytes = reader.varint()?;
+        for _ in 0..reader.varint()? {
+            let status = reader.varint()? as u16;
+            bucket.statuses.insert(status, reader.varint()?);
+        }
+        for _ in 0..reader.varint()? {
+            let len = reader.varint()? as usize;
+            let domain = String::from_utf8_lossy(reader.bytes(len)?).to_string();
+            let requests = reader.varint()?;
+            let bytes = reader.varint()?;
+            bucket.domains.insert(domain, DomainCounters { requests, bytes });
+        }
+        for slot in bucket.latency.iter_mut() {
+            *slot = reader.varint()?;
+        }
+        for _ in 0..reader.varint()? {
+            let index = reader.varint()? as usize;
+            let rank = reader.byte()?;
+            *bucket.visitors.registers.get_mut(index).ok_or("Invalid sketch register in statistics file")? = rank;
+        }
+        let buckets = &mut series[resolution.index()];
+        let position = buckets.partition_point(|b| b.start < bucket.start);
+        buckets.insert(position, bucket);
+    }
+    Ok(series)
+}
+
+static TRAFFIC_STATS: OnceLock<TrafficStats> = OnceLock::new();
+
+/// Start collecting traffic statistics (first call wins)
+///
+/// # Arguments
+/// * `path` - Snapshot file, loaded now and rewritten every [`SAVE_INTERVAL`]
+/// * `retention` - Buckets kept per resolution
+pub fn init_traffic_stats(path: Option<PathBuf>, retention: Retention) -> &'static TrafficStats {
+    TRAFFIC_STATS.get_or_init(|| TrafficStats::new(path, retention))
+}
+
+/// The process-wide statistics, if [`init_traffic_stats`] has been called
+pub fn traffic_stats() -> Option<&'static TrafficStats> {
+    TRAFFIC_STATS.get()
+}
+
+/// Count a finished request (no-op until statistics are initialised)
+pub fn record_request(time: SystemTime, host: Option<&str>, status: u16, bytes: u64, duration: Duration, remote: IpAddr) {
+    if let Some(stats) = traffic_stats() {
+        stats.record(time, host, status, bytes, duration, remote);
+    }
+}
+
+/// Background task that saves the statistics snapshot every [`SAVE_INTERVAL`]
+pub async fn start_traffic_stats_task() {
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Some(stats) = traffic_stats() {
+            let result = tokio::task::spawn_blocking(move || stats.save()).await;
+            if let Ok(Err(e)) = result {
+                log::warn!(target: "stats", "failed to save traffic statistics: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_bucket_start_and_labels() {
+        // 2024-02-29 13:45:10 UTC
+        let time = 1709214310;
+        assert_eq!(Resolution::Minute.label(Resolution::Minute.bucket_start(time)), "2024-02-29 13:45");
+        assert_eq!(Resolution::Hour.label(Resolution::Hour.bucket_start(time)), "2024-02-29 13:00");
+        assert_eq!(Resolution::Day.label(Resolution::Day.bucket_start(time)), "2024-02-29");
+        assert_eq!(Resolution::Month.bucket_start(time), 1706745600); // 2024-02-01
+        assert_eq!(Resolution::Month.label(1706745600), "2024-02");
+        assert_eq!(Resolution::parse("hours"), Some(Resolution::Hour));
+
+        let retention = Retention::parse("minute=60, month=24").unwrap();
+        assert_eq!(retention.minutes, 60);
+        assert_eq!(retention.months, 24);
+        assert_eq!(retention.days, Retention::default().days);
+        assert!(Retention::parse("week=3").is_err());
+        assert!(Retention::parse("day").is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1709214310"), Some(1709214310));
+        assert_eq!(parse_time("2024-02-29"), Some(1709164800));
+        assert_eq!(parse_time("2024-02-29T13:45"), Some(1709214300));
+        assert_eq!(parse_time("2024-02-29T13:45:10Z"), Some(1709214310));
+        assert_eq!(iso_time(1709214310), "2024-02-29T13:45:10Z");
+        assert_eq!(parse_time("2024-13-01"), None);
+        assert_eq!(parse_time("yesterday"), None);
+    }
+
+    #[test]
+    fn test_hyperloglog_estimate() {
+        let mut sketch = HyperLogLog::default();
+        assert_eq!(sketch.estimate(), 0);
+        for i in 0..20000u32 {
+            sketch.insert_ip(IpAddr::V4(Ipv4Addr::from(i)));
+            sketch.insert_ip(IpAddr::V4(Ipv4Addr::from(i)));
+        }
+        let estimate = sketch.estimate() as f64;
+        assert!((estimate - 20000.0).abs() < 20000.0 * 0.1, "estimate {}", estimate);
+
+        let mut small = HyperLogLog::default();
+        for i in 0..10u32 {
+            small.insert_ip(IpAddr::V4(Ipv4Addr::from(i)));
+        }
+        assert!((9..=11).contains(&small.estimate()));
+    }
+
+    #[test]
+    fn test_rollups_and_retention() {
+        let stats = TrafficStats::new(None, Retention { minutes: 2, ..Retention::default() });
+        let client = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        stats.record(at(3600), Some("Example.com"), 200, 100, Duration::from_millis(3), client);
+        stats.record(at(3660), Some("example.com"), 404, 10, Duration::from_millis(40), client);
+        stats.record(at(3720), None, 500, 0, Duration::from_secs(30), IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)));
+
+        let minutes = stats.series(Resolution::Minute, 0, u64::MAX);
+        assert_eq!(minutes.iter().map(|b| b.start).collect::<Vec<_>>(), vec![3660, 3720]);
+
+        let hours = stats.series(Resolution::Hour, 0, u64::MAX);
+        assert_eq!(hours.len(), 1);
+        let hour = &hours[0];
+        assert_eq!(hour.requests, 3);
+        assert_eq!(hour.bytes, 110);
+        assert_eq!(hour.statuses.get(&404), Some(&1));
+        assert_eq!(hour.status_classes(), [0, 1, 0, 1, 1]);
+        assert_eq!(hour.domains["example.com"], DomainCounters { requests: 2, bytes: 110 });
+        assert_eq!(hour.domains["-"].requests, 1);
+        assert_eq!(hour.unique_visitors(), 2);
+        assert_eq!(hour.latency_percentile(1.0), Some(10000.0));
+        let median = hour.latency_percentile(0.5).unwrap();
+        assert!((25.0..=50.0).contains(&median), "median {}", median);
+
+        // Out-of-order time lands in its own bucket, in order
+        stats.record(at(60), Some("example.com"), 200, 1, Duration::ZERO, client);
+        let hours = stats.series(Resolution::Hour, 0, u64::MAX);
+        assert_eq!(hours.iter().map(|b| b.start).collect::<Vec<_>>(), vec![0, 3600]);
+        assert_eq!(stats.series(Resolution::Day, 0, u64::MAX)[0].requests, 4);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traffic.stats");
+        let stats = TrafficStats::new(Some(path.clone()), Retention::default());
+        for i in 0..50u32 {
+            stats.record(
+                at(1700000000 + i as u64 * 7),
+                Some(if i % 2 == 0 { "a.test" } else { "b.test" }),
+                if i % 5 == 0 { 404 } else { 200 },
+                i as u64 * 100,
+                Duration::from_millis(i as u64),
+                IpAddr::V4(Ipv4Addr::from(i)),
+            );
+        }
+        stats.save().unwrap();
+        assert!(!path.with_extension("tmp").exists());
+
+        let loaded = TrafficStats::new(Some(path.clone()), Retention::default());
+        for resolution in Resolution::ALL {
+            assert_eq!(loaded.series(resolution, 0, u64::MAX), stats.series(resolution, 0, u64::MAX));
+        }
+
+        // A torn write is detected and set aside rather than half-loaded
+        let mut data = fs::read(&path).unwrap();
+        data.truncate(data.len() / 2);
+        fs::write(&path, data).unwrap();
+        let damaged = TrafficStats::new(Some(path.clone()), Retention::default());
+        assert!(damaged.series(Resolution::Day, 0, u64::MAX).is_empty());
+        assert!(path.with_extension("corrupt").exists());
+    }
+}
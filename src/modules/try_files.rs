@@ -0,0 +1,286 @@
+//! Clean URLs and try_files Fallback Chains
+//!
+//! This module describes how a request path is mapped to candidate files, in the
+//! spirit of nginx's `try_files`:
+//! - `$uri` tries the path as a file
+//! - `$uri.html` tries the path with `.html` appended (clean URLs)
+//! - `$uri/` tries the path as a directory (trailing-slash redirect, then index files)
+//! - Any other `/path` is tried literally (e.g. `/index.html` as a fallback)
+//! - `=CODE` stops the chain with that status code
+//!
+//! The secure file server walks the candidates in order; this module only expands them.
+
+/// Default directory index files, tried in order
+pub const DEFAULT_INDEX_FILES: [&str; 2] = ["index.html", "index.htm"];
+
+/// One entry of a try_files chain after `$uri` has been substituted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Candidate {
+    /// Serve this URI path if it is a regular file
+    File(String),
+    /// Treat this URI path as a directory (redirect without slash, else index files)
+    Directory(String),
+    /// Stop and answer with this status code
+    Status(u16),
+}
+
+/// A try_files chain that applies to every path under `prefix`
+#[derive(Debug, Clone)]
+pub struct TryFilesLocation {
+    /// URI prefix this chain applies to (longest prefix wins)
+    pub prefix: String,
+    /// Chain entries as written, e.g. `$uri`, `$uri.html`, `$uri/`, `/index.html`, `=404`
+    pub chain: Vec<String>,
+}
+
+/// Configuration for index files, try_files chains, clean URLs and SPA mode
+#[derive(Debug, Clone)]
+pub struct TryFilesConfig {
+    /// Index files tried for directory requests
+    pub index_files: Vec<String>,
+    /// Per-location try_files chains
+    pub locations: Vec<TryFilesLocation>,
+    /// Redirect `/page.html` to `/page` and `/dir/index.html` to `/dir/`
+    pub clean_url_redirects: bool,
+    /// App shell served with 200 for unknown non-asset paths (single-page-app mode)
+    pub spa_shell: Option<String>,
+}
+
+impl Default for TryFilesConfig {
+    fn default() -> Self {
+        Self {
+            index_files: DEFAULT_INDEX_FILES.iter().map(|s| s.to_string()).collect(),
+            locations: Vec::new(),
+            clean_url_redirects: false,
+            spa_shell: None,
+        }
+    }
+}
+
+impl TryFilesConfig {
+    /// Set the index file list from a comma-separated string
+    pub fn set_index_files(&mut self, list: &str) -> Result<(), String> {
+        let files: Vec<String> = list
+            .split(',')
+            .map(|f| f.trim().to_string())
+            .filter(|f| !f.is_empty())
+            .collect();
+        if files.is_empty() {
+            return Err("index file list is empty".to_string());
+        }
+        if let Some(bad) = files.iter().find(|f| f.contains('/') || f.starts_with('.')) {
+            return Err(format!("invalid index file name '{}'", bad));
+        }
+        self.index_files = files;
+        Ok(())
+    }
+
+    /// Add a try_files chain of the form `[PREFIX=]ENTRY,ENTRY,...`
+    ///
+    /// The prefix defaults to `/` and must be given when the first entry is a literal path.
+    pub fn add_location_spec(&mut self, spec: &str) -> Result<(), String> {
+        let (prefix, chain) = match spec.strip_prefix('/') {
+            Some(_) => spec
+                .split_once('=')
+                .ok_or_else(|| format!("invalid try_files '{}' (expected [PREFIX=]ENTRY,ENTRY...)", spec))?,
+            None => ("/", spec),
+        };
+
+        let chain: Vec<String> = chain
+            .split(',')
+            .map(|e| e.trim().to_string())
+            .filter(|e| !e.is_empty())
+            .collect();
+        if chain.is_empty() {
+            return Err(format!("try_files '{}' has no entries", spec));
+        }
+        for entry in &chain {
+            validate_entry(entry)?;
+        }
+
+        self.locations.retain(|l| l.prefix != prefix);
+        self.locations.push(TryFilesLocation {
+            prefix: prefix.to_string(),
+            chain,
+        });
+        Ok(())
+    }
+
+    /// Enable clean URLs: `.html` redirects plus a default `$uri,$uri.html,$uri/` chain
+    pub fn enable_clean_urls(&mut self) {
+        self.clean_url_redirects = true;
+        if !self.locations.iter().any(|l| l.prefix == "/") {
+            self.locations.push(TryFilesLocation {
+                prefix: "/".to_string(),
+                chain: vec!["$uri".to_string(), "$uri.html".to_string(), "$uri/".to_string()],
+            });
+        }
+    }
+
+    /// Expand the candidates for a request path (query string already removed)
+    ///
+    /// # Arguments
+    /// * `path` - Decoded-or-raw URI path starting with `/`
+    ///
+    /// # Returns
+    /// * `Vec<Candidate>` - Candidates to try in order
+    pub fn candidates(&self, path: &str) -> Vec<Candidate> {
+        let chain: Vec<&str> = match self.location_for(path) {
+            Some(location) => location.chain.iter().map(String::as_str).collect(),
+            // Classic behaviour: the file itself, then the directory
+            None => vec!["$uri", "$uri/"],
+        };
+
+        let mut candidates: Vec<Candidate> = chain
+            .iter()
+            .filter_map(|entry| expand_entry(entry, path))
+            .collect();
+
+        // SPA mode: unknown non-asset paths fall back to the app shell
+        if let Some(shell) = &self.spa_shell {
+            let stops = candidates.iter().any(|c| matches!(c, Candidate::Status(_)));
+            if !stops && !is_asset_path(path) {
+                candidates.push(Candidate::File(shell.clone()));
+            }
+        }
+
+        candidates
+    }
+
+    /// Clean URL form of a `.html` path, if clean URL redirects are enabled
+    ///
+    /// # Returns
+    /// * `Option<String>` - `/page` for `/page.html`, `/dir/` for `/dir/index.html`
+    pub fn clean_url_for(&self, path: &str) -> Option<String> {
+        if !self.clean_url_redirects {
+            return None;
+        }
+        if let Some(dir) = path.strip_suffix("index.html") {
+            if dir.ends_with('/') {
+                return Some(dir.to_string());
+            }
+        }
+        path.strip_suffix(".html")
+            .filter(|stem| !stem.is_empty() && !stem.ends_with('/'))
+            .map(str::to_string)
+    }
+
+    /// Find the location with the longest matching prefix
+    fn location_for(&self, path: &str) -> Option<&TryFilesLocation> {
+        self.locations
+            .iter()
+            .filter(|l| path.starts_with(l.prefix.as_str()))
+            .max_by_key(|l| l.prefix.len())
+    }
+}
+
+/// Check that a chain entry is one of the supported forms
+fn validate_entry(entry: &str) -> Result<(), String> {
+    if let Some(code) = entry.strip_prefix('=') {
+        return match code.parse::<u16>() {
+            Ok(code) if (100..=599).contains(&code) => Ok(()),
+            _ => Err(format!("invalid try_files status '{}'", entry)),
+        };
+    }
+    if entry.starts_with("$uri") || entry.starts_with('/') {
+        return Ok(());
+    }
+    Err(format!("invalid try_files entry '{}' (expected $uri..., /path or =CODE)", entry))
+}
+
+/// Substitute `$uri` in a chain entry
+fn expand_entry(entry: &str, path: &str) -> Option<Candidate> {
+    if let Some(code) = entry.strip_prefix('=') {
+        return code.parse().ok().map(Candidate::Status);
+    }
+
+    let expanded = entry.replace("$uri", path);
+    if entry.ends_with('/') {
+        let dir = expanded.trim_end_matches('/');
+        return Some(Candidate::Directory(if dir.is_empty() { "/".to_string() } else { dir.to_string() }));
+    }
+
+    // Directory paths like `/docs/` are only tried as directories or literal fallbacks
+    if entry.starts_with("$uri") && path.ends_with('/') {
+        return None;
+    }
+
+    Some(Candidate::File(expanded))
+}
+
+/// Whether the last path segment looks like an asset (has a file extension)
+fn is_asset_path(path: &str) -> bool {
+    path.rsplit('/').next().map(|segment| segment.contains('.')).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(s: &str) -> Candidate {
+        Candidate::File(s.to_string())
+    }
+
+    fn dir(s: &str) -> Candidate {
+        Candidate::Directory(s.to_string())
+    }
+
+    #[test]
+    fn test_default_chain() {
+        let config = TryFilesConfig::default();
+        assert_eq!(config.candidates("/about"), vec![file("/about"), dir("/about")]);
+        assert_eq!(config.candidates("/"), vec![dir("/")]);
+    }
+
+    #[test]
+    fn test_clean_urls() {
+        let mut config = TryFilesConfig::default();
+        config.enable_clean_urls();
+        assert_eq!(config.candidates("/about"), vec![file("/about"), file("/about.html"), dir("/about")]);
+        assert_eq!(config.candidates("/docs/"), vec![dir("/docs")]);
+
+        assert_eq!(config.clean_url_for("/about.html").as_deref(), Some("/about"));
+        assert_eq!(config.clean_url_for("/docs/index.html").as_deref(), Some("/docs/"));
+        assert_eq!(config.clean_url_for("/index.html").as_deref(), Some("/"));
+        assert_eq!(config.clean_url_for("/about"), None);
+        assert_eq!(config.clean_url_for("/.html"), None);
+    }
+
+    #[test]
+    fn test_location_chains() {
+        let mut config = TryFilesConfig::default();
+        config.add_location_spec("$uri,$uri/,/index.html").unwrap();
+        config.add_location_spec("/api=$uri,=404").unwrap();
+
+        assert_eq!(config.candidates("/app/route"), vec![file("/app/route"), dir("/app/route"), file("/index.html")]);
+        assert_eq!(config.candidates("/api/users"), vec![file("/api/users"), Candidate::Status(404)]);
+
+        assert!(config.add_location_spec("/only-prefix").is_err());
+        assert!(config.add_location_spec("$uri,=999").is_err());
+        assert!(config.add_location_spec("index.html").is_err());
+    }
+
+    #[test]
+    fn test_spa_mode() {
+        let config = TryFilesConfig {
+            spa_shell: Some("/index.html".to_string()),
+            ..TryFilesConfig::default()
+        };
+        assert_eq!(config.candidates("/dashboard/settings"), vec![
+            file("/dashboard/settings"),
+            dir("/dashboard/settings"),
+            file("/index.html"),
+        ]);
+        // Missing assets stay 404 rather than returning the shell
+        assert_eq!(config.candidates("/static/app.js"), vec![file("/static/app.js"), dir("/static/app.js")]);
+    }
+
+    #[test]
+    fn test_index_files() {
+        let mut config = TryFilesConfig::default();
+        config.set_index_files("index.html, default.htm").unwrap();
+        assert_eq!(config.index_files, vec!["index.html", "default.htm"]);
+        assert!(config.set_index_files(" , ").is_err());
+        assert!(config.set_index_files("../index.html").is_err());
+    }
+}
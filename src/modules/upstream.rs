@@ -0,0 +1,738 @@
+//! Upstream Pools
+//!
+//! This module groups several backend instances under one name so that a proxy
+//! route (`upstream:NAME`) can spread requests across them. It provides:
+//! - Round-robin, least-connections and consistent client-IP hash selection
+//! - Active health checks that request a configurable path on an interval
+//! - Passive ejection after consecutive failures, for a configurable time
+//! - Live counters for the upstreams admin panel
+//!
+//! Runtime state is shared through `Arc`s, so clones of an [`UpstreamRegistry`] all
+//! see the same counters and health.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::reverse_proxy::{UpstreamAddr, UpstreamTarget};
+
+/// Default number of consecutive failures before a server is ejected
+pub const DEFAULT_MAX_FAILS: u32 = 3;
+
+/// Default time an ejected server is left alone
+pub const DEFAULT_FAIL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default interval between active health checks
+pub const DEFAULT_HEALTH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How a group picks a server for a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalancePolicy {
+    /// Take turns
+    RoundRobin,
+    /// Fewest requests in flight
+    LeastConnections,
+    /// Consistent hash of the client IP, so a client sticks to one server
+    IpHash,
+}
+
+impl BalancePolicy {
+    /// Parse a policy from its command line form
+    ///
+    /// # Arguments
+    /// * `value` - One of `round-robin`, `least-conn` or `ip-hash`
+    ///
+    /// # Returns
+    /// * `Result<BalancePolicy, String>` - The parsed policy
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "round-robin" => Ok(BalancePolicy::RoundRobin),
+            "least-conn" => Ok(BalancePolicy::LeastConnections),
+            "ip-hash" => Ok(BalancePolicy::IpHash),
+            _ => Err(format!("invalid balance policy '{}' (expected round-robin, least-conn or ip-hash)", value)),
+        }
+    }
+
+    /// Command line name of the policy
+    pub fn name(&self) -> &'static str {
+        match self {
+            BalancePolicy::RoundRobin => "round-robin",
+            BalancePolicy::LeastConnections => "least-conn",
+            BalancePolicy::IpHash => "ip-hash",
+        }
+    }
+}
+
+/// Active health check settings for a group
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    /// Path requested on every server, e.g. `/healthz`
+    pub path: String,
+    /// Time between checks
+    pub interval: Duration,
+}
+
+/// Configuration of one named group, before any runtime state exists
+#[derive(Debug, Clone)]
+pub struct UpstreamGroupConfig {
+    /// Member servers
+    pub servers: Vec<UpstreamTarget>,
+    /// Selection policy
+    pub policy: BalancePolicy,
+    /// Optional active health check
+    pub health_check: Option<HealthCheck>,
+}
+
+impl Default for UpstreamGroupConfig {
+    fn default() -> Self {
+        Self {
+            servers: Vec::new(),
+            policy: BalancePolicy::RoundRobin,
+            health_check: None,
+        }
+    }
+}
+
+/// Configuration of all upstream groups as given on the command line
+#[derive(Debug, Clone)]
+pub struct UpstreamConfig {
+    /// Groups by name
+    pub groups: BTreeMap<String, UpstreamGroupConfig>,
+    /// Consecutive failures before passive ejection
+    pub max_fails: u32,
+    /// How long an ejected server is skipped
+    pub fail_timeout: Duration,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            groups: BTreeMap::new(),
+            max_fails: DEFAULT_MAX_FAILS,
+            fail_timeout: DEFAULT_FAIL_TIMEOUT,
+        }
+    }
+}
+
+impl UpstreamConfig {
+    /// Add servers to a group from `NAME=URL[,URL...]`
+    pub fn add_group_spec(&mut self, spec: &str) -> Result<(), String> {
+        let (name, urls) = split_spec(spec, "NAME=URL[,URL...]")?;
+        let group = self.groups.entry(name).or_default();
+        for url in urls.split(',').map(str::trim).filter(|u| !u.is_empty()) {
+            let target = UpstreamTarget::parse(url)?;
+            if !target.path.is_empty() {
+                return Err(format!("upstream server '{}' must not have a path (put it on the proxy route)", url));
+            }
+            if matches!(target.addr, UpstreamAddr::Group(_)) {
+                return Err(format!("upstream server '{}' cannot be another group", url));
+            }
+            group.servers.push(target);
+        }
+        Ok(())
+    }
+
+    /// Set a group's policy from `NAME=POLICY`
+    pub fn set_policy_spec(&mut self, spec: &str) -> Result<(), String> {
+        let (name, policy) = split_spec(spec, "NAME=POLICY")?;
+        self.groups.entry(name).or_default().policy = BalancePolicy::parse(policy.trim())?;
+        Ok(())
+    }
+
+    /// Set a group's active health check from `NAME=PATH[,INTERVAL_SECS]`
+    pub fn set_health_check_spec(&mut self, spec: &str) -> Result<(), String> {
+        let (name, check) = split_spec(spec, "NAME=PATH[,SECS]")?;
+        let (path, interval) = match check.split_once(',') {
+            Some((path, secs)) => {
+                let secs: u64 = secs
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid health check interval '{}'", secs))?;
+                if secs == 0 {
+                    return Err("health check interval must be at least 1 second".to_string());
+                }
+                (path.trim(), Duration::from_secs(secs))
+            }
+            None => (check.trim(), DEFAULT_HEALTH_INTERVAL),
+        };
+        if !path.starts_with('/') {
+            return Err(format!("health check path '{}' must start with /", path));
+        }
+        self.groups.entry(name).or_default().health_check = Some(HealthCheck {
+            path: path.to_string(),
+            interval,
+        });
+        Ok(())
+    }
+}
+
+/// Split `NAME=VALUE`, lowercasing the name
+fn split_spec<'a>(spec: &'a str, expected: &str) -> Result<(String, &'a str), String> {
+    let (name, value) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("invalid upstream option '{}' (expected {})", spec, expected))?;
+    let name = name.trim().to_lowercase();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("invalid upstream group name '{}'", name));
+    }
+    Ok((name, value))
+}
+
+/// One member of a group with its live state
+#[derive(Debug)]
+pub struct UpstreamServer {
+    /// Where the server lives
+    pub target: UpstreamTarget,
+    /// Requests currently in flight
+    active: AtomicUsize,
+    /// Failures since the last success
+    consecutive_failures: AtomicU32,
+    /// Passive ejection deadline
+    ejected_until: Mutex<Option<Instant>>,
+    /// Result of the last active health check (true until a check fails)
+    check_healthy: AtomicBool,
+    /// Unix time of the last active health check (0 = never)
+    last_check: AtomicU64,
+    /// Total requests sent
+    requests: AtomicU64,
+    /// Total failed requests
+    failures: AtomicU64,
+    /// Most recent error seen by a request or health check
+    last_error: Mutex<Option<String>>,
+}
+
+impl UpstreamServer {
+    fn new(target: UpstreamTarget) -> Self {
+        Self {
+            target,
+            active: AtomicUsize::new(0),
+            consecutive_failures: AtomicU32::new(0),
+            ejected_until: Mutex::new(None),
+            check_healthy: AtomicBool::new(true),
+            last_check: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    /// Human-readable address
+    pub fn address(&self) -> String {
+        match &self.target.addr {
+            UpstreamAddr::Tcp(authority) => authority.clone(),
+            UpstreamAddr::Unix(path) => format!("unix:{}", path.display()),
+            UpstreamAddr::Group(name) => format!("upstream:{}", name),
+        }
+    }
+
+    /// Whether the server is passively ejected right now
+    fn is_ejected(&self) -> bool {
+        let mut ejected = self.ejected_until.lock().unwrap();
+        match *ejected {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                // Ejection expired: give the server another chance
+                *ejected = None;
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Whether requests may be sent to this server
+    pub fn is_available(&self) -> bool {
+        self.check_healthy.load(Ordering::Relaxed) && !self.is_ejected()
+    }
+
+    fn set_last_error(&self, error: &str) {
+        *self.last_error.lock().unwrap() = Some(error.to_string());
+    }
+}
+
+/// A named group of servers
+#[derive(Debug)]
+pub struct UpstreamGroup {
+    /// Group name used in `upstream:NAME`
+    pub name: String,
+    /// Member servers
+    pub servers: Vec<Arc<UpstreamServer>>,
+    /// Selection policy
+    pub policy: BalancePolicy,
+    /// Optional active health check
+    pub health_check: Option<HealthCheck>,
+    /// Consecutive failures before passive ejection
+    pub max_fails: u32,
+    /// How long an ejected server is skipped
+    pub fail_timeout: Duration,
+    /// Round-robin cursor
+    next: AtomicUsize,
+}
+
+impl UpstreamGroup {
+    /// Pick a server for a request
+    ///
+    /// # Arguments
+    /// * `client_ip` - Client address, used by the IP hash policy
+    /// * `exclude` - Indices of servers already tried for this request
+    ///
+    /// # Returns
+    /// * `Option<UpstreamLease>` - The chosen server, or None if no live server is left
+    pub fn select(self: &Arc<Self>, client_ip: IpAddr, exclude: &[usize]) -> Option<UpstreamLease> {
+        let candidates: Vec<usize> = (0..self.servers.len())
+            .filter(|i| !exclude.contains(i) && self.servers[*i].is_available())
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let index = match self.policy {
+            BalancePolicy::RoundRobin => candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()],
+            BalancePolicy::LeastConnections => {
+                // Rotate the starting point so ties are spread evenly
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                (0..candidates.len())
+                    .map(|offset| candidates[(start + offset) % candidates.len()])
+                    .min_by_key(|i| self.servers[*i].active.load(Ordering::Relaxed))
+                    .unwrap_or(candidates[0])
+            }
+            // Rendezvous hashing: only clients of a removed server move elsewhere
+            BalancePolicy::IpHash => *candidates
+                .iter()
+                .max_by_key(|i| rendezvous_score(client_ip, &self.servers[**i].address()))
+                .unwrap_or(&candidates[0]),
+        };
+
+        let server = self.servers[index].clone();
+        server.active.fetch_add(1, Ordering::Relaxed);
+        server.requests.fetch_add(1, Ordering::Relaxed);
+        Some(UpstreamLease {
+            group: self.clone(),
+            index,
+            server,
+        })
+    }
+}
+
+/// Rendezvous score of a client for a server (FNV-1a over both)
+fn rendezvous_score(client_ip: IpAddr, server: &str) -> u64 {
+    let ip_bytes = match client_ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in ip_bytes.iter().chain([0xffu8].iter()).chain(server.as_bytes()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    // Final avalanche so similar inputs spread across the whole range
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^ (hash >> 33)
+}
+
+/// A server chosen for one request; releases its in-flight slot when dropped
+#[derive(Debug)]
+pub struct UpstreamLease {
+    group: Arc<UpstreamGroup>,
+    /// Index of the server in its group
+    pub index: usize,
+    /// The chosen server
+    pub server: Arc<UpstreamServer>,
+}
+
+impl UpstreamLease {
+    /// Record a successful exchange, clearing the failure streak
+    pub fn record_success(&self) {
+        self.server.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Record a failed exchange, ejecting the server after too many in a row
+    pub fn record_failure(&self, error: &str) {
+        self.server.failures.fetch_add(1, Ordering::Relaxed);
+        self.server.set_last_error(error);
+        let failures = self.server.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.group.max_fails > 0 && failures >= self.group.max_fails {
+            let mut ejected = self.server.ejected_until.lock().unwrap();
+            if ejected.is_none() {
+                log::warn!(
+                    target: "proxy",
+                    "ejecting upstream {} from group '{}' for {:?} after {} failures",
+                    self.server.address(),
+                    self.group.name,
+                    self.group.fail_timeout,
+                    failures
+                );
+            }
+            *ejected = Some(Instant::now() + self.group.fail_timeout);
+        }
+    }
+}
+
+impl Drop for UpstreamLease {
+    fn drop(&mut self) {
+        self.server.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Snapshot of one server for display
+#[derive(Debug, Clone)]
+pub struct ServerStatus {
+    /// Server address
+    pub address: String,
+    /// `up`, `ejected` or `down` (failed active check)
+    pub state: &'static str,
+    /// Requests in flight
+    pub active: usize,
+    /// Total requests
+    pub requests: u64,
+    /// Total failures
+    pub failures: u64,
+    /// Current failure streak
+    pub consecutive_failures: u32,
+    /// Unix time of the last health check, if any
+    pub last_check: Option<u64>,
+    /// Most recent error
+    pub last_error: Option<String>,
+}
+
+/// Snapshot of one group for display
+#[derive(Debug, Clone)]
+pub struct GroupStatus {
+    /// Group name
+    pub name: String,
+    /// Policy name
+    pub policy: &'static str,
+    /// Health check path and interval, if enabled
+    pub health_check: Option<HealthCheck>,
+    /// Member servers
+    pub servers: Vec<ServerStatus>,
+}
+
+/// All upstream groups with their live state
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamRegistry {
+    groups: HashMap<String, Arc<UpstreamGroup>>,
+}
+
+impl UpstreamRegistry {
+    /// Build the runtime groups from their configuration
+    pub fn new(config: &UpstreamConfig) -> Result<Self, String> {
+        let mut groups = HashMap::new();
+        for (name, group) in &config.groups {
+            if group.servers.is_empty() {
+                return Err(format!("upstream group '{}' has no servers", name));
+            }
+            groups.insert(
+                name.clone(),
+                Arc::new(UpstreamGroup {
+                    name: name.clone(),
+                    servers: group.servers.iter().cloned().map(|t| Arc::new(UpstreamServer::new(t))).collect(),
+                    policy: group.policy,
+                    health_check: group.health_check.clone(),
+                    max_fails: config.max_fails,
+                    fail_timeout: config.fail_timeout,
+                    next: AtomicUsize::new(0),
+                }),
+            );
+        }
+        Ok(Self { groups })
+    }
+
+    /// Look up a group by name
+    pub fn get(&self, name: &str) -> Option<&Arc<UpstreamGroup>> {
+        self.groups.get(name)
+    }
+
+    /// Whether any groups are configured
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Snapshot of every group, sorted by name
+    pub fn status(&self) -> Vec<GroupStatus> {
+        let mut groups: Vec<GroupStatus> = self
+            .groups
+            .values()
+            .map(|group| GroupStatus {
+                name: group.name.clone(),
+                policy: group.policy.name(),
+                health_check: group.health_check.clone(),
+                servers: group
+                    .servers
+                    .iter()
+                    .map(|server| ServerStatus {
+                        address: server.address(),
+                        state: if !server.check_healthy.load(Ordering::Relaxed) {
+                            "down"
+                        } else if server.is_ejected() {
+                            "ejected"
+                        } else {
+                            "up"
+                        },
+                        active: server.active.load(Ordering::Relaxed),
+                        requests: server.requests.load(Ordering::Relaxed),
+                        failures: server.failures.load(Ordering::Relaxed),
+                        consecutive_failures: server.consecutive_failures.load(Ordering::Relaxed),
+                        last_check: match server.last_check.load(Ordering::Relaxed) {
+                            0 => None,
+                            secs => Some(secs),
+                        },
+                        last_error: server.last_error.lock().unwrap().clone(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        groups
+    }
+
+    /// Start one background task per group that has an active health check
+    pub fn spawn_health_checks(&self) {
+        for group in self.groups.values() {
+            if let Some(check) = group.health_check.clone() {
+                let group = group.clone();
+                log::info!(target: "proxy", "health checking upstream group '{}' at {} every {:?}", group.name, check.path, check.interval);
+                tokio::spawn(async move {
+                    let mut ticker = tokio::time::interval(check.interval);
+                    loop {
+                        ticker.tick().await;
+                        run_health_checks(&group, &check).await;
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Registry shared with the admin panel
+static INSTALLED_REGISTRY: OnceLock<UpstreamRegistry> = OnceLock::new();
+
+/// Make a registry visible to [`installed_registry`] (first call wins)
+pub fn install_registry(registry: UpstreamRegistry) {
+    let _ = INSTALLED_REGISTRY.set(registry);
+}
+
+/// The registry of the running server, if one was installed
+pub fn installed_registry() -> Option<&'static UpstreamRegistry> {
+    INSTALLED_REGISTRY.get()
+}
+
+/// Check every server of a group once
+async fn run_health_checks(group: &UpstreamGroup, check: &HealthCheck) {
+    // A check must finish well within the interval
+    let timeout = check.interval.min(Duration::from_secs(5));
+    for server in &group.servers {
+        let result = probe(&server.target, &check.path, timeout).await;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        server.last_check.store(now, Ordering::Relaxed);
+
+        let healthy = match &result {
+            Ok(status) => (200..400).contains(status),
+            Err(_) => false,
+        };
+        let was_healthy = server.check_healthy.swap(healthy, Ordering::Relaxed);
+        if !healthy {
+            let error = match result {
+                Ok(status) => format!("health check returned {}", status),
+                Err(e) => format!("health check failed: {}", e),
+            };
+            if was_healthy {
+                log::warn!(target: "proxy", "upstream {} in group '{}' is down: {}", server.address(), group.name, error);
+            }
+            server.set_last_error(&error);
+        } else if !was_healthy {
+            log::info!(target: "proxy", "upstream {} in group '{}' is healthy again", server.address(), group.name);
+        }
+    }
+}
+
+/// Request the health check path and return the status code
+async fn probe(target: &UpstreamTarget, path: &str, timeout: Duration) -> Result<u16, String> {
+    let result = tokio::time::timeout(timeout, async {
+        match &target.addr {
+            UpstreamAddr::Tcp(authority) => {
+                let stream = tokio::net::TcpStream::connect(authority.as_str())
+                    .await
+                    .map_err(|e| e.to_string())?;
+                probe_stream(stream, authority, path).await
+            }
+            #[cfg(unix)]
+            UpstreamAddr::Unix(socket) => {
+                let stream = tokio::net::UnixStream::connect(socket).await.map_err(|e| e.to_string())?;
+                probe_stream(stream, "localhost", path).await
+            }
+            #[cfg(not(unix))]
+            UpstreamAddr::Unix(socket) => Err(format!("unix sockets not supported ({})", socket.display())),
+            UpstreamAddr::Group(name) => Err(format!("cannot health check group '{}'", name)),
+        }
+    })
+    .await;
+    match result {
+        Ok(result) => result,
+        Err(_) => Err("timed out".to_string()),
+    }
+}
+
+/// Send a health check request on a connected stream and parse the status line
+async fn probe_stream<S>(mut stream: S, host: &str, path: &str) -> Result<u16, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: easyp-health-check\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+
+    let mut response = Vec::new();
+    let mut chunk = [0u8; 512];
+    while !response.windows(2).any(|w| w == b"\r\n") {
+        let n = stream.read(&mut chunk).await.map_err(|e| e.to_string())?;
+        if n == 0 || response.len() > 4096 {
+            return Err("no status line in response".to_string());
+        }
+        response.extend_from_slice(&chunk[..n]);
+    }
+
+    let line = String::from_utf8_lossy(&response);
+    line.split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| "invalid status line".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn registry(policy: &str, servers: &str) -> UpstreamRegistry {
+        let mut config = UpstreamConfig::default();
+        config.add_group_spec(&format!("app={}", servers)).unwrap();
+        config.set_policy_spec(&format!("app={}", policy)).unwrap();
+        UpstreamRegistry::new(&config).unwrap()
+    }
+
+    fn client(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    #[test]
+    fn test_config_specs() {
+        let mut config = UpstreamConfig::default();
+        config.add_group_spec("App=http://10.0.0.1:3000, http://10.0.0.2:3000").unwrap();
+        config.add_group_spec("app=unix:/run/app.sock").unwrap();
+        config.set_health_check_spec("app=/healthz,2").unwrap();
+        let group = &config.groups["app"];
+        assert_eq!(group.servers.len(), 3);
+        assert_eq!(group.health_check, Some(HealthCheck { path: "/healthz".to_string(), interval: Duration::from_secs(2) }));
+
+        assert!(config.add_group_spec("app=http://10.0.0.3:3000/v1").is_err());
+        assert!(config.add_group_spec("bad name=http://10.0.0.3:3000").is_err());
+        assert!(config.set_policy_spec("app=random").is_err());
+        assert!(config.set_health_check_spec("app=healthz").is_err());
+
+        config.set_policy_spec("empty=ip-hash").unwrap();
+        assert!(UpstreamRegistry::new(&config).is_err());
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let registry = registry("round-robin", "http://a:1,http://b:1,http://c:1");
+        let group = registry.get("app").unwrap();
+        let picks: Vec<usize> = (0..6).map(|_| group.select(client(1), &[]).unwrap().index).collect();
+        assert_eq!(picks, vec![0, 1, 2, 0, 1, 2]);
+        // Retries skip servers that were already tried
+        let retry = group.select(client(1), &[0, 1]).unwrap();
+        assert_eq!(retry.index, 2);
+        assert!(group.select(client(1), &[0, 1, 2]).is_none());
+    }
+
+    #[test]
+    fn test_least_connections() {
+        let registry = registry("least-conn", "http://a:1,http://b:1");
+        let group = registry.get("app").unwrap();
+        let first = group.select(client(1), &[]).unwrap();
+        let second = group.select(client(1), &[]).unwrap();
+        assert_ne!(first.index, second.index);
+
+        // While `first` is still busy, the next pick goes to the other server
+        drop(second);
+        for _ in 0..4 {
+            assert_ne!(group.select(client(1), &[]).unwrap().index, first.index);
+        }
+    }
+
+    #[test]
+    fn test_ip_hash_is_sticky_and_consistent() {
+        let registry = registry("ip-hash", "http://a:1,http://b:1,http://c:1");
+        let group = registry.get("app").unwrap();
+        let before: Vec<usize> = (0..50).map(|i| group.select(client(i), &[]).unwrap().index).collect();
+        let again: Vec<usize> = (0..50).map(|i| group.select(client(i), &[]).unwrap().index).collect();
+        assert_eq!(before, again);
+        assert!((0..3).all(|s| before.contains(&s)));
+
+        // Excluding server 2 only moves the clients that were on it
+        let after: Vec<usize> = (0..50).map(|i| group.select(client(i), &[2]).unwrap().index).collect();
+        for (old, new) in before.iter().zip(&after) {
+            if *old != 2 {
+                assert_eq!(old, new);
+            }
+        }
+    }
+
+    #[test]
+    fn test_passive_ejection() {
+        let mut config = UpstreamConfig::default();
+        config.add_group_spec("app=http://a:1,http://b:1").unwrap();
+        config.max_fails = 2;
+        config.fail_timeout = Duration::from_millis(50);
+        let registry = UpstreamRegistry::new(&config).unwrap();
+        let group = registry.get("app").unwrap();
+
+        for _ in 0..2 {
+            group.select(client(1), &[1]).unwrap().record_failure("connection refused");
+        }
+        assert_eq!(registry.status()[0].servers[0].state, "ejected");
+        for _ in 0..4 {
+            assert_eq!(group.select(client(1), &[]).unwrap().index, 1);
+        }
+
+        // The ejection expires and the server is tried again
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(registry.status()[0].servers[0].state, "up");
+        assert_eq!(registry.status()[0].servers[0].failures, 2);
+    }
+
+    #[tokio::test]
+    async fn test_active_health_check() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for status in ["200 OK", "503 Service Unavailable"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                assert!(String::from_utf8_lossy(&buf[..n]).starts_with("GET /healthz HTTP/1.1\r\n"));
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let registry = registry("round-robin", &format!("http://{}", addr));
+        let group = registry.get("app").unwrap();
+        let check = HealthCheck { path: "/healthz".to_string(), interval: Duration::from_secs(1) };
+
+        run_health_checks(group, &check).await;
+        assert_eq!(registry.status()[0].servers[0].state, "up");
+        run_health_checks(group, &check).await;
+        assert_eq!(registry.status()[0].servers[0].state, "down");
+        assert!(group.select(client(1), &[]).is_none());
+        assert!(registry.status()[0].servers[0].last_check.is_some());
+    }
+}
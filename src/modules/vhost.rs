@@ -0,0 +1,337 @@
+//! Virtual Host Resolution
+//!
+//! This module maps the host of a request to a document root. It supports:
+//! - A configurable root template (e.g. `/srv/sites/{domain}/public`)
+//! - Explicit alias lists that share one document tree
+//! - Wildcard vhosts (`*.example.com` mapped via `{sub}`)
+//! - Canonical-host redirects (www to apex or apex to www)
+//! - A strict mode that rejects unknown hosts instead of serving the default root
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Default root template, matching the historical `/var/www/{domain}` layout
+pub const DEFAULT_ROOT_TEMPLATE: &str = "/var/www/{domain}";
+
+/// Canonical host redirect policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CanonicalHost {
+    /// Serve every configured name as-is
+    None,
+    /// Redirect `www.example.com` to `example.com`
+    WwwToApex,
+    /// Redirect `example.com` to `www.example.com` when the www name is a known vhost
+    ApexToWww,
+}
+
+impl CanonicalHost {
+    /// Parse a canonical host policy from its command line form
+    ///
+    /// # Arguments
+    /// * `value` - One of `none`, `www-to-apex` or `apex-to-www`
+    ///
+    /// # Returns
+    /// * `Result<CanonicalHost, String>` - The parsed policy
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "none" => Ok(CanonicalHost::None),
+            "www-to-apex" => Ok(CanonicalHost::WwwToApex),
+            "apex-to-www" => Ok(CanonicalHost::ApexToWww),
+            _ => Err(format!("invalid canonical host policy '{}' (expected none, www-to-apex or apex-to-www)", value)),
+        }
+    }
+}
+
+/// How to answer requests for hosts that have no vhost configured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownHostPolicy {
+    /// Serve the default document root (`--root`)
+    ServeDefault,
+    /// Answer 421 Misdirected Request
+    Misdirected,
+    /// Answer 404 Not Found
+    NotFound,
+}
+
+impl UnknownHostPolicy {
+    /// Parse an unknown host policy from its command line form
+    ///
+    /// # Arguments
+    /// * `value` - One of `serve`, `421` or `404`
+    ///
+    /// # Returns
+    /// * `Result<UnknownHostPolicy, String>` - The parsed policy
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "serve" => Ok(UnknownHostPolicy::ServeDefault),
+            "421" => Ok(UnknownHostPolicy::Misdirected),
+            "404" => Ok(UnknownHostPolicy::NotFound),
+            _ => Err(format!("invalid unknown host policy '{}' (expected serve, 421 or 404)", value)),
+        }
+    }
+}
+
+/// A wildcard vhost such as `*.example.com=/srv/example/{sub}`
+#[derive(Debug, Clone)]
+pub struct WildcardVhost {
+    /// Suffix including the leading dot (e.g. `.example.com`)
+    pub suffix: String,
+    /// Root template; `{sub}` is the matched label, `{domain}` the full host
+    pub root_template: String,
+}
+
+/// Outcome of resolving a host against the vhost configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VhostResolution {
+    /// Serve files from this document root
+    Root(PathBuf),
+    /// Redirect permanently to this canonical host
+    Redirect(String),
+    /// No vhost matched the host
+    Unknown,
+}
+
+/// Virtual host configuration
+#[derive(Debug, Clone)]
+pub struct VhostConfig {
+    /// Root template; `{domain}` is replaced by the (canonical) host name
+    pub root_template: String,
+    /// Alias host -> host whose document tree it shares
+    pub aliases: HashMap<String, String>,
+    /// Wildcard vhosts, checked in order after exact matches
+    pub wildcards: Vec<WildcardVhost>,
+    /// Canonical-host redirect policy
+    pub canonical_host: CanonicalHost,
+    /// Behaviour for hosts without a vhost
+    pub unknown_host: UnknownHostPolicy,
+}
+
+impl Default for VhostConfig {
+    fn default() -> Self {
+        Self {
+            root_template: DEFAULT_ROOT_TEMPLATE.to_string(),
+            aliases: HashMap::new(),
+            wildcards: Vec::new(),
+            canonical_host: CanonicalHost::None,
+            unknown_host: UnknownHostPolicy::ServeDefault,
+        }
+    }
+}
+
+impl VhostConfig {
+    /// Add an alias list of the form `DOMAIN=ALIAS1,ALIAS2`
+    ///
+    /// Every alias shares the document tree of `DOMAIN`.
+    pub fn add_alias_spec(&mut self, spec: &str) -> Result<(), String> {
+        let (target, aliases) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("invalid alias list '{}' (expected DOMAIN=ALIAS[,ALIAS...])", spec))?;
+        let target = target.trim().to_lowercase();
+        if target.is_empty() {
+            return Err(format!("invalid alias list '{}': empty domain", spec));
+        }
+
+        for alias in aliases.split(',') {
+            let alias = alias.trim().to_lowercase();
+            if alias.is_empty() {
+                continue;
+            }
+            if alias == target {
+                return Err(format!("alias '{}' points to itself", alias));
+            }
+            self.aliases.insert(alias, target.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Add a wildcard vhost of the form `*.example.com=/srv/example/{sub}`
+    pub fn add_wildcard_spec(&mut self, spec: &str) -> Result<(), String> {
+        let (pattern, template) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("invalid wildcard vhost '{}' (expected *.DOMAIN=TEMPLATE)", spec))?;
+        let suffix = pattern
+            .trim()
+            .strip_prefix('*')
+            .filter(|s| s.starts_with('.') && s.len() > 1)
+            .ok_or_else(|| format!("invalid wildcard pattern '{}' (expected *.DOMAIN)", pattern))?;
+        let template = template.trim();
+        if !template.contains("{sub}") && !template.contains("{domain}") {
+            return Err(format!("wildcard template '{}' must contain {{sub}} or {{domain}}", template));
+        }
+
+        self.wildcards.push(WildcardVhost {
+            suffix: suffix.to_lowercase(),
+            root_template: template.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Resolve a host name to a document root, redirect or unknown
+    ///
+    /// # Arguments
+    /// * `host` - Host name without port; must already have passed the domain safety check
+    ///
+    /// # Returns
+    /// * `VhostResolution` - What to do with a request for this host
+    pub fn resolve(&self, host: &str) -> VhostResolution {
+        let host = host.to_lowercase();
+
+        match self.canonical_host {
+            CanonicalHost::WwwToApex => {
+                if let Some(apex) = host.strip_prefix("www.") {
+                    if self.document_root_for(apex).is_some() {
+                        return VhostResolution::Redirect(apex.to_string());
+                    }
+                }
+            }
+            CanonicalHost::ApexToWww => {
+                if !host.starts_with("www.") {
+                    let www = format!("www.{}", host);
+                    if self.document_root_for(&www).is_some() {
+                        return VhostResolution::Redirect(www);
+                    }
+                }
+            }
+            CanonicalHost::None => {}
+        }
+
+        match self.document_root_for(&host) {
+            Some(root) => VhostResolution::Root(root),
+            None => VhostResolution::Unknown,
+        }
+    }
+
+    /// The configured site serving a host, for per-site output such as access logs
+    ///
+    /// Aliases belong to the site whose tree they share; exact and wildcard vhosts
+    /// are their own site. Hosts that no vhost serves have no site.
+    pub fn site_for(&self, host: &str) -> Option<String> {
+        let host = host.to_lowercase();
+        self.document_root_for(&host)?;
+        Some(self.aliases.get(&host).cloned().unwrap_or(host))
+    }
+
+    /// Find the document root for a host without applying canonical redirects
+    fn document_root_for(&self, host: &str) -> Option<PathBuf> {
+        // Aliases share the tree of their target
+        let name = self.aliases.get(host).map(String::as_str).unwrap_or(host);
+
+        let exact = PathBuf::from(self.root_template.replace("{domain}", name));
+        if exact.is_dir() {
+            return Some(exact);
+        }
+
+        for wildcard in &self.wildcards {
+            if let Some(sub) = name.strip_suffix(wildcard.suffix.as_str()) {
+                // A wildcard matches exactly one label
+                if sub.is_empty() || sub.contains('.') {
+                    continue;
+                }
+                let root = PathBuf::from(
+                    wildcard
+                        .root_template
+                        .replace("{sub}", sub)
+                        .replace("{domain}", name),
+                );
+                if root.is_dir() {
+                    return Some(root);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn make_sites(name: &str, sites: &[&str]) -> PathBuf {
+        let base = std::env::temp_dir().join(format!("easyp_vhost_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        for site in sites {
+            fs::create_dir_all(base.join(site).join("public")).unwrap();
+        }
+        base
+    }
+
+    fn config_for(base: &std::path::Path) -> VhostConfig {
+        VhostConfig {
+            root_template: format!("{}/{{domain}}/public", base.display()),
+            ..VhostConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_root_template() {
+        let base = make_sites("template", &["example.com"]);
+        let config = config_for(&base);
+
+        assert_eq!(config.resolve("example.com"), VhostResolution::Root(base.join("example.com/public")));
+        assert_eq!(config.resolve("EXAMPLE.com"), VhostResolution::Root(base.join("example.com/public")));
+        assert_eq!(config.resolve("other.com"), VhostResolution::Unknown);
+    }
+
+    #[test]
+    fn test_aliases_share_tree() {
+        let base = make_sites("alias", &["example.com"]);
+        let mut config = config_for(&base);
+        config.add_alias_spec("example.com=www.example.com, example.net").unwrap();
+
+        let root = VhostResolution::Root(base.join("example.com/public"));
+        assert_eq!(config.resolve("www.example.com"), root);
+        assert_eq!(config.resolve("example.net"), root);
+        assert_eq!(config.site_for("Example.NET").as_deref(), Some("example.com"));
+        assert_eq!(config.site_for("other.net"), None);
+        assert!(config.add_alias_spec("example.com=example.com").is_err());
+        assert!(config.add_alias_spec("example.com").is_err());
+    }
+
+    #[test]
+    fn test_wildcard_vhost() {
+        let base = make_sites("wildcard", &["blog", "shop"]);
+        let mut config = config_for(&base);
+        config
+            .add_wildcard_spec(&format!("*.example.com={}/{{sub}}/public", base.display()))
+            .unwrap();
+
+        assert_eq!(config.resolve("blog.example.com"), VhostResolution::Root(base.join("blog/public")));
+        assert_eq!(config.site_for("blog.example.com").as_deref(), Some("blog.example.com"));
+        assert_eq!(config.site_for("missing.example.com"), None);
+        assert_eq!(config.resolve("missing.example.com"), VhostResolution::Unknown);
+        assert_eq!(config.resolve("a.blog.example.com"), VhostResolution::Unknown);
+        assert_eq!(config.resolve("example.com"), VhostResolution::Unknown);
+        assert!(config.add_wildcard_spec("example.com=/srv/{sub}").is_err());
+        assert!(config.add_wildcard_spec("*.example.com=/srv/static").is_err());
+    }
+
+    #[test]
+    fn test_canonical_redirects() {
+        let base = make_sites("canonical", &["example.com", "www.example.org"]);
+        let mut config = config_for(&base);
+
+        config.canonical_host = CanonicalHost::WwwToApex;
+        assert_eq!(config.resolve("www.example.com"), VhostResolution::Redirect("example.com".to_string()));
+        assert_eq!(config.resolve("example.com"), VhostResolution::Root(base.join("example.com/public")));
+
+        config.canonical_host = CanonicalHost::ApexToWww;
+        assert_eq!(config.resolve("example.org"), VhostResolution::Redirect("www.example.org".to_string()));
+        assert_eq!(config.resolve("www.example.org"), VhostResolution::Root(base.join("www.example.org/public")));
+        // No www vhost exists, so the apex is served directly
+        assert_eq!(config.resolve("example.com"), VhostResolution::Root(base.join("example.com/public")));
+    }
+
+    #[test]
+    fn test_policy_parsing() {
+        assert_eq!(CanonicalHost::parse("www-to-apex").unwrap(), CanonicalHost::WwwToApex);
+        assert_eq!(CanonicalHost::parse("apex-to-www").unwrap(), CanonicalHost::ApexToWww);
+        assert!(CanonicalHost::parse("sideways").is_err());
+        assert_eq!(UnknownHostPolicy::parse("421").unwrap(), UnknownHostPolicy::Misdirected);
+        assert_eq!(UnknownHostPolicy::parse("404").unwrap(), UnknownHostPolicy::NotFound);
+        assert_eq!(UnknownHostPolicy::parse("serve").unwrap(), UnknownHostPolicy::ServeDefault);
+        assert!(UnknownHostPolicy::parse("500").is_err());
+    }
+}
@@ -0,0 +1,699 @@
+//! WebSocket Endpoints
+//!
+//! This module implements the server side of RFC 6455 for extension endpoints
+//! (`extensions/*.ws.rs`, served at `/ws/<name>`, and `extensions/*.admin.ws.rs`,
+//! served at `/ws/<name>_<admin key>`). It provides:
+//! - Upgrade validation and the `Sec-WebSocket-Accept` handshake
+//! - Frame parsing with masking, fragmentation and UTF-8 checks
+//! - A message channel pair handed to the extension, so handlers never touch frames
+//! - Per-connection limits (message size, queue depth) and a global connection cap
+//! - Ping/pong keepalive with an idle timeout
+//!
+//! Only HTTP/1.1 upgrades exist; the server does not speak HTTP/2, so extended
+//! CONNECT (RFC 8441) has nothing to attach to yet.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use super::digest::{base64_encode, sha1};
+use super::reverse_proxy::{read_head, MessageHead, ProxyOutcome};
+
+/// URI prefix for WebSocket endpoints
+pub const WS_PREFIX: &str = "/ws/";
+
+/// GUID appended to the client key (RFC 6455 section 1.3)
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Open WebSocket connections across the server
+static ACTIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// WebSocket limits and keepalive settings
+#[derive(Debug, Clone)]
+pub struct WsConfig {
+    /// Largest message (after reassembly) accepted from a client
+    pub max_message_size: usize,
+    /// Messages buffered per direction before the connection is closed
+    pub queue_depth: usize,
+    /// Interval between server pings
+    pub ping_interval: Duration,
+    /// Close the connection when nothing (not even a pong) arrives for this long
+    pub idle_timeout: Duration,
+    /// Maximum open WebSocket connections across the server
+    pub max_connections: usize,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: 64 * 1024,
+            queue_depth: 64,
+            ping_interval: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(75),
+            max_connections: 256,
+        }
+    }
+}
+
+/// A complete WebSocket message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsMessage {
+    /// UTF-8 text message
+    Text(String),
+    /// Binary message
+    Binary(Vec<u8>),
+}
+
+/// The upgrade request that opened a connection
+#[derive(Debug, Clone)]
+pub struct WsRequest {
+    /// Request path without the query string
+    pub path: String,
+    /// Raw query string (without `?`)
+    pub query: String,
+    /// Host header without port
+    pub host: String,
+    /// Request headers, names lowercased
+    pub headers: HashMap<String, String>,
+    /// Client address
+    pub remote: SocketAddr,
+}
+
+impl WsRequest {
+    /// Look up and decode a query parameter
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query.split('&').find_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            if key != name {
+                return None;
+            }
+            urlencoding::decode(&value.replace('+', " ")).ok().map(|v| v.into_owned())
+        })
+    }
+}
+
+/// What an extension handler receives for one connection
+///
+/// `incoming` yields `None` once the client has gone. Dropping `outgoing` (returning
+/// from the handler) closes the connection normally.
+pub struct WsChannel {
+    /// The upgrade request
+    pub request: WsRequest,
+    /// Messages from the client
+    pub incoming: mpsc::Receiver<WsMessage>,
+    /// Messages to the client
+    pub outgoing: mpsc::Sender<WsMessage>,
+}
+
+/// Number of open WebSocket connections
+pub fn active_connections() -> usize {
+    ACTIVE_CONNECTIONS.load(Ordering::Relaxed)
+}
+
+/// Extension name for a `/ws/<name>` path, if the path has that shape
+pub fn endpoint_name(path: &str) -> Option<&str> {
+    let name = path.split('?').next()?.strip_prefix(WS_PREFIX)?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    Some(name)
+}
+
+/// Compute `Sec-WebSocket-Accept` for a client key
+pub fn accept_key(key: &str) -> String {
+    base64_encode(&sha1(format!("{}{}", key.trim(), WS_GUID).as_bytes()))
+}
+
+/// Why an upgrade request was refused
+#[derive(Debug, PartialEq, Eq)]
+struct HandshakeError {
+    status: u16,
+    reason: &'static str,
+    message: String,
+}
+
+/// Check an upgrade request and return the client key
+fn check_upgrade(head: &MessageHead) -> Result<String, HandshakeError> {
+    let bad = |message: &str| HandshakeError { status: 400, reason: "Bad Request", message: message.to_string() };
+    let has_token = |name: &str, token: &str| {
+        head.get(name)
+            .map(|value| value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+            .unwrap_or(false)
+    };
+
+    let mut parts = head.start_line.split_whitespace();
+    if parts.next() != Some("GET") {
+        return Err(HandshakeError { status: 405, reason: "Method Not Allowed", message: "WebSocket upgrades must use GET".to_string() });
+    }
+    if parts.nth(1) != Some("HTTP/1.1") {
+        return Err(bad("WebSocket upgrades need HTTP/1.1"));
+    }
+    if !has_token("upgrade", "websocket") || !has_token("connection", "upgrade") {
+        return Err(HandshakeError { status: 426, reason: "Upgrade Required", message: "expected a WebSocket upgrade".to_string() });
+    }
+    if head.get("sec-websocket-version").map(str::trim) != Some("13") {
+        return Err(HandshakeError { status: 426, reason: "Upgrade Required", message: "unsupported WebSocket version".to_string() });
+    }
+    // The key is 16 random bytes in base64: 22 characters plus "=="
+    let key = head.get("sec-websocket-key").map(str::trim).unwrap_or("");
+    if key.len() != 24 || !key.ends_with("==") {
+        return Err(bad("missing or malformed Sec-WebSocket-Key"));
+    }
+    Ok(key.to_string())
+}
+
+/// One parsed frame
+#[derive(Debug)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// A close code and reason to send when the connection must end
+type CloseReason = (u16, &'static str);
+
+/// Parse one client frame from the front of `buf`
+///
+/// # Returns
+/// * `Ok(Some((frame, used)))` - A complete frame and the number of bytes it took
+/// * `Ok(None)` - More bytes are needed
+/// * `Err(close)` - Protocol violation; close with this code
+fn parse_frame(buf: &[u8], max_payload: usize) -> Result<Option<(Frame, usize)>, CloseReason> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buf[0] & 0x80 != 0;
+    let opcode = buf[0] & 0x0F;
+    if buf[0] & 0x70 != 0 {
+        return Err((1002, "reserved bits set"));
+    }
+    if !matches!(opcode, OP_CONTINUATION | OP_TEXT | OP_BINARY | OP_CLOSE | OP_PING | OP_PONG) {
+        return Err((1002, "unknown opcode"));
+    }
+    if buf[1] & 0x80 == 0 {
+        return Err((1002, "client frames must be masked"));
+    }
+    let short_len = (buf[1] & 0x7F) as usize;
+    if opcode >= OP_CLOSE && (!fin || short_len > 125) {
+        return Err((1002, "invalid control frame"));
+    }
+
+    let (len, offset) = match short_len {
+        126 => {
+            if buf.len() < 4 {
+                return Ok(None);
+            }
+            (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4)
+        }
+        127 => {
+            if buf.len() < 10 {
+                return Ok(None);
+            }
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&buf[2..10]);
+            (u64::from_be_bytes(bytes), 10)
+        }
+        n => (n as u64, 2),
+    };
+    if len > max_payload as u64 {
+        return Err((1009, "message too big"));
+    }
+    let len = len as usize;
+    let total = offset + 4 + len;
+    if buf.len() < total {
+        return Ok(None);
+    }
+
+    let mask = [buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]];
+    let payload = buf[offset + 4..total]
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ mask[i % 4])
+        .collect();
+    Ok(Some((Frame { fin, opcode, payload }, total)))
+}
+
+/// Encode an unmasked server frame
+fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Decrements the connection count when a connection ends
+struct ConnectionSlot;
+
+impl ConnectionSlot {
+    fn acquire(limit: usize) -> Option<Self> {
+        ACTIVE_CONNECTIONS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < limit).then_some(n + 1))
+            .ok()
+            .map(|_| ConnectionSlot)
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Accept a WebSocket upgrade and run an extension handler on it
+///
+/// The handler runs as its own task with a [`WsChannel`]; this function drives the
+/// socket (framing, keepalive, limits) until either side closes.
+///
+/// # Arguments
+/// * `client` - Client connection (plain TCP or TLS)
+/// * `initial` - Bytes already read from the client (at least part of the request head)
+/// * `remote` - Client address
+/// * `config` - Limits and keepalive settings
+/// * `handler` - Extension entry point
+///
+/// # Returns
+/// * `Result<ProxyOutcome, Box<dyn std::error::Error + Send + Sync>>` - 101 and bytes sent on success,
+///   or the error status that was sent; the caller should close the connection afterwards
+pub async fn serve_websocket<C, F, Fut>(
+    client: &mut C,
+    initial: &[u8],
+    remote: SocketAddr,
+    config: &WsConfig,
+    handler: F,
+) -> Result<ProxyOutcome, Box<dyn std::error::Error + Send + Sync>>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    F: FnOnce(WsChannel) -> Fut,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    let mut buffered = initial.to_vec();
+    let checked = match read_head(client, &mut buffered, config.idle_timeout).await {
+        Ok(head_end) => MessageHead::parse(&buffered[..head_end])
+            .map_err(|e| HandshakeError { status: 400, reason: "Bad Request", message: e })
+            .and_then(|head| check_upgrade(&head).map(|key| (head, key, head_end))),
+        Err(e) => Err(HandshakeError { status: 400, reason: "Bad Request", message: e }),
+    };
+    let slot = ConnectionSlot::acquire(config.max_connections);
+    let (head, key, head_end) = match (checked, &slot) {
+        (Ok(accepted), Some(_)) => accepted,
+        (Err(refused), _) => return refuse(client, refused).await,
+        (Ok(_), None) => {
+            let message = format!("connection limit of {} reached", config.max_connections);
+            return refuse(client, HandshakeError { status: 503, reason: "Service Unavailable", message }).await;
+        }
+    };
+
+    let target = head.start_line.split_whitespace().nth(1).unwrap_or("/");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let host = head.get("host").unwrap_or("");
+    let request = WsRequest {
+        path: path.to_string(),
+        query: query.to_string(),
+        host: host.rsplit_once(':').filter(|(_, p)| p.parse::<u16>().is_ok()).map(|(h, _)| h).unwrap_or(host).to_string(),
+        headers: head.headers.iter().map(|(name, value)| (name.to_lowercase(), value.clone())).collect(),
+        remote,
+    };
+
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    );
+    client.write_all(response.as_bytes()).await?;
+    client.flush().await?;
+    log::debug!(target: "websocket", "WebSocket {} opened from {}", request.path, remote);
+
+    let (incoming_tx, incoming_rx) = mpsc::channel(config.queue_depth);
+    let (outgoing_tx, outgoing_rx) = mpsc::channel(config.queue_depth);
+    let path = request.path.clone();
+    let task = tokio::spawn(handler(WsChannel { request, incoming: incoming_rx, outgoing: outgoing_tx }));
+
+    // Bytes after the head are already frames
+    let pending = buffered.split_off(head_end + 4);
+    let mut bytes_sent = response.len() as u64;
+    let result = drive(client, pending, incoming_tx, outgoing_rx, config, &mut bytes_sent).await;
+    task.abort();
+    drop(slot);
+
+    match &result {
+        Ok((code, reason)) => log::debug!(target: "websocket", "WebSocket {} closed ({} {})", path, code, reason),
+        Err(e) => log::info!(target: "websocket", "WebSocket {} dropped: {}", path, e),
+    }
+    Ok(ProxyOutcome { status: 101, bytes_sent })
+}
+
+/// Answer a refused upgrade with a plain HTTP error
+async fn refuse<C>(client: &mut C, refused: HandshakeError) -> Result<ProxyOutcome, Box<dyn std::error::Error + Send + Sync>>
+where
+    C: AsyncWrite + Unpin,
+{
+    log::debug!(target: "websocket", "WebSocket upgrade refused: {}", refused.message);
+    let body = format!("{} {}", refused.status, refused.reason);
+    let version_header = if refused.status == 426 { "Sec-WebSocket-Version: 13\r\n" } else { "" };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\n{}Content-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        refused.status,
+        refused.reason,
+        version_header,
+        body.len(),
+        body
+    );
+    client.write_all(response.as_bytes()).await?;
+    client.flush().await?;
+    Ok(ProxyOutcome { status: refused.status, bytes_sent: response.len() as u64 })
+}
+
+/// Write one frame, giving up when the client stops reading
+async fn send_frame<C>(client: &mut C, opcode: u8, payload: &[u8], config: &WsConfig, bytes_sent: &mut u64) -> std::io::Result<()>
+where
+    C: AsyncWrite + Unpin,
+{
+    let frame = encode_frame(opcode, payload);
+    let write = async {
+        client.write_all(&frame).await?;
+        client.flush().await
+    };
+    tokio::time::timeout(config.idle_timeout, write)
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "client stopped reading"))??;
+    *bytes_sent += frame.len() as u64;
+    Ok(())
+}
+
+/// Pump frames between the socket and the handler's channels
+///
+/// # Returns
+/// * `Ok((code, reason))` - The connection closed with a close frame
+/// * `Err(e)` - The socket failed or the client vanished without a close frame
+async fn drive<C>(
+    client: &mut C,
+    mut buf: Vec<u8>,
+    incoming: mpsc::Sender<WsMessage>,
+    mut outgoing: mpsc::Receiver<WsMessage>,
+    config: &WsConfig,
+    bytes_sent: &mut u64,
+) -> std::io::Result<(u16, &'static str)>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut chunk = vec![0u8; 8192];
+    let mut partial: Option<(u8, Vec<u8>)> = None;
+    let mut last_seen = Instant::now();
+    let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + config.ping_interval, config.ping_interval);
+
+    let (code, reason) = 'connection: loop {
+        // Handle every complete frame before waiting for more input
+        loop {
+            let (frame, used) = match parse_frame(&buf, config.max_message_size) {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) => break,
+                Err(close) => break 'connection close,
+            };
+            buf.drain(..used);
+
+            let message = match frame.opcode {
+                OP_PING => {
+                    send_frame(client, OP_PONG, &frame.payload, config, bytes_sent).await?;
+                    continue;
+                }
+                OP_PONG => continue,
+                OP_CLOSE => {
+                    // Echo the client's code, then we are done
+                    let code = match frame.payload.get(..2) {
+                        Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+                        None => 1000,
+                    };
+                    let echo = if (1000..5000).contains(&code) { code.to_be_bytes().to_vec() } else { Vec::new() };
+                    let _ = send_frame(client, OP_CLOSE, &echo, config, bytes_sent).await;
+                    return Ok((code, "closed by client"));
+                }
+                OP_CONTINUATION => {
+                    let Some((opcode, mut data)) = partial.take() else {
+                        break 'connection (1002, "unexpected continuation frame");
+                    };
+                    if data.len() + frame.payload.len() > config.max_message_size {
+                        break 'connection (1009, "message too big");
+                    }
+                    data.extend_from_slice(&frame.payload);
+                    if !frame.fin {
+                        partial = Some((opcode, data));
+                        continue;
+                    }
+                    (opcode, data)
+                }
+                opcode => {
+                    if partial.is_some() {
+                        break 'connection (1002, "expected continuation frame");
+                    }
+                    if !frame.fin {
+                        partial = Some((opcode, frame.payload));
+                        continue;
+                    }
+                    (opcode, frame.payload)
+                }
+            };
+
+            let message = match message {
+                (OP_TEXT, data) => match String::from_utf8(data) {
+                    Ok(text) => WsMessage::Text(text),
+                    Err(_) => break 'connection (1007, "invalid UTF-8 in text message"),
+                },
+                (_, data) => WsMessage::Binary(data),
+            };
+            if let Err(mpsc::error::TrySendError::Full(_)) = incoming.try_send(message) {
+                break 'connection (1008, "message queue full");
+            }
+        }
+
+        tokio::select! {
+            read = client.read(&mut chunk) => {
+                let n = read?;
+                if n == 0 {
+                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "client went away"));
+                }
+                buf.extend_from_slice(&chunk[..n]);
+                last_seen = Instant::now();
+            }
+            message = outgoing.recv() => match message {
+                Some(WsMessage::Text(text)) => send_frame(client, OP_TEXT, text.as_bytes(), config, bytes_sent).await?,
+                Some(WsMessage::Binary(data)) => send_frame(client, OP_BINARY, &data, config, bytes_sent).await?,
+                None => break (1000, "handler finished"),
+            },
+            _ = ping.tick() => {
+                if last_seen.elapsed() > config.idle_timeout {
+                    break (1001, "idle timeout");
+                }
+                send_frame(client, OP_PING, b"", config, bytes_sent).await?;
+            }
+        }
+    };
+
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    send_frame(client, OP_CLOSE, &payload, config, bytes_sent).await?;
+    Ok((code, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode a masked client frame
+    fn client_frame(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![first_byte];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    /// Read one unmasked server frame
+    async fn server_frame<S: AsyncRead + Unpin>(stream: &mut S) -> (u8, Vec<u8>) {
+        let mut head = [0u8; 2];
+        stream.read_exact(&mut head).await.unwrap();
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0u8; 2];
+                stream.read_exact(&mut len).await.unwrap();
+                u16::from_be_bytes(len) as usize
+            }
+            n => n as usize,
+        };
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).await.unwrap();
+        (head[0] & 0x0F, payload)
+    }
+
+    fn upgrade_request(key: &str) -> String {
+        format!(
+            "GET /ws/echo?room=a+b HTTP/1.1\r\nHost: example.com:8443\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            key
+        )
+    }
+
+    async fn echo(mut channel: WsChannel) -> Result<(), String> {
+        let room = channel.request.query_param("room").unwrap_or_default();
+        while let Some(message) = channel.incoming.recv().await {
+            let reply = match message {
+                WsMessage::Text(text) if text == "bye" => return Ok(()),
+                WsMessage::Text(text) => WsMessage::Text(format!("{}: {}", room, text)),
+                other => other,
+            };
+            channel.outgoing.send(reply).await.map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // Example from RFC 6455 section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_endpoint_and_upgrade_checks() {
+        assert_eq!(endpoint_name("/ws/comment?page=/"), Some("comment"));
+        assert_eq!(endpoint_name("/ws/"), None);
+        assert_eq!(endpoint_name("/ws/../x"), None);
+        assert_eq!(endpoint_name("/other/comment"), None);
+
+        let good = upgrade_request("dGhlIHNhbXBsZSBub25jZQ==");
+        let head = MessageHead::parse(good.trim_end().as_bytes()).unwrap();
+        assert_eq!(check_upgrade(&head).unwrap(), "dGhlIHNhbXBsZSBub25jZQ==");
+
+        let old = good.replace("Version: 13", "Version: 8");
+        assert_eq!(check_upgrade(&MessageHead::parse(old.trim_end().as_bytes()).unwrap()).unwrap_err().status, 426);
+        let post = good.replace("GET ", "POST ");
+        assert_eq!(check_upgrade(&MessageHead::parse(post.trim_end().as_bytes()).unwrap()).unwrap_err().status, 405);
+        let no_key = good.replace("dGhlIHNhbXBsZSBub25jZQ==", "short");
+        assert_eq!(check_upgrade(&MessageHead::parse(no_key.trim_end().as_bytes()).unwrap()).unwrap_err().status, 400);
+    }
+
+    #[test]
+    fn test_frame_parsing() {
+        let frame = client_frame(0x81, b"hello");
+        let (parsed, used) = parse_frame(&frame, 1024).unwrap().unwrap();
+        assert_eq!((parsed.fin, parsed.opcode, parsed.payload.as_slice(), used), (true, OP_TEXT, &b"hello"[..], frame.len()));
+        assert!(parse_frame(&frame[..frame.len() - 1], 1024).unwrap().is_none());
+
+        let long = client_frame(0x82, &[7u8; 300]);
+        assert_eq!(parse_frame(&long, 1024).unwrap().unwrap().0.payload.len(), 300);
+        assert_eq!(parse_frame(&long, 100).unwrap_err().0, 1009);
+
+        let mut unmasked = client_frame(0x81, b"x");
+        unmasked[1] &= 0x7F;
+        assert_eq!(parse_frame(&unmasked, 1024).unwrap_err().0, 1002);
+        assert_eq!(parse_frame(&client_frame(0xC1, b"x"), 1024).unwrap_err().0, 1002);
+        assert_eq!(parse_frame(&client_frame(0x09, b"x"), 1024).unwrap_err().0, 1002);
+
+        assert_eq!(encode_frame(OP_TEXT, b"hi"), vec![0x81, 2, b'h', b'i']);
+        assert_eq!(&encode_frame(OP_BINARY, &[0u8; 200])[..4], &[0x82, 126, 0, 200]);
+    }
+
+    #[tokio::test]
+    async fn test_echo_session() {
+        let (mut client, mut server_side) = tokio::io::duplex(64 * 1024);
+        let request = upgrade_request("dGhlIHNhbXBsZSBub25jZQ==");
+        let config = WsConfig::default();
+
+        let server = tokio::spawn(async move {
+            serve_websocket(&mut server_side, request.as_bytes(), "127.0.0.1:9".parse().unwrap(), &config, echo)
+                .await
+                .unwrap()
+        });
+
+        let mut head = vec![0u8; 0];
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            client.read_exact(&mut byte).await.unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        // A fragmented text message with a ping in between
+        client.write_all(&client_frame(0x01, b"hel")).await.unwrap();
+        client.write_all(&client_frame(0x89, b"p")).await.unwrap();
+        client.write_all(&client_frame(0x80, b"lo")).await.unwrap();
+        assert_eq!(server_frame(&mut client).await, (OP_PONG, b"p".to_vec()));
+        assert_eq!(server_frame(&mut client).await, (OP_TEXT, b"a b: hello".to_vec()));
+
+        client.write_all(&client_frame(0x82, &[1, 2, 3])).await.unwrap();
+        assert_eq!(server_frame(&mut client).await, (OP_BINARY, vec![1, 2, 3]));
+
+        // The handler returning closes the connection normally
+        client.write_all(&client_frame(0x81, b"bye")).await.unwrap();
+        let (opcode, payload) = server_frame(&mut client).await;
+        assert_eq!((opcode, &payload[..2]), (OP_CLOSE, &1000u16.to_be_bytes()[..]));
+        assert_eq!(server.await.unwrap().status, 101);
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        // Oversized message closes with 1009
+        let (mut client, mut server_side) = tokio::io::duplex(64 * 1024);
+        let mut request = upgrade_request("dGhlIHNhbXBsZSBub25jZQ==").into_bytes();
+        request.extend_from_slice(&client_frame(0x81, &[b'x'; 200]));
+        let config = WsConfig { max_message_size: 100, ..WsConfig::default() };
+        let outcome = serve_websocket(&mut server_side, &request, "127.0.0.1:9".parse().unwrap(), &config, echo).await.unwrap();
+        assert_eq!(outcome.status, 101);
+        drop(server_side);
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        let close = &response[response.len() - 20..];
+        assert!(close.windows(2).any(|w| w == 1009u16.to_be_bytes()));
+
+        // No pong within the idle timeout closes with 1001
+        let (mut client, mut server_side) = tokio::io::duplex(64 * 1024);
+        let request = upgrade_request("dGhlIHNhbXBsZSBub25jZQ==");
+        let config = WsConfig {
+            ping_interval: Duration::from_millis(20),
+            idle_timeout: Duration::from_millis(50),
+            ..WsConfig::default()
+        };
+        serve_websocket(&mut server_side, request.as_bytes(), "127.0.0.1:9".parse().unwrap(), &config, echo).await.unwrap();
+        drop(server_side);
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert!(response.windows(2).any(|w| w == 1001u16.to_be_bytes()));
+
+        // Refused upgrades get a plain HTTP answer
+        let (_client, mut server_side) = tokio::io::duplex(4096);
+        let request = upgrade_request("dGhlIHNhbXBsZSBub25jZQ==").replace("Upgrade: websocket\r\n", "");
+        let outcome = serve_websocket(&mut server_side, request.as_bytes(), "127.0.0.1:9".parse().unwrap(), &WsConfig::default(), echo)
+            .await
+            .unwrap();
+        assert_eq!(outcome.status, 426);
+    }
+}
@@ -0,0 +1,216 @@
+//! Certificate Fields
+//!
+//! This module pulls the fields certificate management needs out of DER certificates,
+//! parsed with x509-parser (PEM files are read with rustls-pemfile). It provides:
+//! - [`parse_certificate`]: validity period, DNS names and the fields OCSP needs
+//!   (serial, issuer, key, responder URLs) of a certificate
+//! - [`format_name`]: a distinguished name as an RFC 4514 string, and [`common_name`]
+//!
+//! Signatures are not checked here.
+
+use x509_parser::asn1_rs::oid;
+use x509_parser::extensions::{GeneralName, ParsedExtension};
+use x509_parser::oid_registry::{
+    Oid, OID_DOMAIN_COMPONENT, OID_PKIX_ACCESS_DESCRIPTOR_OCSP, OID_USERID, OID_X509_COMMON_NAME, OID_X509_COUNTRY_NAME,
+    OID_X509_LOCALITY_NAME, OID_X509_ORGANIZATIONAL_UNIT, OID_X509_ORGANIZATION_NAME, OID_X509_STATE_OR_PROVINCE_NAME,
+    OID_X509_STREET_ADDRESS,
+};
+use x509_parser::prelude::{FromDer, X509Certificate, X509Name};
+
+/// Fields of a certificate used for reloading and monitoring
+#[derive(Debug, Clone)]
+pub struct CertInfo {
+    /// Unix seconds
+    pub not_before: i64,
+    /// Unix seconds
+    pub not_after: i64,
+    /// dNSName entries of the subjectAltName extension, lowercase
+    pub dns_names: Vec<String>,
+    /// rfc822Name (e-mail) entries of the subjectAltName extension, as encoded
+    pub email_names: Vec<String>,
+    /// Serial number contents, as encoded
+    pub serial: Vec<u8>,
+    /// Complete DER encoding of the issuer name
+    pub issuer: Vec<u8>,
+    /// Complete DER encoding of the subject name
+    pub subject: Vec<u8>,
+    /// subjectPublicKey bits, without the unused-bits byte
+    pub public_key_bits: Vec<u8>,
+    /// OCSP responder URLs from the authorityInfoAccess extension
+    pub ocsp_urls: Vec<String>,
+}
+
+/// Parse a DER certificate
+///
+/// # Arguments
+/// * `der` - The certificate, e.g. the first certificate of a chain
+///
+/// # Returns
+/// * `Result<CertInfo, String>` - Decoded fields, or why the certificate is malformed
+pub fn parse_certificate(der: &[u8]) -> Result<CertInfo, String> {
+    let (_, cert) = X509Certificate::from_der(der).map_err(|e| format!("bad certificate: {}", e))?;
+
+    let (mut dns_names, mut email_names, mut ocsp_urls) = (Vec::new(), Vec::new(), Vec::new());
+    for extension in cert.extensions() {
+        match extension.parsed_extension() {
+            ParsedExtension::SubjectAlternativeName(names) => {
+                for name in &names.general_names {
+                    match name {
+                        GeneralName::DNSName(name) => dns_names.push(name.to_ascii_lowercase()),
+                        GeneralName::RFC822Name(name) => email_names.push(name.to_string()),
+                        _ => {}
+                    }
+                }
+            }
+            ParsedExtension::AuthorityInfoAccess(access) => {
+                for description in access.iter() {
+                    if let (true, GeneralName::URI(url)) =
+                        (description.access_method == OID_PKIX_ACCESS_DESCRIPTOR_OCSP, &description.access_location)
+                    {
+                        ocsp_urls.push(url.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(CertInfo {
+        not_before: cert.validity().not_before.timestamp(),
+        not_after: cert.validity().not_after.timestamp(),
+        dns_names,
+        email_names,
+        serial: cert.raw_serial().to_vec(),
+        issuer: cert.issuer().as_raw().to_vec(),
+        subject: cert.subject().as_raw().to_vec(),
+        public_key_bits: cert.public_key().subject_public_key.data.to_vec(),
+        ocsp_urls,
+    })
+}
+
+/// Short name of a distinguished name attribute, or its dotted OID
+fn attribute_name(oid: &Oid) -> String {
+    // RFC 4514 section 3 names, plus the e-mail attribute OpenSSL prints
+    let known = [
+        (OID_X509_COMMON_NAME, "CN"),
+        (OID_X509_COUNTRY_NAME, "C"),
+        (OID_X509_LOCALITY_NAME, "L"),
+        (OID_X509_STATE_OR_PROVINCE_NAME, "ST"),
+        (OID_X509_STREET_ADDRESS, "STREET"),
+        (OID_X509_ORGANIZATION_NAME, "O"),
+        (OID_X509_ORGANIZATIONAL_UNIT, "OU"),
+        (OID_DOMAIN_COMPONENT, "DC"),
+        (OID_USERID, "UID"),
+        (oid!(1.2.840.113549.1.9.1), "emailAddress"),
+    ];
+    known.iter().find(|(known, _)| known == oid).map(|(_, name)| name.to_string()).unwrap_or_else(|| oid.to_id_string())
+}
+
+/// Attributes of a DER-encoded Name (as in [`CertInfo::subject`]), in encoded order
+///
+/// # Returns
+/// * `Option<Vec<(String, String)>>` - Short attribute names and their string values
+pub fn name_attributes(name: &[u8]) -> Option<Vec<(String, String)>> {
+    let (_, name) = X509Name::from_der(name).ok()?;
+    let attributes = name
+        .iter_attributes()
+        .map(|attribute| {
+            let value = match attribute.as_str() {
+                Ok(value) => value.to_string(),
+                Err(_) => String::from_utf8_lossy(attribute.as_slice()).to_string(),
+            };
+            (attribute_name(attribute.attr_type()), value)
+        })
+        .collect();
+    Some(attributes)
+}
+
+/// Format a DER-encoded Name as an RFC 4514 string, most specific attribute first
+///
+/// # Returns
+/// * `String` - e.g. `CN=alice,O=Example\, Inc,C=AU`; empty if the name is malformed
+pub fn format_name(name: &[u8]) -> String {
+    let escape = |value: &str| {
+        let mut escaped = String::with_capacity(value.len());
+        for (i, c) in value.chars().enumerate() {
+            let edge = (i == 0 && (c == ' ' || c == '#')) || (i + 1 == value.chars().count() && c == ' ');
+            if edge || matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    };
+    name_attributes(name)
+        .unwrap_or_default()
+        .iter()
+        .rev()
+        .map(|(key, value)| format!("{}={}", key, escape(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The last (most specific) common name of a DER-encoded Name
+pub fn common_name(name: &[u8]) -> Option<String> {
+    name_attributes(name)?.into_iter().rev().find(|(key, _)| key == "CN").map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EC_CERT: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIIBmDCCAT+gAwIBAgIBATAKBggqhkjOPQQDAjAXMRUwEwYDVQQDDAx0ZXN0LmV4\n\
+YW1wbGUwHhcNMjYxMDE4MTM0ODI3WhcNMzYxMDE1MTM0ODI3WjAXMRUwEwYDVQQD\n\
+DAx0ZXN0LmV4YW1wbGUwWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAATJ3HeWsR9K\n\
+fvWTF0KuiaTwZ4h+UcEK+gQrxC63BZq7ras0t1eoOmSEHDJ9TPsoFob3/kWm0MC0\n\
+5/YJ7MzyI8LHo3wwejAdBgNVHQ4EFgQUCjeMXzbye2QGXoNxnvWXPDaEnH4wHwYD\n\
+VR0jBBgwFoAUCjeMXzbye2QGXoNxnvWXPDaEnH4wDwYDVR0TAQH/BAUwAwEB/zAn\n\
+BgNVHREEIDAeggx0ZXN0LmV4YW1wbGWCDioudGVzdC5leGFtcGxlMAoGCCqGSM49\n\
+BAMCA0cAMEQCIETwv2w0/PgxA8CEYu0y7dgwecwLm7jKmGTyuzQO6zsHAiAknR8X\n\
+Uo/CNm1/LnOyGBUsIKpejhbKCr6gUXkTL09wtw==\n\
+-----END CERTIFICATE-----\n";
+
+    const RSA_CERT: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIICDTCCAXagAwIBAgIBAjANBgkqhkiG9w0BAQsFADAWMRQwEgYDVQQDDAtyc2Eu\n\
+ZXhhbXBsZTAeFw0yNjEwMTgxMzQ4MjdaFw0zNjEwMTUxMzQ4MjdaMBYxFDASBgNV\n\
+BAMMC3JzYS5leGFtcGxlMIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQC/NSdO\n\
+aPSHRID4QGl6jnAZMT0eXoxCeW9mtKvl6x6HK57x0UlNmFO6lbiMSOpZmOkKI0B8\n\
+BsKzCQC0GgLPoYHVWzeCt8TVhSxBIrlLliJhX+4a+oox1owCcKIf1UQdz5qCvOpl\n\
+xliyKMG//owBvhoACy71uXaDKJzXWIfgTIdrjQIDAQABo2swaTAdBgNVHQ4EFgQU\n\
+ixrHn4qvlBsWPVPkb6WcRkZXNDIwHwYDVR0jBBgwFoAUixrHn4qvlBsWPVPkb6Wc\n\
+RkZXNDIwDwYDVR0TAQH/BAUwAwEB/zAWBgNVHREEDzANggtyc2EuZXhhbXBsZTAN\n\
+BgkqhkiG9w0BAQsFAAOBgQB/4in4pkVIIzjM+Jj5f4JLuSU6f828+SN7Foa69OJS\n\
+DVBfEMAKr3cOr2JNpC2WqN6cgGhX4xZAT2UaT5f6JmRoGRe12mptfju6HqeTmnQq\n\
++JX/kwNEVxRXmgtjo9Ictf0N9TQ5NkppWn5QVrCtEnogRKviGfMJnyvHZpcDlDJJ\n\
+BQ==\n\
+-----END CERTIFICATE-----\n";
+
+    fn leaf(pem: &str) -> CertInfo {
+        let der = rustls_pemfile::certs(&mut pem.as_bytes()).next().unwrap().unwrap();
+        parse_certificate(&der).unwrap()
+    }
+
+    #[test]
+    fn test_parse_certificate() {
+        let cert = leaf(EC_CERT);
+        assert_eq!(cert.not_before, 1792331307);
+        assert_eq!(cert.not_after, 2107691307);
+        assert_eq!(cert.dns_names, vec!["test.example", "*.test.example"]);
+        assert_eq!(cert.public_key_bits.len(), 65);
+        assert_eq!(cert.serial, vec![1]);
+        assert_eq!(format_name(&cert.subject), "CN=test.example");
+        assert_eq!(common_name(&cert.issuer).as_deref(), Some("test.example"));
+        assert!(cert.email_names.is_empty());
+
+        let cert = leaf(RSA_CERT);
+        assert_eq!(cert.dns_names, vec!["rsa.example"]);
+        // RSAPublicKey: a 1024-bit modulus, then exponent 65537
+        assert!(cert.public_key_bits.starts_with(&[0x30, 0x81, 0x89, 0x02, 0x81, 0x81, 0x00]));
+        assert!(cert.public_key_bits.ends_with(&[0x02, 0x03, 0x01, 0x00, 0x01]));
+
+        assert!(parse_certificate(b"\x30\x03\x02\x01\x00").is_err());
+    }
+}
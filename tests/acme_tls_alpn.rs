@@ -0,0 +1,251 @@
+//! TLS-ALPN-01 handshakes and issuance
+//!
+//! `test_resolver_handshakes` runs real rustls handshakes in memory against
+//! `TlsAlpnResolver`: a ClientHello offering only `acme-tls/1` must get the challenge
+//! certificate, any other must get the normal one.
+//!
+//! `test_tls_alpn_certificate_issued` runs against a local ACME test server and is ignored
+//! by default: it needs a running ACME server that validates on port 443 and a
+//! domain that resolves to this machine. With pebble, set `"tlsPort": 443` in its config,
+//! start it with `-dnsserver` pointing at a resolver that knows the domain, and run (as
+//! root, so port 443 can be bound):
+//!
+//! ```text
+//! EASYP_TEST_ACME_DIRECTORY=https://localhost:14000/dir \
+//! EASYP_TEST_ACME_DOMAIN=test.example.com \
+//! cargo test --test acme_tls_alpn -- --ignored
+//! ```
+//!
+//! The server is started with port 80 unavailable to ACME (`--challenge-type tls-alpn01`),
+//! and a request for the domain must end with a certificate in the ACME cache.
+
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, ServerConfig, ServerConnection, SignatureScheme};
+
+// The package has no library target, so the modules under test are compiled in here
+#[allow(dead_code)]
+#[path = "../src/modules/acme_challenge.rs"]
+mod acme_challenge;
+#[allow(dead_code)]
+#[path = "../src/modules/digest.rs"]
+mod digest;
+#[allow(dead_code)]
+#[path = "../src/modules/hostname.rs"]
+mod hostname;
+#[allow(dead_code)]
+#[path = "../src/modules/metrics.rs"]
+mod metrics;
+#[allow(dead_code)]
+#[path = "../src/modules/tls_alpn.rs"]
+mod tls_alpn;
+#[allow(dead_code)]
+#[path = "../src/modules/x509.rs"]
+mod x509;
+
+use acme_challenge::{acme_identifier, ACME_TLS_ALPN_PROTOCOL};
+use tls_alpn::{TlsAlpnChallenges, TlsAlpnResolver};
+
+/// id-pe-acmeIdentifier (1.3.6.1.5.5.7.1.31)
+const OID_ACME_IDENTIFIER: &[u8] = &[0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x1f];
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Stands in for the site certificate resolver
+#[derive(Debug)]
+struct SiteResolver(Arc<CertifiedKey>);
+
+impl ResolvesServerCert for SiteResolver {
+    fn resolve(&self, client_hello: &ClientHello<'_>) -> Result<rustls::sign::CertifiedSigner, rustls::Error> {
+        self.0.signer(client_hello.signature_schemes()).ok_or(rustls::Error::NoSuitableCertificate)
+    }
+}
+
+/// Accepts whatever the server presents, as an ACME validator does, and keeps it
+#[derive(Debug)]
+struct RecordingVerifier {
+    provider: Arc<CryptoProvider>,
+    presented: Mutex<Option<CertificateDer<'static>>>,
+}
+
+impl ServerCertVerifier for RecordingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        *self.presented.lock().unwrap() = Some(end_entity.clone().into_owned());
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Handshake in memory with a client offering `alpn` for www.example.com
+///
+/// # Returns
+/// * `Result<CertificateDer, rustls::Error>` - The certificate the server presented
+fn handshake(resolver: Arc<TlsAlpnResolver>, alpn: &[&[u8]]) -> Result<CertificateDer<'static>, rustls::Error> {
+    let mut server_config = ServerConfig::builder_with_provider(provider()).with_no_client_auth().with_cert_resolver(resolver)?;
+    // As the HTTPS listener advertises when TLS-ALPN-01 is enabled
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec(), ACME_TLS_ALPN_PROTOCOL.to_vec()];
+
+    let verifier = Arc::new(RecordingVerifier { provider: provider(), presented: Mutex::new(None) });
+    let mut client_config = ClientConfig::builder_with_provider(provider())
+        .dangerous()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth()?;
+    client_config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+
+    let mut server = ServerConnection::new(Arc::new(server_config))?;
+    let mut client = ClientConnection::new(Arc::new(client_config), ServerName::try_from("www.example.com").unwrap())?;
+    for _ in 0..10 {
+        if !client.is_handshaking() && !server.is_handshaking() {
+            break;
+        }
+        let mut flight = Vec::new();
+        client.write_tls(&mut flight).unwrap();
+        server.read_tls(&mut flight.as_slice()).unwrap();
+        server.process_new_packets()?;
+
+        let mut flight = Vec::new();
+        server.write_tls(&mut flight).unwrap();
+        client.read_tls(&mut flight.as_slice()).unwrap();
+        client.process_new_packets()?;
+    }
+    assert!(!client.is_handshaking(), "handshake did not complete");
+    let presented = verifier.presented.lock().unwrap().take();
+    Ok(presented.expect("no certificate presented"))
+}
+
+#[test]
+fn test_resolver_handshakes() {
+    let site = tls_alpn::challenge_certificate("www.example.com", "unused.thumbprint").unwrap();
+    let site_der = site.end_entity_cert().unwrap().clone().into_owned();
+    let challenges = Arc::new(TlsAlpnChallenges::new());
+    let resolver = Arc::new(TlsAlpnResolver::new(challenges.clone(), Arc::new(SiteResolver(Arc::new(site)))));
+
+    // No challenge pending: a validation handshake is refused rather than given the site certificate
+    assert!(handshake(resolver.clone(), &[ACME_TLS_ALPN_PROTOCOL]).is_err());
+
+    let challenge = challenges.insert("www.example.com", "token.thumbprint").unwrap();
+    let presented = handshake(resolver.clone(), &[ACME_TLS_ALPN_PROTOCOL]).unwrap();
+    assert_eq!(&presented, challenge.end_entity_cert().unwrap());
+    assert_eq!(x509::parse_certificate(&presented).unwrap().dns_names, vec!["www.example.com"]);
+    assert!(presented.windows(OID_ACME_IDENTIFIER.len()).any(|window| window == OID_ACME_IDENTIFIER));
+    let digest = acme_identifier("token.thumbprint");
+    assert!(presented.windows(digest.len()).any(|window| window == digest));
+
+    // Browsers, and clients offering acme-tls/1 among other protocols, get the site certificate
+    assert_eq!(handshake(resolver.clone(), &[b"http/1.1".as_slice()]).unwrap(), site_der);
+    assert_eq!(handshake(resolver.clone(), &[]).unwrap(), site_der);
+    assert_eq!(handshake(resolver, &[ACME_TLS_ALPN_PROTOCOL, b"http/1.1"]).unwrap(), site_der);
+}
+
+/// Kills the server when the test ends, even on panic
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Find a rustls-acme `cached_cert*` file up to two levels below `dir`
+fn find_cached_cert(dir: &Path, depth: usize) -> Option<PathBuf> {
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() && depth > 0 {
+            if let Some(found) = find_cached_cert(&path, depth - 1) {
+                return Some(found);
+            }
+        } else if entry.file_name().to_string_lossy().starts_with("cached_cert") {
+            return Some(path);
+        }
+    }
+    None
+}
+
+#[test]
+#[ignore]
+fn test_tls_alpn_certificate_issued() {
+    let directory = std::env::var("EASYP_TEST_ACME_DIRECTORY").expect("EASYP_TEST_ACME_DIRECTORY must name the ACME directory URL");
+    let domain = std::env::var("EASYP_TEST_ACME_DOMAIN").expect("EASYP_TEST_ACME_DOMAIN must resolve to this machine");
+    let address = std::env::var("EASYP_TEST_ACME_IP").unwrap_or_else(|_| "127.0.0.1".to_string());
+
+    let work_dir = std::env::temp_dir().join(format!("easyp-tls-alpn-{}", std::process::id()));
+    let cache_dir = work_dir.join("cache");
+    let root_dir = work_dir.join("root");
+    std::fs::create_dir_all(&cache_dir).unwrap();
+    std::fs::create_dir_all(&root_dir).unwrap();
+    std::fs::write(root_dir.join("index.html"), "tls-alpn-01").unwrap();
+
+    let _server = Server(
+        Command::new(env!("CARGO_BIN_EXE_easyp"))
+            .args(["--port", "443", "--http-port", "8080"])
+            .args(["--challenge-type", "tls-alpn01"])
+            .args(["--acme-directory", &directory])
+            .args(["--acme-email", "admin@example.com"])
+            .args(["--allowed-ips", &address])
+            .arg("--cache-dir")
+            .arg(&cache_dir)
+            .arg("--root")
+            .arg(&root_dir)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("failed to start easyp"),
+    );
+
+    // Certificates are ordered on demand, so keep making requests for the domain until
+    // the ACME server has validated it over acme-tls/1 and the certificate is cached
+    let deadline = Instant::now() + Duration::from_secs(120);
+    let cert = loop {
+        let _ = Command::new("curl")
+            .args(["--silent", "--insecure", "--max-time", "10", "--output", "/dev/null"])
+            .args(["--resolve", &format!("{}:443:{}", domain, address)])
+            .arg(format!("https://{}/", domain))
+            .status();
+        if let Some(cert) = find_cached_cert(&cache_dir, 2) {
+            break cert;
+        }
+        assert!(Instant::now() < deadline, "no certificate for {} in {}", domain, cache_dir.display());
+        std::thread::sleep(Duration::from_secs(2));
+    };
+
+    assert!(std::fs::metadata(&cert).unwrap().len() > 0);
+    let _ = std::fs::remove_dir_all(&work_dir);
+}